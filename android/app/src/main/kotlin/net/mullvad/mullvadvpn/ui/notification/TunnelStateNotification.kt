@@ -87,6 +87,12 @@ class TunnelStateNotification(
                     ParameterGenerationError.NoMatchingBridgeRelay -> {
                         R.string.no_matching_bridge_relay
                     }
+                    ParameterGenerationError.NoMatchingPortForwardingRelay -> {
+                        R.string.no_matching_port_forwarding_relay
+                    }
+                    ParameterGenerationError.NoMatchingObfuscatorRelay -> {
+                        R.string.no_matching_obfuscator_relay
+                    }
                     ParameterGenerationError.NoWireguardKey -> R.string.no_wireguard_key
                     ParameterGenerationError.CustomTunnelHostResultionError -> {
                         R.string.custom_tunnel_host_resolution_error
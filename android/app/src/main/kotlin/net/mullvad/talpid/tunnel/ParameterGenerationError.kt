@@ -1,5 +1,10 @@
 package net.mullvad.talpid.tunnel
 
 enum class ParameterGenerationError {
-    NoMatchingRelay, NoMatchingBridgeRelay, NoWireguardKey, CustomTunnelHostResultionError
+    NoMatchingRelay,
+    NoMatchingBridgeRelay,
+    NoMatchingPortForwardingRelay,
+    NoMatchingObfuscatorRelay,
+    NoWireguardKey,
+    CustomTunnelHostResultionError
 }
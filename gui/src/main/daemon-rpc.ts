@@ -348,6 +348,7 @@ export class DaemonRpc {
       auto: grpcTypes.BridgeState.State.AUTO,
       on: grpcTypes.BridgeState.State.ON,
       off: grpcTypes.BridgeState.State.OFF,
+      'on-demand': grpcTypes.BridgeState.State.ON_DEMAND,
     };
 
     const grpcBridgeState = new grpcTypes.BridgeState();
@@ -978,6 +979,7 @@ function convertFromBridgeState(bridgeState: grpcTypes.BridgeState.State): Bridg
     [grpcTypes.BridgeState.State.AUTO]: 'auto',
     [grpcTypes.BridgeState.State.ON]: 'on',
     [grpcTypes.BridgeState.State.OFF]: 'off',
+    [grpcTypes.BridgeState.State.ON_DEMAND]: 'on-demand',
   };
 
   return bridgeStateMap[bridgeState];
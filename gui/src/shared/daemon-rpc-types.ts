@@ -366,7 +366,7 @@ export interface ISettings {
   splitTunnel: SplitTunnelSettings;
 }
 
-export type BridgeState = 'auto' | 'on' | 'off';
+export type BridgeState = 'auto' | 'on' | 'off' | 'on-demand';
 
 export type SplitTunnelSettings = {
   enableExclusions: boolean;
@@ -59,11 +59,20 @@ impl HttpsConnectorWithSniHandle {
             .tx
             .unbounded_send(HttpsConnectorRequest::SetConnectionMode(proxy));
     }
+
+    /// Bind new sockets to `address`, or let the OS choose the source address if `address` is
+    /// `None`. Sockets are only bound if `address` is of the same family as the destination.
+    pub fn set_bind_address(&self, address: Option<IpAddr>) {
+        let _ = self
+            .tx
+            .unbounded_send(HttpsConnectorRequest::SetBindAddress(address));
+    }
 }
 
 enum HttpsConnectorRequest {
     Reset,
     SetConnectionMode(ApiConnectionMode),
+    SetBindAddress(Option<IpAddr>),
 }
 
 #[derive(Clone)]
@@ -126,6 +135,7 @@ pub struct HttpsConnectorWithSni {
 struct HttpsConnectorWithSniInner {
     stream_handles: Vec<AbortableStreamHandle>,
     proxy_config: InnerConnectionMode,
+    bind_address: Option<IpAddr>,
 }
 
 #[cfg(target_os = "android")]
@@ -142,6 +152,7 @@ impl HttpsConnectorWithSni {
         let inner = Arc::new(Mutex::new(HttpsConnectorWithSniInner {
             stream_handles: vec![],
             proxy_config: InnerConnectionMode::Direct,
+            bind_address: None,
         }));
 
         let inner_copy = inner.clone();
@@ -152,20 +163,26 @@ impl HttpsConnectorWithSni {
                 let handles = {
                     let mut inner = inner_copy.lock().unwrap();
 
-                    if let HttpsConnectorRequest::SetConnectionMode(config) = request {
-                        match InnerConnectionMode::try_from(config) {
-                            Ok(config) => {
-                                inner.proxy_config = config;
-                            }
-                            Err(error) => {
-                                log::error!(
-                                    "{}",
-                                    error.display_chain_with_msg(
-                                        "Failed to parse new API proxy config"
-                                    )
-                                );
+                    match request {
+                        HttpsConnectorRequest::SetConnectionMode(config) => {
+                            match InnerConnectionMode::try_from(config) {
+                                Ok(config) => {
+                                    inner.proxy_config = config;
+                                }
+                                Err(error) => {
+                                    log::error!(
+                                        "{}",
+                                        error.display_chain_with_msg(
+                                            "Failed to parse new API proxy config"
+                                        )
+                                    );
+                                }
                             }
                         }
+                        HttpsConnectorRequest::SetBindAddress(address) => {
+                            inner.bind_address = address;
+                        }
+                        HttpsConnectorRequest::Reset => (),
                     }
 
                     std::mem::take(&mut inner.stream_handles)
@@ -193,6 +210,7 @@ impl HttpsConnectorWithSni {
 
     async fn open_socket(
         addr: SocketAddr,
+        bind_address: Option<IpAddr>,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> std::io::Result<TcpStream> {
         let socket = match addr {
@@ -200,6 +218,12 @@ impl HttpsConnectorWithSni {
             SocketAddr::V6(_) => TcpSocket::new_v6()?,
         };
 
+        if let Some(bind_address) = bind_address {
+            if bind_address.is_ipv4() == addr.is_ipv4() {
+                socket.bind(SocketAddr::new(bind_address, 0))?;
+            }
+        }
+
         #[cfg(target_os = "android")]
         if let Some(mut tx) = socket_bypass_tx {
             let (done_tx, done_rx) = oneshot::channel();
@@ -294,12 +318,16 @@ impl Service<Uri> for HttpsConnectorWithSni {
             // is selected while connecting.
             let stream = loop {
                 let notify = abort_notify.notified();
-                let config = { inner.lock().unwrap().proxy_config.clone() };
+                let (config, bind_address) = {
+                    let inner = inner.lock().unwrap();
+                    (inner.proxy_config.clone(), inner.bind_address)
+                };
                 let stream_fut = async {
                     match config {
                         InnerConnectionMode::Direct => {
                             let socket = Self::open_socket(
                                 addr,
+                                bind_address,
                                 #[cfg(target_os = "android")]
                                 socket_bypass_tx.clone(),
                             )
@@ -310,6 +338,7 @@ impl Service<Uri> for HttpsConnectorWithSni {
                         InnerConnectionMode::Proxied(proxy_config) => {
                             let socket = Self::open_socket(
                                 proxy_config.peer,
+                                bind_address,
                                 #[cfg(target_os = "android")]
                                 socket_bypass_tx.clone(),
                             )
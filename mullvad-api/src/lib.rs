@@ -15,6 +15,10 @@ use std::{
     future::Future,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 use talpid_types::ErrorExt;
 
@@ -129,6 +133,7 @@ pub struct Runtime {
     handle: tokio::runtime::Handle,
     pub address_cache: AddressCache,
     api_availability: availability::ApiAvailability,
+    request_errors: Arc<AtomicU64>,
     #[cfg(target_os = "android")]
     socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
 }
@@ -176,6 +181,7 @@ impl Runtime {
             handle,
             address_cache: AddressCache::new(None)?,
             api_availability: ApiAvailability::new(availability::State::default()),
+            request_errors: Arc::new(AtomicU64::new(0)),
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
@@ -223,6 +229,7 @@ impl Runtime {
             handle,
             address_cache,
             api_availability: ApiAvailability::new(availability::State::default()),
+            request_errors: Arc::new(AtomicU64::new(0)),
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         })
@@ -242,6 +249,7 @@ impl Runtime {
             self.address_cache.clone(),
             proxy_provider,
             new_address_callback,
+            self.request_errors.clone(),
             #[cfg(target_os = "android")]
             socket_bypass_tx,
         )
@@ -295,6 +303,12 @@ impl Runtime {
     pub fn availability_handle(&self) -> ApiAvailabilityHandle {
         self.api_availability.handle()
     }
+
+    /// Returns the number of requests, made by any request service created by this runtime,
+    /// that failed or received an error response.
+    pub fn request_error_count(&self) -> u64 {
+        self.request_errors.load(Ordering::Relaxed)
+    }
 }
 
 #[derive(Clone)]
@@ -477,6 +491,10 @@ pub struct AppVersionResponse {
     pub latest: AppVersion,
     pub latest_stable: Option<AppVersion>,
     pub latest_beta: AppVersion,
+    /// Fraction of installations, between 0 and 1, that `latest_stable` has been released to.
+    /// This is missing once the version has been released to everyone.
+    #[serde(default)]
+    pub latest_stable_rollout: Option<f32>,
 }
 
 impl AppVersionProxy {
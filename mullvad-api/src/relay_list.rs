@@ -316,6 +316,7 @@ fn relay(relay: Relay, location: location::Location) -> relay_list::Relay {
         bridges: Default::default(),
         obfuscators: Default::default(),
         location: Some(location),
+        port_forwarding: relay.port_forwarding,
     }
 }
 
@@ -343,6 +344,8 @@ struct Relay {
     ipv4_addr_in: Ipv4Addr,
     weight: u64,
     include_in_country: bool,
+    #[serde(default)]
+    port_forwarding: bool,
 }
 
 impl Relay {
@@ -20,11 +20,16 @@ use hyper::{
 use mullvad_types::account::AccountToken;
 use std::{
     future::Future,
+    net::{IpAddr, SocketAddr},
     str::FromStr,
-    sync::{Arc, Weak},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
     time::Duration,
 };
 use talpid_types::ErrorExt;
+use tokio::sync::Semaphore;
 
 pub use hyper::StatusCode;
 
@@ -40,6 +45,9 @@ const API_IP_CHECK_ERROR_INTERVAL: Duration = Duration::from_secs(15 * 60);
 pub type Result<T> = std::result::Result<T, Error>;
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
 
+/// Highest number of requests that may be allowed in flight at the same time.
+pub const MAX_CONCURRENCY_LIMIT: usize = 1024;
+
 /// Describes all the ways a REST request can fail
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -121,6 +129,10 @@ pub(crate) struct RequestService<
     new_address_callback: F,
     address_cache: AddressCache,
     api_availability: ApiAvailabilityHandle,
+    request_errors: Arc<AtomicU64>,
+    user_agent: HeaderValue,
+    /// Limits the number of requests that are in flight at the same time, if set.
+    concurrency_limit: Option<Arc<Semaphore>>,
 }
 
 impl<
@@ -135,6 +147,7 @@ impl<
         address_cache: AddressCache,
         mut proxy_config_provider: T,
         new_address_callback: F,
+        request_errors: Arc<AtomicU64>,
         #[cfg(target_os = "android")] socket_bypass_tx: Option<mpsc::Sender<SocketBypassRequest>>,
     ) -> RequestServiceHandle {
         let (connector, connector_handle) = HttpsConnectorWithSni::new(
@@ -163,6 +176,9 @@ impl<
             new_address_callback,
             address_cache,
             api_availability,
+            request_errors,
+            user_agent: HeaderValue::from_static(USER_AGENT),
+            concurrency_limit: None,
         };
         let handle = RequestServiceHandle { tx: command_tx };
         tokio::spawn(service.into_future());
@@ -175,9 +191,14 @@ impl<
                 let tx = self.command_tx.upgrade();
                 let timeout = request.timeout();
 
-                let hyper_request = request.into_request();
+                let mut hyper_request = request.into_request();
+                hyper_request
+                    .headers_mut()
+                    .insert(header::USER_AGENT, self.user_agent.clone());
 
                 let api_availability = self.api_availability.clone();
+                let request_errors = self.request_errors.clone();
+                let concurrency_limit = self.concurrency_limit.clone();
                 let suspend_fut = api_availability.wait_for_unsuspend();
                 let request_fut = self.client.request(hyper_request).map_err(Error::from);
 
@@ -187,17 +208,38 @@ impl<
                 };
 
                 let future = async move {
+                    // Wait for a free slot before starting the timeout, so that queued
+                    // requests do not time out.
+                    let _permit = match &concurrency_limit {
+                        Some(limit) => limit.acquire().await.ok(),
+                        None => None,
+                    };
+
                     let response = tokio::time::timeout(timeout, request_future)
                         .await
                         .map_err(Error::TimeoutError);
 
                     let response = flatten_result(response).map_err(|error| error.map_aborted());
 
+                    let is_error = match &response {
+                        // Conditional requests, such as relay list updates, are answered with
+                        // "304 Not Modified" when nothing has changed
+                        Ok(response) => {
+                            !response.status().is_success()
+                                && response.status() != StatusCode::NOT_MODIFIED
+                        }
+                        Err(Error::Aborted) => false,
+                        Err(_) => true,
+                    };
+                    if is_error {
+                        request_errors.fetch_add(1, Ordering::Relaxed);
+                    }
+
                     if let Err(err) = &response {
                         if err.is_network_error() && !api_availability.get_state().is_offline() {
                             log::error!("{}", err.display_chain_with_msg("HTTP request failed"));
                             if let Some(tx) = tx {
-                                let _ = tx.unbounded_send(RequestCommand::NextApiConfig);
+                                let _ = tx.unbounded_send(RequestCommand::NextApiConfig(None));
                             }
                         }
                     }
@@ -213,7 +255,18 @@ impl<
             RequestCommand::Reset => {
                 self.connector_handle.reset();
             }
-            RequestCommand::NextApiConfig => {
+            RequestCommand::SetUserAgent(user_agent) => {
+                self.user_agent = user_agent;
+            }
+            RequestCommand::SetConcurrencyLimit(limit) => {
+                self.concurrency_limit =
+                    limit.map(|limit| Arc::new(Semaphore::new(limit.min(MAX_CONCURRENCY_LIMIT))));
+            }
+            RequestCommand::SetBindAddress(address) => {
+                self.connector_handle.set_bind_address(address);
+            }
+            RequestCommand::NextApiConfig(completion_tx) => {
+                let mut new_endpoint = None;
                 if let Some(new_config) = self.proxy_config_provider.next().await {
                     let endpoint = match new_config.get_endpoint() {
                         Some(endpoint) => endpoint,
@@ -222,8 +275,12 @@ impl<
                     // Switch to new connection mode unless rejected by address change callback
                     if (self.new_address_callback)(endpoint).await {
                         self.connector_handle.set_connection_mode(new_config);
+                        new_endpoint = Some(endpoint);
                     }
                 }
+                if let Some(completion_tx) = completion_tx {
+                    let _ = completion_tx.send(new_endpoint);
+                }
             }
         }
     }
@@ -260,9 +317,51 @@ impl RequestServiceHandle {
     /// Forcibly update the connection mode.
     pub async fn next_api_endpoint(&self) -> Result<()> {
         self.tx
-            .unbounded_send(RequestCommand::NextApiConfig)
+            .unbounded_send(RequestCommand::NextApiConfig(None))
             .map_err(|_| Error::SendError)
     }
+
+    /// Appends `tag` to the User-Agent of subsequent requests, or restores the default User-Agent
+    /// if `tag` is `None`. Returns an error if the resulting User-Agent is not a valid header
+    /// value.
+    pub fn set_user_agent_tag(&self, tag: Option<&str>) -> Result<()> {
+        let user_agent = match tag {
+            Some(tag) => HeaderValue::from_str(&format!("{} {}", USER_AGENT, tag))
+                .map_err(Error::InvalidHeaderError)?,
+            None => HeaderValue::from_static(USER_AGENT),
+        };
+        self.tx
+            .unbounded_send(RequestCommand::SetUserAgent(user_agent))
+            .map_err(|_| Error::SendError)
+    }
+
+    /// Limits the number of requests that are in flight at the same time to `limit`, or removes
+    /// the limit if `limit` is `None`. Requests beyond the limit wait until an earlier request
+    /// has completed. Requests that were submitted before the limit was changed are not affected.
+    /// Limits above [`MAX_CONCURRENCY_LIMIT`] are lowered to it.
+    pub fn set_concurrency_limit(&self, limit: Option<usize>) -> Result<()> {
+        self.tx
+            .unbounded_send(RequestCommand::SetConcurrencyLimit(limit))
+            .map_err(|_| Error::SendError)
+    }
+
+    /// Binds the sockets of subsequent connections to `address`, or lets the OS choose the source
+    /// address if `address` is `None`. In-flight requests are dropped.
+    pub fn set_bind_address(&self, address: Option<IpAddr>) -> Result<()> {
+        self.tx
+            .unbounded_send(RequestCommand::SetBindAddress(address))
+            .map_err(|_| Error::SendError)
+    }
+
+    /// Forcibly update the connection mode and wait until it has been switched. Returns the
+    /// endpoint of the new connection mode, or `None` if the connection mode was not changed.
+    pub async fn rotate_api_endpoint(&self) -> Result<Option<SocketAddr>> {
+        let (completion_tx, completion_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RequestCommand::NextApiConfig(Some(completion_tx)))
+            .map_err(|_| Error::SendError)?;
+        completion_rx.await.map_err(|_| Error::ReceiveError)
+    }
 }
 
 #[derive(Debug)]
@@ -272,7 +371,10 @@ pub(crate) enum RequestCommand {
         oneshot::Sender<std::result::Result<Response, Error>>,
     ),
     Reset,
-    NextApiConfig,
+    NextApiConfig(Option<oneshot::Sender<Option<SocketAddr>>>),
+    SetUserAgent(HeaderValue),
+    SetConcurrencyLimit(Option<usize>),
+    SetBindAddress(Option<IpAddr>),
 }
 
 /// A REST request that is sent to the RequestService to be executed.
@@ -9,6 +9,7 @@ use std::io::{self, Write};
 
 const NOT_LOGGED_IN_ERROR: &str = "Not logged in to any account";
 const DEVICE_NOT_FOUND_ERROR: &str = "There is no such device";
+const INVALID_DEVICE_ERROR: &str = "Received an invalid device from the daemon";
 const INVALID_ACCOUNT_ERROR: &str = "The account does not exist";
 const TOO_MANY_DEVICES_ERROR: &str =
     "There are too many devices on this account. Revoke one to log in";
@@ -119,9 +120,17 @@ impl Account {
 
     async fn login(&self, token: AccountToken) -> Result<()> {
         let mut rpc = new_rpc_client().await?;
-        rpc.login_account(token.clone())
-            .await
-            .map_err(map_device_error)?;
+        if let Err(error) = rpc.login_account(token.clone()).await {
+            if let Some(device_list) = mullvad_management_interface::devices_from_status(&error) {
+                eprintln!("Devices on the account:");
+                for device in device_list.devices {
+                    let device =
+                        Device::try_from(device).map_err(|_| Error::Other(INVALID_DEVICE_ERROR))?;
+                    eprintln!("{}", device.pretty_name());
+                }
+            }
+            return Err(map_device_error(error));
+        }
         println!("Mullvad account \"{}\" set", token);
         Ok(())
     }
@@ -92,8 +92,8 @@ fn create_set_custom_settings_subcommand() -> clap::App<'static> {
     {
         local_subcommand = local_subcommand.about(
             "Registers a local SOCKS5 proxy. The server must be excluded using \
-           'mullvad-exclude', or `SO_MARK` must be set to '0x6d6f6c65', in order \
-           to bypass firewall restrictions",
+           'mullvad-exclude', or `SO_MARK` must be set to the fwmark printed by this \
+           command, in order to bypass firewall restrictions",
         );
     }
     #[cfg(target_os = "macos")]
@@ -168,13 +168,23 @@ fn create_set_custom_settings_subcommand() -> clap::App<'static> {
 }
 
 fn create_set_state_subcommand() -> clap::App<'static> {
-    clap::App::new("state").about("Set bridge state").arg(
-        clap::Arg::new("policy")
-            .help("Specifies whether a bridge should be used")
-            .required(true)
-            .index(1)
-            .possible_values(&["auto", "on", "off"]),
-    )
+    clap::App::new("state")
+        .about("Set bridge state")
+        .arg(
+            clap::Arg::new("policy")
+                .help("Specifies whether a bridge should be used")
+                .required(true)
+                .index(1)
+                .possible_values(&["auto", "on", "off", "on-demand"]),
+        )
+        .arg(
+            clap::Arg::new("threshold")
+                .help(
+                    "Number of failed connection attempts before a bridge is used. Only \
+                     applies to 'on-demand'",
+                )
+                .index(2),
+        )
 }
 
 impl Bridge {
@@ -278,6 +288,19 @@ impl Bridge {
             "auto" => BridgeState::Auto,
             "on" => BridgeState::On,
             "off" => BridgeState::Off,
+            "on-demand" => {
+                let threshold = if matches.is_present("threshold") {
+                    matches.value_of_t_or_exit("threshold")
+                } else {
+                    BridgeState::DEFAULT_ON_DEMAND_THRESHOLD
+                };
+                if threshold == 0 {
+                    return Err(Error::InvalidCommand(
+                        "The threshold must be at least 1. Use 'on' to always use a bridge",
+                    ));
+                }
+                BridgeState::OnDemand { threshold }
+            }
             _ => unreachable!(),
         };
         let mut rpc = new_rpc_client().await?;
@@ -306,6 +329,16 @@ impl Bridge {
                 packed_proxy,
             )))
             .await?;
+
+            #[cfg(target_os = "linux")]
+            {
+                let settings = rpc.get_settings(()).await?.into_inner();
+                println!(
+                    "The proxy must be excluded using 'mullvad-exclude', or `SO_MARK` must be set \
+                    to {:#x}",
+                    settings.tunnel_fwmark
+                );
+            }
         } else if let Some(args) = matches.subcommand_matches("remote") {
             let remote_ip = args.value_of_t_or_exit("remote-ip");
             let remote_port = args.value_of_t_or_exit("remote-port");
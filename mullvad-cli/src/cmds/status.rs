@@ -1,6 +1,7 @@
 use crate::{format, new_rpc_client, Command, Error, Result};
 use mullvad_management_interface::{
-    types::daemon_event::Event as EventType, ManagementServiceClient,
+    types::{auto_connect_suppressed, daemon_event::Event as EventType},
+    ManagementServiceClient,
 };
 
 pub struct Status;
@@ -99,6 +100,65 @@ impl Command for Status {
                             println!("Remove device event: {:#?}", device);
                         }
                     }
+                    EventType::SessionLabel(label) => {
+                        if debug {
+                            println!("New session label: {:?}", label);
+                        }
+                    }
+                    EventType::ConnectionReady(info) => {
+                        if debug {
+                            println!("Connection ready: {:#?}", info);
+                        } else {
+                            format::print_connection_ready(&info);
+                        }
+                    }
+                    EventType::PostUpgradeComplete(()) => {
+                        if debug {
+                            println!("Post-upgrade migration complete");
+                        }
+                    }
+                    EventType::SettingsWriteFailed(error) => {
+                        eprintln!(
+                            "Failed to save a settings change, so it was reverted: {}",
+                            error
+                        );
+                    }
+                    EventType::BackupRelaySettings(active) => {
+                        if active {
+                            println!(
+                                "No relay matches the relay settings. Using the backup relay settings"
+                            );
+                        } else if debug {
+                            println!("Stopped using the backup relay settings");
+                        }
+                    }
+                    EventType::AutoConnectSuppressed(suppressed) => {
+                        let reason =
+                            match auto_connect_suppressed::Reason::from_i32(suppressed.reason) {
+                                Some(auto_connect_suppressed::Reason::NoDevice) => {
+                                    "no device is logged in"
+                                }
+                                Some(auto_connect_suppressed::Reason::MigrationPending) => {
+                                    "the post-upgrade migration has not completed"
+                                }
+                                None => "unknown reason",
+                            };
+                        println!("Did not auto-connect at startup: {}", reason);
+                    }
+                    EventType::EndpointChanged(location) => {
+                        if debug {
+                            println!("New relays selected: {:#?}", location);
+                        }
+                    }
+                    EventType::ProtocolFallback(active) => {
+                        if active {
+                            println!(
+                                "Falling back to OpenVPN after failing to connect using WireGuard"
+                            );
+                        } else if debug {
+                            println!("Stopped falling back to OpenVPN");
+                        }
+                    }
                 }
             }
         }
@@ -5,8 +5,8 @@ use mullvad_management_interface::types::{
     },
     tunnel_state,
     tunnel_state::State::*,
-    ErrorState, ObfuscationType, ProxyType, TransportProtocol, TunnelState, TunnelStateRelayInfo,
-    TunnelType,
+    ConnectionReadyInfo, ErrorState, ObfuscationType, ProxyType, TransportProtocol, TunnelState,
+    TunnelStateRelayInfo, TunnelType,
 };
 use mullvad_types::auth_failed::AuthFailed;
 
@@ -26,11 +26,35 @@ pub fn print_state(state: &TunnelState, verbose: bool) {
                 format_relay_connection(relay_info.as_ref().unwrap(), verbose)
             );
         }
+        Disconnected(tunnel_state::Disconnected {
+            paused_until: Some(paused_until),
+        }) => {
+            let ndt = chrono::NaiveDateTime::from_timestamp(paused_until.seconds, 0);
+            let utc = chrono::DateTime::<chrono::Utc>::from_utc(ndt, chrono::Utc);
+            println!(
+                "Paused until {}",
+                utc.with_timezone(&chrono::Local).format("%H:%M:%S")
+            );
+        }
         Disconnected(_) => println!("Disconnected"),
         Disconnecting(_) => println!("Disconnecting..."),
     }
 }
 
+pub fn print_connection_ready(info: &ConnectionReadyInfo) {
+    let location = if info.city.is_empty() {
+        info.country.clone()
+    } else {
+        format!("{}, {}", info.city, info.country)
+    };
+    let unverified = if info.verified { "" } else { " (unverified)" };
+    if info.ipv4.is_empty() {
+        println!("Connected - {}{}", location, unverified);
+    } else {
+        println!("Connected - {} ({}){}", location, info.ipv4, unverified);
+    }
+}
+
 fn format_relay_connection(relay_info: &TunnelStateRelayInfo, verbose: bool) -> String {
     let endpoint = relay_info.tunnel_endpoint.as_ref().unwrap();
     let location = &relay_info.location.as_ref().unwrap();
@@ -205,6 +229,10 @@ fn error_state_to_string(error_state: &ErrorState) -> String {
         #[cfg(target_os = "windows")]
         SplitTunnelError => "The split tunneling module reported an error",
         #[cfg(not(target_os = "android"))]
+        TunnelUnverified => "The connectivity of the tunnel could not be verified",
+        #[cfg(not(target_os = "android"))]
+        ConnectDeadlineExceeded => "The tunnel did not connect within the configured time",
+        #[cfg(not(target_os = "android"))]
         _ => unreachable!("unknown error cause"),
     };
 
@@ -215,6 +243,12 @@ fn tunnel_parameter_error_to_string(parameter_error: i32) -> &'static str {
     match GenerationError::from_i32(parameter_error).expect("unknown generation error") {
         GenerationError::NoMatchingRelay => "Failure to select a matching tunnel relay",
         GenerationError::NoMatchingBridgeRelay => "Failure to select a matching bridge relay",
+        GenerationError::NoMatchingPortForwardingRelay => {
+            "Failure to select a matching relay with port forwarding"
+        }
+        GenerationError::NoMatchingObfuscatorRelay => {
+            "Failure to select a matching relay that supports the selected obfuscation"
+        }
         GenerationError::NoWireguardKey => "No wireguard key available",
         GenerationError::CustomTunnelHostResolutionError => {
             "Can't resolve hostname for custom tunnel host"
@@ -6,6 +6,7 @@ fn main() {
     let product_version = env!("CARGO_PKG_VERSION").replacen(".0", "", 1);
     fs::write(out_dir.join("product-version.txt"), &product_version).unwrap();
     fs::write(out_dir.join("git-commit-date.txt"), commit_date()).unwrap();
+    fs::write(out_dir.join("git-commit-hash.txt"), commit_hash()).unwrap();
 
     #[cfg(windows)]
     {
@@ -39,3 +40,15 @@ fn commit_date() -> String {
         .trim()
         .to_owned()
 }
+
+/// Returns the hash of the git commit being built, or an empty string if it is unknown.
+fn commit_hash() -> String {
+    Command::new("git")
+        .args(&["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_default()
+}
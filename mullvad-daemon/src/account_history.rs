@@ -1,5 +1,7 @@
+use chrono::{DateTime, Utc};
 use mullvad_types::account::AccountToken;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::path::Path;
 use talpid_types::ErrorExt;
 use tokio::{
@@ -29,7 +31,24 @@ static ACCOUNT_HISTORY_FILE: &str = "account-history.json";
 
 pub struct AccountHistory {
     file: io::BufWriter<fs::File>,
-    token: Option<AccountToken>,
+    /// Recently used accounts, most recent first.
+    entries: Vec<AccountHistoryEntry>,
+    limit: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AccountHistoryEntry {
+    account: AccountToken,
+    last_used: DateTime<Utc>,
+}
+
+impl AccountHistoryEntry {
+    fn new(account: AccountToken) -> Self {
+        AccountHistoryEntry {
+            account,
+            last_used: Utc::now(),
+        }
+    }
 }
 
 lazy_static::lazy_static! {
@@ -37,9 +56,11 @@ lazy_static::lazy_static! {
 }
 
 impl AccountHistory {
+    /// Opens the account history, which retains at most `limit` accounts.
     pub async fn new(
         settings_dir: &Path,
         current_token: Option<AccountToken>,
+        limit: usize,
     ) -> Result<AccountHistory> {
         let mut options = fs::OpenOptions::new();
         #[cfg(unix)]
@@ -63,19 +84,43 @@ impl AccountHistory {
             .map(io::BufReader::new)
             .map_err(Error::Read)?;
 
+        let current_entries = || {
+            current_token
+                .map(|token| vec![AccountHistoryEntry::new(token)])
+                .unwrap_or_default()
+        };
+
         let mut buffer = String::new();
-        let (token, should_save): (Option<AccountToken>, bool) =
+        let (mut entries, mut should_save): (Vec<AccountHistoryEntry>, bool) =
             match reader.read_to_string(&mut buffer).await {
-                Ok(_) if ACCOUNT_REGEX.is_match(&buffer) => (Some(buffer), false),
-                Ok(0) => (current_token, true),
-                Ok(_) | Err(_) => {
+                // Older versions only stored a single account token
+                Ok(_) if ACCOUNT_REGEX.is_match(&buffer) => {
+                    (vec![AccountHistoryEntry::new(buffer)], true)
+                }
+                Ok(0) => (current_entries(), true),
+                Ok(_) => match serde_json::from_str(&buffer) {
+                    Ok(entries) => (entries, false),
+                    Err(_) => {
+                        log::warn!("Failed to parse account history");
+                        (current_entries(), true)
+                    }
+                },
+                Err(_) => {
                     log::warn!("Failed to parse account history");
-                    (current_token, true)
+                    (current_entries(), true)
                 }
             };
+        if entries.len() > limit {
+            entries.truncate(limit);
+            should_save = true;
+        }
 
         let file = io::BufWriter::new(reader.into_inner());
-        let mut history = AccountHistory { file, token };
+        let mut history = AccountHistory {
+            file,
+            entries,
+            limit,
+        };
         if should_save {
             if let Err(error) = history.save_to_disk().await {
                 log::error!(
@@ -87,20 +132,42 @@ impl AccountHistory {
         Ok(history)
     }
 
-    /// Gets the account token in the history
+    /// Gets the most recently used account token in the history
     pub fn get(&self) -> Option<AccountToken> {
-        self.token.clone()
+        self.entries.first().map(|entry| entry.account.clone())
+    }
+
+    /// Gets all account tokens in the history, most recently used first
+    pub fn get_list(&self) -> Vec<AccountToken> {
+        self.entries
+            .iter()
+            .map(|entry| entry.account.clone())
+            .collect()
     }
 
-    /// Replace the account token in the history
-    pub async fn set(&mut self, new_entry: AccountToken) -> Result<()> {
-        self.token = Some(new_entry);
+    /// Adds an account token to the front of the history, removing any older occurrence of it.
+    /// The least recently used token is dropped if the history is full.
+    pub async fn push(&mut self, new_entry: AccountToken) -> Result<()> {
+        self.entries.retain(|entry| entry.account != new_entry);
+        self.entries.insert(0, AccountHistoryEntry::new(new_entry));
+        self.entries.truncate(self.limit);
         self.save_to_disk().await
     }
 
+    /// Sets the maximum number of accounts to retain, dropping the least recently used ones if
+    /// there are already more than that.
+    pub async fn set_limit(&mut self, limit: usize) -> Result<()> {
+        self.limit = limit;
+        if self.entries.len() > limit {
+            self.entries.truncate(limit);
+            self.save_to_disk().await?;
+        }
+        Ok(())
+    }
+
     /// Remove account history
     pub async fn clear(&mut self) -> Result<()> {
-        self.token = None;
+        self.entries.clear();
         self.save_to_disk().await
     }
 
@@ -110,12 +177,8 @@ impl AccountHistory {
             .seek(io::SeekFrom::Start(0))
             .await
             .map_err(Error::Write)?;
-        if let Some(ref token) = self.token {
-            self.file
-                .write_all(token.as_bytes())
-                .await
-                .map_err(Error::Write)?;
-        }
+        let contents = serde_json::to_vec(&self.entries).map_err(Error::Serialize)?;
+        self.file.write_all(&contents).await.map_err(Error::Write)?;
         self.file.flush().await.map_err(Error::Write)?;
         self.file.get_mut().sync_all().await.map_err(Error::Write)
     }
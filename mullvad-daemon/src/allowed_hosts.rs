@@ -0,0 +1,321 @@
+//! Resolves the hostnames of the allowed hosts and keeps the firewall exceptions for them up to
+//! date.
+
+use futures::channel::{mpsc, oneshot};
+use mullvad_types::settings::AllowedHost;
+use std::{net::IpAddr, sync::Weak, time::Duration};
+use talpid_core::tunnel_state_machine::TunnelCommand;
+use talpid_types::net::{Endpoint, TransportProtocol};
+use tokio::{fs, task::JoinHandle};
+use trust_dns_resolver::{
+    config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::ResolveError,
+    TokioAsyncResolver,
+};
+
+/// How often the hostnames are resolved again.
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How soon to try again when a hostname could not be resolved at all.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// File that lists the resolvers used by the system resolver.
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+/// File that lists the upstream resolvers of systemd-resolved. When systemd-resolved is used,
+/// [RESOLV_CONF_PATH] only lists its local stub resolver.
+#[cfg(target_os = "linux")]
+const SYSTEMD_RESOLV_CONF_PATH: &str = "/run/systemd/resolve/resolv.conf";
+const DNS_PORT: u16 = 53;
+
+/// Periodically resolves the allowed hosts and tells the tunnel state machine which endpoints
+/// to allow.
+pub struct AllowedHostsResolver {
+    tunnel_cmd_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl AllowedHostsResolver {
+    pub fn new(tunnel_cmd_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>) -> Self {
+        Self {
+            tunnel_cmd_tx,
+            task: None,
+        }
+    }
+
+    /// Replaces the hosts to allow. The exceptions for the previous hosts are removed once the
+    /// new hostnames have been resolved, or immediately if `hosts` is empty.
+    pub fn set_hosts(&mut self, hosts: Vec<AllowedHost>) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        self.task = Some(tokio::spawn(resolve_hosts(
+            hosts,
+            self.tunnel_cmd_tx.clone(),
+        )));
+    }
+}
+
+impl Drop for AllowedHostsResolver {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+    }
+}
+
+async fn resolve_hosts(
+    hosts: Vec<AllowedHost>,
+    tunnel_cmd_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>,
+) {
+    // The most recent addresses of each host. They are kept if a host cannot be resolved, such
+    // as when DNS is blocked.
+    let mut host_endpoints = vec![vec![]; hosts.len()];
+    let mut allowed_endpoints = None;
+
+    loop {
+        if !hosts.is_empty() {
+            // DNS is dropped while connecting or blocking. The lookups are sent by the daemon
+            // itself, rather than by a local stub resolver, so that the resolvers can be allowed
+            // like the hosts, that is for the daemon only, until the lookups are done.
+            let nameservers = system_nameservers().await;
+            let resolvers = resolver_endpoints(&nameservers);
+            if !resolvers.is_empty() {
+                let endpoints = unique_endpoints(host_endpoints.iter().flatten().chain(&resolvers));
+                if !allow_endpoints(&tunnel_cmd_tx, endpoints).await {
+                    return;
+                }
+                allowed_endpoints = None;
+            }
+
+            match new_resolver(&nameservers) {
+                Ok(resolver) => {
+                    for (host, endpoints) in hosts.iter().zip(host_endpoints.iter_mut()) {
+                        match resolve(&resolver, host).await {
+                            Ok(resolved) if !resolved.is_empty() => *endpoints = resolved,
+                            Ok(_) => log::warn!("Allowed host {} has no addresses", host),
+                            Err(error) => {
+                                log::warn!("Failed to resolve allowed host {}: {}", host, error)
+                            }
+                        }
+                    }
+                }
+                Err(error) => log::error!("Failed to create resolver for allowed hosts: {}", error),
+            }
+        }
+
+        let endpoints = unique_endpoints(host_endpoints.iter().flatten());
+        if allowed_endpoints.as_ref() != Some(&endpoints) {
+            log::debug!(
+                "Allowed host endpoints: {}",
+                endpoints
+                    .iter()
+                    .map(|endpoint| endpoint.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            if !allow_endpoints(&tunnel_cmd_tx, endpoints.clone()).await {
+                return;
+            }
+            allowed_endpoints = Some(endpoints);
+        }
+
+        if hosts.is_empty() {
+            return;
+        }
+        let interval = if host_endpoints.iter().any(|endpoints| endpoints.is_empty()) {
+            RETRY_INTERVAL
+        } else {
+            RESOLVE_INTERVAL
+        };
+        tokio::time::sleep(interval).await;
+    }
+}
+
+fn unique_endpoints<'a>(endpoints: impl Iterator<Item = &'a Endpoint>) -> Vec<Endpoint> {
+    let mut unique: Vec<Endpoint> = vec![];
+    for endpoint in endpoints {
+        if !unique.contains(endpoint) {
+            unique.push(*endpoint);
+        }
+    }
+    unique
+}
+
+/// Returns the resolvers that the system resolver uses. If only loopback resolvers are
+/// configured and systemd-resolved is running, its upstream resolvers are returned instead.
+async fn system_nameservers() -> Vec<IpAddr> {
+    let nameservers = read_nameservers(RESOLV_CONF_PATH).await;
+    #[cfg(target_os = "linux")]
+    {
+        if nameservers.iter().all(IpAddr::is_loopback) {
+            let upstream = read_nameservers(SYSTEMD_RESOLV_CONF_PATH).await;
+            if !upstream.is_empty() {
+                return upstream;
+            }
+        }
+    }
+    nameservers
+}
+
+async fn read_nameservers(path: &str) -> Vec<IpAddr> {
+    match fs::read_to_string(path).await {
+        Ok(contents) => parse_nameservers(&contents),
+        Err(error) => {
+            log::warn!("Failed to read {}: {}", path, error);
+            vec![]
+        }
+    }
+}
+
+/// Returns the addresses of the resolvers listed in `resolv_conf`.
+fn parse_nameservers(resolv_conf: &str) -> Vec<IpAddr> {
+    let mut nameservers = vec![];
+    for line in resolv_conf.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("nameserver") {
+            continue;
+        }
+        if let Some(address) = words.next().and_then(|address| address.parse().ok()) {
+            nameservers.push(address);
+        }
+    }
+    nameservers
+}
+
+/// Returns the DNS endpoints of `nameservers`. Loopback resolvers are left out, since loopback
+/// traffic is always allowed.
+fn resolver_endpoints(nameservers: &[IpAddr]) -> Vec<Endpoint> {
+    let mut endpoints = vec![];
+    for address in nameservers {
+        if address.is_loopback() {
+            continue;
+        }
+        for protocol in [TransportProtocol::Udp, TransportProtocol::Tcp] {
+            endpoints.push(Endpoint::new(*address, DNS_PORT, protocol));
+        }
+    }
+    endpoints
+}
+
+/// Returns a resolver that sends its queries to `nameservers` from the daemon process. Both A and
+/// AAAA records are looked up.
+fn new_resolver(nameservers: &[IpAddr]) -> Result<TokioAsyncResolver, ResolveError> {
+    let config = ResolverConfig::from_parts(
+        None,
+        vec![],
+        NameServerConfigGroup::from_ips_clear(nameservers, DNS_PORT, true),
+    );
+    let options = ResolverOpts {
+        ip_strategy: LookupIpStrategy::Ipv4AndIpv6,
+        ..ResolverOpts::default()
+    };
+    TokioAsyncResolver::tokio(config, options)
+}
+
+async fn resolve(
+    resolver: &TokioAsyncResolver,
+    host: &AllowedHost,
+) -> Result<Vec<Endpoint>, ResolveError> {
+    let addresses = resolver.lookup_ip(host.hostname.as_str()).await?;
+    Ok(host_addresses_endpoints(host, addresses.iter()))
+}
+
+/// Returns an endpoint on the port and protocol of `host` for each address it resolved to.
+fn host_addresses_endpoints(
+    host: &AllowedHost,
+    addresses: impl IntoIterator<Item = IpAddr>,
+) -> Vec<Endpoint> {
+    let endpoints = addresses
+        .into_iter()
+        .map(|address| Endpoint::new(address, host.port, host.protocol))
+        .collect::<Vec<_>>();
+    unique_endpoints(endpoints.iter())
+}
+
+/// Sets the endpoints that the firewall should allow. Returns `false` if the tunnel state
+/// machine is not running.
+async fn allow_endpoints(
+    tunnel_cmd_tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>,
+    endpoints: Vec<Endpoint>,
+) -> bool {
+    let tunnel_cmd_tx = match tunnel_cmd_tx.upgrade() {
+        Some(tunnel_cmd_tx) => tunnel_cmd_tx,
+        None => return false,
+    };
+    let (result_tx, result_rx) = oneshot::channel();
+    if tunnel_cmd_tx
+        .unbounded_send(TunnelCommand::AllowHosts(endpoints, result_tx))
+        .is_err()
+    {
+        return false;
+    }
+    // Wait for the firewall policy to be updated.
+    result_rx.await.is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_nameservers() {
+        let resolv_conf = "# Generated\n\
+            nameserver 192.0.2.1\n\
+            nameserver 127.0.0.53\n\
+            search example.com\n\
+            nameserver fe80::1%eth0\n\
+            nameserver 2001:db8::1\n";
+
+        assert_eq!(
+            parse_nameservers(resolv_conf),
+            vec![ip("192.0.2.1"), ip("127.0.0.53"), ip("2001:db8::1")]
+        );
+    }
+
+    #[test]
+    fn test_resolver_endpoints() {
+        let nameservers = [ip("192.0.2.1"), ip("127.0.0.53"), ip("2001:db8::1")];
+
+        let dns = |address: &str, protocol| Endpoint::new(ip(address), 53, protocol);
+        assert_eq!(
+            resolver_endpoints(&nameservers),
+            vec![
+                dns("192.0.2.1", TransportProtocol::Udp),
+                dns("192.0.2.1", TransportProtocol::Tcp),
+                dns("2001:db8::1", TransportProtocol::Udp),
+                dns("2001:db8::1", TransportProtocol::Tcp),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_with_multiple_addresses() {
+        let host = AllowedHost {
+            hostname: "example.com".to_owned(),
+            port: 443,
+            protocol: TransportProtocol::Tcp,
+        };
+        let addresses = [
+            ip("192.0.2.1"),
+            ip("192.0.2.2"),
+            ip("2001:db8::1"),
+            ip("192.0.2.1"),
+            ip("2001:db8::2"),
+        ];
+
+        let endpoint = |address: &str| Endpoint::new(ip(address), 443, TransportProtocol::Tcp);
+        assert_eq!(
+            host_addresses_endpoints(&host, addresses),
+            vec![
+                endpoint("192.0.2.1"),
+                endpoint("192.0.2.2"),
+                endpoint("2001:db8::1"),
+                endpoint("2001:db8::2"),
+            ]
+        );
+    }
+}
@@ -31,11 +31,18 @@ use talpid_types::{
 ///   location and matches all bridge constraints.
 /// * When no matching bridge is found, e.g. if the selected hosting providers don't match any
 ///   bridge, [`ApiConnectionMode::Direct`] is returned.
+///
+/// If a bridge relay is pinned, a bridge on that relay is returned for every attempt instead, as
+/// long as the relay is available.
 pub struct ApiConnectionModeProvider {
     cache_dir: PathBuf,
 
     relay_selector: RelaySelector,
     retry_attempt: u32,
+    /// The connection mode that was most recently returned.
+    current_mode: Arc<Mutex<ApiConnectionMode>>,
+    /// Hostname of the bridge relay to always use, if any.
+    pinned_bridge: Arc<Mutex<Option<String>>>,
 
     current_task: Option<Pin<Box<dyn Future<Output = ApiConnectionMode> + Send>>>,
 }
@@ -59,25 +66,44 @@ impl Stream for ApiConnectionModeProvider {
         }
 
         // Create a new task.
-        let config = if Self::should_use_bridge(self.retry_attempt) {
-            self.relay_selector
-                .get_bridge_forced()
-                .map(|settings| match settings {
-                    ProxySettings::Shadowsocks(ss_settings) => {
-                        ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss_settings))
-                    }
-                    _ => {
-                        log::error!("Received unexpected proxy settings type");
-                        ApiConnectionMode::Direct
-                    }
-                })
-                .unwrap_or(ApiConnectionMode::Direct)
+        let pinned_bridge = self
+            .pinned_bridge
+            .lock()
+            .unwrap()
+            .clone()
+            .and_then(|hostname| {
+                let bridge = self.relay_selector.get_bridge_by_hostname(&hostname);
+                if bridge.is_none() {
+                    log::warn!(
+                        "Pinned API bridge {} is unavailable. Selecting a bridge automatically",
+                        hostname
+                    );
+                }
+                bridge
+            });
+        let bridge = if pinned_bridge.is_some() {
+            pinned_bridge
+        } else if Self::should_use_bridge(self.retry_attempt) {
+            self.relay_selector.get_bridge_forced()
         } else {
-            ApiConnectionMode::Direct
+            None
         };
+        let config = bridge
+            .map(|settings| match settings {
+                ProxySettings::Shadowsocks(ss_settings) => {
+                    ApiConnectionMode::Proxied(ProxyConfig::Shadowsocks(ss_settings))
+                }
+                _ => {
+                    log::error!("Received unexpected proxy settings type");
+                    ApiConnectionMode::Direct
+                }
+            })
+            .unwrap_or(ApiConnectionMode::Direct);
 
         self.retry_attempt = self.retry_attempt.wrapping_add(1);
 
+        *self.current_mode.lock().unwrap() = config.clone();
+
         let cache_dir = self.cache_dir.clone();
         self.current_task = Some(Box::pin(async move {
             if let Err(error) = config.save(&cache_dir).await {
@@ -94,44 +120,80 @@ impl Stream for ApiConnectionModeProvider {
 }
 
 impl ApiConnectionModeProvider {
-    pub(crate) fn new(cache_dir: PathBuf, relay_selector: RelaySelector) -> Self {
+    pub(crate) fn new(
+        cache_dir: PathBuf,
+        relay_selector: RelaySelector,
+        pinned_bridge: Option<String>,
+    ) -> Self {
         Self {
             cache_dir,
 
             relay_selector,
             retry_attempt: 0,
+            current_mode: Arc::new(Mutex::new(ApiConnectionMode::Direct)),
+            pinned_bridge: Arc::new(Mutex::new(pinned_bridge)),
 
             current_task: None,
         }
     }
 
+    /// Returns a handle to the connection mode that is currently in use.
+    pub(crate) fn current_mode(&self) -> Arc<Mutex<ApiConnectionMode>> {
+        self.current_mode.clone()
+    }
+
+    /// Returns a handle to the hostname of the pinned bridge relay.
+    pub(crate) fn pinned_bridge(&self) -> Arc<Mutex<Option<String>>> {
+        self.pinned_bridge.clone()
+    }
+
     fn should_use_bridge(retry_attempt: u32) -> bool {
         retry_attempt % 3 > 0
     }
 }
 
+/// Describes how the daemon currently reaches the API.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiConnectionModeInfo {
+    /// The connection mode most recently returned by [`ApiConnectionModeProvider`].
+    pub mode: ApiConnectionMode,
+    /// The address of the bridge if the API is reached through a proxy, or otherwise the address
+    /// of the API itself.
+    pub endpoint: SocketAddr,
+}
+
 /// Notifies the tunnel state machine that the API (real or proxied) endpoint has
 /// changed. [ApiEndpointUpdaterHandle::callback()] creates a callback that may
 /// be passed to the `mullvad-api` runtime.
 pub(super) struct ApiEndpointUpdaterHandle {
     tunnel_cmd_tx: Arc<Mutex<Option<Weak<mpsc::UnboundedSender<TunnelCommand>>>>>,
+    allowed_endpoint: Arc<Mutex<AllowedEndpoint>>,
 }
 
 impl ApiEndpointUpdaterHandle {
-    pub fn new() -> Self {
+    pub fn new(initial_endpoint: AllowedEndpoint) -> Self {
         Self {
             tunnel_cmd_tx: Arc::new(Mutex::new(None)),
+            allowed_endpoint: Arc::new(Mutex::new(initial_endpoint)),
         }
     }
 
+    /// Returns a handle to the endpoint that the firewall currently allows the API to be reached
+    /// through.
+    pub fn allowed_endpoint(&self) -> Arc<Mutex<AllowedEndpoint>> {
+        self.allowed_endpoint.clone()
+    }
+
     pub fn set_tunnel_command_tx(&self, tunnel_cmd_tx: Weak<mpsc::UnboundedSender<TunnelCommand>>) {
         *self.tunnel_cmd_tx.lock().unwrap() = Some(tunnel_cmd_tx);
     }
 
     pub fn callback(&self) -> impl ApiEndpointUpdateCallback {
         let tunnel_tx = self.tunnel_cmd_tx.clone();
+        let allowed_endpoint = self.allowed_endpoint.clone();
         move |address: SocketAddr| {
             let inner_tx = tunnel_tx.clone();
+            let allowed_endpoint = allowed_endpoint.clone();
             async move {
                 let tunnel_tx = if let Some(Some(tunnel_tx)) = { inner_tx.lock().unwrap().as_ref() }
                     .map(|tx: &Weak<mpsc::UnboundedSender<TunnelCommand>>| tx.upgrade())
@@ -142,12 +204,12 @@ impl ApiEndpointUpdaterHandle {
                     return false;
                 };
                 let (result_tx, result_rx) = oneshot::channel();
-                let _ = tunnel_tx.unbounded_send(TunnelCommand::AllowEndpoint(
-                    get_allowed_endpoint(address.clone()),
-                    result_tx,
-                ));
+                let endpoint = get_allowed_endpoint(address.clone());
+                let _ = tunnel_tx
+                    .unbounded_send(TunnelCommand::AllowEndpoint(endpoint.clone(), result_tx));
                 // Wait for the firewall policy to be updated.
                 let _ = result_rx.await;
+                *allowed_endpoint.lock().unwrap() = endpoint;
                 log::debug!("API endpoint: {}", address);
                 true
             }
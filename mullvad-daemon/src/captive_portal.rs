@@ -0,0 +1,105 @@
+use mullvad_types::states::CaptivePortalStatus;
+use std::{io, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+/// Host serving a fixed plain-text body over plain HTTP. HTTPS cannot be used, since a captive
+/// portal can never answer a TLS connection with a valid certificate.
+const PROBE_HOST: &str = "detectportal.firefox.com";
+const PROBE_PATH: &str = "/success.txt";
+const PROBE_PORT: u16 = 80;
+/// Body of the response when nothing intercepts the request.
+const EXPECTED_BODY: &str = "success";
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Responses are not read beyond this size. A real response is much smaller.
+const MAX_RESPONSE_SIZE: u64 = 16 * 1024;
+
+/// Sends a single plain HTTP request to a known endpoint and infers whether a captive portal is
+/// intercepting traffic from the response. Redirects are not followed.
+pub async fn detect_captive_portal() -> CaptivePortalStatus {
+    match tokio::time::timeout(PROBE_TIMEOUT, probe()).await {
+        Ok(Ok(response)) => status_from_response(&response),
+        Ok(Err(error)) => {
+            log::debug!("Captive portal probe failed: {}", error);
+            CaptivePortalStatus::Indeterminate
+        }
+        Err(_) => {
+            log::debug!("Captive portal probe timed out");
+            CaptivePortalStatus::Indeterminate
+        }
+    }
+}
+
+async fn probe() -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect((PROBE_HOST, PROBE_PORT)).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        PROBE_PATH, PROBE_HOST
+    );
+    stream.write_all(request.as_bytes()).await?;
+
+    let mut response = vec![];
+    stream
+        .take(MAX_RESPONSE_SIZE)
+        .read_to_end(&mut response)
+        .await?;
+    Ok(response)
+}
+
+fn status_from_response(response: &[u8]) -> CaptivePortalStatus {
+    let response = String::from_utf8_lossy(response);
+    let (head, body) = match response.split_once("\r\n\r\n") {
+        Some(parts) => parts,
+        None => return CaptivePortalStatus::Indeterminate,
+    };
+    let code: u16 = match head
+        .lines()
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+    {
+        Some(code) => code,
+        None => return CaptivePortalStatus::Indeterminate,
+    };
+
+    match code {
+        // A portal may also answer with its own page instead of redirecting.
+        200 if body.trim() == EXPECTED_BODY => CaptivePortalStatus::NoPortal,
+        200..=399 | 511 => CaptivePortalStatus::PortalDetected,
+        _ => CaptivePortalStatus::Indeterminate,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_from_response() {
+        let status = |response: &str| status_from_response(response.as_bytes());
+
+        assert_eq!(
+            status("HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nsuccess\n"),
+            CaptivePortalStatus::NoPortal
+        );
+        assert_eq!(
+            status("HTTP/1.1 200 OK\r\n\r\n<html>Log in</html>"),
+            CaptivePortalStatus::PortalDetected
+        );
+        assert_eq!(
+            status("HTTP/1.1 302 Found\r\nLocation: http://portal/\r\n\r\n"),
+            CaptivePortalStatus::PortalDetected
+        );
+        assert_eq!(
+            status("HTTP/1.1 511 Network Authentication Required\r\n\r\n"),
+            CaptivePortalStatus::PortalDetected
+        );
+        assert_eq!(
+            status("HTTP/1.1 500 Internal Server Error\r\n\r\n"),
+            CaptivePortalStatus::Indeterminate
+        );
+        assert_eq!(status("garbage"), CaptivePortalStatus::Indeterminate);
+    }
+}
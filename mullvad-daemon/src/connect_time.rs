@@ -0,0 +1,113 @@
+//! Estimates how long it takes to connect the tunnel, based on recent successful connects.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+use talpid_types::net::{TransportProtocol, TunnelEndpoint, TunnelType};
+
+/// Number of successful connects to remember.
+const HISTORY_SIZE: usize = 20;
+
+/// Number of successful connects using a protocol that are needed to estimate the connect time.
+const MIN_SAMPLES: usize = 3;
+
+struct Sample {
+    tunnel_type: TunnelType,
+    protocol: TransportProtocol,
+    duration: Duration,
+}
+
+/// Remembers how long recent connects took, per tunnel type and transport protocol.
+#[derive(Default)]
+pub struct ConnectTimeHistory {
+    samples: VecDeque<Sample>,
+    connecting_since: Option<Instant>,
+}
+
+impl ConnectTimeHistory {
+    /// Record that an attempt to connect started.
+    pub fn record_connecting(&mut self) {
+        self.connecting_since = Some(Instant::now());
+    }
+
+    /// Record that the tunnel connected to `endpoint`. The time since the most recent attempt
+    /// started is added to the history.
+    pub fn record_connected(&mut self, endpoint: &TunnelEndpoint) {
+        if let Some(connecting_since) = self.connecting_since.take() {
+            if self.samples.len() == HISTORY_SIZE {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(Sample {
+                tunnel_type: endpoint.tunnel_type,
+                protocol: endpoint.endpoint.protocol,
+                duration: connecting_since.elapsed(),
+            });
+        }
+    }
+
+    /// Forget the current attempt, such as when it failed or was cancelled.
+    pub fn discard_attempt(&mut self) {
+        self.connecting_since = None;
+    }
+
+    /// Returns the median duration of the recent connects using `tunnel_type` and, if given,
+    /// `protocol`. Returns `None` if there are too few of them.
+    pub fn estimate(
+        &self,
+        tunnel_type: TunnelType,
+        protocol: Option<TransportProtocol>,
+    ) -> Option<Duration> {
+        let mut durations: Vec<Duration> = self
+            .samples
+            .iter()
+            .filter(|sample| {
+                sample.tunnel_type == tunnel_type
+                    && protocol.map(|p| sample.protocol == p).unwrap_or(true)
+            })
+            .map(|sample| sample.duration)
+            .collect();
+        if durations.len() < MIN_SAMPLES {
+            return None;
+        }
+        durations.sort();
+        Some(durations[durations.len() / 2])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(tunnel_type: TunnelType, protocol: TransportProtocol, millis: u64) -> Sample {
+        Sample {
+            tunnel_type,
+            protocol,
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn test_estimate_connect_time() {
+        let mut history = ConnectTimeHistory::default();
+        history.samples.extend([
+            sample(TunnelType::Wireguard, TransportProtocol::Udp, 300),
+            sample(TunnelType::Wireguard, TransportProtocol::Udp, 100),
+            sample(TunnelType::OpenVpn, TransportProtocol::Tcp, 2000),
+        ]);
+        assert_eq!(history.estimate(TunnelType::Wireguard, None), None);
+
+        history
+            .samples
+            .push_back(sample(TunnelType::Wireguard, TransportProtocol::Udp, 200));
+        assert_eq!(
+            history.estimate(TunnelType::Wireguard, Some(TransportProtocol::Udp)),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(
+            history.estimate(TunnelType::Wireguard, Some(TransportProtocol::Tcp)),
+            None
+        );
+        assert_eq!(history.estimate(TunnelType::OpenVpn, None), None);
+    }
+}
@@ -0,0 +1,84 @@
+//! Scores the quality of the tunnel connection from 0 to 100.
+//!
+//! A number of DNS queries are sent through the tunnel to the resolver that it uses, and the score
+//! is a weighted sum of:
+//!
+//! - Latency: The median time until an answered query was answered. Latencies up to
+//!   [GOOD_LATENCY] score full marks and latencies of [BAD_LATENCY] or more score nothing.
+//! - Packet loss: The share of queries that were not answered in time. Losing [BAD_PACKET_LOSS]
+//!   or more of the queries scores nothing.
+//!
+//! The time since the last WireGuard handshake is not used, since it is not reported by all tunnel
+//! implementations.
+
+use crate::dns;
+use std::{net::IpAddr, time::Duration};
+
+/// Number of queries to send when measuring the connection quality.
+const NUM_PROBES: usize = 5;
+
+const GOOD_LATENCY: Duration = Duration::from_millis(50);
+const BAD_LATENCY: Duration = Duration::from_millis(500);
+const BAD_PACKET_LOSS: f64 = 0.5;
+
+const LATENCY_WEIGHT: f64 = 0.5;
+const PACKET_LOSS_WEIGHT: f64 = 0.5;
+
+/// Measures the connection quality by querying `resolver`.
+pub async fn measure(resolver: IpAddr) -> Option<u8> {
+    let probes: Vec<Option<Duration>> = dns::test_servers(&[resolver; NUM_PROBES])
+        .await
+        .into_iter()
+        .map(|result| result.latency)
+        .collect();
+    score(&probes)
+}
+
+/// Returns the score for the round-trip times of `probes`, where `None` is a probe that was not
+/// answered. Returns `None` if there are no probes.
+fn score(probes: &[Option<Duration>]) -> Option<u8> {
+    if probes.is_empty() {
+        return None;
+    }
+
+    let mut latencies: Vec<Duration> = probes.iter().flatten().copied().collect();
+    latencies.sort();
+    let latency_score = latencies
+        .get(latencies.len() / 2)
+        .map(|median| latency_score(*median))
+        .unwrap_or(0.0);
+
+    let packet_loss = 1.0 - latencies.len() as f64 / probes.len() as f64;
+    let packet_loss_score = 1.0 - (packet_loss / BAD_PACKET_LOSS).min(1.0);
+
+    let score = LATENCY_WEIGHT * latency_score + PACKET_LOSS_WEIGHT * packet_loss_score;
+    Some((score * 100.0).round() as u8)
+}
+
+fn latency_score(latency: Duration) -> f64 {
+    if latency <= GOOD_LATENCY {
+        1.0
+    } else if latency >= BAD_LATENCY {
+        0.0
+    } else {
+        1.0 - (latency - GOOD_LATENCY).as_secs_f64() / (BAD_LATENCY - GOOD_LATENCY).as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_connection_quality_score() {
+        let fast = Some(Duration::from_millis(20));
+        let slow = Some(Duration::from_millis(275));
+
+        assert_eq!(score(&[]), None);
+        assert_eq!(score(&[fast, fast, fast, fast]), Some(100));
+        assert_eq!(score(&[None, None, None, None]), Some(0));
+        assert_eq!(score(&[slow, slow, slow, slow]), Some(75));
+        assert_eq!(score(&[fast, fast, fast, None]), Some(75));
+        assert_eq!(score(&[fast, fast, None, None]), Some(50));
+    }
+}
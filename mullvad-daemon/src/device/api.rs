@@ -1,4 +1,10 @@
-use std::pin::Pin;
+use std::{
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 
 use futures::{future::FusedFuture, Future};
 use mullvad_types::{device::Device, wireguard::WireguardData};
@@ -26,8 +32,14 @@ impl CurrentApiCall {
         self.current_call = Some(Call::OneshotKeyRotation(rotation));
     }
 
-    pub fn set_timed_rotation(&mut self, rotation: ApiCall<WireguardData>) {
-        self.current_call = Some(Call::TimerKeyRotation(rotation));
+    /// Sets a key rotation that starts once a timer has elapsed. `timer_elapsed` must be set by
+    /// `rotation` when the timer has elapsed.
+    pub fn set_timed_rotation(
+        &mut self,
+        rotation: ApiCall<WireguardData>,
+        timer_elapsed: Arc<AtomicBool>,
+    ) {
+        self.current_call = Some(Call::TimerKeyRotation(rotation, timer_elapsed));
     }
 
     pub fn set_validation(&mut self, validation: ApiCall<Device>) {
@@ -42,7 +54,16 @@ impl CurrentApiCall {
     }
 
     pub fn is_running_timed_totation(&self) -> bool {
-        matches!(&self.current_call, Some(Call::TimerKeyRotation(_)))
+        matches!(&self.current_call, Some(Call::TimerKeyRotation(..)))
+    }
+
+    /// Returns whether a key is being rotated, as opposed to waiting for the rotation timer.
+    pub fn is_rotating_key(&self) -> bool {
+        match &self.current_call {
+            Some(Call::OneshotKeyRotation(_)) => true,
+            Some(Call::TimerKeyRotation(_, timer_elapsed)) => timer_elapsed.load(Ordering::SeqCst),
+            _ => false,
+        }
     }
 
     pub fn is_idle(&self) -> bool {
@@ -88,7 +109,7 @@ type ApiCall<T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send>>;
 
 enum Call {
     Login(ApiCall<PrivateAccountAndDevice>, Option<ResponseTx<()>>),
-    TimerKeyRotation(ApiCall<WireguardData>),
+    TimerKeyRotation(ApiCall<WireguardData>, Arc<AtomicBool>),
     OneshotKeyRotation(ApiCall<WireguardData>),
     Validation(ApiCall<Device>),
 }
@@ -109,7 +130,7 @@ impl futures::Future for Call {
                     std::task::Poll::Pending
                 }
             }
-            TimerKeyRotation(call) | OneshotKeyRotation(call) => {
+            TimerKeyRotation(call, _) | OneshotKeyRotation(call) => {
                 Pin::new(call).poll(cx).map(ApiResult::Rotation)
             }
             Validation(call) => Pin::new(call).poll(cx).map(ApiResult::Validation),
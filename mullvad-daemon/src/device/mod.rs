@@ -14,7 +14,7 @@ use std::{
     future::Future,
     path::Path,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
     time::{Duration, SystemTime},
@@ -194,12 +194,15 @@ type ResponseTx<T> = oneshot::Sender<Result<T, Error>>;
 
 enum AccountManagerCommand {
     Login(AccountToken, ResponseTx<()>),
+    SwitchAccount(AccountToken, ResponseTx<()>),
     Logout(ResponseTx<()>),
     SetData(PrivateAccountAndDevice, ResponseTx<()>),
     GetData(ResponseTx<Option<PrivateAccountAndDevice>>),
     GetDataAfterLogin(ResponseTx<Option<PrivateAccountAndDevice>>),
     RotateKey(ResponseTx<()>),
     SetRotationInterval(RotationInterval, ResponseTx<()>),
+    SetRotationEnabled(bool, ResponseTx<()>),
+    IsRotatingKey(ResponseTx<bool>),
     ValidateDevice(ResponseTx<()>),
     ReceiveEvents(Box<dyn Sender<PrivateDeviceEvent> + Send>, ResponseTx<()>),
     Shutdown(oneshot::Sender<()>),
@@ -210,6 +213,7 @@ pub(crate) struct AccountManagerHandle {
     cmd_tx: mpsc::UnboundedSender<AccountManagerCommand>,
     pub account_service: AccountService,
     pub device_service: DeviceService,
+    rotation_failures: Arc<AtomicU64>,
 }
 
 impl AccountManagerHandle {
@@ -218,6 +222,13 @@ impl AccountManagerHandle {
             .await
     }
 
+    /// Logs in to another account. The current device is only removed once a device has been
+    /// created for the new account, so a failed switch leaves the current device in place.
+    pub async fn switch_account(&self, token: AccountToken) -> Result<(), Error> {
+        self.send_command(|tx| AccountManagerCommand::SwitchAccount(token, tx))
+            .await
+    }
+
     pub async fn logout(&self) -> Result<(), Error> {
         self.send_command(|tx| AccountManagerCommand::Logout(tx))
             .await
@@ -248,6 +259,26 @@ impl AccountManagerHandle {
             .await
     }
 
+    /// Pauses or resumes automatic key rotation without changing the rotation interval. Manual
+    /// rotation is still possible while automatic rotation is paused.
+    pub async fn set_rotation_enabled(&self, enabled: bool) -> Result<(), Error> {
+        self.send_command(|tx| AccountManagerCommand::SetRotationEnabled(enabled, tx))
+            .await
+    }
+
+    /// Returns whether a key rotation is in progress. This is also the case while a manual
+    /// rotation is queued behind a device validation.
+    pub async fn is_rotating_key(&self) -> Result<bool, Error> {
+        self.send_command(|tx| AccountManagerCommand::IsRotatingKey(tx))
+            .await
+    }
+
+    /// Returns the number of key rotations that have failed since the account manager was
+    /// started.
+    pub fn rotation_failure_count(&self) -> u64 {
+        self.rotation_failures.load(Ordering::Relaxed)
+    }
+
     pub async fn validate_device(&self) -> Result<(), Error> {
         self.send_command(|tx| AccountManagerCommand::ValidateDevice(tx))
             .await
@@ -288,11 +319,13 @@ pub(crate) struct AccountManager {
     device_service: DeviceService,
     data: Option<PrivateAccountAndDevice>,
     rotation_interval: RotationInterval,
+    rotation_enabled: bool,
     listeners: Vec<Box<dyn Sender<PrivateDeviceEvent> + Send>>,
     last_validation: Option<SystemTime>,
     validation_requests: Vec<ResponseTx<()>>,
     rotation_requests: Vec<ResponseTx<()>>,
     data_requests: Vec<ResponseTx<Option<PrivateAccountAndDevice>>>,
+    rotation_failures: Arc<AtomicU64>,
 }
 
 impl AccountManager {
@@ -310,16 +343,19 @@ impl AccountManager {
         let (cmd_tx, cmd_rx) = mpsc::unbounded();
 
         let device_service = DeviceService::new(rest_handle, api_availability);
+        let rotation_failures = Arc::new(AtomicU64::new(0));
         let manager = AccountManager {
             cacher,
             device_service: device_service.clone(),
             data,
             rotation_interval: initial_rotation_interval,
+            rotation_enabled: true,
             listeners: vec![],
             last_validation: None,
             validation_requests: vec![],
             rotation_requests: vec![],
             data_requests: vec![],
+            rotation_failures: rotation_failures.clone(),
         };
 
         tokio::spawn(manager.run(cmd_rx));
@@ -327,6 +363,7 @@ impl AccountManager {
             cmd_tx,
             account_service,
             device_service,
+            rotation_failures,
         };
         Ok(handle)
     }
@@ -352,6 +389,9 @@ impl AccountManager {
                                 .generate_for_account(token);
                             current_api_call.set_login(Box::pin(job), tx);
                         }
+                        Some(AccountManagerCommand::SwitchAccount(token, tx)) => {
+                            self.switch_account(token, tx, &mut current_api_call);
+                        }
                         Some(AccountManagerCommand::Logout(tx)) => {
                             current_api_call.clear();
                             self.logout(tx).await;
@@ -395,6 +435,20 @@ impl AccountManager {
                             }
                             let _ = tx.send(Ok(()));
                         }
+                        Some(AccountManagerCommand::SetRotationEnabled(enabled, tx)) => {
+                            self.rotation_enabled = enabled;
+                            // When resuming, the timer is restarted below and counts from when
+                            // the key was last rotated.
+                            if !enabled && current_api_call.is_running_timed_totation() {
+                                current_api_call.clear();
+                            }
+                            let _ = tx.send(Ok(()));
+                        }
+                        Some(AccountManagerCommand::IsRotatingKey(tx)) => {
+                            let is_rotating = current_api_call.is_rotating_key()
+                                || !self.rotation_requests.is_empty();
+                            let _ = tx.send(Ok(is_rotating));
+                        }
                         Some(AccountManagerCommand::ValidateDevice(tx)) => {
                             self.handle_validation_request(tx, &mut current_api_call);
                         }
@@ -410,8 +464,9 @@ impl AccountManager {
             }
 
             if current_api_call.is_idle() {
-                if let Some(timed_rotation) = self.spawn_timed_key_rotation() {
-                    current_api_call.set_timed_rotation(Box::pin(timed_rotation))
+                let timer_elapsed = Arc::new(AtomicBool::new(false));
+                if let Some(timed_rotation) = self.spawn_timed_key_rotation(timer_elapsed.clone()) {
+                    current_api_call.set_timed_rotation(Box::pin(timed_rotation), timer_elapsed)
                 }
             }
         }
@@ -451,6 +506,25 @@ impl AccountManager {
         }
     }
 
+    fn switch_account(
+        &mut self,
+        token: AccountToken,
+        tx: ResponseTx<()>,
+        current_api_call: &mut api::CurrentApiCall,
+    ) {
+        if current_api_call.is_logging_in() {
+            let _ = tx.send(Err(Error::AccountChange));
+            return;
+        }
+        if self.data.as_ref().map(|data| &data.account_token) == Some(&token) {
+            let _ = tx.send(Ok(()));
+            return;
+        }
+
+        let job = self.device_service.generate_for_account(token);
+        current_api_call.set_login(Box::pin(job), tx);
+    }
+
     async fn consume_api_result(
         &mut self,
         result: api::ApiResult,
@@ -458,7 +532,7 @@ impl AccountManager {
     ) {
         use api::ApiResult::*;
         match result {
-            Login(data, tx) => self.consume_login(data, tx).await,
+            Login(data, tx) => self.consume_login(data, tx, api_call).await,
             Rotation(rotation_response) => self.consume_rotation_result(rotation_response).await,
             Validation(data_response) => self.consume_validation(data_response, api_call).await,
         }
@@ -468,13 +542,42 @@ impl AccountManager {
         &mut self,
         device_response: Result<PrivateAccountAndDevice, Error>,
         tx: ResponseTx<()>,
+        api_call: &mut api::CurrentApiCall,
     ) {
-        let _ =
-            tx.send(async { self.set(PrivateDeviceEvent::Login(device_response?)).await }.await);
+        let result = async { self.set(PrivateDeviceEvent::Login(device_response?)).await }.await;
+        if result.is_ok() {
+            // Requests concerning the previous device are cancelled, since it has been replaced
+            Self::drain_requests(&mut self.rotation_requests, || Err(Error::AccountChange));
+            Self::drain_requests(&mut self.validation_requests, || Err(Error::AccountChange));
+        } else {
+            self.resume_device_requests(api_call);
+        }
+        let _ = tx.send(result);
         let data = self.data.clone();
         Self::drain_requests(&mut self.data_requests, || Ok(data.clone()));
     }
 
+    /// Restarts the API call for requests concerning the current device, which was replaced by a
+    /// login attempt that did not succeed.
+    fn resume_device_requests(&mut self, api_call: &mut api::CurrentApiCall) {
+        if !self.rotation_requests.is_empty() {
+            match self.initiate_key_rotation() {
+                Ok(call) => api_call.set_oneshot_rotation(Box::pin(call)),
+                Err(err) => self.drain_requests_with_err(err),
+            }
+        } else if !self.validation_requests.is_empty() {
+            match self.validation_call() {
+                Ok(call) => api_call.set_validation(Box::pin(call)),
+                Err(err) => {
+                    let cloneable_err = Arc::new(err);
+                    Self::drain_requests(&mut self.validation_requests, || {
+                        Err(Error::ResponseFailure(cloneable_err.clone()))
+                    });
+                }
+            }
+        }
+    }
+
     async fn consume_validation(
         &mut self,
         response: Result<Device, Error>,
@@ -552,6 +655,14 @@ impl AccountManager {
             .clone()
             .expect("Received a key rotation result whilst having no data");
 
+        if api_result.is_err() {
+            let _ = self.rotation_failures.fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |failures| Some(failures.saturating_add(1)),
+            );
+        }
+
         match api_result {
             Ok(wg_data) => {
                 config.device.wg_data = wg_data;
@@ -596,7 +707,11 @@ impl AccountManager {
 
     fn spawn_timed_key_rotation(
         &self,
+        timer_elapsed: Arc<AtomicBool>,
     ) -> Option<impl Future<Output = Result<WireguardData, Error>> + Send + 'static> {
+        if !self.rotation_enabled {
+            return None;
+        }
         let config = self.data.as_ref()?;
         let key_rotation_timer = self.key_rotation_timer(config.device.wg_data.created);
 
@@ -606,6 +721,7 @@ impl AccountManager {
 
         Some(async move {
             key_rotation_timer.await;
+            timer_elapsed.store(true, Ordering::SeqCst);
             device_service
                 .rotate_key_with_backoff(account_token, device_id)
                 .await
@@ -913,3 +1029,104 @@ impl TunnelStateChangeHandler {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use mullvad_api::proxy::ApiConnectionMode;
+    use talpid_types::net::wireguard::PrivateKey;
+
+    async fn new_account_manager(
+        settings_dir: &Path,
+        data: Option<PrivateAccountAndDevice>,
+    ) -> AccountManager {
+        let runtime = mullvad_api::Runtime::new(tokio::runtime::Handle::current()).unwrap();
+        let rest_handle = runtime
+            .mullvad_rest_handle(ApiConnectionMode::Direct.into_repeat(), |_| async { true })
+            .await;
+        let (cacher, _) = DeviceCacher::new(settings_dir).await.unwrap();
+        AccountManager {
+            cacher,
+            device_service: DeviceService::new(rest_handle, runtime.availability_handle()),
+            data,
+            rotation_interval: RotationInterval::default(),
+            rotation_enabled: true,
+            listeners: vec![],
+            last_validation: None,
+            validation_requests: vec![],
+            rotation_requests: vec![],
+            data_requests: vec![],
+            rotation_failures: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn new_device_data(account_token: &str) -> PrivateAccountAndDevice {
+        PrivateAccountAndDevice {
+            account_token: account_token.to_owned(),
+            device: PrivateDevice {
+                id: "device".to_owned(),
+                name: "device".to_owned(),
+                wg_data: WireguardData {
+                    private_key: PrivateKey::new_from_random(),
+                    addresses: wireguard::AssociatedAddresses {
+                        ipv4_address: "10.0.0.1/32".parse().unwrap(),
+                        ipv6_address: "fc00::1/128".parse().unwrap(),
+                    },
+                    created: Utc::now(),
+                },
+                ports: vec![],
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_account_switch_keeps_device_requests() {
+        let settings_dir = std::env::temp_dir().join("mullvad-test-failed-account-switch");
+        let _ = std::fs::remove_dir_all(&settings_dir);
+        std::fs::create_dir_all(&settings_dir).unwrap();
+
+        let mut manager = new_account_manager(&settings_dir, Some(new_device_data("1"))).await;
+        let mut api_call = api::CurrentApiCall::new();
+
+        let (rotation_tx, mut rotation_rx) = oneshot::channel();
+        manager.rotation_requests.push(rotation_tx);
+        let (login_tx, login_rx) = oneshot::channel();
+        manager
+            .consume_login(Err(Error::InvalidAccount), login_tx, &mut api_call)
+            .await;
+
+        assert!(matches!(login_rx.await, Ok(Err(Error::InvalidAccount))));
+        assert!(matches!(rotation_rx.try_recv(), Ok(None)));
+        assert_eq!(manager.rotation_requests.len(), 1);
+        assert!(api_call.is_rotating_key());
+
+        let _ = std::fs::remove_dir_all(&settings_dir);
+    }
+
+    #[tokio::test]
+    async fn test_account_switch_cancels_device_requests() {
+        let settings_dir = std::env::temp_dir().join("mullvad-test-account-switch");
+        let _ = std::fs::remove_dir_all(&settings_dir);
+        std::fs::create_dir_all(&settings_dir).unwrap();
+
+        let mut manager = new_account_manager(&settings_dir, None).await;
+        let mut api_call = api::CurrentApiCall::new();
+
+        let (validation_tx, validation_rx) = oneshot::channel();
+        manager.validation_requests.push(validation_tx);
+        let (login_tx, login_rx) = oneshot::channel();
+        manager
+            .consume_login(Ok(new_device_data("2")), login_tx, &mut api_call)
+            .await;
+
+        assert!(matches!(login_rx.await, Ok(Ok(()))));
+        assert!(matches!(validation_rx.await, Ok(Err(Error::AccountChange))));
+        assert!(manager.validation_requests.is_empty());
+        assert_eq!(
+            manager.data.map(|data| data.account_token),
+            Some("2".to_owned())
+        );
+
+        let _ = std::fs::remove_dir_all(&settings_dir);
+    }
+}
@@ -0,0 +1,112 @@
+//! Collects the information that support usually asks for into a single file.
+//!
+//! The settings are reduced to the values that differ from the defaults, with sensitive fields
+//! redacted. In addition, WireGuard keys, account numbers and OpenVPN auth tokens are redacted
+//! from the whole bundle before it is written.
+
+use crate::{logging::DAEMON_LOG_FILENAME, tunnel_log};
+use chrono::{DateTime, Utc};
+use mullvad_types::settings::MigrationRecord;
+use serde::Serialize;
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+/// Number of lines to include from the end of each log file.
+const LOG_LINES: usize = 1000;
+/// Directory in the cache directory that bundles are written to.
+const BUNDLE_DIR: &str = "diagnostics";
+
+/// Information about the daemon to include in a diagnostics bundle.
+#[derive(Debug, Serialize)]
+pub struct DiagnosticsInfo {
+    pub version: String,
+    pub commit: Option<String>,
+    pub daemon_started_at: DateTime<Utc>,
+    pub tunnel_state: String,
+    pub non_default_settings: serde_json::Value,
+    pub relay_list_updated_at: DateTime<Utc>,
+    pub migration_history: Vec<MigrationRecord>,
+    pub recent_device_events: Vec<String>,
+    pub api_errors: u64,
+    pub metrics: String,
+}
+
+#[derive(Serialize)]
+struct Bundle {
+    created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    info: DiagnosticsInfo,
+    daemon_log: Vec<String>,
+    tunnel_log: Vec<String>,
+}
+
+/// Writes `info` and the end of the daemon and tunnel logs in `log_dir`, if any, to a new file
+/// in a directory in `cache_dir`. Returns the path of the file.
+///
+/// Only the daemon may write to the directory, so the file cannot be swapped for a link to
+/// another file. Anyone may read the file, since sensitive values have been redacted.
+pub async fn create_bundle(
+    info: DiagnosticsInfo,
+    log_dir: Option<&Path>,
+    cache_dir: &Path,
+) -> io::Result<PathBuf> {
+    let (daemon_log, tunnel_log) = match log_dir {
+        Some(log_dir) => (
+            read_log(&log_dir.join(DAEMON_LOG_FILENAME)).await?,
+            tunnel_log::read_recent_lines(log_dir, LOG_LINES).await?,
+        ),
+        None => (vec![], vec![]),
+    };
+
+    let created_at = Utc::now();
+    let bundle = Bundle {
+        created_at,
+        info,
+        daemon_log,
+        tunnel_log,
+    };
+    let contents = serde_json::to_string_pretty(&bundle)?
+        .lines()
+        .map(tunnel_log::redact)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let dir = cache_dir.join(BUNDLE_DIR);
+    create_bundle_dir(&dir).await?;
+    let path = dir.join(format!(
+        "mullvad-diagnostics-{}-{:016x}.json",
+        created_at.format("%Y%m%dT%H%M%SZ"),
+        rand::random::<u64>()
+    ));
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    {
+        options.mode(0o600);
+    }
+    let mut file = options.create_new(true).write(true).open(&path).await?;
+    file.write_all(contents.as_bytes()).await?;
+    file.sync_all().await?;
+
+    log::info!("Wrote diagnostics bundle to {}", path.display());
+    Ok(path)
+}
+
+async fn create_bundle_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, std::fs::Permissions::from_mode(0o755)).await?;
+    }
+    Ok(())
+}
+
+async fn read_log(path: &Path) -> io::Result<Vec<String>> {
+    match tunnel_log::read_last_lines(path, LOG_LINES).await {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        result => result,
+    }
+}
@@ -1,5 +1,10 @@
 use mullvad_types::settings::{DnsOptions, DnsState};
-use std::net::{IpAddr, Ipv4Addr};
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+use tokio::net::UdpSocket;
 
 /// When we want to block certain contents with the help of DNS server side,
 /// we compute the resolver IP to use based on these constants. The last
@@ -11,6 +16,19 @@ const DNS_MALWARE_BLOCKING_IP_BIT: u8 = 1 << 2; // 0b00000100
 const DNS_ADULT_BLOCKING_IP_BIT: u8 = 1 << 3; // 0b00001000
 const DNS_GAMBLING_BLOCKING_IP_BIT: u8 = 1 << 4; // 0b00010000
 
+/// How long to wait for a response when testing a DNS server.
+const DNS_TEST_TIMEOUT: Duration = Duration::from_secs(3);
+/// Labels of the hostname that is looked up when testing a DNS server.
+const DNS_TEST_HOSTNAME: &[&str] = &["mullvad", "net"];
+
+/// Result of testing whether a DNS server responds to queries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsTestResult {
+    pub server: IpAddr,
+    /// Time until a response was received, or `None` if the server did not respond in time.
+    pub latency: Option<Duration>,
+}
+
 /// Return the resolvers as a vector of `IpAddr`s. Returns `None` when no special resolvers
 /// are requested and the tunnel default gateway should be used.
 pub fn addresses_from_options(options: &DnsOptions) -> Option<Vec<IpAddr>> {
@@ -53,3 +71,148 @@ pub fn addresses_from_options(options: &DnsOptions) -> Option<Vec<IpAddr>> {
         }
     }
 }
+
+/// Returns the resolvers to send to the tunnel state machine when the DNS options change from
+/// `current` to `new`, or `None` if the resolvers in use would not change. Toggling a content
+/// blocker category only changes the resolver, which is applied without reconnecting.
+pub fn addresses_after_update(
+    current: &DnsOptions,
+    new: &DnsOptions,
+) -> Option<Option<Vec<IpAddr>>> {
+    let new_addresses = addresses_from_options(new);
+    if new_addresses == addresses_from_options(current) {
+        return None;
+    }
+    Some(new_addresses)
+}
+
+/// Sends an A query to each server over UDP and reports whether, and how quickly, it responded.
+pub async fn test_servers(servers: &[IpAddr]) -> Vec<DnsTestResult> {
+    test_servers_with(servers, |server| {
+        let bind_addr: SocketAddr = match server {
+            IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        std::net::UdpSocket::bind(bind_addr)
+    })
+    .await
+}
+
+/// Like [test_servers], but each query is sent from the socket that `bind` returns for the
+/// server.
+pub async fn test_servers_with(
+    servers: &[IpAddr],
+    mut bind: impl FnMut(IpAddr) -> io::Result<std::net::UdpSocket>,
+) -> Vec<DnsTestResult> {
+    futures::future::join_all(servers.iter().map(|&server| {
+        let socket = bind(server);
+        async move {
+            let query = async { query_server(socket?, server).await };
+            let latency = match tokio::time::timeout(DNS_TEST_TIMEOUT, query).await {
+                Ok(Ok(latency)) => Some(latency),
+                Ok(Err(error)) => {
+                    log::debug!("Failed to query DNS server {}: {}", server, error);
+                    None
+                }
+                Err(_) => None,
+            };
+            DnsTestResult { server, latency }
+        }
+    }))
+    .await
+}
+
+async fn query_server(socket: std::net::UdpSocket, server: IpAddr) -> io::Result<Duration> {
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket)?;
+    socket.connect((server, 53)).await?;
+
+    let id = rand::random();
+    let start = Instant::now();
+    socket.send(&build_query(id)).await?;
+
+    let mut buffer = [0u8; 512];
+    loop {
+        let len = socket.recv(&mut buffer).await?;
+        if is_response(&buffer[..len], id) {
+            return Ok(start.elapsed());
+        }
+    }
+}
+
+/// Builds a recursive query for the A record of [DNS_TEST_HOSTNAME].
+fn build_query(id: u16) -> Vec<u8> {
+    let mut query = Vec::with_capacity(32);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Flags: recursion desired. One question and no other records.
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in DNS_TEST_HOSTNAME {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    // Type A, class IN
+    query.extend_from_slice(&[0, 1, 0, 1]);
+    query
+}
+
+/// Returns whether `packet` is a DNS response to the query with the given ID. The response code is
+/// not checked, since any answer shows that the server is reachable.
+fn is_response(packet: &[u8], id: u16) -> bool {
+    packet.len() >= 12 && packet[..2] == id.to_be_bytes() && packet[2] & 0x80 != 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_toggle_content_blocker() {
+        let mut options = DnsOptions::default();
+        options.default_options.block_ads = true;
+        assert_eq!(
+            addresses_after_update(&DnsOptions::default(), &options),
+            Some(Some(vec!["100.64.0.1".parse().unwrap()]))
+        );
+
+        let mut malware_options = options.clone();
+        malware_options.default_options.block_malware = true;
+        assert_eq!(
+            addresses_after_update(&options, &malware_options),
+            Some(Some(vec!["100.64.0.5".parse().unwrap()]))
+        );
+        assert_eq!(
+            addresses_after_update(&options, &DnsOptions::default()),
+            Some(None)
+        );
+
+        // Content blockers are not used with custom DNS
+        let mut custom_options = DnsOptions {
+            state: DnsState::Custom,
+            ..DnsOptions::default()
+        };
+        custom_options.custom_options.addresses = vec!["10.0.0.1".parse().unwrap()];
+        let mut blocking_custom_options = custom_options.clone();
+        blocking_custom_options.default_options.block_trackers = true;
+        assert_eq!(
+            addresses_after_update(&custom_options, &blocking_custom_options),
+            None
+        );
+    }
+
+    #[test]
+    fn test_dns_query() {
+        let query = build_query(0x1234);
+        assert_eq!(
+            query,
+            b"\x12\x34\x01\x00\x00\x01\x00\x00\x00\x00\x00\x00\x07mullvad\x03net\x00\x00\x01\x00\x01"
+        );
+        assert!(!is_response(&query, 0x1234));
+
+        let mut response = query.clone();
+        response[2] |= 0x80;
+        assert!(is_response(&response, 0x1234));
+        assert!(!is_response(&response, 0x4321));
+        assert!(!is_response(&response[..8], 0x1234));
+    }
+}
@@ -4,11 +4,67 @@ use mullvad_api::{
     rest::{Error, RequestServiceHandle},
 };
 use mullvad_types::location::{AmIMullvad, GeoIpLocation};
-use talpid_types::ErrorExt;
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+use talpid_types::{net::TunnelEndpoint, ErrorExt};
 
 const URI_V4: &str = "https://ipv4.am.i.mullvad.net/json";
 const URI_V6: &str = "https://ipv6.am.i.mullvad.net/json";
 
+/// How long a fetched location is reused for, unless overridden by
+/// `MULLVAD_LOCATION_CACHE_TTL_SECS`.
+const DEFAULT_LOCATION_CACHE_TTL: Duration = Duration::from_secs(10);
+
+lazy_static::lazy_static! {
+    static ref LOCATION_CACHE_TTL: Duration = env::var("MULLVAD_LOCATION_CACHE_TTL_SECS")
+        .ok()
+        .and_then(|ttl| ttl.parse().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_LOCATION_CACHE_TTL);
+}
+
+/// Remembers the most recently fetched location for a short while, so that frequent location
+/// requests do not all reach the network. A location is only returned for the tunnel endpoint it
+/// was fetched over.
+pub struct LocationCache {
+    entry: Option<CachedLocation>,
+}
+
+struct CachedLocation {
+    endpoint: Option<TunnelEndpoint>,
+    location: GeoIpLocation,
+    fetched_at: Instant,
+}
+
+impl LocationCache {
+    pub fn new() -> Self {
+        LocationCache { entry: None }
+    }
+
+    /// Returns the cached location if it is still fresh and was fetched over `endpoint`.
+    pub fn get(&self, endpoint: Option<TunnelEndpoint>) -> Option<GeoIpLocation> {
+        self.entry
+            .as_ref()
+            .filter(|entry| entry.endpoint == endpoint)
+            .filter(|entry| entry.fetched_at.elapsed() < *LOCATION_CACHE_TTL)
+            .map(|entry| entry.location.clone())
+    }
+
+    pub fn insert(&mut self, endpoint: Option<TunnelEndpoint>, location: GeoIpLocation) {
+        self.entry = Some(CachedLocation {
+            endpoint,
+            location,
+            fetched_at: Instant::now(),
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.entry = None;
+    }
+}
+
 pub async fn send_location_request(
     request_sender: RequestServiceHandle,
 ) -> Result<GeoIpLocation, Error> {
@@ -43,6 +99,16 @@ pub async fn send_location_request(
     }
 }
 
+/// Fetches the IPv4 and IPv6 GeoIP information without merging them.
+pub async fn send_am_i_mullvad_requests(
+    request_sender: RequestServiceHandle,
+) -> (Result<AmIMullvad, Error>, Result<AmIMullvad, Error>) {
+    join!(
+        send_location_request_internal(URI_V4, request_sender.clone()),
+        send_location_request_internal(URI_V6, request_sender)
+    )
+}
+
 async fn send_location_request_internal(
     uri: &'static str,
     service: RequestServiceHandle,
@@ -0,0 +1,276 @@
+//! Checks whether traffic escapes the tunnel while connected.
+//!
+//! The DNS probe is deliberately not exempted from the firewall: it is sent outside the tunnel,
+//! through a physical interface, to public resolvers that the tunnel does not use. Any answer
+//! means that DNS queries can bypass the tunnel.
+
+use crate::{dns, geoip};
+use mullvad_api::rest::RequestServiceHandle;
+use mullvad_types::location::AmIMullvad;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use nix::{ifaddrs::getifaddrs, sys::socket::SockAddr};
+#[cfg(not(target_os = "android"))]
+use std::net::SocketAddr;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::os::unix::io::AsRawFd;
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket},
+};
+
+/// Resolvers that are queried directly to test whether DNS queries can leak.
+const DNS_LEAK_TEST_SERVERS: &[IpAddr] = &[
+    IpAddr::V4(Ipv4Addr::new(194, 242, 2, 2)),
+    IpAddr::V6(Ipv6Addr::new(0x2a07, 0xe340, 0, 0, 0, 0, 0, 2)),
+];
+
+/// Outcome of a single leak test category.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LeakTestOutcome {
+    Passed,
+    /// Traffic escaped the tunnel. Contains a description of the leak.
+    Failed(String),
+    /// The check could not be completed. Contains the reason.
+    Inconclusive(String),
+}
+
+/// Results of [run].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeakTestReport {
+    /// Whether DNS queries to resolvers other than the tunnel resolvers were blocked.
+    pub dns: LeakTestOutcome,
+    /// Whether IPv6 traffic only reached the internet through the exit relay.
+    pub ipv6: LeakTestOutcome,
+    /// Whether the public IPv4 address belongs to the exit relay.
+    pub exit_ip: LeakTestOutcome,
+}
+
+impl LeakTestReport {
+    pub fn passed(&self) -> bool {
+        [&self.dns, &self.ipv6, &self.exit_ip]
+            .iter()
+            .all(|outcome| **outcome == LeakTestOutcome::Passed)
+    }
+}
+
+/// Describes the tunnel that is tested.
+pub struct TunnelInfo {
+    /// Custom DNS servers used in the tunnel, if any.
+    pub resolvers: Vec<IpAddr>,
+    /// Whether IPv6 is enabled in the tunnel.
+    pub ipv6: bool,
+    /// Public addresses of the exit relay. Traffic must reach the internet from one of these.
+    pub exit_ips: Vec<IpAddr>,
+    /// Name of the tunnel interface.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub interface: Option<String>,
+    /// Address of the relay that the tunnel is connected to. Traffic to it is routed outside the
+    /// tunnel.
+    #[cfg(any(target_os = "macos", windows))]
+    pub relay_address: IpAddr,
+}
+
+/// Runs all leak tests.
+pub async fn run(service: RequestServiceHandle, tunnel: TunnelInfo) -> LeakTestReport {
+    let (dns, (v4_result, v6_result)) = futures::join!(
+        test_dns(&tunnel),
+        geoip::send_am_i_mullvad_requests(service)
+    );
+
+    let exit_ip = match v4_result {
+        Ok(location) => check_exit_ip(&location, &tunnel.exit_ips),
+        Err(error) => LeakTestOutcome::Inconclusive(format!(
+            "Unable to determine the public IPv4 address: {}",
+            error
+        )),
+    };
+    let ipv6 = match v6_result {
+        Ok(location) => check_exit_ip(&location, &tunnel.exit_ips),
+        Err(error) if tunnel.ipv6 => LeakTestOutcome::Inconclusive(format!(
+            "Unable to determine the public IPv6 address: {}",
+            error
+        )),
+        // IPv6 should be blocked when it is disabled in the tunnel
+        Err(_) => LeakTestOutcome::Passed,
+    };
+
+    LeakTestReport { dns, ipv6, exit_ip }
+}
+
+async fn test_dns(tunnel: &TunnelInfo) -> LeakTestOutcome {
+    let servers: Vec<IpAddr> = DNS_LEAK_TEST_SERVERS
+        .iter()
+        .filter(|server| !tunnel.resolvers.contains(server))
+        .cloned()
+        .collect();
+    if servers.is_empty() {
+        return LeakTestOutcome::Inconclusive(
+            "All test resolvers are used by the tunnel".to_owned(),
+        );
+    }
+
+    let mut bind_failures = 0;
+    let results = dns::test_servers_with(&servers, |server| {
+        let result = bind_outside_tunnel(server, tunnel);
+        if result.is_err() {
+            bind_failures += 1;
+        }
+        result
+    })
+    .await;
+    if bind_failures == servers.len() {
+        return LeakTestOutcome::Inconclusive(
+            "Unable to send DNS queries outside the tunnel".to_owned(),
+        );
+    }
+
+    let leaking_servers: Vec<String> = results
+        .into_iter()
+        .filter(|result| result.latency.is_some())
+        .map(|result| result.server.to_string())
+        .collect();
+    if leaking_servers.is_empty() {
+        LeakTestOutcome::Passed
+    } else {
+        LeakTestOutcome::Failed(format!(
+            "DNS queries to {} were answered",
+            leaking_servers.join(", ")
+        ))
+    }
+}
+
+fn check_exit_ip(location: &AmIMullvad, exit_ips: &[IpAddr]) -> LeakTestOutcome {
+    if exit_ips.contains(&location.ip) {
+        LeakTestOutcome::Passed
+    } else if location.mullvad_exit_ip {
+        LeakTestOutcome::Failed(format!(
+            "Traffic reached the internet from {}, which is not the selected exit relay",
+            location.ip
+        ))
+    } else {
+        LeakTestOutcome::Failed(format!(
+            "Traffic reached the internet from {}, which is not a Mullvad relay",
+            location.ip
+        ))
+    }
+}
+
+/// Returns a socket bound to a physical interface, so that traffic from it is not routed through
+/// the tunnel.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn bind_outside_tunnel(server: IpAddr, tunnel: &TunnelInfo) -> io::Result<UdpSocket> {
+    let address = local_address_outside_tunnel(server, tunnel)?;
+    let interface = interface_with_address(address, tunnel)?;
+    let socket = UdpSocket::bind(SocketAddr::new(address, 0))?;
+
+    #[cfg(target_os = "linux")]
+    nix::sys::socket::setsockopt(
+        socket.as_raw_fd(),
+        nix::sys::socket::sockopt::BindToDevice,
+        &std::ffi::OsString::from(&interface),
+    )
+    .map_err(nix_to_io_error)?;
+
+    #[cfg(target_os = "macos")]
+    {
+        let index = nix::net::if_::if_nametoindex(interface.as_str()).map_err(nix_to_io_error)?;
+        let (level, option) = match address {
+            IpAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_BOUND_IF),
+            IpAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_BOUND_IF),
+        };
+        // SAFETY: `index` outlives the call and its size is passed along with it
+        let result = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                option,
+                &index as *const _ as *const libc::c_void,
+                std::mem::size_of_val(&index) as libc::socklen_t,
+            )
+        };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(socket)
+}
+
+/// Returns a socket bound to the address that traffic outside the tunnel is sent from. Sockets
+/// bound to an address only send traffic through the interface of the address.
+#[cfg(windows)]
+fn bind_outside_tunnel(server: IpAddr, tunnel: &TunnelInfo) -> io::Result<UdpSocket> {
+    let address = local_address_outside_tunnel(server, tunnel)?;
+    UdpSocket::bind(SocketAddr::new(address, 0))
+}
+
+#[cfg(target_os = "android")]
+fn bind_outside_tunnel(_server: IpAddr, _tunnel: &TunnelInfo) -> io::Result<UdpSocket> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "Sending traffic outside the tunnel is not supported",
+    ))
+}
+
+/// Returns the local address that traffic to `server` is sent from when it is routed outside the
+/// tunnel.
+#[cfg(not(target_os = "android"))]
+#[cfg_attr(target_os = "linux", allow(unused_variables))]
+fn local_address_outside_tunnel(server: IpAddr, tunnel: &TunnelInfo) -> io::Result<IpAddr> {
+    let bind_addr: SocketAddr = match server {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let route_probe = UdpSocket::bind(bind_addr)?;
+
+    // Marked traffic is routed using the main routing table rather than the tunnel table
+    #[cfg(target_os = "linux")]
+    let destination = {
+        nix::sys::socket::setsockopt(
+            route_probe.as_raw_fd(),
+            nix::sys::socket::sockopt::Mark,
+            &talpid_core::linux::tunnel_fw_mark(),
+        )
+        .map_err(nix_to_io_error)?;
+        server
+    };
+    // Traffic to the relay is always routed outside the tunnel
+    #[cfg(not(target_os = "linux"))]
+    let destination = {
+        if server.is_ipv4() != tunnel.relay_address.is_ipv4() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "The relay is not reachable over this IP version",
+            ));
+        }
+        tunnel.relay_address
+    };
+
+    // Connecting a UDP socket selects a route without sending anything
+    route_probe.connect((destination, 53))?;
+    Ok(route_probe.local_addr()?.ip())
+}
+
+/// Returns the name of the interface that has `address`, unless it is the tunnel interface.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn interface_with_address(address: IpAddr, tunnel: &TunnelInfo) -> io::Result<String> {
+    getifaddrs()
+        .map_err(nix_to_io_error)?
+        .find(|interface| match interface.address {
+            Some(SockAddr::Inet(interface_address)) => interface_address.ip().to_std() == address,
+            _ => false,
+        })
+        .map(|interface| interface.interface_name)
+        .filter(|name| Some(name) != tunnel.interface.as_ref())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "No interface outside the tunnel has the address",
+            )
+        })
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn nix_to_io_error(error: nix::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, error)
+}
@@ -5,26 +5,48 @@
 extern crate serde;
 
 pub mod account_history;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod allowed_hosts;
 mod api;
+mod captive_portal;
+mod connect_time;
+mod connection_quality;
 pub mod device;
+mod diagnostics;
 mod dns;
 pub mod exception_logging;
 #[cfg(target_os = "macos")]
 pub mod exclusion_gid;
 mod geoip;
+mod leak_test;
 pub mod logging;
 #[cfg(not(target_os = "android"))]
 pub mod management_interface;
+mod metrics;
 mod migrations;
 #[cfg(not(target_os = "android"))]
 pub mod rpc_uniqueness_check;
 pub mod runtime;
+mod runtime_stats;
+mod selection_telemetry;
 pub mod settings;
+mod sleep_monitor;
+mod stability;
 mod target_state;
+mod tunnel_log;
 pub mod version;
 mod version_check;
+mod wifi_monitor;
+
+pub use api::ApiConnectionModeInfo;
+pub use dns::DnsTestResult;
+pub use leak_test::{LeakTestOutcome, LeakTestReport};
+pub use runtime_stats::RuntimeStats;
+pub use stability::StabilityCounters;
 
 use crate::target_state::PersistentTargetState;
+#[cfg(not(target_os = "android"))]
+use chrono::{DateTime, Utc};
 use device::{PrivateAccountAndDevice, PrivateDeviceEvent};
 use futures::{
     channel::{mpsc, oneshot},
@@ -34,38 +56,62 @@ use futures::{
 use mullvad_api::availability::ApiAvailabilityHandle;
 use mullvad_relay_selector::{
     updater::{RelayListUpdater, RelayListUpdaterHandle},
-    RelaySelector, SelectedBridge, SelectedObfuscator, SelectedRelay, SelectorConfig,
+    NormalSelectedRelay, RelayListSignatureInfo, RelaySelector, SelectedBridge, SelectedObfuscator,
+    SelectedRelay, SelectionOutcome, SelectorConfig,
 };
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use mullvad_types::settings::AllowedHost;
+#[cfg(windows)]
+use mullvad_types::settings::SplitTunnelSettings;
 use mullvad_types::{
     account::{AccountData, AccountToken, VoucherSubmission},
-    device::{AccountAndDevice, Device, DeviceEvent, DeviceId, RemoveDeviceEvent},
+    auth_failed::{AuthFailed, EXPIRED_ACCOUNT_REASON},
+    device::{AccountAndDevice, Device, DeviceEvent, DeviceId, DevicePort, RemoveDeviceEvent},
     endpoint::MullvadEndpoint,
     location::GeoIpLocation,
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
-    relay_list::{Relay, RelayList},
-    settings::{DnsOptions, Settings},
-    states::{TargetState, TunnelState},
-    version::{AppVersion, AppVersionInfo},
+    relay_constraints::{
+        BridgeSettings, BridgeState, Constraint, LocationConstraint, ObfuscationSettings,
+        RelayConstraintOverride, RelaySettings, RelaySettingsUpdate, SelectedObfuscation,
+        TransportPort,
+    },
+    relay_list::{ProtocolCounts, ProviderInfo, Relay, RelayList},
+    settings::{
+        DnsOptions, ExpiryPolicy, MigrationRecord, OnDemandRules, ReconnectPolicy,
+        ReconnectTrigger, Settings, UnverifiedPolicy,
+    },
+    states::{
+        ActiveTunnelInfo, AutoConnectSuppressedReason, BlockedCapabilities, BootLeakStatus,
+        CaptivePortalStatus, ConnectTrigger, ConnectionReadyInfo, TargetState, TunnelState,
+    },
+    version::{AppVersion, AppVersionInfo, DaemonInfo},
     wireguard::{PublicKey, RotationInterval},
 };
 use settings::SettingsPersister;
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+use std::collections::HashSet;
+#[cfg(target_os = "windows")]
+use std::ffi::OsString;
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
 #[cfg(not(target_os = "android"))]
 use std::path::Path;
-#[cfg(target_os = "windows")]
-use std::{collections::HashSet, ffi::OsString};
 use std::{
+    collections::VecDeque,
     marker::PhantomData,
     mem,
+    net::IpAddr,
     path::PathBuf,
     pin::Pin,
-    sync::{mpsc as sync_mpsc, Arc, Weak},
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc as sync_mpsc, Arc, Weak,
+    },
+    time::{Duration, Instant},
 };
 #[cfg(any(target_os = "linux", windows))]
 use talpid_core::split_tunnel;
 use talpid_core::{
+    firewall::{Firewall, FirewallBackendInfo, FirewallException},
     mpsc::Sender,
     tunnel_state_machine::{self, TunnelCommand, TunnelParametersGenerator},
 };
@@ -73,9 +119,17 @@ use talpid_core::{
 use talpid_types::android::AndroidContext;
 #[cfg(not(target_os = "android"))]
 use talpid_types::net::openvpn;
+#[cfg(target_os = "linux")]
+use talpid_types::net::LinuxRoutingOptions;
 use talpid_types::{
-    net::{wireguard, TunnelEndpoint, TunnelParameters, TunnelType},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    net::{
+        wireguard, AllowedEndpoint, ObfuscationEndpoint, TunnelEndpoint, TunnelParameters,
+        TunnelType,
+    },
+    tunnel::{
+        ConnectionRetryBackoff, ErrorStateCause, ParameterGenerationError, RouteChangeReconnect,
+        TunnelStateTransition,
+    },
     ErrorExt,
 };
 #[cfg(not(target_os = "android"))]
@@ -85,6 +139,33 @@ use tokio::io;
 /// Delay between generating a new WireGuard key and reconnecting
 const WG_RECONNECT_DELAY: Duration = Duration::from_secs(4 * 60);
 
+/// Longest duration that the tunnel may be paused for
+#[cfg(not(target_os = "android"))]
+const MAX_PAUSE_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Number of device events to keep for [`DaemonCommand::GetRecentDeviceEvents`]
+const DEVICE_EVENT_HISTORY_LEN: usize = 50;
+
+/// Minimum time between re-evaluations of the offline state requested through
+/// [`DaemonCommand::RefreshOfflineState`]
+const OFFLINE_STATE_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Maximum number of characters in a session label
+const MAX_SESSION_LABEL_LEN: usize = 64;
+
+/// How long to wait for traffic to get through the tunnel after the machine resumes from sleep
+/// before reconnecting
+const RESUME_CONNECTIVITY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long dropped packets are logged for after enabling firewall debug logging
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+const FIREWALL_DEBUG_LOGGING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How often the account expiry is looked up while the tunnel is secured, so that
+/// [Settings::on_account_expiry] also applies to WireGuard, which does not report failed
+/// authentication
+const ACCOUNT_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
 pub type ResponseTx<T, E> = oneshot::Sender<Result<T, E>>;
 
 #[derive(err_derive::Error, Debug)]
@@ -102,6 +183,12 @@ pub enum Error {
     #[error(display = "REST request failed")]
     RestError(#[error(source)] mullvad_api::rest::Error),
 
+    #[error(display = "API requests are paused while the daemon is inactive or suspended")]
+    ApiPaused,
+
+    #[error(display = "API requests cannot be made while the device is offline")]
+    ApiOffline,
+
     #[error(display = "API availability check failed")]
     ApiCheckError(#[error(source)] mullvad_api::availability::Error),
 
@@ -114,6 +201,11 @@ pub enum Error {
     #[error(display = "Failed to log in to account")]
     LoginError(#[error(source)] device::Error),
 
+    /// The account already has the maximum number of devices. Contains the devices of the
+    /// account, one of which must be removed before logging in again.
+    #[error(display = "There are too many devices on the account")]
+    TooManyDevices(Vec<Device>),
+
     #[error(display = "Failed to log out of account")]
     LogoutError(#[error(source)] device::Error),
 
@@ -129,10 +221,17 @@ pub enum Error {
     #[error(display = "Failed to update device")]
     UpdateDeviceError(#[error(source)] device::Error),
 
+    #[error(display = "Failed to migrate device from old settings")]
+    DeviceMigrationError(#[error(source)] device::Error),
+
     #[cfg(target_os = "linux")]
     #[error(display = "Unable to initialize split tunneling")]
     InitSplitTunneling(#[error(source)] split_tunnel::Error),
 
+    #[cfg(target_os = "linux")]
+    #[error(display = "Unable to update the excluded cgroups")]
+    SplitTunnelCgroupError(#[error(source)] split_tunnel::Error),
+
     #[cfg(windows)]
     #[error(display = "Split tunneling error")]
     SplitTunnelError(#[error(source)] split_tunnel::Error),
@@ -140,24 +239,81 @@ pub enum Error {
     #[error(display = "An account is already set")]
     AlreadyLoggedIn,
 
+    #[error(display = "Account creation was cancelled")]
+    AccountCreationCancelled,
+
     #[error(display = "No wireguard private key available")]
     NoKeyAvailable,
 
     #[error(display = "No bridge available")]
     NoBridgeAvailable,
 
+    #[error(
+        display = "No bridge relay with the hostname {} exists in the relay list",
+        _0
+    )]
+    UnknownBridge(String),
+
     #[error(display = "No matching entry relay was found")]
     NoEntryRelayAvailable,
 
+    #[error(display = "No relays can be selected with the relay settings")]
+    NoRelaySelected(#[error(source)] mullvad_relay_selector::Error),
+
+    #[error(display = "The tunnel is not connected through a bridge")]
+    NotUsingBridge,
+
+    #[error(display = "No other bridge matches the bridge settings")]
+    NoOtherBridge,
+
     #[error(display = "No account token is set")]
     NoAccountToken,
 
     #[error(display = "No account history available for the token")]
     NoAccountTokenHistory,
 
+    #[error(display = "Captive portal detection requires an unblocked, disconnected tunnel")]
+    CaptivePortalDetectionUnavailable,
+
+    #[error(display = "The tunnel is not connected")]
+    TunnelNotConnected,
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "The tunnel can only be paused while the target state is secured")]
+    TunnelNotSecured,
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "The tunnel is not paused")]
+    TunnelNotPaused,
+
+    #[cfg(not(target_os = "android"))]
+    #[error(display = "The pause duration must be non-zero and at most 24 hours")]
+    InvalidPauseDuration,
+
     #[error(display = "Settings error")]
     SettingsError(#[error(source)] settings::Error),
 
+    #[error(display = "Unable to write to the selection telemetry file")]
+    SelectionTelemetryPathError(#[error(source)] io::Error),
+
+    #[error(display = "Logging to file is disabled")]
+    NoLogDirectory,
+
+    #[error(
+        display = "The session label must be non-empty and at most {} characters",
+        MAX_SESSION_LABEL_LEN
+    )]
+    InvalidSessionLabel,
+
+    #[error(display = "Failed to read the tunnel log")]
+    ReadTunnelLogError(#[error(source)] io::Error),
+
+    #[error(display = "Failed to read migration history")]
+    MigrationHistoryError(#[error(source)] migrations::Error),
+
+    #[error(display = "Failed to create the diagnostics bundle")]
+    CreateDiagnosticsBundleError(#[error(source)] io::Error),
+
     #[error(display = "Account history error")]
     AccountHistory(#[error(source)] account_history::Error),
 
@@ -173,6 +329,17 @@ pub enum Error {
     #[error(display = "Failed to clear settings")]
     ClearSettingsError(#[error(source)] settings::Error),
 
+    #[error(display = "The relay list does not contain any relays")]
+    EmptyRelayList,
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[error(display = "Failed to set firewall debug logging")]
+    FirewallDebugLoggingError(#[error(source)] talpid_core::firewall::Error),
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    #[error(display = "Logging dropped packets is not supported by the firewall on this platform")]
+    FirewallDebugLoggingUnsupported,
+
     #[error(display = "Tunnel state machine error")]
     TunnelError(#[error(source)] tunnel_state_machine::Error),
 
@@ -208,37 +375,102 @@ pub enum DaemonCommand {
     SetTargetState(oneshot::Sender<bool>, TargetState),
     /// Reconnect the tunnel, if one is connecting/connected.
     Reconnect(oneshot::Sender<bool>),
+    /// Reconnect through a different bridge than the one in use. The bridge is skipped until the
+    /// next disconnect or manual connect.
+    #[cfg(not(target_os = "android"))]
+    NextBridge(ResponseTx<(), Error>),
+    /// Disconnect the tunnel and reconnect it automatically after the given duration.
+    #[cfg(not(target_os = "android"))]
+    PauseTunnel(ResponseTx<(), Error>, Duration),
+    /// Reconnect a paused tunnel before its pause duration has elapsed.
+    #[cfg(not(target_os = "android"))]
+    ResumeTunnel(ResponseTx<(), Error>),
     /// Request the current state.
     GetState(oneshot::Sender<TunnelState>),
+    /// Get why the tunnel was not connected at startup even though auto-connect is enabled, if
+    /// it was not
+    GetAutoConnectSuppressed(oneshot::Sender<Option<AutoConnectSuppressedReason>>),
     /// Get the current geographical location.
     GetCurrentLocation(oneshot::Sender<Option<GeoIpLocation>>),
+    /// Return the most recently fetched location, if it is still fresh, without sending any
+    /// requests
+    GetCachedLocation(oneshot::Sender<Option<GeoIpLocation>>),
+    /// Forget the most recently fetched location
+    ClearLocationCache,
+    /// Get details about the relays and options used by the connected tunnel.
+    GetActiveTunnelInfo(ResponseTx<ActiveTunnelInfo, Error>),
+    /// Probe for a captive portal. Only available while disconnected and not blocking traffic.
+    DetectCaptivePortal(ResponseTx<CaptivePortalStatus, Error>),
+    /// Get whether the LAN, DNS and the internet can be reached in the current tunnel state
+    GetBlockedCapabilities(oneshot::Sender<BlockedCapabilities>),
+    /// Get the API endpoint that the firewall currently allows traffic to in every tunnel state
+    GetAllowedEndpoint(oneshot::Sender<AllowedEndpoint>),
+    /// Get whether a multihop entry and exit relay can be selected with the current constraints
+    CanUseMultihop(oneshot::Sender<bool>),
+    /// Get the tunnel type that the first connection attempt would use with the current settings
+    ResolveTunnelTypeConstraint(oneshot::Sender<TunnelType>),
+    /// Get what caused the tunnel to be connected in the current session
+    GetConnectTrigger(oneshot::Sender<ConnectTrigger>),
+    /// Get whether the current settings prevent traffic from leaking while the device boots
+    GetBootLeakProtectionStatus(oneshot::Sender<BootLeakStatus>),
     CreateNewAccount(ResponseTx<String, Error>),
+    /// Cancel the account creation in progress, if any. Returns whether it was cancelled before
+    /// logging in to the new account
+    CancelAccountCreation(ResponseTx<bool, Error>),
     /// Request the metadata for an account.
-    GetAccountData(
-        ResponseTx<AccountData, mullvad_api::rest::Error>,
-        AccountToken,
-    ),
+    GetAccountData(ResponseTx<AccountData, Error>, AccountToken),
     /// Request www auth token for an account
     GetWwwAuthToken(ResponseTx<String, Error>),
     /// Submit voucher to add time to the current account. Returns time added in seconds
     SubmitVoucher(ResponseTx<VoucherSubmission, Error>, String),
     /// Request account history
     GetAccountHistory(oneshot::Sender<Option<AccountToken>>),
-    /// Remove the last used account, if there is one
+    /// Request all recently used accounts, most recently used first
+    GetAccountHistoryList(oneshot::Sender<Vec<AccountToken>>),
+    /// Set the maximum number of accounts to remember in the account history
+    SetAccountHistoryLimit(ResponseTx<(), settings::Error>, u32),
+    /// Remove all accounts from the account history
     ClearAccountHistory(ResponseTx<(), Error>),
     /// Get the list of countries and cities where there are relays.
     GetRelayLocations(oneshot::Sender<RelayList>),
+    /// Get whether the relay list in use is signed and whether the signature is valid
+    GetRelayListSignatureInfo(oneshot::Sender<RelayListSignatureInfo>),
+    /// Get the number of relays in each country and city that match the current tunnel protocol
+    /// and provider constraints
+    GetRelayCountsByLocation(oneshot::Sender<Vec<(LocationConstraint, usize)>>),
+    /// Get the number of relays that support WireGuard and OpenVPN respectively, out of those that
+    /// match the current location and provider constraints
+    GetRelayCountsByProtocol(oneshot::Sender<ProtocolCounts>),
+    /// Get the providers of the active relays, with the number of relays that can be selected
+    /// from each
+    GetProviders(oneshot::Sender<Vec<ProviderInfo>>),
+    /// Get the relays that would be selected if the update was applied to the relay settings.
+    /// The settings are not changed.
+    PreviewRelaySettings(ResponseTx<SelectionOutcome, Error>, RelaySettingsUpdate),
+    /// Connect using the given relay constraints instead of the saved ones. The saved relay
+    /// settings are used again after the next disconnect or manual connect.
+    ConnectToRelay(ResponseTx<(), Error>, RelayConstraintOverride),
     /// Trigger an asynchronous relay list update. This returns before the relay list is actually
     /// updated.
     UpdateRelayLocations,
+    /// Select relays from the given relay list until it is cleared or the daemon restarts. The
+    /// list is not written to the relay cache
+    SetEphemeralRelayList(ResponseTx<(), Error>, RelayList),
+    /// Go back to selecting relays from the cached or downloaded relay list
+    ClearEphemeralRelayList(oneshot::Sender<()>),
     /// Log in with a given account and create a new device.
     LoginAccount(ResponseTx<(), Error>, AccountToken),
     /// Log out of the current account and remove the device, if they exist.
     LogoutAccount(ResponseTx<(), Error>),
+    /// Log in to another account as a single operation. The device of the current account is
+    /// removed only if the login succeeds, and the tunnel is reconnected using the new device
+    SwitchAccount(ResponseTx<(), Error>, AccountToken),
     /// Return the current device configuration, if there is one.
     GetDevice(ResponseTx<Option<AccountAndDevice>, Error>),
     /// Update/check the current device, if there is one.
     UpdateDevice(ResponseTx<(), Error>),
+    /// Return the ports forwarded to the current device, after updating the device from the API
+    GetDevicePorts(ResponseTx<Vec<DevicePort>, Error>),
     /// Return all the devices for a given account token.
     ListDevices(ResponseTx<Vec<Device>, Error>, AccountToken),
     /// Remove device from a given account.
@@ -251,8 +483,71 @@ pub enum DaemonCommand {
     SetShowBetaReleases(ResponseTx<(), settings::Error>, bool),
     /// Set the block_when_disconnected setting.
     SetBlockWhenDisconnected(ResponseTx<(), settings::Error>, bool),
+    /// Set whether all IPv6 traffic should be blocked in the firewall when IPv6 is disabled.
+    SetBlockIpv6(ResponseTx<(), settings::Error>, bool),
+    /// Set whether processes excluded from the tunnel may communicate while traffic is blocked.
+    #[cfg(target_os = "linux")]
+    SetAllowExcludedWhenBlocked(ResponseTx<(), settings::Error>, bool),
+    /// Set the firewall mark and routing table used by the tunnel
+    #[cfg(target_os = "linux")]
+    SetLinuxRoutingOptions(ResponseTx<(), settings::Error>, LinuxRoutingOptions),
+    /// Set the hosts that the daemon may reach outside the tunnel while connecting or blocking
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    SetAllowedHosts(ResponseTx<(), settings::Error>, Vec<AllowedHost>),
+    /// Set whether only relays where port forwarding is available should be selected
+    SetPortForwarding(ResponseTx<(), settings::Error>, bool),
     /// Set the auto-connect setting.
     SetAutoConnect(ResponseTx<(), settings::Error>, bool),
+    /// Set the file to write relay selection telemetry to, or disable it. The file must be located
+    /// directly in the log or cache directory
+    SetSelectionTelemetryPath(ResponseTx<(), Error>, Option<PathBuf>),
+    /// Set whether relays that could recently not be connected to should be selected less often
+    SetDeprioritizeFailingRelays(ResponseTx<(), settings::Error>, bool),
+    /// Set whether the first retry should use the same relays as the failed attempt
+    SetRetrySameRelay(ResponseTx<(), settings::Error>, bool),
+    /// Set the OpenVPN transports to cycle through on successive retries
+    SetOpenVpnEndpointPreference(ResponseTx<(), settings::Error>, Vec<TransportPort>),
+    /// Set whether to reconnect when the exit relay in use is removed from the relay list
+    SetReconnectOnRelayRemoval(ResponseTx<(), settings::Error>, bool),
+    /// Set the rules that select the target state depending on the Wi-Fi network
+    SetOnDemandRules(ResponseTx<(), settings::Error>, OnDemandRules),
+    /// Set whether changes to a group of settings reconnect the tunnel immediately or are deferred
+    /// until the next time the tunnel connects
+    SetReconnectPolicy(
+        ResponseTx<(), settings::Error>,
+        ReconnectTrigger,
+        ReconnectPolicy,
+    ),
+    /// Set what to do when the connectivity of a newly connected tunnel could not be verified
+    SetOnUnverifiedConnection(ResponseTx<(), settings::Error>, UnverifiedPolicy),
+    /// Set what to do when the account expires while the tunnel is secured
+    SetOnAccountExpiry(ResponseTx<(), settings::Error>, ExpiryPolicy),
+    /// Set how long the tunnel may try to connect before all traffic is blocked, or `None` to
+    /// keep retrying
+    SetConnectDeadlineToBlock(ResponseTx<(), settings::Error>, Option<Duration>),
+    /// Set or clear the hostname of the bridge relay to always reach the API through
+    SetApiBridge(ResponseTx<(), Error>, Option<String>),
+    /// Set or clear the relay settings to use when no relay matches the relay settings
+    SetBackupRelaySettings(ResponseTx<(), settings::Error>, Option<RelaySettings>),
+    /// Set or clear the identifier appended to the User-Agent of API requests
+    SetApiClientTag(ResponseTx<(), settings::Error>, Option<String>),
+    /// Set the maximum number of API requests that are in flight at the same time, or remove the
+    /// limit. Requests beyond the limit are queued
+    SetApiConcurrencyLimit(ResponseTx<(), settings::Error>, Option<u32>),
+    /// Set the local address to bind the sockets used to reach the API to, or let the OS choose
+    SetApiBindInterface(ResponseTx<(), settings::Error>, Option<IpAddr>),
+    /// Get how old the cached relay list needs to be before a new one is downloaded
+    GetRelayListUpdateInterval(oneshot::Sender<Duration>),
+    /// Set how old the cached relay list needs to be before a new one is downloaded
+    SetRelayListUpdateInterval(ResponseTx<(), settings::Error>, Duration),
+    /// Set the delay between consecutive attempts to connect the tunnel
+    SetConnectionRetryBackoff(ResponseTx<(), settings::Error>, ConnectionRetryBackoff),
+    /// Set whether the tunnel is disconnected when routes or interfaces change such that the
+    /// device is offline, and how long the device must stay offline first
+    SetRouteChangeReconnect(ResponseTx<(), settings::Error>, RouteChangeReconnect),
+    /// Set the number of failed WireGuard connection attempts after which OpenVPN is used, or
+    /// disable the fallback
+    SetProtocolFallbackThreshold(ResponseTx<(), settings::Error>, Option<u32>),
     /// Set the mssfix argument for OpenVPN
     SetOpenVpnMssfix(ResponseTx<(), settings::Error>, Option<u16>),
     /// Set proxy details for OpenVPN
@@ -263,23 +558,106 @@ pub enum DaemonCommand {
     SetEnableIpv6(ResponseTx<(), settings::Error>, bool),
     /// Set DNS options or servers to use
     SetDnsOptions(ResponseTx<(), settings::Error>, DnsOptions),
+    /// Test whether the given DNS servers respond to queries. The queries are sent through the
+    /// tunnel when connected.
+    TestDnsServers(ResponseTx<Vec<DnsTestResult>, Error>, Vec<IpAddr>),
+    /// Test whether DNS, IPv6 or other traffic escapes the tunnel. Requires a connected tunnel.
+    RunLeakTest(ResponseTx<LeakTestReport, Error>),
     /// Toggle macOS network check leak
     /// Set MTU for wireguard tunnels
     SetWireguardMtu(ResponseTx<(), settings::Error>, Option<u16>),
+    /// Set the persistent keepalive interval for wireguard tunnels
+    SetWireguardKeepalive(ResponseTx<(), settings::Error>, Option<u16>),
     /// Set automatic key rotation interval for wireguard tunnels
     SetWireguardRotationInterval(ResponseTx<(), settings::Error>, Option<RotationInterval>),
     /// Get the daemon settings
     GetSettings(oneshot::Sender<Settings>),
+    /// Get only the settings that differ from the defaults, with sensitive values redacted
+    GetNonDefaultSettings(oneshot::Sender<serde_json::Value>),
+    /// Check the settings for contradicting options, without changing them
+    ValidateSettings(ResponseTx<Vec<settings::SettingsIssue>, Error>),
+    /// Pause or resume automatic rotation of the wireguard key. This is not persisted
+    SetKeyRotationEnabled(ResponseTx<(), Error>, bool),
     /// Generate new wireguard key
     RotateWireguardKey(ResponseTx<(), Error>),
+    /// Return whether the wireguard key is being rotated right now
+    IsRotatingKey(oneshot::Sender<bool>),
     /// Return a public key of the currently set wireguard private key, if there is one
     GetWireguardKey(ResponseTx<Option<PublicKey>, Error>),
+    /// Return a short fingerprint of the public key of the current device
+    GetWireguardKeyFingerprint(ResponseTx<Option<String>, Error>),
     /// Get information about the currently running and latest app versions
     GetVersionInfo(oneshot::Sender<Option<AppVersionInfo>>),
     /// Return whether the daemon is performing post-upgrade tasks
     IsPerformingPostUpgrade(oneshot::Sender<bool>),
+    /// Return the settings migrations that have been applied on this install, oldest first
+    GetMigrationHistory(ResponseTx<Vec<MigrationRecord>, Error>),
+    /// Retry creating a device from the account token and WireGuard key found in old settings.
+    /// Returns whether a device was recovered
+    RetryDeviceMigration(ResponseTx<bool, Error>),
+    /// Return the last N lines of output from the tunnel process, with secrets redacted
+    GetRecentTunnelLog(ResponseTx<Vec<String>, Error>, usize),
+    /// Return the last N device events, oldest first
+    GetRecentDeviceEvents(ResponseTx<Vec<DeviceEvent>, Error>, usize),
+    /// Set or clear a label for the current session. The label is not persisted and is cleared
+    /// when the tunnel is disconnected
+    SetSessionLabel(ResponseTx<(), Error>, Option<String>),
+    /// Get the label of the current session, if one is set
+    GetSessionLabel(oneshot::Sender<Option<String>>),
+    /// Get the connection mode that is currently used to reach the API
+    GetApiConnectionMode(oneshot::Sender<ApiConnectionModeInfo>),
+    /// Close all API connections and switch to the next API connection mode
+    ResetApiConnection(ResponseTx<(), Error>),
+    /// Get the obfuscation protocols that are supported by this build
+    GetSupportedObfuscators(oneshot::Sender<Vec<SelectedObfuscation>>),
+    /// Return whether the most recently selected relays support the selected obfuscation
+    CurrentRelaySupportsObfuscation(oneshot::Sender<bool>),
+    /// Get counters describing the daemon, in the Prometheus text format
+    GetMetrics(oneshot::Sender<String>),
+    /// Get the number of reconnects and failures since the daemon started or the counters were
+    /// reset
+    GetStabilityCounters(oneshot::Sender<StabilityCounters>),
+    /// Reset the counters returned by `GetStabilityCounters`
+    ResetStabilityCounters(oneshot::Sender<()>),
+    /// Get the memory and number of handles used by the daemon
+    GetRuntimeStats(oneshot::Sender<RuntimeStats>),
+    /// Write the logs, non-default settings and other information that is useful for
+    /// troubleshooting to a single file in the diagnostics directory of the cache directory, with
+    /// secrets redacted. The file is only readable by the daemon's user. Returns the path of the
+    /// file
+    CreateDiagnosticsBundle(ResponseTx<PathBuf, Error>),
+    /// Get the DNS servers that are currently configured for the tunnel. This includes the
+    /// default relay resolver when custom DNS is disabled. Empty unless connected
+    GetActiveDnsServers(oneshot::Sender<Vec<IpAddr>>),
+    /// Measure the quality of the tunnel connection as a score from 0 to 100. `None` is returned
+    /// unless connected
+    GetConnectionQuality(oneshot::Sender<Option<u8>>),
+    /// Estimate how long it takes to connect using the current tunnel protocol, based on recent
+    /// successful connects. `None` is returned if there are too few of them
+    EstimateConnectTime(oneshot::Sender<Option<Duration>>),
+    /// Re-evaluate whether the device is offline, in case a network change was missed. Returns
+    /// whether the device is offline. Requests made shortly after another one return the last
+    /// known state
+    RefreshOfflineState(ResponseTx<bool, Error>),
+    /// Enable or disable logging of packets dropped by the firewall to the daemon log. Logging is
+    /// disabled automatically after some time
+    SetFirewallDebugLogging(ResponseTx<(), Error>, bool),
+    /// Get the name and version of the firewall implementation used on this platform
+    GetFirewallBackendInfo(oneshot::Sender<FirewallBackendInfo>),
+    /// Get all traffic that the firewall currently lets through outside the tunnel, or while
+    /// blocking, such as the relay, the API endpoint and local networks. Empty if no firewall
+    /// policy is enforced, in which case nothing is blocked
+    GetActiveFirewallExceptions(oneshot::Sender<Vec<FirewallException>>),
+    /// Get the config currently used by the relay selector, with credentials redacted
+    GetSelectorConfig(oneshot::Sender<SelectorConfig>),
     /// Get current version of the app
     GetCurrentVersion(oneshot::Sender<AppVersion>),
+    /// Get the version of the format of the settings file, as stored in `settings_version` before
+    /// the settings were migrated. This is unrelated to the app version. `None` if there was no
+    /// settings file or it had no version
+    GetSettingsVersion(oneshot::Sender<Option<u32>>),
+    /// Get the daemon version, build commit, start time and uptime
+    GetDaemonInfo(oneshot::Sender<DaemonInfo>),
     /// Remove settings and clear the cache
     #[cfg(not(target_os = "android"))]
     FactoryReset(ResponseTx<(), Error>),
@@ -295,6 +673,12 @@ pub enum DaemonCommand {
     /// Clear list of processes excluded from the tunnel
     #[cfg(target_os = "linux")]
     ClearSplitTunnelProcesses(ResponseTx<(), split_tunnel::Error>),
+    /// Exclude all processes in a net_cls cgroup from the tunnel
+    #[cfg(target_os = "linux")]
+    AddSplitTunnelCgroup(ResponseTx<(), Error>, PathBuf),
+    /// Stop excluding the processes in a net_cls cgroup from the tunnel
+    #[cfg(target_os = "linux")]
+    RemoveSplitTunnelCgroup(ResponseTx<(), Error>, PathBuf),
     /// Exclude traffic of an application from the tunnel
     #[cfg(windows)]
     AddSplitTunnelApp(ResponseTx<(), Error>, PathBuf),
@@ -307,6 +691,9 @@ pub enum DaemonCommand {
     /// Disable split tunnel
     #[cfg(windows)]
     SetSplitTunnelState(ResponseTx<(), Error>, bool),
+    /// Set whether excluded apps should be left untouched in the split tunnel driver on reconnect
+    #[cfg(windows)]
+    SetSplitTunnelKeepExclusionsOnReconnect(ResponseTx<(), settings::Error>, bool),
     /// Toggle wireguard-nt on or off
     #[cfg(target_os = "windows")]
     UseWireGuardNt(ResponseTx<(), Error>, bool),
@@ -343,9 +730,31 @@ pub(crate) enum InternalDaemonEvent {
     DeviceEvent(PrivateDeviceEvent),
     /// Handles updates from versions without devices.
     DeviceMigrationEvent(Result<PrivateAccountAndDevice, device::Error>),
+    /// The post-upgrade migration has completed, or there was nothing to migrate.
+    MigrationComplete,
     /// The split tunnel paths or state were updated.
     #[cfg(target_os = "windows")]
     ExcludedPathsEvent(ExcludedPathsUpdate, oneshot::Sender<Result<(), Error>>),
+    /// The machine resumed from sleep or hibernation.
+    SystemResumed,
+    /// No traffic got through the tunnel after the machine resumed from sleep.
+    ResumedTunnelUnreachable,
+    /// A new relay list was fetched.
+    RelayListUpdated(RelayList),
+    /// The set of connected Wi-Fi networks may have changed. Contains their SSIDs.
+    WifiNetworksChanged(Vec<String>),
+    /// The GeoIP location was looked up after connecting to the given endpoint. `None` if the
+    /// lookup failed.
+    ConnectedLocationResolved(TunnelEndpoint, Option<GeoIpLocation>),
+    /// The expiry of the given account was looked up and has passed.
+    AccountExpired(AccountToken),
+    /// It is time to look up the expiry of the logged in account.
+    CheckAccountExpiry,
+    /// The connect deadline that was started at the given instant has passed.
+    ConnectDeadlineExceeded(Instant),
+    /// Firewall debug logging was enabled at the given instant for as long as it may be.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    FirewallDebugLoggingExpired(Instant),
 }
 
 #[cfg(target_os = "windows")]
@@ -354,6 +763,58 @@ pub(crate) enum ExcludedPathsUpdate {
     SetPaths(HashSet<PathBuf>),
 }
 
+/// Returns the apps that are excluded in the tunnel for the given split tunnel settings.
+#[cfg(windows)]
+fn tunnel_excluded_apps(enabled: bool, apps: &HashSet<PathBuf>) -> HashSet<&PathBuf> {
+    if enabled {
+        apps.iter().collect()
+    } else {
+        HashSet::new()
+    }
+}
+
+/// Returns the list of apps to send to the tunnel state machine after applying `update`, or
+/// `None` if the set of apps excluded in the tunnel would not change.
+#[cfg(windows)]
+fn excluded_apps_after_update(
+    current: &SplitTunnelSettings,
+    update: &ExcludedPathsUpdate,
+) -> Option<Vec<OsString>> {
+    let (enabled, apps) = match update {
+        ExcludedPathsUpdate::SetState(state) => (*state, &current.apps),
+        ExcludedPathsUpdate::SetPaths(paths) => (current.enable_exclusions, paths),
+    };
+    let new_apps = tunnel_excluded_apps(enabled, apps);
+    if new_apps == tunnel_excluded_apps(current.enable_exclusions, &current.apps) {
+        return None;
+    }
+    Some(new_apps.into_iter().map(OsString::from).collect())
+}
+
+/// Returns the list of apps to reinstall in the split tunnel driver when reconnecting, or `None`
+/// if the exclusions should be left as they are.
+#[cfg(windows)]
+fn excluded_apps_on_reconnect(current: &SplitTunnelSettings) -> Option<Vec<OsString>> {
+    if current.keep_exclusions_on_reconnect || !current.enable_exclusions {
+        return None;
+    }
+    Some(current.apps.iter().map(OsString::from).collect())
+}
+
+/// Tells the tunnel state machine about the resolvers to use after the DNS options changed from
+/// `current` to `new`. The resolvers are applied to the running tunnel, without reconnecting.
+fn send_dns_update(
+    tunnel_command_tx: &mpsc::UnboundedSender<TunnelCommand>,
+    current: &DnsOptions,
+    new: &DnsOptions,
+) {
+    if let Some(resolvers) = dns::addresses_after_update(current, new) {
+        tunnel_command_tx
+            .unbounded_send(TunnelCommand::Dns(resolvers))
+            .expect("Tunnel state machine has stopped");
+    }
+}
+
 impl From<TunnelStateTransition> for InternalDaemonEvent {
     fn from(tunnel_state_transition: TunnelStateTransition) -> Self {
         InternalDaemonEvent::TunnelStateTransition(tunnel_state_transition)
@@ -393,6 +854,8 @@ impl DaemonExecutionState {
             Running => {
                 match tunnel_state {
                     TunnelState::Disconnected => mem::replace(self, Finished),
+                    #[cfg(not(target_os = "android"))]
+                    TunnelState::Paused { .. } => mem::replace(self, Finished),
                     _ => mem::replace(self, Exiting),
                 };
             }
@@ -544,6 +1007,35 @@ pub trait EventListener {
 
     /// Notify that a device was revoked using `RemoveDevice`.
     fn notify_remove_device_event(&self, event: RemoveDeviceEvent);
+
+    /// Notify that the label of the current session was set or cleared.
+    fn notify_session_label(&self, label: Option<String>);
+
+    /// Notify that the post-upgrade migration has completed. This is sent once.
+    fn notify_post_upgrade_complete(&self);
+
+    /// Notify that the tunnel is connected and its exit location has been looked up. This is sent
+    /// after the connected state has been notified.
+    fn notify_connection_ready(&self, info: ConnectionReadyInfo);
+
+    /// Notify that the daemon started or stopped falling back to OpenVPN after repeatedly failing
+    /// to connect using WireGuard.
+    fn notify_protocol_fallback(&self, active: bool);
+
+    /// Notify that the daemon started or stopped using the backup relay settings because no relay
+    /// matched the relay settings.
+    fn notify_backup_relay_settings(&self, active: bool);
+
+    /// Notify that different relays were selected for the tunnel. Only the location and the
+    /// hostnames of `location` are set.
+    fn notify_endpoint_changed(&self, location: GeoIpLocation);
+
+    /// Notify that auto-connect is enabled but the tunnel was not connected at startup. This is
+    /// sent once, when the daemon starts.
+    fn notify_auto_connect_suppressed(&self, reason: AutoConnectSuppressedReason);
+
+    /// Notify that a settings change could not be saved and was reverted.
+    fn notify_settings_write_failed(&self, error: String);
 }
 
 pub struct Daemon<L: EventListener> {
@@ -553,26 +1045,85 @@ pub struct Daemon<L: EventListener> {
     state: DaemonExecutionState,
     #[cfg(target_os = "linux")]
     exclude_pids: split_tunnel::PidManager,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    allowed_hosts: allowed_hosts::AllowedHostsResolver,
     rx: mpsc::UnboundedReceiver<InternalDaemonEvent>,
     tx: DaemonEventSender,
     reconnection_job: Option<AbortHandle>,
+    /// When the current connect deadline was started, and the job that fires when it passes.
+    connect_deadline_job: Option<(Instant, AbortHandle)>,
+    /// Job that periodically requests an account expiry check. See
+    /// [ACCOUNT_EXPIRY_CHECK_INTERVAL].
+    account_expiry_check_job: Option<AbortHandle>,
+    #[cfg(not(target_os = "android"))]
+    pause_job: Option<PauseJob>,
+    account_creation_job: Option<AccountCreationJob>,
+    /// When firewall debug logging was enabled, and the job that fires when it should be
+    /// disabled again.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    firewall_debug_logging_job: Option<(Instant, AbortHandle)>,
     event_listener: L,
     migration_complete: migrations::MigrationComplete,
+    /// Whether clients have been notified that the post-upgrade migration has completed.
+    post_upgrade_complete_notified: bool,
+    device_migration_data: Option<migrations::MigrationData>,
     settings: SettingsPersister,
+    settings_dir: PathBuf,
+    cache_dir: PathBuf,
+    log_dir: Option<PathBuf>,
     account_history: account_history::AccountHistory,
     device_checker: device::TunnelStateChangeHandler,
     account_manager: device::AccountManagerHandle,
     api_runtime: mullvad_api::Runtime,
     api_handle: mullvad_api::rest::MullvadRestHandle,
+    api_connection_mode: Arc<std::sync::Mutex<mullvad_api::proxy::ApiConnectionMode>>,
+    api_bridge: Arc<std::sync::Mutex<Option<String>>>,
+    /// Endpoint that the firewall allows the API to be reached through in every tunnel state.
+    api_allowed_endpoint: Arc<std::sync::Mutex<AllowedEndpoint>>,
     version_updater_handle: version_check::VersionUpdaterHandle,
     relay_selector: RelaySelector,
     relay_list_updater: RelayListUpdaterHandle,
     last_generated_relays: Option<LastSelectedRelays>,
+    /// Whether the most recently generated tunnel parameters are for a custom tunnel endpoint, in
+    /// which case `last_generated_relays` does not describe the tunnel.
+    last_generated_custom_endpoint: bool,
+    /// Hostname of the exit relay in use if it was missing from the last relay list.
+    missing_exit_relay: Option<String>,
+    /// SSIDs of the Wi-Fi networks that are connected, if known.
+    wifi_ssids: Option<Vec<String>>,
+    selection_telemetry: selection_telemetry::SelectionTelemetry,
+    metrics: metrics::Metrics,
+    stability: stability::StabilityTracker,
+    connect_times: connect_time::ConnectTimeHistory,
+    /// The most recent device events, oldest first.
+    recent_device_events: VecDeque<DeviceEvent>,
+    /// Label of the current session. Cleared when the tunnel is disconnected.
+    session_label: Option<String>,
+    /// What caused the tunnel to be connected in the current session.
+    connect_trigger: ConnectTrigger,
+    /// Why the tunnel was not connected at startup even though auto-connect is enabled.
+    auto_connect_suppressed: Option<AutoConnectSuppressedReason>,
+    /// Whether OpenVPN is used instead of WireGuard until the next manual connect. See
+    /// [Settings::protocol_fallback_threshold].
+    protocol_fallback_active: bool,
+    /// Whether the backup relay settings were used for the current connection attempt. See
+    /// [Settings::backup_relay_settings].
+    backup_relay_settings_active: bool,
+    /// Relay constraints used instead of the saved ones until the next disconnect or manual
+    /// connect.
+    relay_override: Option<RelayConstraintOverride>,
+    /// Relays selected for the current connection attempt whose outcome has not been recorded by
+    /// the relay selector yet.
+    pending_relay_outcome: Vec<Relay>,
+    location_cache: Arc<parking_lot::Mutex<geoip::LocationCache>>,
     app_version_info: Option<AppVersionInfo>,
     shutdown_tasks: Vec<Pin<Box<dyn Future<Output = ()>>>>,
     tunnel_state_machine_handle: tunnel_state_machine::JoinHandle,
     #[cfg(target_os = "windows")]
     volume_update_tx: mpsc::UnboundedSender<()>,
+    started_at: DateTime<Utc>,
+    start_instant: Instant,
+    last_offline_state_refresh: Option<Instant>,
 }
 
 impl<L> Daemon<L>
@@ -588,6 +1139,9 @@ where
         command_channel: DaemonCommandChannel,
         #[cfg(target_os = "android")] android_context: AndroidContext,
     ) -> Result<Self, Error> {
+        let started_at = Utc::now();
+        let start_instant = Instant::now();
+
         #[cfg(target_os = "macos")]
         let exclusion_gid = {
             bump_filehandle_limit();
@@ -610,8 +1164,11 @@ where
         let api_availability = api_runtime.availability_handle();
         api_availability.suspend();
 
-        let endpoint_updater = api::ApiEndpointUpdaterHandle::new();
+        let initial_api_endpoint =
+            api::get_allowed_endpoint(api_runtime.address_cache.get_address().await);
+        let endpoint_updater = api::ApiEndpointUpdaterHandle::new(initial_api_endpoint.clone());
 
+        let settings_file_version = SettingsPersister::read_file_version(&settings_dir).await;
         let migration_data = migrations::migrate_all(&cache_dir, &settings_dir)
             .await
             .unwrap_or_else(|error| {
@@ -621,17 +1178,33 @@ where
                 );
                 None
             });
-        let settings = SettingsPersister::load(&settings_dir).await;
+        let settings = SettingsPersister::load(&settings_dir, settings_file_version).await;
 
         let initial_selector_config = new_selector_config(&settings);
         let relay_selector = RelaySelector::new(initial_selector_config, &resource_dir, &cache_dir);
 
-        let proxy_provider =
-            api::ApiConnectionModeProvider::new(cache_dir.clone(), relay_selector.clone());
+        let proxy_provider = api::ApiConnectionModeProvider::new(
+            cache_dir.clone(),
+            relay_selector.clone(),
+            settings.api_bridge.clone(),
+        );
+        let api_connection_mode = proxy_provider.current_mode();
+        let api_bridge = proxy_provider.pinned_bridge();
+        let api_allowed_endpoint = endpoint_updater.allowed_endpoint();
         let api_handle = api_runtime
             .mullvad_rest_handle(proxy_provider, endpoint_updater.callback())
             .await;
+        if settings.api_client_tag.is_some() {
+            Self::apply_api_client_tag(&api_handle, settings.api_client_tag.as_deref());
+        }
+        if settings.api_concurrency_limit.is_some() {
+            Self::apply_api_concurrency_limit(&api_handle, settings.api_concurrency_limit);
+        }
+        if settings.api_bind_interface.is_some() {
+            Self::apply_api_bind_interface(&api_handle, settings.api_bind_interface);
+        }
 
+        let device_migration_data = migration_data.clone();
         let migration_complete = if let Some(migration_data) = migration_data {
             migrations::migrate_device(
                 migration_data,
@@ -639,6 +1212,7 @@ where
                 internal_event_tx.clone(),
             )
         } else {
+            let _ = internal_event_tx.send(InternalDaemonEvent::MigrationComplete);
             migrations::MigrationComplete::new(true)
         };
 
@@ -666,16 +1240,41 @@ where
         let account_history = account_history::AccountHistory::new(
             &settings_dir,
             data.as_ref().map(|device| device.account_token.clone()),
+            settings.account_history_limit as usize,
         )
         .await
         .map_err(Error::LoadAccountHistory)?;
 
-        let target_state = if settings.auto_connect {
+        let auto_connect_suppressed = match &data {
+            None if settings.auto_connect && !migration_complete.is_complete() => {
+                Some(AutoConnectSuppressedReason::MigrationPending)
+            }
+            None if settings.auto_connect => Some(AutoConnectSuppressedReason::NoDevice),
+            _ => None,
+        };
+        let target_state = if let Some(reason) = auto_connect_suppressed {
+            // The cached target state is kept, so that an unclean shutdown while connected is
+            // still recovered from
+            log::info!("Not auto-connecting since {}", reason);
+            PersistentTargetState::new(&cache_dir).await
+        } else if settings.auto_connect {
             log::info!("Automatically connecting since auto-connect is turned on");
             PersistentTargetState::force(&cache_dir, TargetState::Secured).await
         } else {
             PersistentTargetState::new(&cache_dir).await
         };
+        let auto_connect_suppressed =
+            auto_connect_suppressed.filter(|_| *target_state == TargetState::Unsecured);
+        let connect_trigger = if *target_state == TargetState::Unsecured {
+            ConnectTrigger::NotConnected
+        } else if target_state.cached_state() == Some(TargetState::Secured) {
+            // The previous instance did not shut down cleanly while connected
+            ConnectTrigger::PersistedTargetState
+        } else if settings.auto_connect {
+            ConnectTrigger::AutoConnect
+        } else {
+            ConnectTrigger::PersistedTargetState
+        };
 
         #[cfg(windows)]
         let exclude_paths = if settings.split_tunnel.enable_exclusions {
@@ -689,8 +1288,6 @@ where
             vec![]
         };
 
-        let initial_api_endpoint =
-            api::get_allowed_endpoint(api_runtime.address_cache.get_address().await);
         let tunnel_parameters_generator = MullvadTunnelParametersGenerator {
             tx: internal_event_tx.clone(),
         };
@@ -701,14 +1298,21 @@ where
             tunnel_state_machine::InitialTunnelState {
                 allow_lan: settings.allow_lan,
                 block_when_disconnected: settings.block_when_disconnected,
+                block_ipv6: settings.should_block_ipv6(),
                 dns_servers: dns::addresses_from_options(&settings.tunnel_options.dns_options),
                 allowed_endpoint: initial_api_endpoint,
                 reset_firewall: *target_state != TargetState::Secured,
+                #[cfg(target_os = "linux")]
+                allow_excluded_when_blocked: settings.allow_excluded_when_blocked,
+                #[cfg(target_os = "linux")]
+                linux_routing_options: settings.linux_routing_options,
+                connection_retry_backoff: settings.connection_retry_backoff,
+                route_change_reconnect: settings.route_change_reconnect,
                 #[cfg(windows)]
                 exclude_paths,
             },
             tunnel_parameters_generator,
-            log_dir,
+            log_dir.clone(),
             resource_dir.clone(),
             internal_event_tx.to_specialized_sender(),
             offline_state_tx,
@@ -727,14 +1331,18 @@ where
         Self::forward_offline_state(api_availability.clone(), offline_state_rx).await;
 
         let relay_list_listener = event_listener.clone();
+        let relay_list_event_tx = internal_event_tx.clone();
         let on_relay_list_update = move |relay_list: &RelayList| {
             relay_list_listener.notify_relay_list(relay_list.clone());
+            let _ =
+                relay_list_event_tx.send(InternalDaemonEvent::RelayListUpdated(relay_list.clone()));
         };
 
         let mut relay_list_updater = RelayListUpdater::new(
             relay_selector.clone(),
             api_handle.clone(),
             &cache_dir,
+            settings.relay_list_update_interval,
             on_relay_list_update,
         );
 
@@ -749,36 +1357,106 @@ where
         );
         tokio::spawn(version_updater.run());
 
+        sleep_monitor::spawn(internal_event_tx.clone());
+        wifi_monitor::spawn(internal_event_tx.clone());
+
         // Attempt to download a fresh relay list
         relay_list_updater.update().await;
 
+        let mut telemetry_dirs = vec![cache_dir.clone()];
+        telemetry_dirs.extend(log_dir.clone());
+        let selection_telemetry = selection_telemetry::SelectionTelemetry::new(
+            settings.selection_telemetry_path.clone(),
+            telemetry_dirs,
+        )
+        .await;
+
+        #[cfg(target_os = "linux")]
+        let exclude_pids = split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?;
+        #[cfg(target_os = "linux")]
+        for cgroup in &settings.split_tunnel_cgroups {
+            if let Err(error) = exclude_pids.add_cgroup(cgroup) {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg(&format!(
+                        "Unable to exclude cgroup {}",
+                        cgroup.display()
+                    ))
+                );
+            }
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let mut allowed_hosts =
+            allowed_hosts::AllowedHostsResolver::new(Arc::downgrade(&tunnel_command_tx));
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if !settings.allowed_hosts.is_empty() {
+            allowed_hosts.set_hosts(settings.allowed_hosts.clone());
+        }
+
         let daemon = Daemon {
             tunnel_command_tx,
             tunnel_state: TunnelState::Disconnected,
             target_state,
             state: DaemonExecutionState::Running,
             #[cfg(target_os = "linux")]
-            exclude_pids: split_tunnel::PidManager::new().map_err(Error::InitSplitTunneling)?,
+            exclude_pids,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_hosts,
             rx: internal_event_rx,
             tx: internal_event_tx,
             reconnection_job: None,
+            connect_deadline_job: None,
+            account_expiry_check_job: None,
+            #[cfg(not(target_os = "android"))]
+            pause_job: None,
+            account_creation_job: None,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            firewall_debug_logging_job: None,
             event_listener,
             migration_complete,
+            post_upgrade_complete_notified: false,
+            device_migration_data,
             settings,
+            settings_dir,
+            cache_dir,
+            log_dir,
             account_history,
             device_checker: device::TunnelStateChangeHandler::new(account_manager.clone()),
             account_manager,
             api_runtime,
             api_handle,
+            api_connection_mode,
+            api_bridge,
+            api_allowed_endpoint,
             version_updater_handle,
             relay_selector,
             relay_list_updater,
             last_generated_relays: None,
+            last_generated_custom_endpoint: false,
+            missing_exit_relay: None,
+            wifi_ssids: None,
+            selection_telemetry,
+            metrics: metrics::Metrics::default(),
+            stability: stability::StabilityTracker::new(),
+            connect_times: connect_time::ConnectTimeHistory::default(),
+            recent_device_events: VecDeque::with_capacity(DEVICE_EVENT_HISTORY_LEN),
+            session_label: None,
+            connect_trigger,
+            auto_connect_suppressed,
+            protocol_fallback_active: false,
+            backup_relay_settings_active: false,
+            relay_override: None,
+            pending_relay_outcome: vec![],
+            location_cache: Arc::new(parking_lot::Mutex::new(geoip::LocationCache::new())),
             app_version_info,
             shutdown_tasks: vec![],
             tunnel_state_machine_handle,
             #[cfg(target_os = "windows")]
             volume_update_tx,
+            started_at,
+            start_instant,
+            last_offline_state_refresh: None,
         };
 
         api_availability.unsuspend();
@@ -791,6 +1469,8 @@ where
     pub async fn run(mut self) -> Result<(), Error> {
         if *self.target_state == TargetState::Secured {
             self.connect_tunnel();
+        } else if let Some(reason) = self.auto_connect_suppressed {
+            self.event_listener.notify_auto_connect_suppressed(reason);
         }
 
         while let Some(event) = self.rx.next().await {
@@ -879,8 +1559,27 @@ where
             }
             DeviceEvent(event) => self.handle_device_event(event).await,
             DeviceMigrationEvent(event) => self.handle_device_migration_event(event).await,
+            MigrationComplete => self.handle_migration_complete(),
             #[cfg(windows)]
             ExcludedPathsEvent(update, tx) => self.handle_new_excluded_paths(update, tx).await,
+            SystemResumed => self.handle_system_resumed().await,
+            ResumedTunnelUnreachable => self.handle_resumed_tunnel_unreachable(),
+            RelayListUpdated(relay_list) => self.handle_relay_list_updated(relay_list),
+            WifiNetworksChanged(ssids) => self.handle_wifi_networks_changed(ssids).await,
+            ConnectedLocationResolved(endpoint, location) => {
+                self.handle_connected_location_resolved(endpoint, location)
+            }
+            AccountExpired(account_token) => self.handle_account_expired_event(account_token).await,
+            CheckAccountExpiry => self.handle_check_account_expiry().await,
+            ConnectDeadlineExceeded(started) => self.handle_connect_deadline_exceeded(started),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            FirewallDebugLoggingExpired(enabled_at) => {
+                self.handle_firewall_debug_logging_expired(enabled_at)
+            }
+        }
+
+        if let Some(error) = self.settings.take_write_failure() {
+            self.event_listener.notify_settings_write_failed(error);
         }
     }
 
@@ -894,6 +1593,14 @@ where
             .handle_state_transition(&tunnel_state_transition);
 
         let tunnel_state = match tunnel_state_transition {
+            #[cfg(not(target_os = "android"))]
+            TunnelStateTransition::Disconnected => match &self.pause_job {
+                Some(pause_job) => TunnelState::Paused {
+                    resume_at: pause_job.resume_at,
+                },
+                None => TunnelState::Disconnected,
+            },
+            #[cfg(target_os = "android")]
             TunnelStateTransition::Disconnected => TunnelState::Disconnected,
             TunnelStateTransition::Connecting(endpoint) => TunnelState::Connecting {
                 endpoint,
@@ -916,19 +1623,34 @@ where
             self.unschedule_reconnect();
         }
 
+        self.location_cache.lock().clear();
+
         log::debug!("New tunnel state: {:?}", tunnel_state);
 
-        match tunnel_state {
-            TunnelState::Disconnected => {
-                self.api_handle.availability.reset_inactivity_timer();
-            }
-            _ => {
-                self.api_handle.availability.stop_inactivity_timer();
-            }
+        if tunnel_state.is_connected() {
+            self.selection_telemetry.record_outcome(true).await;
+            self.record_relay_outcome(true);
+        } else if tunnel_state.is_in_error_state() {
+            self.selection_telemetry.record_outcome(false).await;
+            self.record_relay_outcome(false);
+        } else if tunnel_state.is_disconnected() {
+            self.selection_telemetry.discard_selection();
+            self.pending_relay_outcome.clear();
+        }
+
+        if tunnel_state.is_disconnected() {
+            self.clear_relay_override();
+            self.clear_excluded_bridges();
+            self.api_handle.availability.reset_inactivity_timer();
+        } else {
+            self.api_handle.availability.stop_inactivity_timer();
         }
 
+        let mut account_expired = false;
         match tunnel_state {
             TunnelState::Disconnected => self.state.disconnected(),
+            #[cfg(not(target_os = "android"))]
+            TunnelState::Paused { .. } => self.state.disconnected(),
             TunnelState::Error(ref error_state) => {
                 if error_state.is_blocking() {
                     log::info!(
@@ -942,15 +1664,218 @@ where
                     );
                 }
 
-                if let ErrorStateCause::AuthFailed(_) = error_state.cause() {
-                    self.schedule_reconnect(Duration::from_secs(60))
+                if let ErrorStateCause::AuthFailed(reason) = error_state.cause() {
+                    let expired = reason
+                        .as_deref()
+                        .map(|reason| AuthFailed::from(reason).is_expired_account())
+                        .unwrap_or(false);
+                    if expired {
+                        account_expired = true;
+                    } else {
+                        self.schedule_reconnect(Duration::from_secs(60))
+                    }
                 }
             }
             _ => {}
         }
 
+        if tunnel_state.is_disconnected() && self.session_label.take().is_some() {
+            self.event_listener.notify_session_label(None);
+        }
+
+        self.metrics
+            .record_state_transition(&self.tunnel_state, &tunnel_state);
+        self.stability
+            .record_state_transition(&self.tunnel_state, &tunnel_state);
+        self.update_account_expiry_check(&tunnel_state);
+        match &tunnel_state {
+            TunnelState::Connecting { .. } => self.start_connect_deadline(),
+            TunnelState::Connected { .. } => self.reset_connect_deadline(),
+            _ if tunnel_state.is_disconnected() => self.reset_connect_deadline(),
+            _ => (),
+        }
+        match &tunnel_state {
+            TunnelState::Connecting { .. } => self.connect_times.record_connecting(),
+            TunnelState::Connected { endpoint, .. } => {
+                self.connect_times.record_connected(endpoint)
+            }
+            TunnelState::Disconnecting(_) => (),
+            _ => self.connect_times.discard_attempt(),
+        }
+        let old_api_bind_address = self.api_bind_address();
         self.tunnel_state = tunnel_state.clone();
+        let api_bind_address = self.api_bind_address();
+        if api_bind_address != old_api_bind_address {
+            Self::apply_api_bind_interface(&self.api_handle, api_bind_address);
+        }
         self.event_listener.notify_new_state(tunnel_state);
+
+        if let TunnelState::Connected { endpoint, .. } = self.tunnel_state {
+            self.resolve_connected_location(endpoint).await;
+        }
+        if account_expired {
+            self.handle_account_expired().await;
+        }
+    }
+
+    /// Starts or stops the periodic account expiry check. It only runs while the tunnel is
+    /// secured and the expiry policy is to do something other than retrying.
+    fn update_account_expiry_check(&mut self, tunnel_state: &TunnelState) {
+        let should_check = *self.target_state == TargetState::Secured
+            && !tunnel_state.is_disconnected()
+            && self.settings.on_account_expiry != ExpiryPolicy::Retry;
+        if !should_check {
+            if let Some(job) = self.account_expiry_check_job.take() {
+                job.abort();
+            }
+            return;
+        }
+        if self.account_expiry_check_job.is_some() {
+            return;
+        }
+
+        let daemon_tx = self.tx.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            loop {
+                tokio::time::sleep(ACCOUNT_EXPIRY_CHECK_INTERVAL).await;
+                if daemon_tx
+                    .send(InternalDaemonEvent::CheckAccountExpiry)
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        }));
+
+        tokio::spawn(future);
+        self.account_expiry_check_job = Some(abort_handle);
+    }
+
+    async fn handle_check_account_expiry(&mut self) {
+        if *self.target_state != TargetState::Secured {
+            return;
+        }
+        let account_token = match self.account_manager.data().await {
+            Ok(Some(device)) => device.account_token,
+            _ => return,
+        };
+        let account = self.account_manager.account_service.clone();
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            match account.check_expiry(account_token.clone()).await {
+                Ok(expiry) if expiry <= Utc::now() => {
+                    let _ = daemon_tx.send(InternalDaemonEvent::AccountExpired(account_token));
+                }
+                Ok(_) => (),
+                Err(error) => log::debug!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to check the account expiry")
+                ),
+            }
+        });
+    }
+
+    async fn handle_account_expired_event(&mut self, account_token: AccountToken) {
+        match self.account_manager.data().await {
+            Ok(Some(device)) if device.account_token == account_token => {
+                self.handle_account_expired().await
+            }
+            _ => (),
+        }
+    }
+
+    /// Applies the policy for when the account has expired while the tunnel is secured.
+    async fn handle_account_expired(&mut self) {
+        if *self.target_state != TargetState::Secured {
+            return;
+        }
+        match self.settings.on_account_expiry {
+            ExpiryPolicy::Retry => {
+                // Other errors are retried by the tunnel state machine
+                if self.tunnel_state.is_in_error_state() {
+                    log::warn!("The account has expired. Reconnecting in a minute");
+                    self.schedule_reconnect(Duration::from_secs(60));
+                }
+            }
+            ExpiryPolicy::Disconnect => {
+                log::warn!("The account has expired. Disconnecting");
+                self.set_target_state(TargetState::Unsecured).await;
+            }
+            ExpiryPolicy::Block => {
+                if self.tunnel_state.is_in_error_state() {
+                    log::warn!("The account has expired. Not reconnecting");
+                } else {
+                    log::warn!("The account has expired. Blocking all traffic");
+                    self.send_tunnel_command(TunnelCommand::Block(ErrorStateCause::AuthFailed(
+                        Some(EXPIRED_ACCOUNT_REASON.to_owned()),
+                    )));
+                }
+            }
+        }
+    }
+
+    /// Looks up the GeoIP location of the tunnel connected to `endpoint`, so that the connection
+    /// ready event can be sent.
+    async fn resolve_connected_location(&mut self, endpoint: TunnelEndpoint) {
+        let location_future = self.get_cached_geo_location(Some(endpoint)).await;
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let location = location_future.await.ok();
+            let _ = daemon_tx.send(InternalDaemonEvent::ConnectedLocationResolved(
+                endpoint, location,
+            ));
+        });
+    }
+
+    fn handle_connected_location_resolved(
+        &mut self,
+        endpoint: TunnelEndpoint,
+        location: Option<GeoIpLocation>,
+    ) {
+        let relay_location = match &self.tunnel_state {
+            TunnelState::Connected {
+                endpoint: current_endpoint,
+                location,
+            } if *current_endpoint == endpoint => location.clone(),
+            // The tunnel state changed while the location was looked up
+            _ => return,
+        };
+        if location.is_none() && !self.handle_unverified_connection() {
+            return;
+        }
+        match ConnectionReadyInfo::new(endpoint, relay_location, location) {
+            Some(info) => self.event_listener.notify_connection_ready(info),
+            None => log::debug!("Not sending connection ready event since the location is unknown"),
+        }
+    }
+
+    /// Applies the policy for tunnels whose connectivity could not be verified, since the location
+    /// could not be looked up through them. Returns whether the tunnel is kept up.
+    fn handle_unverified_connection(&mut self) -> bool {
+        match self.settings.on_unverified_connection {
+            UnverifiedPolicy::Keep => {
+                log::warn!("Unable to verify the tunnel connectivity. Keeping the tunnel up");
+                true
+            }
+            UnverifiedPolicy::Reconnect => {
+                log::warn!("Unable to verify the tunnel connectivity. Reconnecting");
+                self.reconnect_tunnel();
+                false
+            }
+            #[cfg(not(target_os = "android"))]
+            UnverifiedPolicy::Block => {
+                log::warn!("Unable to verify the tunnel connectivity. Blocking all traffic");
+                self.send_tunnel_command(TunnelCommand::Block(ErrorStateCause::TunnelUnverified));
+                false
+            }
+            // The error state cannot be represented in the Android app
+            #[cfg(target_os = "android")]
+            UnverifiedPolicy::Block => {
+                log::warn!("Unable to verify the tunnel connectivity. Reconnecting");
+                self.reconnect_tunnel();
+                false
+            }
+        }
     }
 
     async fn reset_rpc_sockets_on_tunnel_state_transition(
@@ -981,8 +1906,94 @@ where
             }
         };
 
-        let result = match self.relay_selector.get_relay(retry_attempt) {
+        let selection = if let Some(relay_override) = &self.relay_override {
+            let mut config = self.relay_selector.get_config();
+            config.relay_settings = relay_override.apply(&config.relay_settings);
+            self.relay_selector
+                .get_relay_with_config(&config, retry_attempt)
+        } else if self.should_use_protocol_fallback(retry_attempt) {
+            match self
+                .relay_selector
+                .get_relay_with_tunnel_type(retry_attempt, TunnelType::OpenVpn)
+            {
+                Ok(selection) => {
+                    if !self.protocol_fallback_active {
+                        log::warn!(
+                            "Failed to connect using WireGuard {} times. Using OpenVPN until the next manual connect",
+                            retry_attempt
+                        );
+                        self.protocol_fallback_active = true;
+                        self.event_listener.notify_protocol_fallback(true);
+                    }
+                    Ok(selection)
+                }
+                Err(error) => {
+                    log::warn!(
+                        "{}",
+                        error.display_chain_with_msg(
+                            "Unable to fall back to OpenVPN. Using the preferred tunnel protocol"
+                        )
+                    );
+                    self.relay_selector.get_relay(retry_attempt)
+                }
+            }
+        } else {
+            self.relay_selector.get_relay(retry_attempt)
+        };
+
+        let selection = match selection {
+            Ok(selection) => {
+                self.reset_backup_relay_settings();
+                Ok(selection)
+            }
+            // A relay chosen with `ConnectToRelay` is never replaced by the backup relay settings
+            Err(error) if self.relay_override.is_some() => {
+                self.reset_backup_relay_settings();
+                Err(error)
+            }
+            Err(error) => match self.settings.backup_relay_settings.clone() {
+                Some(backup_relay_settings) => {
+                    let mut config = self.relay_selector.get_config();
+                    config.relay_settings = backup_relay_settings;
+                    match self
+                        .relay_selector
+                        .get_relay_with_config(&config, retry_attempt)
+                    {
+                        Ok(selection) => {
+                            if !self.backup_relay_settings_active {
+                                log::warn!(
+                                    "{}",
+                                    error.display_chain_with_msg(
+                                        "No relay matches the relay settings. Using the backup relay settings"
+                                    )
+                                );
+                                self.backup_relay_settings_active = true;
+                                self.event_listener.notify_backup_relay_settings(true);
+                            }
+                            Ok(selection)
+                        }
+                        Err(backup_error) => {
+                            log::warn!(
+                                "{}",
+                                backup_error.display_chain_with_msg(
+                                    "No relay matches the backup relay settings either"
+                                )
+                            );
+                            Err(error)
+                        }
+                    }
+                }
+                None => Err(error),
+            },
+        };
+
+        let result = match selection {
+            Ok((_, _, None)) if self.obfuscation_required() => {
+                log::error!("The selected relay does not support the selected obfuscation");
+                Err(ParameterGenerationError::NoMatchingObfuscatorRelay)
+            }
             Ok((SelectedRelay::Custom(custom_relay), _bridge, _obfsucator)) => {
+                self.last_generated_custom_endpoint = true;
                 custom_relay
                     // TODO(emilsp): generate proxy settings for custom tunnels
                     .to_tunnel_parameters(self.settings.tunnel_options.clone(), None)
@@ -992,6 +2003,19 @@ where
                     })
             }
             Ok((SelectedRelay::Normal(constraints), bridge, obfuscator)) => {
+                self.selection_telemetry
+                    .record_selection(
+                        &constraints.exit_relay,
+                        constraints.entry_relay.as_ref(),
+                        &constraints.endpoint,
+                    )
+                    .await;
+                // The previously selected relays were never connected to
+                self.record_relay_outcome(false);
+                self.pending_relay_outcome = std::iter::once(&constraints.exit_relay)
+                    .chain(constraints.entry_relay.as_ref())
+                    .cloned()
+                    .collect();
                 let result = self
                     .create_tunnel_parameters(
                         &constraints.exit_relay,
@@ -1001,7 +2025,12 @@ where
                         obfuscator,
                         data,
                     )
-                    .await;
+                    .await
+                    .map(|(parameters, relays)| {
+                        self.last_generated_custom_endpoint = false;
+                        self.set_last_generated_relays(relays);
+                        parameters
+                    });
                 result.map_err(|error| match error {
                     Error::NoKeyAvailable => ParameterGenerationError::NoWireguardKey,
                     Error::NoBridgeAvailable => ParameterGenerationError::NoMatchingBridgeRelay,
@@ -1017,6 +2046,12 @@ where
             Err(mullvad_relay_selector::Error::NoBridge) => {
                 Err(ParameterGenerationError::NoMatchingBridgeRelay)
             }
+            Err(mullvad_relay_selector::Error::NoPortForwardingRelay) => {
+                Err(ParameterGenerationError::NoMatchingPortForwardingRelay)
+            }
+            Err(mullvad_relay_selector::Error::NoObfuscator) => {
+                Err(ParameterGenerationError::NoMatchingObfuscatorRelay)
+            }
             Err(_error) => Err(ParameterGenerationError::NoMatchingRelay),
         };
         if tunnel_parameters_tx.send(result).is_err() {
@@ -1024,6 +2059,58 @@ where
         }
     }
 
+    /// Returns whether OpenVPN should be used instead of WireGuard for the given connection
+    /// attempt, either because the fallback is already active or because the threshold has been
+    /// reached. This only applies if the tunnel protocol is constrained to WireGuard.
+    fn should_use_protocol_fallback(&self, retry_attempt: u32) -> bool {
+        let threshold = match self.settings.protocol_fallback_threshold {
+            Some(threshold) => threshold,
+            None => return false,
+        };
+        let wireguard_only = match &self.settings.relay_settings {
+            RelaySettings::Normal(constraints) => {
+                constraints.tunnel_protocol == Constraint::Only(TunnelType::Wireguard)
+            }
+            RelaySettings::CustomTunnelEndpoint(_) => false,
+        };
+        wireguard_only && (self.protocol_fallback_active || retry_attempt >= threshold)
+    }
+
+    /// Stops using the OpenVPN fallback, so that the preferred tunnel protocol is used again.
+    fn reset_protocol_fallback(&mut self) {
+        if mem::take(&mut self.protocol_fallback_active) {
+            log::info!("Reverting to the preferred tunnel protocol");
+            self.event_listener.notify_protocol_fallback(false);
+        }
+    }
+
+    /// Stops using the backup relay settings, so that the relay settings are used again.
+    fn reset_backup_relay_settings(&mut self) {
+        if mem::take(&mut self.backup_relay_settings_active) {
+            log::info!("Reverting to the relay settings");
+            self.event_listener.notify_backup_relay_settings(false);
+        }
+    }
+
+    /// Stops using the relay constraint override, so that the saved relay settings are used again.
+    fn clear_relay_override(&mut self) {
+        if self.relay_override.take().is_some() {
+            log::debug!("Reverting to the saved relay settings");
+        }
+    }
+
+    /// Allows all bridges skipped with [DaemonCommand::NextBridge] to be selected again.
+    fn clear_excluded_bridges(&mut self) {
+        self.relay_selector.clear_excluded_bridges();
+    }
+
+    fn record_relay_outcome(&mut self, success: bool) {
+        for relay in self.pending_relay_outcome.drain(..) {
+            self.relay_selector
+                .record_connection_result(&relay, success);
+        }
+    }
+
     #[cfg_attr(target_os = "android", allow(unused_variables))]
     async fn create_tunnel_parameters(
         &mut self,
@@ -1033,7 +2120,7 @@ where
         bridge: Option<SelectedBridge>,
         obfuscator: Option<SelectedObfuscator>,
         device: PrivateAccountAndDevice,
-    ) -> Result<TunnelParameters, Error> {
+    ) -> Result<(TunnelParameters, LastSelectedRelays), Error> {
         let tunnel_options = self.settings.tunnel_options.clone();
         match endpoint {
             #[cfg(not(target_os = "android"))]
@@ -1046,12 +2133,12 @@ where
                     None => (None, None),
                 };
 
-                self.last_generated_relays = Some(LastSelectedRelays::OpenVpn {
+                let relays = LastSelectedRelays::OpenVpn {
                     relay: relay.clone(),
                     bridge: bridge_relay,
-                });
+                };
 
-                Ok(openvpn::TunnelParameters {
+                let parameters = openvpn::TunnelParameters {
                     config: openvpn::ConnectionConfig::new(
                         endpoint,
                         device.account_token,
@@ -1060,8 +2147,8 @@ where
                     options: tunnel_options.openvpn,
                     generic_options: tunnel_options.generic,
                     proxy: bridge_settings,
-                }
-                .into())
+                };
+                Ok((parameters.into(), relays))
             }
             #[cfg(target_os = "android")]
             MullvadEndpoint::OpenVpn(endpoint) => {
@@ -1081,16 +2168,19 @@ where
                     None => (None, None),
                 };
 
-                self.last_generated_relays = Some(LastSelectedRelays::WireGuard {
+                let relays = LastSelectedRelays::WireGuard {
                     wg_entry: entry_relay.clone(),
                     wg_exit: relay.clone(),
                     obfuscator: obfuscator_relay,
-                });
+                };
+
+                let mut peer = endpoint.peer;
+                peer.persistent_keepalive = tunnel_options.wireguard.options.wireguard_keepalive;
 
-                Ok(wireguard::TunnelParameters {
+                let parameters = wireguard::TunnelParameters {
                     connection: wireguard::ConnectionConfig {
                         tunnel,
-                        peer: endpoint.peer,
+                        peer,
                         exit_peer: endpoint.exit_peer,
                         ipv4_gateway: endpoint.ipv4_gateway,
                         ipv6_gateway: Some(endpoint.ipv6_gateway),
@@ -1098,12 +2188,18 @@ where
                     options: tunnel_options.wireguard.options,
                     generic_options: tunnel_options.generic,
                     obfuscation: obfuscator_config,
-                }
-                .into())
+                };
+                Ok((parameters.into(), relays))
             }
         }
     }
 
+    /// Returns whether tunnels must not be connected without obfuscation, i.e. whether an
+    /// obfuscation protocol is selected explicitly.
+    fn obfuscation_required(&self) -> bool {
+        self.settings.obfuscation_settings.selected_obfuscation == SelectedObfuscation::Udp2Tcp
+    }
+
     fn schedule_reconnect(&mut self, delay: Duration) {
         self.unschedule_reconnect();
 
@@ -1127,6 +2223,102 @@ where
         }
     }
 
+    /// Starts the connect deadline, unless it is already running or disabled. All traffic is
+    /// blocked if the tunnel has not connected when it passes.
+    fn start_connect_deadline(&mut self) {
+        let deadline = match self.settings.connect_deadline_to_block {
+            Some(deadline) => deadline,
+            None => return,
+        };
+        if self.connect_deadline_job.is_some() {
+            return;
+        }
+
+        let started = Instant::now();
+        let daemon_tx = self.tx.clone();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::sleep(deadline).await;
+            let _ = daemon_tx.send(InternalDaemonEvent::ConnectDeadlineExceeded(started));
+        }));
+
+        tokio::spawn(future);
+        self.connect_deadline_job = Some((started, abort_handle));
+    }
+
+    fn reset_connect_deadline(&mut self) {
+        if let Some((_, job)) = self.connect_deadline_job.take() {
+            job.abort();
+        }
+    }
+
+    fn handle_connect_deadline_exceeded(&mut self, started: Instant) {
+        match &self.connect_deadline_job {
+            Some((current, _)) if *current == started => self.connect_deadline_job = None,
+            // The deadline was reset after the event was sent
+            _ => return,
+        }
+        if *self.target_state != TargetState::Secured
+            || self.tunnel_state.is_connected()
+            || self.tunnel_state.is_in_error_state()
+        {
+            return;
+        }
+
+        log::warn!(
+            "The tunnel did not connect within {} seconds. Blocking all traffic",
+            started.elapsed().as_secs()
+        );
+        #[cfg(not(target_os = "android"))]
+        let cause = ErrorStateCause::ConnectDeadlineExceeded;
+        // The error state cannot represent the new cause in the Android app
+        #[cfg(target_os = "android")]
+        let cause = ErrorStateCause::StartTunnelError;
+        self.send_tunnel_command(TunnelCommand::Block(cause));
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn schedule_resume(&mut self, delay: Duration) {
+        if let Some(job) = self.pause_job.take() {
+            job.abort_handle.abort();
+        }
+
+        let daemon_command_tx = self.tx.to_specialized_sender();
+        let (future, abort_handle) = abortable(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            log::debug!("Resuming paused tunnel");
+            let (tx, rx) = oneshot::channel();
+            let _ = daemon_command_tx.send(DaemonCommand::ResumeTunnel(tx));
+            // suppress "unable to send" warning:
+            let _ = rx.await;
+        }));
+
+        tokio::spawn(future);
+        let resume_at = Utc::now() + chrono::Duration::milliseconds(delay.as_millis() as i64);
+        self.pause_job = Some(PauseJob {
+            abort_handle,
+            resume_at,
+        });
+
+        // Extending an ongoing pause does not cause a tunnel state transition
+        if let TunnelState::Paused { .. } = self.tunnel_state {
+            self.tunnel_state = TunnelState::Paused { resume_at };
+            self.event_listener
+                .notify_new_state(self.tunnel_state.clone());
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    fn cancel_pause(&mut self) {
+        if let Some(job) = self.pause_job.take() {
+            job.abort_handle.abort();
+            if let TunnelState::Paused { .. } = self.tunnel_state {
+                self.tunnel_state = TunnelState::Disconnected;
+                self.event_listener
+                    .notify_new_state(TunnelState::Disconnected);
+            }
+        }
+    }
+
     async fn handle_command(&mut self, command: DaemonCommand) {
         use self::DaemonCommand::*;
         if !self.state.is_running() {
@@ -1141,23 +2333,59 @@ where
         match command {
             SetTargetState(tx, state) => self.on_set_target_state(tx, state).await,
             Reconnect(tx) => self.on_reconnect(tx),
+            #[cfg(not(target_os = "android"))]
+            NextBridge(tx) => self.on_next_bridge(tx),
+            #[cfg(not(target_os = "android"))]
+            PauseTunnel(tx, duration) => self.on_pause_tunnel(tx, duration).await,
+            #[cfg(not(target_os = "android"))]
+            ResumeTunnel(tx) => self.on_resume_tunnel(tx).await,
             GetState(tx) => self.on_get_state(tx),
+            GetAutoConnectSuppressed(tx) => self.on_get_auto_connect_suppressed(tx),
             GetCurrentLocation(tx) => self.on_get_current_location(tx).await,
+            GetCachedLocation(tx) => self.on_get_cached_location(tx),
+            ClearLocationCache => self.location_cache.lock().clear(),
+            GetActiveTunnelInfo(tx) => self.on_get_active_tunnel_info(tx),
+            DetectCaptivePortal(tx) => self.on_detect_captive_portal(tx).await,
+            GetBlockedCapabilities(tx) => self.on_get_blocked_capabilities(tx),
+            GetAllowedEndpoint(tx) => self.on_get_allowed_endpoint(tx),
+            CanUseMultihop(tx) => self.on_can_use_multihop(tx),
+            ResolveTunnelTypeConstraint(tx) => self.on_resolve_tunnel_type_constraint(tx),
+            GetConnectTrigger(tx) => {
+                Self::oneshot_send(tx, self.connect_trigger, "get_connect_trigger response")
+            }
+            GetBootLeakProtectionStatus(tx) => self.on_get_boot_leak_protection_status(tx),
             CreateNewAccount(tx) => self.on_create_new_account(tx).await,
+            CancelAccountCreation(tx) => self.on_cancel_account_creation(tx),
             GetAccountData(tx, account_token) => self.on_get_account_data(tx, account_token).await,
             GetWwwAuthToken(tx) => self.on_get_www_auth_token(tx).await,
             SubmitVoucher(tx, voucher) => self.on_submit_voucher(tx, voucher).await,
             GetRelayLocations(tx) => self.on_get_relay_locations(tx),
+            GetRelayListSignatureInfo(tx) => self.on_get_relay_list_signature_info(tx),
+            GetRelayCountsByLocation(tx) => self.on_get_relay_counts_by_location(tx),
+            GetRelayCountsByProtocol(tx) => self.on_get_relay_counts_by_protocol(tx),
+            GetProviders(tx) => self.on_get_providers(tx),
+            PreviewRelaySettings(tx, update) => self.on_preview_relay_settings(tx, update),
+            ConnectToRelay(tx, relay_override) => {
+                self.on_connect_to_relay(tx, relay_override).await
+            }
             UpdateRelayLocations => self.on_update_relay_locations().await,
+            SetEphemeralRelayList(tx, relay_list) => {
+                self.on_set_ephemeral_relay_list(tx, relay_list)
+            }
+            ClearEphemeralRelayList(tx) => self.on_clear_ephemeral_relay_list(tx),
             LoginAccount(tx, account_token) => self.on_login_account(tx, account_token),
             LogoutAccount(tx) => self.on_logout_account(tx),
+            SwitchAccount(tx, account_token) => self.on_switch_account(tx, account_token),
             GetDevice(tx) => self.on_get_device(tx).await,
             UpdateDevice(tx) => self.on_update_device(tx).await,
+            GetDevicePorts(tx) => self.on_get_device_ports(tx).await,
             ListDevices(tx, account_token) => self.on_list_devices(tx, account_token).await,
             RemoveDevice(tx, account_token, device_id) => {
                 self.on_remove_device(tx, account_token, device_id).await
             }
             GetAccountHistory(tx) => self.on_get_account_history(tx),
+            GetAccountHistoryList(tx) => self.on_get_account_history_list(tx),
+            SetAccountHistoryLimit(tx, limit) => self.on_set_account_history_limit(tx, limit).await,
             ClearAccountHistory(tx) => self.on_clear_account_history(tx).await,
             UpdateRelaySettings(tx, update) => self.on_update_relay_settings(tx, update).await,
             SetAllowLan(tx, allow_lan) => self.on_set_allow_lan(tx, allow_lan).await,
@@ -1166,7 +2394,68 @@ where
                 self.on_set_block_when_disconnected(tx, block_when_disconnected)
                     .await
             }
+            SetBlockIpv6(tx, block_ipv6) => self.on_set_block_ipv6(tx, block_ipv6).await,
+            #[cfg(target_os = "linux")]
+            SetAllowExcludedWhenBlocked(tx, allow_excluded) => {
+                self.on_set_allow_excluded_when_blocked(tx, allow_excluded)
+                    .await
+            }
+            #[cfg(target_os = "linux")]
+            SetLinuxRoutingOptions(tx, options) => {
+                self.on_set_linux_routing_options(tx, options).await
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            SetAllowedHosts(tx, hosts) => self.on_set_allowed_hosts(tx, hosts).await,
             SetAutoConnect(tx, auto_connect) => self.on_set_auto_connect(tx, auto_connect).await,
+            SetSelectionTelemetryPath(tx, path) => {
+                self.on_set_selection_telemetry_path(tx, path).await
+            }
+            SetDeprioritizeFailingRelays(tx, deprioritize) => {
+                self.on_set_deprioritize_failing_relays(tx, deprioritize)
+                    .await
+            }
+            SetRetrySameRelay(tx, retry_same_relay) => {
+                self.on_set_retry_same_relay(tx, retry_same_relay).await
+            }
+            SetOpenVpnEndpointPreference(tx, preference) => {
+                self.on_set_openvpn_endpoint_preference(tx, preference)
+                    .await
+            }
+            SetOnDemandRules(tx, rules) => self.on_set_on_demand_rules(tx, rules).await,
+            SetReconnectPolicy(tx, trigger, policy) => {
+                self.on_set_reconnect_policy(tx, trigger, policy).await
+            }
+            SetOnUnverifiedConnection(tx, policy) => {
+                self.on_set_on_unverified_connection(tx, policy).await
+            }
+            SetOnAccountExpiry(tx, policy) => self.on_set_on_account_expiry(tx, policy).await,
+            SetConnectDeadlineToBlock(tx, deadline) => {
+                self.on_set_connect_deadline_to_block(tx, deadline).await
+            }
+            SetApiBridge(tx, hostname) => self.on_set_api_bridge(tx, hostname).await,
+            SetBackupRelaySettings(tx, relay_settings) => {
+                self.on_set_backup_relay_settings(tx, relay_settings).await
+            }
+            SetApiClientTag(tx, tag) => self.on_set_api_client_tag(tx, tag).await,
+            SetApiConcurrencyLimit(tx, limit) => self.on_set_api_concurrency_limit(tx, limit).await,
+            SetApiBindInterface(tx, address) => self.on_set_api_bind_interface(tx, address).await,
+            GetRelayListUpdateInterval(tx) => self.on_get_relay_list_update_interval(tx),
+            SetRelayListUpdateInterval(tx, interval) => {
+                self.on_set_relay_list_update_interval(tx, interval).await
+            }
+            SetReconnectOnRelayRemoval(tx, reconnect) => {
+                self.on_set_reconnect_on_relay_removal(tx, reconnect).await
+            }
+            SetConnectionRetryBackoff(tx, backoff) => {
+                self.on_set_connection_retry_backoff(tx, backoff).await
+            }
+            SetRouteChangeReconnect(tx, route_change_reconnect) => {
+                self.on_set_route_change_reconnect(tx, route_change_reconnect)
+                    .await
+            }
+            SetProtocolFallbackThreshold(tx, threshold) => {
+                self.on_set_protocol_fallback_threshold(tx, threshold).await
+            }
             SetOpenVpnMssfix(tx, mssfix_arg) => self.on_set_openvpn_mssfix(tx, mssfix_arg).await,
             SetBridgeSettings(tx, bridge_settings) => {
                 self.on_set_bridge_settings(tx, bridge_settings).await
@@ -1174,16 +2463,54 @@ where
             SetBridgeState(tx, bridge_state) => self.on_set_bridge_state(tx, bridge_state).await,
             SetEnableIpv6(tx, enable_ipv6) => self.on_set_enable_ipv6(tx, enable_ipv6).await,
             SetDnsOptions(tx, dns_servers) => self.on_set_dns_options(tx, dns_servers).await,
+            TestDnsServers(tx, servers) => self.on_test_dns_servers(tx, servers),
+            RunLeakTest(tx) => self.on_run_leak_test(tx).await,
             SetWireguardMtu(tx, mtu) => self.on_set_wireguard_mtu(tx, mtu).await,
+            SetWireguardKeepalive(tx, interval) => {
+                self.on_set_wireguard_keepalive(tx, interval).await
+            }
+            SetPortForwarding(tx, port_forwarding) => {
+                self.on_set_port_forwarding(tx, port_forwarding).await
+            }
             SetWireguardRotationInterval(tx, interval) => {
                 self.on_set_wireguard_rotation_interval(tx, interval).await
             }
             GetSettings(tx) => self.on_get_settings(tx),
+            GetNonDefaultSettings(tx) => self.on_get_non_default_settings(tx),
+            ValidateSettings(tx) => self.on_validate_settings(tx),
+            SetKeyRotationEnabled(tx, enabled) => self.on_set_key_rotation_enabled(tx, enabled),
             RotateWireguardKey(tx) => self.on_rotate_wireguard_key(tx).await,
+            IsRotatingKey(tx) => self.on_is_rotating_key(tx),
             GetWireguardKey(tx) => self.on_get_wireguard_key(tx).await,
+            GetWireguardKeyFingerprint(tx) => self.on_get_wireguard_key_fingerprint(tx).await,
             GetVersionInfo(tx) => self.on_get_version_info(tx).await,
             IsPerformingPostUpgrade(tx) => self.on_is_performing_post_upgrade(tx).await,
+            GetMigrationHistory(tx) => self.on_get_migration_history(tx),
+            RetryDeviceMigration(tx) => self.on_retry_device_migration(tx),
+            GetRecentTunnelLog(tx, count) => self.on_get_recent_tunnel_log(tx, count),
+            GetRecentDeviceEvents(tx, count) => self.on_get_recent_device_events(tx, count),
+            SetSessionLabel(tx, label) => self.on_set_session_label(tx, label),
+            GetSessionLabel(tx) => self.on_get_session_label(tx),
+            GetApiConnectionMode(tx) => self.on_get_api_connection_mode(tx),
+            ResetApiConnection(tx) => self.on_reset_api_connection(tx),
+            GetSupportedObfuscators(tx) => self.on_get_supported_obfuscators(tx),
+            CurrentRelaySupportsObfuscation(tx) => self.on_current_relay_supports_obfuscation(tx),
+            GetMetrics(tx) => self.on_get_metrics(tx),
+            GetStabilityCounters(tx) => self.on_get_stability_counters(tx),
+            ResetStabilityCounters(tx) => self.on_reset_stability_counters(tx),
+            GetRuntimeStats(tx) => self.on_get_runtime_stats(tx),
+            CreateDiagnosticsBundle(tx) => self.on_create_diagnostics_bundle(tx),
+            GetActiveDnsServers(tx) => self.on_get_active_dns_servers(tx),
+            GetConnectionQuality(tx) => self.on_get_connection_quality(tx),
+            EstimateConnectTime(tx) => self.on_estimate_connect_time(tx),
+            RefreshOfflineState(tx) => self.on_refresh_offline_state(tx),
+            SetFirewallDebugLogging(tx, enabled) => self.on_set_firewall_debug_logging(tx, enabled),
+            GetFirewallBackendInfo(tx) => self.on_get_firewall_backend_info(tx),
+            GetActiveFirewallExceptions(tx) => self.on_get_active_firewall_exceptions(tx),
+            GetSelectorConfig(tx) => self.on_get_selector_config(tx),
             GetCurrentVersion(tx) => self.on_get_current_version(tx),
+            GetSettingsVersion(tx) => self.on_get_settings_version(tx),
+            GetDaemonInfo(tx) => self.on_get_daemon_info(tx),
             #[cfg(not(target_os = "android"))]
             FactoryReset(tx) => self.on_factory_reset(tx).await,
             #[cfg(target_os = "linux")]
@@ -1194,6 +2521,12 @@ where
             RemoveSplitTunnelProcess(tx, pid) => self.on_remove_split_tunnel_process(tx, pid),
             #[cfg(target_os = "linux")]
             ClearSplitTunnelProcesses(tx) => self.on_clear_split_tunnel_processes(tx),
+            #[cfg(target_os = "linux")]
+            AddSplitTunnelCgroup(tx, cgroup) => self.on_add_split_tunnel_cgroup(tx, cgroup).await,
+            #[cfg(target_os = "linux")]
+            RemoveSplitTunnelCgroup(tx, cgroup) => {
+                self.on_remove_split_tunnel_cgroup(tx, cgroup).await
+            }
             #[cfg(windows)]
             AddSplitTunnelApp(tx, path) => self.on_add_split_tunnel_app(tx, path).await,
             #[cfg(windows)]
@@ -1202,6 +2535,11 @@ where
             ClearSplitTunnelApps(tx) => self.on_clear_split_tunnel_apps(tx).await,
             #[cfg(windows)]
             SetSplitTunnelState(tx, enabled) => self.on_set_split_tunnel_state(tx, enabled).await,
+            #[cfg(windows)]
+            SetSplitTunnelKeepExclusionsOnReconnect(tx, keep_exclusions) => {
+                self.on_set_split_tunnel_keep_exclusions_on_reconnect(tx, keep_exclusions)
+                    .await
+            }
             #[cfg(target_os = "windows")]
             UseWireGuardNt(tx, state) => self.on_use_wireguard_nt(tx, state).await,
             #[cfg(target_os = "windows")]
@@ -1224,7 +2562,12 @@ where
     async fn handle_device_event(&mut self, event: PrivateDeviceEvent) {
         match &event {
             PrivateDeviceEvent::Login(device) => {
-                if let Err(error) = self.account_history.set(device.account_token.clone()).await {
+                self.auto_connect_suppressed = None;
+                if let Err(error) = self
+                    .account_history
+                    .push(device.account_token.clone())
+                    .await
+                {
                     log::error!(
                         "{}",
                         error.display_chain_with_msg("Failed to update account history")
@@ -1247,14 +2590,19 @@ where
                 }
             }
             PrivateDeviceEvent::RotatedKey(_) => {
+                self.metrics.record_key_rotation();
                 if let Some(TunnelType::Wireguard) = self.get_target_tunnel_type() {
                     self.schedule_reconnect(WG_RECONNECT_DELAY);
                 }
             }
             _ => (),
         }
-        self.event_listener
-            .notify_device_event(DeviceEvent::from(event));
+        let event = DeviceEvent::from(event);
+        if self.recent_device_events.len() == DEVICE_EVENT_HISTORY_LEN {
+            self.recent_device_events.pop_front();
+        }
+        self.recent_device_events.push_back(event.clone());
+        self.event_listener.notify_device_event(event);
     }
 
     async fn handle_device_migration_event(
@@ -1282,6 +2630,13 @@ where
         });
     }
 
+    fn handle_migration_complete(&mut self) {
+        if !mem::replace(&mut self.post_upgrade_complete_notified, true) {
+            log::debug!("Post-upgrade migration complete");
+            self.event_listener.notify_post_upgrade_complete();
+        }
+    }
+
     #[cfg(windows)]
     async fn handle_new_excluded_paths(
         &mut self,
@@ -1308,12 +2663,129 @@ where
         }
     }
 
+    /// Checks that the tunnel still works after the machine has been asleep. The tunnel state
+    /// machine keeps blocking traffic while it reconnects, so nothing leaks in the meantime.
+    async fn handle_system_resumed(&mut self) {
+        if *self.target_state != TargetState::Secured {
+            return;
+        }
+        match self.tunnel_state {
+            TunnelState::Connected { .. } => {
+                log::debug!("Verifying tunnel connectivity after resuming from sleep");
+                let location_future = self.get_geo_location().await;
+                let daemon_tx = self.tx.clone();
+                tokio::spawn(async move {
+                    let is_reachable =
+                        tokio::time::timeout(RESUME_CONNECTIVITY_TIMEOUT, location_future)
+                            .await
+                            .map(|result| result.is_ok())
+                            .unwrap_or(false);
+                    if !is_reachable {
+                        let _ = daemon_tx.send(InternalDaemonEvent::ResumedTunnelUnreachable);
+                    }
+                });
+            }
+            TunnelState::Error(_) => {
+                log::info!("Reconnecting after resuming from sleep");
+                self.connect_tunnel();
+            }
+            _ => (),
+        }
+    }
+
+    fn handle_resumed_tunnel_unreachable(&mut self) {
+        if let TunnelState::Connected { .. } = self.tunnel_state {
+            log::info!("Tunnel is unreachable after resuming from sleep. Reconnecting");
+            self.reconnect_tunnel();
+        }
+    }
+
+    /// Reconnects if the exit relay in use has been missing from, or inactive in, two consecutive
+    /// relay lists. A single missing update is tolerated in case the list was incomplete.
+    fn handle_relay_list_updated(&mut self, relay_list: RelayList) {
+        let watch_exit = self.settings.reconnect_on_relay_removal
+            && self.tunnel_state.is_connected()
+            && matches!(self.settings.get_relay_settings(), RelaySettings::Normal(_));
+        let exit_hostname = match &self.last_generated_relays {
+            Some(LastSelectedRelays::WireGuard { wg_exit, .. }) if watch_exit => {
+                wg_exit.hostname.clone()
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(LastSelectedRelays::OpenVpn { relay, .. }) if watch_exit => relay.hostname.clone(),
+            _ => {
+                self.missing_exit_relay = None;
+                return;
+            }
+        };
+
+        let is_listed = relay_list
+            .countries
+            .iter()
+            .flat_map(|country| &country.cities)
+            .flat_map(|city| &city.relays)
+            .any(|relay| relay.hostname == exit_hostname && relay.active);
+        if is_listed {
+            self.missing_exit_relay = None;
+        } else if self.missing_exit_relay.as_ref() == Some(&exit_hostname) {
+            log::info!(
+                "Exit relay {} was removed from the relay list. Reconnecting",
+                exit_hostname
+            );
+            self.missing_exit_relay = None;
+            self.reconnect_tunnel();
+        } else {
+            log::debug!(
+                "Exit relay {} is missing from the relay list",
+                exit_hostname
+            );
+            self.missing_exit_relay = Some(exit_hostname);
+        }
+    }
+
+    async fn handle_wifi_networks_changed(&mut self, ssids: Vec<String>) {
+        // Only a change of network overrides a manually set target state
+        if self.wifi_ssids.as_ref() == Some(&ssids) {
+            return;
+        }
+        log::debug!("Connected Wi-Fi networks: {:?}", ssids);
+        self.wifi_ssids = Some(ssids);
+        self.apply_on_demand_rules().await;
+    }
+
+    async fn apply_on_demand_rules(&mut self) {
+        let target_state = match &self.wifi_ssids {
+            Some(ssids) => self.settings.on_demand.target_state(ssids),
+            None => None,
+        };
+        if let Some(target_state) = target_state {
+            if self.state.is_running() && target_state != *self.target_state {
+                log::info!(
+                    "Setting target state to {} due to on-demand rule",
+                    target_state
+                );
+                #[cfg(not(target_os = "android"))]
+                self.cancel_pause();
+                self.set_target_state(target_state).await;
+                if target_state == TargetState::Secured {
+                    self.connect_trigger = ConnectTrigger::OnDemandRule;
+                }
+            }
+        }
+    }
+
     async fn on_set_target_state(
         &mut self,
         tx: oneshot::Sender<bool>,
         new_target_state: TargetState,
     ) {
         if self.state.is_running() {
+            #[cfg(not(target_os = "android"))]
+            self.cancel_pause();
+            if new_target_state == TargetState::Secured {
+                self.reset_protocol_fallback();
+            }
+            self.clear_relay_override();
+            self.clear_excluded_bridges();
             let state_change_initated = self.set_target_state(new_target_state).await;
             Self::oneshot_send(tx, state_change_initated, "state change initiated");
         } else {
@@ -1323,6 +2795,12 @@ where
 
     fn on_reconnect(&mut self, tx: oneshot::Sender<bool>) {
         if *self.target_state == TargetState::Secured || self.tunnel_state.is_in_error_state() {
+            self.reset_protocol_fallback();
+            self.clear_relay_override();
+            self.clear_excluded_bridges();
+            self.reset_connect_deadline();
+            #[cfg(windows)]
+            self.reapply_split_tunnel_apps();
             self.connect_tunnel();
             Self::oneshot_send(tx, true, "reconnect issued");
         } else {
@@ -1331,719 +2809,2387 @@ where
         }
     }
 
+    #[cfg(not(target_os = "android"))]
+    fn on_next_bridge(&mut self, tx: ResponseTx<(), Error>) {
+        let is_connecting_or_connected = matches!(
+            self.tunnel_state,
+            TunnelState::Connecting { .. } | TunnelState::Connected { .. }
+        );
+        let current_bridge = match &self.last_generated_relays {
+            Some(LastSelectedRelays::OpenVpn {
+                bridge: Some(bridge),
+                ..
+            }) if is_connecting_or_connected => bridge.hostname.clone(),
+            _ => {
+                Self::oneshot_send(tx, Err(Error::NotUsingBridge), "next_bridge response");
+                return;
+            }
+        };
+
+        if !self.relay_selector.exclude_bridge(&current_bridge) {
+            Self::oneshot_send(tx, Err(Error::NoOtherBridge), "next_bridge response");
+            return;
+        }
+
+        log::info!("Reconnecting using a bridge other than {}", current_bridge);
+        self.connect_tunnel();
+        Self::oneshot_send(tx, Ok(()), "next_bridge response");
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn on_pause_tunnel(&mut self, tx: ResponseTx<(), Error>, duration: Duration) {
+        if duration.is_zero() || duration > MAX_PAUSE_DURATION {
+            Self::oneshot_send(
+                tx,
+                Err(Error::InvalidPauseDuration),
+                "pause_tunnel response",
+            );
+            return;
+        }
+        if *self.target_state != TargetState::Secured && self.pause_job.is_none() {
+            Self::oneshot_send(tx, Err(Error::TunnelNotSecured), "pause_tunnel response");
+            return;
+        }
+
+        log::info!("Pausing the tunnel for {} seconds", duration.as_secs());
+        self.schedule_resume(duration);
+        self.set_target_state(TargetState::Unsecured).await;
+        Self::oneshot_send(tx, Ok(()), "pause_tunnel response");
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn on_resume_tunnel(&mut self, tx: ResponseTx<(), Error>) {
+        match self.pause_job.take() {
+            Some(job) => {
+                log::info!("Resuming paused tunnel");
+                self.set_target_state(TargetState::Secured).await;
+                Self::oneshot_send(tx, Ok(()), "resume_tunnel response");
+                // The timer may be waiting for this response, so it must be aborted last
+                job.abort_handle.abort();
+            }
+            None => {
+                Self::oneshot_send(tx, Err(Error::TunnelNotPaused), "resume_tunnel response");
+            }
+        }
+    }
+
     fn on_get_state(&self, tx: oneshot::Sender<TunnelState>) {
         Self::oneshot_send(tx, self.tunnel_state.clone(), "current state");
     }
 
+    fn on_get_auto_connect_suppressed(
+        &self,
+        tx: oneshot::Sender<Option<AutoConnectSuppressedReason>>,
+    ) {
+        Self::oneshot_send(
+            tx,
+            self.auto_connect_suppressed,
+            "get_auto_connect_suppressed response",
+        );
+    }
+
     async fn on_is_performing_post_upgrade(&self, tx: oneshot::Sender<bool>) {
         let performing_post_upgrade = !self.migration_complete.is_complete();
         Self::oneshot_send(tx, performing_post_upgrade, "performing post upgrade");
     }
 
-    async fn on_get_current_location(&mut self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
-        use self::TunnelState::*;
+    fn on_get_migration_history(&self, tx: ResponseTx<Vec<MigrationRecord>, Error>) {
+        let settings_dir = self.settings_dir.clone();
+        tokio::spawn(async move {
+            let result = migrations::migration_history(&settings_dir)
+                .await
+                .map_err(Error::MigrationHistoryError);
+            Self::oneshot_send(tx, result, "migration history");
+        });
+    }
 
-        match &self.tunnel_state {
-            Disconnected => {
-                let location = self.get_geo_location().await;
-                tokio::spawn(async {
-                    Self::oneshot_send(tx, location.await.ok(), "current location");
-                });
-            }
-            Connecting { location, .. } => {
-                Self::oneshot_send(tx, location.clone(), "current location")
+    fn on_retry_device_migration(&self, tx: ResponseTx<bool, Error>) {
+        let migration_data = match &self.device_migration_data {
+            Some(migration_data) if self.migration_complete.is_complete() => migration_data.clone(),
+            Some(_) => {
+                log::info!("Device migration is already in progress");
+                Self::oneshot_send(tx, Ok(false), "retry_device_migration response");
+                return;
             }
-            Disconnecting(..) => {
-                Self::oneshot_send(tx, self.build_location_from_relay(), "current location")
+            None => {
+                log::info!("There are no old settings to migrate a device from");
+                Self::oneshot_send(tx, Ok(false), "retry_device_migration response");
+                return;
             }
-            Connected { location, .. } => {
-                let relay_location = location.clone();
-                let location_future = self.get_geo_location().await;
-                tokio::spawn(async {
-                    let location = location_future.await;
-                    Self::oneshot_send(
-                        tx,
-                        location.ok().map(|fetched_location| GeoIpLocation {
-                            ipv4: fetched_location.ipv4,
-                            ipv6: fetched_location.ipv6,
-                            ..relay_location.unwrap_or(fetched_location)
-                        }),
-                        "current location",
-                    );
-                });
+        };
+        let account_manager = self.account_manager.clone();
+        let api_handle = self.api_handle.clone();
+        tokio::spawn(async move {
+            let result = async {
+                if account_manager
+                    .data_after_login()
+                    .await
+                    .map_err(Error::DeviceMigrationError)?
+                    .is_some()
+                {
+                    log::info!("Not retrying device migration since a device already exists");
+                    return Ok(false);
+                }
+                let data = migrations::retry_migrate_device(migration_data, api_handle)
+                    .await
+                    .map_err(Error::DeviceMigrationError)?;
+                account_manager
+                    .set(data)
+                    .await
+                    .map_err(Error::DeviceMigrationError)?;
+                Ok::<_, Error>(true)
             }
-            Error(_) => {
-                // We are not online at all at this stage so no location data is available.
-                Self::oneshot_send(tx, None, "current location");
+            .await;
+            if let Err(error) = &result {
+                log::error!("{}", error.display_chain());
             }
-        }
+            Self::oneshot_send(tx, result, "retry_device_migration response");
+        });
     }
 
-    async fn get_geo_location(&mut self) -> impl Future<Output = Result<GeoIpLocation, ()>> {
-        let rest_service = self.api_runtime.rest_handle().await;
-        async {
-            geoip::send_location_request(rest_service)
+    fn on_get_recent_tunnel_log(&self, tx: ResponseTx<Vec<String>, Error>, count: usize) {
+        let log_dir = match &self.log_dir {
+            Some(log_dir) => log_dir.clone(),
+            None => {
+                Self::oneshot_send(tx, Err(Error::NoLogDirectory), "recent tunnel log");
+                return;
+            }
+        };
+        tokio::spawn(async move {
+            let result = tunnel_log::read_recent_lines(&log_dir, count)
                 .await
-                .map_err(|e| {
-                    log::warn!("Unable to fetch GeoIP location: {}", e.display_chain());
-                })
-        }
+                .map_err(Error::ReadTunnelLogError);
+            Self::oneshot_send(tx, result, "recent tunnel log");
+        });
     }
 
-    fn build_location_from_relay(&self) -> Option<GeoIpLocation> {
-        let relays = self.last_generated_relays.as_ref()?;
-        let hostname;
-        let bridge_hostname;
-        let entry_hostname;
-        let obfuscator_hostname;
-        let location;
-        let take_hostname =
-            |relay: &Option<Relay>| relay.as_ref().map(|relay| relay.hostname.clone());
+    fn on_get_recent_device_events(&self, tx: ResponseTx<Vec<DeviceEvent>, Error>, count: usize) {
+        let skip = self.recent_device_events.len().saturating_sub(count);
+        let events = self
+            .recent_device_events
+            .iter()
+            .skip(skip)
+            .cloned()
+            .collect();
+        Self::oneshot_send(tx, Ok(events), "get_recent_device_events response");
+    }
 
-        match relays {
-            LastSelectedRelays::WireGuard {
-                wg_entry: entry,
-                wg_exit: exit,
-                obfuscator,
-            } => {
-                entry_hostname = take_hostname(entry);
-                hostname = exit.hostname.clone();
-                obfuscator_hostname = take_hostname(obfuscator);
-                bridge_hostname = None;
-                location = exit.location.as_ref().cloned().unwrap();
+    fn on_set_session_label(&mut self, tx: ResponseTx<(), Error>, label: Option<String>) {
+        if let Some(label) = &label {
+            if label.is_empty() || label.chars().count() > MAX_SESSION_LABEL_LEN {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::InvalidSessionLabel),
+                    "set_session_label response",
+                );
+                return;
             }
-            #[cfg(not(target_os = "android"))]
-            LastSelectedRelays::OpenVpn { relay, bridge } => {
-                hostname = relay.hostname.clone();
-                bridge_hostname = take_hostname(bridge);
-                entry_hostname = None;
-                obfuscator_hostname = None;
-                location = relay.location.as_ref().cloned().unwrap();
-            }
-        };
+        }
+        Self::oneshot_send(tx, Ok(()), "set_session_label response");
+        if self.session_label != label {
+            self.session_label = label.clone();
+            self.event_listener.notify_session_label(label);
+        }
+    }
 
-        Some(GeoIpLocation {
-            ipv4: None,
-            ipv6: None,
-            country: location.country,
-            city: Some(location.city),
-            latitude: location.latitude,
-            longitude: location.longitude,
-            mullvad_exit_ip: true,
-            hostname: Some(hostname),
-            bridge_hostname,
-            entry_hostname,
-            obfuscator_hostname,
-        })
+    fn on_get_session_label(&self, tx: oneshot::Sender<Option<String>>) {
+        Self::oneshot_send(tx, self.session_label.clone(), "get_session_label response");
     }
 
-    async fn on_create_new_account(&mut self, tx: ResponseTx<String, Error>) {
-        let account_manager = self.account_manager.clone();
-        tokio::spawn(async move {
-            let result = async {
-                if let Ok(Some(_)) = account_manager.data().await {
-                    return Err(Error::AlreadyLoggedIn);
+    fn on_get_supported_obfuscators(&self, tx: oneshot::Sender<Vec<SelectedObfuscation>>) {
+        Self::oneshot_send(
+            tx,
+            SelectedObfuscation::protocols(),
+            "get_supported_obfuscators response",
+        );
+    }
+
+    fn on_current_relay_supports_obfuscation(&self, tx: oneshot::Sender<bool>) {
+        let supported = match self.settings.obfuscation_settings.selected_obfuscation {
+            SelectedObfuscation::Off => true,
+            SelectedObfuscation::Auto | SelectedObfuscation::Udp2Tcp => {
+                match (
+                    self.settings.get_relay_settings(),
+                    &self.last_generated_relays,
+                ) {
+                    (
+                        RelaySettings::Normal(_),
+                        Some(LastSelectedRelays::WireGuard {
+                            wg_entry,
+                            wg_exit,
+                            obfuscator,
+                        }),
+                    ) => {
+                        obfuscator.is_some()
+                            || !wg_entry
+                                .as_ref()
+                                .unwrap_or(wg_exit)
+                                .obfuscators
+                                .udp2tcp
+                                .is_empty()
+                    }
+                    _ => false,
                 }
-                let token = account_manager
-                    .account_service
-                    .create_account()
-                    .await
-                    .map_err(Error::RestError)?;
-                account_manager
-                    .login(token.clone())
-                    .await
-                    .map_err(|error| {
-                        log::error!(
-                            "{}",
-                            error.display_chain_with_msg("Creating new account failed")
-                        );
-                        Error::LoginError(error)
-                    })?;
-                Ok(token)
-            };
-            Self::oneshot_send(tx, result.await, "create new account");
-        });
+            }
+        };
+        Self::oneshot_send(tx, supported, "current_relay_supports_obfuscation response");
     }
 
-    async fn on_get_account_data(
-        &mut self,
-        tx: ResponseTx<AccountData, mullvad_api::rest::Error>,
-        account_token: AccountToken,
-    ) {
-        let account = self.account_manager.account_service.clone();
+    fn on_get_metrics(&mut self, tx: oneshot::Sender<String>) {
+        let metrics = self.metrics.clone();
+        let tunnel_state = self.tunnel_state.clone();
+        let api_errors = self.api_runtime.request_error_count();
+        let (stats_tx, stats_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::GetTrafficStats(stats_tx));
         tokio::spawn(async move {
-            let result = account.check_expiry(account_token).await;
+            let traffic = stats_rx.await.ok().flatten();
             Self::oneshot_send(
                 tx,
-                result.map(|expiry| AccountData { expiry }),
-                "account data",
+                metrics.render(&tunnel_state, traffic, api_errors),
+                "get_metrics response",
             );
         });
     }
 
-    async fn on_get_www_auth_token(&mut self, tx: ResponseTx<String, Error>) {
-        if let Ok(Some(device)) = self.account_manager.data().await {
-            let future = self
-                .account_manager
-                .account_service
-                .get_www_auth_token(device.account_token);
-            tokio::spawn(async {
-                Self::oneshot_send(
-                    tx,
-                    future.await.map_err(Error::RestError),
-                    "get_www_auth_token response",
-                );
-            });
-        } else {
-            Self::oneshot_send(
-                tx,
-                Err(Error::NoAccountToken),
-                "get_www_auth_token response",
-            );
-        }
+    fn on_get_stability_counters(&self, tx: oneshot::Sender<StabilityCounters>) {
+        let counters = self
+            .stability
+            .counters(self.account_manager.rotation_failure_count());
+        Self::oneshot_send(tx, counters, "get_stability_counters response");
     }
 
-    async fn on_submit_voucher(
-        &mut self,
-        tx: ResponseTx<VoucherSubmission, Error>,
-        voucher: String,
-    ) {
-        if let Ok(Some(device)) = self.account_manager.data().await {
-            let mut account = self.account_manager.account_service.clone();
-            tokio::spawn(async move {
-                Self::oneshot_send(
-                    tx,
-                    account
-                        .submit_voucher(device.account_token, voucher)
-                        .await
-                        .map_err(Error::RestError),
-                    "submit_voucher response",
-                );
-            });
-        } else {
-            Self::oneshot_send(tx, Err(Error::NoAccountToken), "submit_voucher response");
-        }
+    fn on_reset_stability_counters(&mut self, tx: oneshot::Sender<()>) {
+        self.stability
+            .reset(self.account_manager.rotation_failure_count());
+        Self::oneshot_send(tx, (), "reset_stability_counters response");
     }
 
-    fn on_get_relay_locations(&mut self, tx: oneshot::Sender<RelayList>) {
-        Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
+    fn on_get_runtime_stats(&self, tx: oneshot::Sender<RuntimeStats>) {
+        Self::oneshot_send(tx, RuntimeStats::collect(), "get_runtime_stats response");
     }
 
-    async fn on_update_relay_locations(&mut self) {
-        self.relay_list_updater.update().await;
-    }
+    fn on_create_diagnostics_bundle(&mut self, tx: ResponseTx<PathBuf, Error>) {
+        let commit = Some(version::COMMIT_HASH.trim())
+            .filter(|hash| !hash.is_empty())
+            .map(str::to_owned);
+        let recent_device_events = self
+            .recent_device_events
+            .iter()
+            .map(|event| match &event.device {
+                Some(device) => format!(
+                    "Using device \"{}\" (remote change: {})",
+                    device.device.name, event.remote
+                ),
+                None => format!("Logged out (remote change: {})", event.remote),
+            })
+            .collect();
+        let mut info = diagnostics::DiagnosticsInfo {
+            version: version::PRODUCT_VERSION.to_owned(),
+            commit,
+            daemon_started_at: self.started_at,
+            tunnel_state: format!("{:?}", self.tunnel_state),
+            non_default_settings: settings::non_default_settings(&self.settings),
+            relay_list_updated_at: DateTime::<Utc>::from(self.relay_selector.last_updated()),
+            migration_history: vec![],
+            recent_device_events,
+            api_errors: self.api_runtime.request_error_count(),
+            metrics: String::new(),
+        };
 
-    fn on_login_account(&mut self, tx: ResponseTx<(), Error>, account_token: String) {
-        let account_manager = self.account_manager.clone();
+        let metrics = self.metrics.clone();
+        let tunnel_state = self.tunnel_state.clone();
+        let settings_dir = self.settings_dir.clone();
+        let cache_dir = self.cache_dir.clone();
+        let log_dir = self.log_dir.clone();
+        let (stats_tx, stats_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::GetTrafficStats(stats_tx));
         tokio::spawn(async move {
-            let result = async {
-                account_manager.login(account_token).await.map_err(|error| {
-                    log::error!("{}", error.display_chain_with_msg("Login failed"));
-                    Error::LoginError(error)
-                })
-            };
-            Self::oneshot_send(tx, result.await, "login_account response");
+            let traffic = stats_rx.await.ok().flatten();
+            info.metrics = metrics.render(&tunnel_state, traffic, info.api_errors);
+            info.migration_history = migrations::migration_history(&settings_dir)
+                .await
+                .unwrap_or_else(|error| {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read migration history")
+                    );
+                    vec![]
+                });
+
+            let result = diagnostics::create_bundle(info, log_dir.as_deref(), &cache_dir)
+                .await
+                .map_err(Error::CreateDiagnosticsBundleError);
+            if let Err(error) = &result {
+                log::error!("{}", error.display_chain());
+            }
+            Self::oneshot_send(tx, result, "create_diagnostics_bundle response");
         });
     }
 
-    fn on_logout_account(&mut self, tx: ResponseTx<(), Error>) {
-        let account_manager = self.account_manager.clone();
+    fn on_get_active_dns_servers(&mut self, tx: oneshot::Sender<Vec<IpAddr>>) {
+        let (servers_tx, servers_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::GetDnsServers(servers_tx));
         tokio::spawn(async move {
-            let result = async {
-                account_manager.logout().await.map_err(|error| {
-                    log::error!("{}", error.display_chain_with_msg("Logout failed"));
-                    Error::LogoutError(error)
-                })
-            };
-            Self::oneshot_send(tx, result.await, "logout_account response");
+            let servers = servers_rx.await.unwrap_or_default();
+            Self::oneshot_send(tx, servers, "get_active_dns_servers response");
         });
     }
 
-    async fn on_get_device(&mut self, tx: ResponseTx<Option<AccountAndDevice>, Error>) {
-        let account_manager = self.account_manager.clone();
-        tokio::spawn(async move {
+    fn on_refresh_offline_state(&mut self, tx: ResponseTx<bool, Error>) {
+        let recently_refreshed = self
+            .last_offline_state_refresh
+            .map(|last_refresh| last_refresh.elapsed() < OFFLINE_STATE_REFRESH_INTERVAL)
+            .unwrap_or(false);
+        let availability = self.api_handle.availability.clone();
+        if recently_refreshed {
             Self::oneshot_send(
                 tx,
-                Ok(account_manager
-                    .data()
-                    .await
-                    .unwrap_or(None)
-                    .map(AccountAndDevice::from)),
-                "get_device response",
+                Ok(availability.get_state().is_offline()),
+                "refresh_offline_state response",
             );
+            return;
+        }
+        self.last_offline_state_refresh = Some(Instant::now());
+
+        let (result_tx, result_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::RefreshOfflineState(result_tx));
+        tokio::spawn(async move {
+            let is_offline = result_rx
+                .await
+                .unwrap_or_else(|_| availability.get_state().is_offline());
+            Self::oneshot_send(tx, Ok(is_offline), "refresh_offline_state response");
         });
     }
 
-    async fn on_update_device(&mut self, tx: ResponseTx<(), Error>) {
-        let account_manager = self.account_manager.clone();
+    fn on_get_connection_quality(&mut self, tx: oneshot::Sender<Option<u8>>) {
+        if !self.tunnel_state.is_connected() {
+            Self::oneshot_send(tx, None, "get_connection_quality response");
+            return;
+        }
+        let (servers_tx, servers_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::GetDnsServers(servers_tx));
         tokio::spawn(async move {
-            let result = match account_manager.validate_device().await {
-                Ok(_) | Err(device::Error::NoDevice) => Ok(()),
-                Err(error) => Err(error),
+            let resolver = servers_rx
+                .await
+                .ok()
+                .and_then(|servers| servers.first().copied());
+            let quality = match resolver {
+                Some(resolver) => connection_quality::measure(resolver).await,
+                None => None,
             };
+            Self::oneshot_send(tx, quality, "get_connection_quality response");
+        });
+    }
+
+    fn on_estimate_connect_time(&self, tx: oneshot::Sender<Option<Duration>>) {
+        let (tunnel_type, protocol) = match &self.tunnel_state {
+            TunnelState::Connecting { endpoint, .. } | TunnelState::Connected { endpoint, .. } => {
+                (endpoint.tunnel_type, Some(endpoint.endpoint.protocol))
+            }
+            _ => (self.relay_selector.resolve_tunnel_type(), None),
+        };
+        Self::oneshot_send(
+            tx,
+            self.connect_times.estimate(tunnel_type, protocol),
+            "estimate_connect_time response",
+        );
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn on_set_firewall_debug_logging(&mut self, tx: ResponseTx<(), Error>, enabled: bool) {
+        if let Some((_, job)) = self.firewall_debug_logging_job.take() {
+            job.abort();
+        }
+        if enabled {
+            let enabled_at = Instant::now();
+            let daemon_tx = self.tx.clone();
+            let (future, abort_handle) = abortable(Box::pin(async move {
+                tokio::time::sleep(FIREWALL_DEBUG_LOGGING_TIMEOUT).await;
+                let _ =
+                    daemon_tx.send(InternalDaemonEvent::FirewallDebugLoggingExpired(enabled_at));
+            }));
+            tokio::spawn(future);
+            self.firewall_debug_logging_job = Some((enabled_at, abort_handle));
+        }
+
+        let result_rx = self.set_firewall_drop_logging(enabled);
+        tokio::spawn(async move {
+            match result_rx.await {
+                Ok(result) => Self::oneshot_send(
+                    tx,
+                    result.map_err(Error::FirewallDebugLoggingError),
+                    "set_firewall_debug_logging response",
+                ),
+                Err(_) => log::error!("The tunnel failed to return a result"),
+            }
+        });
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn handle_firewall_debug_logging_expired(&mut self, enabled_at: Instant) {
+        match &self.firewall_debug_logging_job {
+            Some((current, _)) if *current == enabled_at => self.firewall_debug_logging_job = None,
+            // Logging was disabled or enabled again after the event was sent
+            _ => return,
+        }
+        log::debug!("Disabling firewall debug logging");
+        // Errors are logged by the tunnel state machine
+        let _ = self.set_firewall_drop_logging(false);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn set_firewall_drop_logging(
+        &mut self,
+        enabled: bool,
+    ) -> oneshot::Receiver<Result<(), talpid_core::firewall::Error>> {
+        let (result_tx, result_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::SetFirewallDropLogging(enabled, result_tx));
+        result_rx
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn on_set_firewall_debug_logging(&mut self, tx: ResponseTx<(), Error>, _enabled: bool) {
+        Self::oneshot_send(
+            tx,
+            Err(Error::FirewallDebugLoggingUnsupported),
+            "set_firewall_debug_logging response",
+        );
+    }
+
+    fn on_get_active_firewall_exceptions(&mut self, tx: oneshot::Sender<Vec<FirewallException>>) {
+        let (exceptions_tx, exceptions_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::GetFirewallExceptions(exceptions_tx));
+        tokio::spawn(async move {
+            let exceptions = exceptions_rx.await.unwrap_or_default();
+            Self::oneshot_send(tx, exceptions, "get_active_firewall_exceptions response");
+        });
+    }
+
+    fn on_get_firewall_backend_info(&self, tx: oneshot::Sender<FirewallBackendInfo>) {
+        tokio::task::spawn_blocking(move || {
             Self::oneshot_send(
                 tx,
-                result.map_err(Error::UpdateDeviceError),
-                "update_device response",
+                Firewall::backend_info(),
+                "get_firewall_backend_info response",
             );
         });
     }
 
-    async fn on_list_devices(&self, tx: ResponseTx<Vec<Device>, Error>, token: AccountToken) {
-        let service = self.account_manager.device_service.clone();
+    fn on_get_selector_config(&self, tx: oneshot::Sender<SelectorConfig>) {
+        Self::oneshot_send(
+            tx,
+            redact_selector_config(self.relay_selector.get_config()),
+            "get_selector_config response",
+        );
+    }
+
+    fn on_get_api_connection_mode(&self, tx: oneshot::Sender<ApiConnectionModeInfo>) {
+        let mode = self.api_connection_mode.lock().unwrap().clone();
+        let address_cache = self.api_runtime.address_cache.clone();
         tokio::spawn(async move {
+            let endpoint = match mode.get_endpoint() {
+                Some(endpoint) => endpoint,
+                None => address_cache.get_address().await,
+            };
             Self::oneshot_send(
                 tx,
-                service
-                    .list_devices(token)
-                    .await
-                    .map_err(Error::ListDevicesError),
-                "list_devices response",
+                ApiConnectionModeInfo { mode, endpoint },
+                "api connection mode",
             );
         });
     }
 
-    async fn on_remove_device(
-        &mut self,
-        tx: ResponseTx<(), Error>,
-        token: AccountToken,
-        device_id: DeviceId,
-    ) {
-        let device_service = self.account_manager.device_service.clone();
-        let event_listener = self.event_listener.clone();
-
+    fn on_reset_api_connection(&self, tx: ResponseTx<(), Error>) {
+        log::info!("Resetting API connection");
+        let service = self.api_handle.service();
+        service.reset();
         tokio::spawn(async move {
-            let mut devices = match device_service
-                .list_devices(token.clone())
-                .await
-                .map_err(Error::ListDevicesError)
-            {
-                Ok(devices) => devices,
+            let result = match service.rotate_api_endpoint().await {
+                Ok(Some(endpoint)) => {
+                    log::info!("Reset API connection. New API endpoint: {}", endpoint);
+                    Ok(())
+                }
+                Ok(None) => {
+                    log::warn!("Reset API connection, but the connection mode was not changed");
+                    Ok(())
+                }
                 Err(error) => {
-                    Self::oneshot_send(tx, Err(error), "remove_device response");
-                    return;
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to rotate API endpoint")
+                    );
+                    Err(Error::RestError(error))
                 }
             };
-            if let Err(error) = device_service
-                .remove_device(token.clone(), device_id.clone())
-                .await
-                .map_err(Error::RemoveDeviceError)
-            {
-                Self::oneshot_send(tx, Err(error), "remove_device response");
-                return;
-            };
-            let removed_device =
-                if let Some(index) = devices.iter().position(|device| device.id == device_id) {
-                    devices.swap_remove(index)
-                } else {
-                    log::error!("List did not contain the revoked device");
-                    Device {
-                        id: device_id,
-                        name: "unknown device".to_string(),
-                        pubkey: talpid_types::net::wireguard::PublicKey::from([0u8; 32]),
-                        ports: vec![],
-                    }
-                };
-            event_listener.notify_remove_device_event(RemoveDeviceEvent {
-                account_token: token,
-                removed_device,
-                new_devices: devices,
-            });
-            Self::oneshot_send(tx, Ok(()), "remove_device response");
+            Self::oneshot_send(tx, result, "reset_api_connection response");
         });
     }
 
-    fn on_get_account_history(&mut self, tx: oneshot::Sender<Option<AccountToken>>) {
-        Self::oneshot_send(
-            tx,
-            self.account_history.get(),
-            "get_account_history response",
-        );
-    }
+    async fn on_get_current_location(&mut self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
+        use self::TunnelState::*;
 
-    async fn on_clear_account_history(&mut self, tx: ResponseTx<(), Error>) {
-        let result = self
-            .account_history
-            .clear()
-            .await
-            .map_err(Error::AccountHistory);
-        Self::oneshot_send(tx, result, "clear_account_history response");
+        match &self.tunnel_state {
+            Disconnected => {
+                let location = self.get_cached_geo_location(None).await;
+                tokio::spawn(async {
+                    Self::oneshot_send(tx, location.await.ok(), "current location");
+                });
+            }
+            #[cfg(not(target_os = "android"))]
+            Paused { .. } => {
+                let location = self.get_cached_geo_location(None).await;
+                tokio::spawn(async {
+                    Self::oneshot_send(tx, location.await.ok(), "current location");
+                });
+            }
+            Connecting { location, .. } => {
+                Self::oneshot_send(tx, location.clone(), "current location")
+            }
+            Disconnecting(..) => {
+                Self::oneshot_send(tx, self.build_location_from_relay(), "current location")
+            }
+            Connected { location, endpoint } => {
+                let relay_location = location.clone();
+                let endpoint = *endpoint;
+                let location_future = self.get_cached_geo_location(Some(endpoint)).await;
+                tokio::spawn(async {
+                    let location = location_future.await;
+                    Self::oneshot_send(
+                        tx,
+                        location.ok().map(|fetched_location| GeoIpLocation {
+                            ipv4: fetched_location.ipv4,
+                            ipv6: fetched_location.ipv6,
+                            ..relay_location.unwrap_or(fetched_location)
+                        }),
+                        "current location",
+                    );
+                });
+            }
+            Error(_) => {
+                // We are not online at all at this stage so no location data is available.
+                Self::oneshot_send(tx, None, "current location");
+            }
+        }
     }
 
-    async fn on_get_version_info(&mut self, tx: oneshot::Sender<Option<AppVersionInfo>>) {
-        if self.app_version_info.is_none() {
-            log::debug!("No version cache found. Fetching new info");
-            let mut handle = self.version_updater_handle.clone();
-            tokio::spawn(async move {
-                Self::oneshot_send(
-                    tx,
-                    handle
-                        .run_version_check()
-                        .await
-                        .map_err(|error| {
-                            log::error!(
-                                "{}",
-                                error.display_chain_with_msg("Error running version check")
-                            )
-                        })
-                        .ok(),
-                    "get_version_info response",
-                );
-            });
-        } else {
-            Self::oneshot_send(
-                tx,
-                self.app_version_info.clone(),
-                "get_version_info response",
-            );
+    fn on_get_cached_location(&mut self, tx: oneshot::Sender<Option<GeoIpLocation>>) {
+        let location = match &self.tunnel_state {
+            TunnelState::Connected { endpoint, .. } => {
+                self.location_cache.lock().get(Some(*endpoint))
+            }
+            #[cfg(not(target_os = "android"))]
+            TunnelState::Paused { .. } => self.location_cache.lock().get(None),
+            TunnelState::Disconnected => self.location_cache.lock().get(None),
+            _ => None,
+        };
+        Self::oneshot_send(tx, location, "cached location");
+    }
+
+    /// Like `get_geo_location`, but returns the cached location for `endpoint` if it is fresh, and
+    /// caches newly fetched locations.
+    async fn get_cached_geo_location(
+        &mut self,
+        endpoint: Option<TunnelEndpoint>,
+    ) -> impl Future<Output = Result<GeoIpLocation, ()>> {
+        let cached_location = self.location_cache.lock().get(endpoint);
+        let location_cache = self.location_cache.clone();
+        let location_future = self.get_geo_location().await;
+        async move {
+            if let Some(location) = cached_location {
+                return Ok(location);
+            }
+            let location = location_future.await?;
+            location_cache.lock().insert(endpoint, location.clone());
+            Ok(location)
         }
     }
 
-    fn on_get_current_version(&mut self, tx: oneshot::Sender<AppVersion>) {
+    async fn get_geo_location(&mut self) -> impl Future<Output = Result<GeoIpLocation, ()>> {
+        let rest_service = self.api_runtime.rest_handle().await;
+        async {
+            geoip::send_location_request(rest_service)
+                .await
+                .map_err(|e| {
+                    log::warn!("Unable to fetch GeoIP location: {}", e.display_chain());
+                })
+        }
+    }
+
+    fn on_get_blocked_capabilities(&self, tx: oneshot::Sender<BlockedCapabilities>) {
+        let capabilities = BlockedCapabilities::new(
+            &self.tunnel_state,
+            self.settings.allow_lan,
+            self.settings.block_when_disconnected,
+        );
+        Self::oneshot_send(tx, capabilities, "get_blocked_capabilities response");
+    }
+
+    fn on_get_allowed_endpoint(&self, tx: oneshot::Sender<AllowedEndpoint>) {
+        let endpoint = self.api_allowed_endpoint.lock().unwrap().clone();
+        Self::oneshot_send(tx, endpoint, "get_allowed_endpoint response");
+    }
+
+    fn on_resolve_tunnel_type_constraint(&self, tx: oneshot::Sender<TunnelType>) {
         Self::oneshot_send(
             tx,
-            version::PRODUCT_VERSION.to_owned(),
-            "get_current_version response",
+            self.relay_selector.resolve_tunnel_type(),
+            "resolve_tunnel_type_constraint response",
         );
     }
 
-    #[cfg(not(target_os = "android"))]
-    async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
-        let mut last_error = Ok(());
+    fn on_get_boot_leak_protection_status(&self, tx: oneshot::Sender<BootLeakStatus>) {
+        let status = BootLeakStatus::new(
+            self.settings.auto_connect,
+            self.settings.block_when_disconnected,
+        );
+        Self::oneshot_send(tx, status, "get_boot_leak_protection_status response");
+    }
 
-        if let Err(error) = self.account_manager.logout().await {
-            log::error!(
-                "{}",
-                error.display_chain_with_msg("Failed to clear device cache")
-            );
-            last_error = Err(Error::LogoutError(error));
-        }
+    fn on_can_use_multihop(&self, tx: oneshot::Sender<bool>) {
+        Self::oneshot_send(
+            tx,
+            self.relay_selector.can_use_multihop(),
+            "can_use_multihop response",
+        );
+    }
 
-        if let Err(error) = self.account_history.clear().await {
-            log::error!(
-                "{}",
-                error.display_chain_with_msg("Failed to clear account history")
+    async fn on_detect_captive_portal(&mut self, tx: ResponseTx<CaptivePortalStatus, Error>) {
+        // The probe is only meaningful if the firewall lets it out.
+        if !self.tunnel_state.is_disconnected() || self.settings.block_when_disconnected {
+            Self::oneshot_send(
+                tx,
+                Err(Error::CaptivePortalDetectionUnavailable),
+                "captive portal status",
             );
-            last_error = Err(Error::ClearAccountHistoryError(error));
+            return;
         }
 
-        if let Err(e) = self.settings.reset().await {
-            log::error!("Failed to reset settings: {}", e);
-            last_error = Err(Error::ClearSettingsError(e));
+        tokio::spawn(async move {
+            let status = captive_portal::detect_captive_portal().await;
+            log::debug!("Captive portal status: {}", status);
+            Self::oneshot_send(tx, Ok(status), "captive portal status");
+        });
+    }
+
+    fn on_get_active_tunnel_info(&self, tx: ResponseTx<ActiveTunnelInfo, Error>) {
+        let endpoint = match &self.tunnel_state {
+            TunnelState::Connected { endpoint, .. } => endpoint.clone(),
+            _ => {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::TunnelNotConnected),
+                    "get_active_tunnel_info response",
+                );
+                return;
+            }
+        };
+
+        let mut info = ActiveTunnelInfo {
+            endpoint,
+            exit_hostname: None,
+            entry_hostname: None,
+            bridge_hostname: None,
+            obfuscator_hostname: None,
+            mtu: None,
+            mssfix: None,
+            openvpn_cipher: None,
+            obfuscation_type: None,
+            obfuscation_protocol: None,
+            enable_ipv6: false,
+        };
+        let take_hostname =
+            |relay: &Option<Relay>| relay.as_ref().map(|relay| relay.hostname.clone());
+
+        // Relays are only selected for connection attempts, so the last generated relays belong to
+        // the connected tunnel. The settings may have changed since then without a reconnect.
+        let last_generated_relays = if self.last_generated_custom_endpoint {
+            None
+        } else {
+            self.last_generated_relays.as_ref()
+        };
+        match last_generated_relays {
+            Some(LastSelectedRelays::WireGuard {
+                wg_entry,
+                wg_exit,
+                obfuscator,
+            }) => {
+                info.exit_hostname = Some(wg_exit.hostname.clone());
+                info.entry_hostname = take_hostname(wg_entry);
+                info.obfuscator_hostname = take_hostname(obfuscator);
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(LastSelectedRelays::OpenVpn { relay, bridge }) => {
+                info.exit_hostname = Some(relay.hostname.clone());
+                info.bridge_hostname = take_hostname(bridge);
+            }
+            None => (),
         }
 
-        // Shut the daemon down.
-        self.trigger_shutdown_event();
+        let (parameters_tx, parameters_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::GetTunnelParameters(parameters_tx));
 
-        self.shutdown_tasks.push(Box::pin(async move {
-            if let Err(e) = Self::clear_cache_directory().await {
-                log::error!(
-                    "{}",
-                    e.display_chain_with_msg("Failed to clear cache directory")
-                );
-                last_error = Err(Error::ClearCacheError);
+        tokio::spawn(async move {
+            let parameters = match parameters_rx.await.ok().flatten() {
+                Some(parameters) => parameters,
+                None => {
+                    Self::oneshot_send(
+                        tx,
+                        Err(Error::TunnelNotConnected),
+                        "get_active_tunnel_info response",
+                    );
+                    return;
+                }
+            };
+            match parameters {
+                TunnelParameters::Wireguard(parameters) => {
+                    info.mtu = parameters.options.mtu;
+                    if let Some(obfuscation) = &parameters.obfuscation {
+                        let obfuscation = ObfuscationEndpoint::from(obfuscation);
+                        info.obfuscation_type = Some(obfuscation.obfuscation_type);
+                        info.obfuscation_protocol = Some(obfuscation.endpoint.protocol);
+                    }
+                    info.enable_ipv6 = parameters.generic_options.enable_ipv6;
+                }
+                TunnelParameters::OpenVpn(parameters) => {
+                    info.mssfix = parameters.options.mssfix;
+                    info.openvpn_cipher = Some(talpid_types::net::openvpn::DATA_CIPHER.to_string());
+                    info.enable_ipv6 = parameters.generic_options.enable_ipv6;
+                }
             }
+            Self::oneshot_send(tx, Ok(info), "get_active_tunnel_info response");
+        });
+    }
 
-            if let Err(e) = Self::clear_log_directory().await {
-                log::error!(
-                    "{}",
-                    e.display_chain_with_msg("Failed to clear log directory")
-                );
-                last_error = Err(Error::ClearLogsError);
+    /// Updates the relays used by the tunnel, and notifies clients if any of them changed.
+    fn set_last_generated_relays(&mut self, relays: LastSelectedRelays) {
+        let previous_location = self.build_location_from_relay();
+        self.last_generated_relays = Some(relays);
+        let location = self.build_location_from_relay();
+        if location != previous_location {
+            if let Some(location) = location {
+                self.event_listener.notify_endpoint_changed(location);
             }
-            Self::oneshot_send(tx, last_error, "factory_reset response");
-        }));
+        }
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_get_split_tunnel_processes(&mut self, tx: ResponseTx<Vec<i32>, split_tunnel::Error>) {
-        let result = self.exclude_pids.list().map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to obtain PIDs"));
-            error
-        });
-        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
+    fn build_location_from_relay(&self) -> Option<GeoIpLocation> {
+        let relays = self.last_generated_relays.as_ref()?;
+        let hostname;
+        let bridge_hostname;
+        let entry_hostname;
+        let obfuscator_hostname;
+        let location;
+        let take_hostname =
+            |relay: &Option<Relay>| relay.as_ref().map(|relay| relay.hostname.clone());
+
+        match relays {
+            LastSelectedRelays::WireGuard {
+                wg_entry: entry,
+                wg_exit: exit,
+                obfuscator,
+            } => {
+                entry_hostname = take_hostname(entry);
+                hostname = exit.hostname.clone();
+                obfuscator_hostname = take_hostname(obfuscator);
+                bridge_hostname = None;
+                location = exit.location.as_ref().cloned().unwrap();
+            }
+            #[cfg(not(target_os = "android"))]
+            LastSelectedRelays::OpenVpn { relay, bridge } => {
+                hostname = relay.hostname.clone();
+                bridge_hostname = take_hostname(bridge);
+                entry_hostname = None;
+                obfuscator_hostname = None;
+                location = relay.location.as_ref().cloned().unwrap();
+            }
+        };
+
+        Some(GeoIpLocation {
+            ipv4: None,
+            ipv6: None,
+            country: location.country,
+            city: Some(location.city),
+            latitude: location.latitude,
+            longitude: location.longitude,
+            mullvad_exit_ip: true,
+            hostname: Some(hostname),
+            bridge_hostname,
+            entry_hostname,
+            obfuscator_hostname,
+        })
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_add_split_tunnel_process(&mut self, tx: ResponseTx<(), split_tunnel::Error>, pid: i32) {
-        let result = self.exclude_pids.add(pid).map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to add PID"));
-            error
+    async fn on_create_new_account(&mut self, tx: ResponseTx<String, Error>) {
+        let account_manager = self.account_manager.clone();
+        let api_availability = self.api_handle.availability.clone();
+        let account_service = account_manager.account_service.clone();
+        let (create_account, abort_handle) =
+            abortable(async move { account_service.create_account().await });
+        let claimed = Arc::new(AtomicBool::new(false));
+        self.account_creation_job = Some(AccountCreationJob {
+            abort_handle,
+            claimed: claimed.clone(),
+        });
+        tokio::spawn(async move {
+            let result = async {
+                if let Ok(Some(_)) = account_manager.data().await {
+                    claimed.store(true, Ordering::SeqCst);
+                    return Err(Error::AlreadyLoggedIn);
+                }
+                let created = create_account.await;
+                // Past this point, the account creation can no longer be cancelled
+                if claimed.swap(true, Ordering::SeqCst) {
+                    log::info!("Account creation was cancelled");
+                    return Err(Error::AccountCreationCancelled);
+                }
+                let token = created
+                    .map_err(|_| Error::AccountCreationCancelled)?
+                    .map_err(|error| map_api_error(error, &api_availability))?;
+                account_manager
+                    .login(token.clone())
+                    .await
+                    .map_err(|error| {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Creating new account failed")
+                        );
+                        Error::LoginError(error)
+                    })?;
+                Ok(token)
+            };
+            Self::oneshot_send(tx, result.await, "create new account");
         });
-        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
+    }
+
+    fn on_cancel_account_creation(&mut self, tx: ResponseTx<bool, Error>) {
+        let cancelled = match self.account_creation_job.take() {
+            Some(job) if !job.claimed.swap(true, Ordering::SeqCst) => {
+                job.abort_handle.abort();
+                true
+            }
+            _ => false,
+        };
+        Self::oneshot_send(tx, Ok(cancelled), "cancel_account_creation response");
+    }
+
+    async fn on_get_account_data(
+        &mut self,
+        tx: ResponseTx<AccountData, Error>,
+        account_token: AccountToken,
+    ) {
+        let account = self.account_manager.account_service.clone();
+        let api_availability = self.api_handle.availability.clone();
+        let daemon_tx = self.tx.clone();
+        tokio::spawn(async move {
+            let result = account.check_expiry(account_token.clone()).await;
+            if matches!(result, Ok(expiry) if expiry <= Utc::now()) {
+                let _ = daemon_tx.send(InternalDaemonEvent::AccountExpired(account_token));
+            }
+            Self::oneshot_send(
+                tx,
+                result
+                    .map(|expiry| AccountData { expiry })
+                    .map_err(|error| map_api_error(error, &api_availability)),
+                "account data",
+            );
+        });
+    }
+
+    async fn on_get_www_auth_token(&mut self, tx: ResponseTx<String, Error>) {
+        if let Ok(Some(device)) = self.account_manager.data().await {
+            let future = self
+                .account_manager
+                .account_service
+                .get_www_auth_token(device.account_token);
+            let api_availability = self.api_handle.availability.clone();
+            tokio::spawn(async move {
+                Self::oneshot_send(
+                    tx,
+                    future
+                        .await
+                        .map_err(|error| map_api_error(error, &api_availability)),
+                    "get_www_auth_token response",
+                );
+            });
+        } else {
+            Self::oneshot_send(
+                tx,
+                Err(Error::NoAccountToken),
+                "get_www_auth_token response",
+            );
+        }
+    }
+
+    async fn on_submit_voucher(
+        &mut self,
+        tx: ResponseTx<VoucherSubmission, Error>,
+        voucher: String,
+    ) {
+        if let Ok(Some(device)) = self.account_manager.data().await {
+            let mut account = self.account_manager.account_service.clone();
+            let api_availability = self.api_handle.availability.clone();
+            tokio::spawn(async move {
+                Self::oneshot_send(
+                    tx,
+                    account
+                        .submit_voucher(device.account_token, voucher)
+                        .await
+                        .map_err(|error| map_api_error(error, &api_availability)),
+                    "submit_voucher response",
+                );
+            });
+        } else {
+            Self::oneshot_send(tx, Err(Error::NoAccountToken), "submit_voucher response");
+        }
+    }
+
+    fn on_get_relay_locations(&mut self, tx: oneshot::Sender<RelayList>) {
+        Self::oneshot_send(tx, self.relay_selector.get_locations(), "relay locations");
+    }
+
+    fn on_get_relay_list_signature_info(&self, tx: oneshot::Sender<RelayListSignatureInfo>) {
+        Self::oneshot_send(
+            tx,
+            self.relay_selector.relay_list_signature_info(),
+            "relay list signature info",
+        );
+    }
+
+    fn on_get_relay_counts_by_location(
+        &mut self,
+        tx: oneshot::Sender<Vec<(LocationConstraint, usize)>>,
+    ) {
+        Self::oneshot_send(
+            tx,
+            self.relay_selector.get_relay_counts_by_location(),
+            "relay counts by location",
+        );
+    }
+
+    fn on_get_relay_counts_by_protocol(&mut self, tx: oneshot::Sender<ProtocolCounts>) {
+        Self::oneshot_send(
+            tx,
+            self.relay_selector.get_relay_counts_by_protocol(),
+            "relay counts by protocol",
+        );
+    }
+
+    fn on_get_providers(&mut self, tx: oneshot::Sender<Vec<ProviderInfo>>) {
+        Self::oneshot_send(tx, self.relay_selector.get_providers(), "providers");
+    }
+
+    fn on_preview_relay_settings(
+        &self,
+        tx: ResponseTx<SelectionOutcome, Error>,
+        update: RelaySettingsUpdate,
+    ) {
+        // The update is applied to a copy of the settings, which is discarded
+        let mut settings = self.settings.to_settings();
+        settings.update_relay_settings(update);
+        let result = self
+            .relay_selector
+            .preview_relay(&new_selector_config(&settings))
+            .map_err(Error::NoRelaySelected);
+        Self::oneshot_send(tx, result, "preview_relay_settings response");
+    }
+
+    async fn on_connect_to_relay(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        relay_override: RelayConstraintOverride,
+    ) {
+        if !self.state.is_running() {
+            log::warn!("Ignoring connect to relay request due to shutdown");
+            return;
+        }
+
+        let mut config = self.relay_selector.get_config();
+        config.relay_settings = relay_override.apply(&config.relay_settings);
+        if let Err(error) = self.relay_selector.get_relay_with_config(&config, 0) {
+            Self::oneshot_send(
+                tx,
+                Err(Error::NoRelaySelected(error)),
+                "connect_to_relay response",
+            );
+            return;
+        }
+
+        log::debug!("Connecting using relay constraints {:?}", relay_override);
+        #[cfg(not(target_os = "android"))]
+        self.cancel_pause();
+        self.reset_protocol_fallback();
+        self.relay_override = Some(relay_override);
+        if !self.set_target_state(TargetState::Secured).await {
+            self.connect_trigger = ConnectTrigger::ClientCommand;
+            self.connect_tunnel();
+        }
+        Self::oneshot_send(tx, Ok(()), "connect_to_relay response");
+    }
+
+    async fn on_update_relay_locations(&mut self) {
+        self.relay_list_updater.update().await;
+    }
+
+    fn on_set_ephemeral_relay_list(&mut self, tx: ResponseTx<(), Error>, relay_list: RelayList) {
+        let has_relays = relay_list
+            .countries
+            .iter()
+            .flat_map(|country| &country.cities)
+            .any(|city| !city.relays.is_empty());
+        if !has_relays {
+            Self::oneshot_send(
+                tx,
+                Err(Error::EmptyRelayList),
+                "set_ephemeral_relay_list response",
+            );
+            return;
+        }
+        log::info!("Using an ephemeral relay list");
+        self.relay_selector
+            .set_ephemeral_relay_list(relay_list.clone());
+        Self::oneshot_send(tx, Ok(()), "set_ephemeral_relay_list response");
+        self.event_listener.notify_relay_list(relay_list.clone());
+        self.handle_relay_list_updated(relay_list);
+    }
+
+    fn on_clear_ephemeral_relay_list(&mut self, tx: oneshot::Sender<()>) {
+        let restored = self.relay_selector.clear_ephemeral_relay_list();
+        Self::oneshot_send(tx, (), "clear_ephemeral_relay_list response");
+        if let Some(relay_list) = restored {
+            log::info!("No longer using an ephemeral relay list");
+            self.event_listener.notify_relay_list(relay_list.clone());
+            self.handle_relay_list_updated(relay_list);
+        }
+    }
+
+    fn on_login_account(&mut self, tx: ResponseTx<(), Error>, account_token: String) {
+        let account_manager = self.account_manager.clone();
+        tokio::spawn(async move {
+            let result = match account_manager.login(account_token.clone()).await {
+                Ok(()) => Ok(()),
+                Err(error) => Err(Self::login_error(&account_manager, account_token, error).await),
+            };
+            Self::oneshot_send(tx, result, "login_account response");
+        });
+    }
+
+    fn on_switch_account(&mut self, tx: ResponseTx<(), Error>, account_token: String) {
+        let account_manager = self.account_manager.clone();
+        tokio::spawn(async move {
+            let result = match account_manager.switch_account(account_token.clone()).await {
+                Ok(()) => Ok(()),
+                Err(error) => Err(Self::login_error(&account_manager, account_token, error).await),
+            };
+            Self::oneshot_send(tx, result, "switch_account response");
+        });
+    }
+
+    /// Converts an error from logging in to `account_token` to a daemon error. The devices of the
+    /// account are listed if it has too many of them.
+    async fn login_error(
+        account_manager: &device::AccountManagerHandle,
+        account_token: String,
+        error: device::Error,
+    ) -> Error {
+        match error {
+            device::Error::MaxDevicesReached => {
+                log::error!("Login failed: Too many devices");
+                match account_manager
+                    .device_service
+                    .list_devices(account_token)
+                    .await
+                {
+                    Ok(devices) => Error::TooManyDevices(devices),
+                    Err(error) => {
+                        log::error!("{}", error.display_chain_with_msg("Failed to list devices"));
+                        Error::LoginError(device::Error::MaxDevicesReached)
+                    }
+                }
+            }
+            error => {
+                log::error!("{}", error.display_chain_with_msg("Login failed"));
+                Error::LoginError(error)
+            }
+        }
+    }
+
+    fn on_logout_account(&mut self, tx: ResponseTx<(), Error>) {
+        let account_manager = self.account_manager.clone();
+        tokio::spawn(async move {
+            let result = async {
+                account_manager.logout().await.map_err(|error| {
+                    log::error!("{}", error.display_chain_with_msg("Logout failed"));
+                    Error::LogoutError(error)
+                })
+            };
+            Self::oneshot_send(tx, result.await, "logout_account response");
+        });
+    }
+
+    async fn on_get_device(&mut self, tx: ResponseTx<Option<AccountAndDevice>, Error>) {
+        let account_manager = self.account_manager.clone();
+        tokio::spawn(async move {
+            Self::oneshot_send(
+                tx,
+                Ok(account_manager
+                    .data()
+                    .await
+                    .unwrap_or(None)
+                    .map(AccountAndDevice::from)),
+                "get_device response",
+            );
+        });
+    }
+
+    async fn on_update_device(&mut self, tx: ResponseTx<(), Error>) {
+        let account_manager = self.account_manager.clone();
+        tokio::spawn(async move {
+            let result = match account_manager.validate_device().await {
+                Ok(_) | Err(device::Error::NoDevice) => Ok(()),
+                Err(error) => Err(error),
+            };
+            Self::oneshot_send(
+                tx,
+                result.map_err(Error::UpdateDeviceError),
+                "update_device response",
+            );
+        });
+    }
+
+    async fn on_get_device_ports(&mut self, tx: ResponseTx<Vec<DevicePort>, Error>) {
+        let account_manager = self.account_manager.clone();
+        tokio::spawn(async move {
+            // Validating the device updates the stored ports and emits a device event if they
+            // changed remotely.
+            let result = match account_manager.validate_device().await {
+                Ok(()) => match account_manager.data().await {
+                    Ok(Some(data)) => Ok(data.device.ports),
+                    Ok(None) => Err(Error::NoAccountToken),
+                    Err(error) => Err(Error::UpdateDeviceError(error)),
+                },
+                Err(device::Error::NoDevice) => Err(Error::NoAccountToken),
+                Err(error) => Err(Error::UpdateDeviceError(error)),
+            };
+            Self::oneshot_send(tx, result, "get_device_ports response");
+        });
+    }
+
+    async fn on_list_devices(&self, tx: ResponseTx<Vec<Device>, Error>, token: AccountToken) {
+        let service = self.account_manager.device_service.clone();
+        tokio::spawn(async move {
+            Self::oneshot_send(
+                tx,
+                service
+                    .list_devices(token)
+                    .await
+                    .map_err(Error::ListDevicesError),
+                "list_devices response",
+            );
+        });
+    }
+
+    async fn on_remove_device(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        token: AccountToken,
+        device_id: DeviceId,
+    ) {
+        let device_service = self.account_manager.device_service.clone();
+        let event_listener = self.event_listener.clone();
+
+        tokio::spawn(async move {
+            let mut devices = match device_service
+                .list_devices(token.clone())
+                .await
+                .map_err(Error::ListDevicesError)
+            {
+                Ok(devices) => devices,
+                Err(error) => {
+                    Self::oneshot_send(tx, Err(error), "remove_device response");
+                    return;
+                }
+            };
+            if let Err(error) = device_service
+                .remove_device(token.clone(), device_id.clone())
+                .await
+                .map_err(Error::RemoveDeviceError)
+            {
+                Self::oneshot_send(tx, Err(error), "remove_device response");
+                return;
+            };
+            let removed_device =
+                if let Some(index) = devices.iter().position(|device| device.id == device_id) {
+                    devices.swap_remove(index)
+                } else {
+                    log::error!("List did not contain the revoked device");
+                    Device {
+                        id: device_id,
+                        name: "unknown device".to_string(),
+                        pubkey: talpid_types::net::wireguard::PublicKey::from([0u8; 32]),
+                        ports: vec![],
+                    }
+                };
+            event_listener.notify_remove_device_event(RemoveDeviceEvent {
+                account_token: token,
+                removed_device,
+                new_devices: devices,
+            });
+            Self::oneshot_send(tx, Ok(()), "remove_device response");
+        });
+    }
+
+    fn on_get_account_history(&mut self, tx: oneshot::Sender<Option<AccountToken>>) {
+        Self::oneshot_send(
+            tx,
+            self.account_history.get(),
+            "get_account_history response",
+        );
+    }
+
+    fn on_get_account_history_list(&mut self, tx: oneshot::Sender<Vec<AccountToken>>) {
+        Self::oneshot_send(
+            tx,
+            self.account_history.get_list(),
+            "get_account_history_list response",
+        );
+    }
+
+    async fn on_set_account_history_limit(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        limit: u32,
+    ) {
+        match self.settings.set_account_history_limit(limit).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_account_history_limit response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Err(error) = self.account_history.set_limit(limit as usize).await {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to update account history")
+                        );
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_account_history_limit response");
+            }
+        }
+    }
+
+    async fn on_clear_account_history(&mut self, tx: ResponseTx<(), Error>) {
+        let result = self
+            .account_history
+            .clear()
+            .await
+            .map_err(Error::AccountHistory);
+        Self::oneshot_send(tx, result, "clear_account_history response");
+    }
+
+    async fn on_get_version_info(&mut self, tx: oneshot::Sender<Option<AppVersionInfo>>) {
+        if self.app_version_info.is_none() {
+            log::debug!("No version cache found. Fetching new info");
+            let mut handle = self.version_updater_handle.clone();
+            tokio::spawn(async move {
+                Self::oneshot_send(
+                    tx,
+                    handle
+                        .run_version_check()
+                        .await
+                        .map_err(|error| {
+                            log::error!(
+                                "{}",
+                                error.display_chain_with_msg("Error running version check")
+                            )
+                        })
+                        .ok(),
+                    "get_version_info response",
+                );
+            });
+        } else {
+            Self::oneshot_send(
+                tx,
+                self.app_version_info.clone(),
+                "get_version_info response",
+            );
+        }
+    }
+
+    fn on_get_current_version(&mut self, tx: oneshot::Sender<AppVersion>) {
+        Self::oneshot_send(
+            tx,
+            version::PRODUCT_VERSION.to_owned(),
+            "get_current_version response",
+        );
+    }
+
+    fn on_get_settings_version(&self, tx: oneshot::Sender<Option<u32>>) {
+        let version = self.settings.file_version();
+        Self::oneshot_send(tx, version, "get_settings_version response");
+    }
+
+    fn on_get_daemon_info(&mut self, tx: oneshot::Sender<DaemonInfo>) {
+        let commit = Some(version::COMMIT_HASH.trim())
+            .filter(|hash| !hash.is_empty())
+            .map(str::to_owned);
+        let info = DaemonInfo {
+            version: version::PRODUCT_VERSION.to_owned(),
+            commit,
+            started_at: self.started_at,
+            uptime: self.start_instant.elapsed(),
+        };
+        Self::oneshot_send(tx, info, "get_daemon_info response");
+    }
+
+    #[cfg(not(target_os = "android"))]
+    async fn on_factory_reset(&mut self, tx: ResponseTx<(), Error>) {
+        let mut last_error = Ok(());
+
+        if let Err(error) = self.account_manager.logout().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to clear device cache")
+            );
+            last_error = Err(Error::LogoutError(error));
+        }
+
+        if let Err(error) = self.account_history.clear().await {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to clear account history")
+            );
+            last_error = Err(Error::ClearAccountHistoryError(error));
+        }
+
+        if let Err(e) = self.settings.reset().await {
+            log::error!("Failed to reset settings: {}", e);
+            last_error = Err(Error::ClearSettingsError(e));
+        }
+
+        // Shut the daemon down.
+        self.trigger_shutdown_event();
+
+        self.shutdown_tasks.push(Box::pin(async move {
+            if let Err(e) = Self::clear_cache_directory().await {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to clear cache directory")
+                );
+                last_error = Err(Error::ClearCacheError);
+            }
+
+            if let Err(e) = Self::clear_log_directory().await {
+                log::error!(
+                    "{}",
+                    e.display_chain_with_msg("Failed to clear log directory")
+                );
+                last_error = Err(Error::ClearLogsError);
+            }
+            Self::oneshot_send(tx, last_error, "factory_reset response");
+        }));
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_get_split_tunnel_processes(&mut self, tx: ResponseTx<Vec<i32>, split_tunnel::Error>) {
+        let result = self.exclude_pids.list().map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to obtain PIDs"));
+            error
+        });
+        Self::oneshot_send(tx, result, "get_split_tunnel_processes response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_add_split_tunnel_process(&mut self, tx: ResponseTx<(), split_tunnel::Error>, pid: i32) {
+        let result = self.exclude_pids.add(pid).map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to add PID"));
+            error
+        });
+        Self::oneshot_send(tx, result, "add_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_remove_split_tunnel_process(
+        &mut self,
+        tx: ResponseTx<(), split_tunnel::Error>,
+        pid: i32,
+    ) {
+        let result = self.exclude_pids.remove(pid).map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to remove PID"));
+            error
+        });
+        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn on_clear_split_tunnel_processes(&mut self, tx: ResponseTx<(), split_tunnel::Error>) {
+        let result = self.exclude_pids.clear().map_err(|error| {
+            log::error!("{}", error.display_chain_with_msg("Unable to clear PIDs"));
+            error
+        });
+        Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_add_split_tunnel_cgroup(&mut self, tx: ResponseTx<(), Error>, cgroup: PathBuf) {
+        if let Err(error) = self.exclude_pids.add_cgroup(&cgroup) {
+            log::error!("{}", error.display_chain_with_msg("Unable to add cgroup"));
+            Self::oneshot_send(
+                tx,
+                Err(Error::SplitTunnelCgroupError(error)),
+                "add_split_tunnel_cgroup response",
+            );
+            return;
+        }
+
+        let mut cgroups = self.settings.split_tunnel_cgroups.clone();
+        cgroups.insert(cgroup);
+        self.set_split_tunnel_cgroups(tx, cgroups, "add_split_tunnel_cgroup response")
+            .await;
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_remove_split_tunnel_cgroup(&mut self, tx: ResponseTx<(), Error>, cgroup: PathBuf) {
+        if let Err(error) = self.exclude_pids.remove_cgroup(&cgroup) {
+            // The cgroup may no longer exist, so it is still removed from the settings
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Unable to remove cgroup")
+            );
+        }
+
+        let mut cgroups = self.settings.split_tunnel_cgroups.clone();
+        cgroups.remove(&cgroup);
+        self.set_split_tunnel_cgroups(tx, cgroups, "remove_split_tunnel_cgroup response")
+            .await;
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn set_split_tunnel_cgroups(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        cgroups: HashSet<PathBuf>,
+        response_msg: &'static str,
+    ) {
+        match self.settings.set_split_tunnel_cgroups(cgroups).await {
+            Ok(settings_changed) => {
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+                Self::oneshot_send(tx, Ok(()), response_msg);
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(Error::SettingsError(e)), response_msg);
+            }
+        }
+    }
+
+    /// Update the split app paths in both the settings and tunnel
+    #[cfg(windows)]
+    async fn set_split_tunnel_paths(
+        &mut self,
+        tx: ResponseTx<(), Error>,
+        response_msg: &'static str,
+        settings: Settings,
+        update: ExcludedPathsUpdate,
+    ) {
+        if let Some(tunnel_list) = excluded_apps_after_update(&settings.split_tunnel, &update) {
+            let (result_tx, result_rx) = oneshot::channel();
+            self.send_tunnel_command(TunnelCommand::SetExcludedApps(result_tx, tunnel_list));
+            let daemon_tx = self.tx.clone();
+
+            tokio::spawn(async move {
+                match result_rx.await {
+                    Ok(Ok(_)) => (),
+                    Ok(Err(error)) => {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg("Failed to set excluded apps list")
+                        );
+                        Self::oneshot_send(tx, Err(Error::SplitTunnelError(error)), response_msg);
+                        return;
+                    }
+                    Err(_) => {
+                        log::error!("The tunnel failed to return a result");
+                        return;
+                    }
+                }
+
+                let _ = daemon_tx.send(InternalDaemonEvent::ExcludedPathsEvent(update, tx));
+            });
+        } else {
+            let _ = self
+                .tx
+                .send(InternalDaemonEvent::ExcludedPathsEvent(update, tx));
+        }
+    }
+
+    #[cfg(windows)]
+    async fn on_add_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
+        let settings = self.settings.to_settings();
+
+        let mut new_list = settings.split_tunnel.apps.clone();
+        new_list.insert(path);
+
+        self.set_split_tunnel_paths(
+            tx,
+            "add_split_tunnel_app response",
+            settings,
+            ExcludedPathsUpdate::SetPaths(new_list),
+        )
+        .await;
+    }
+
+    #[cfg(windows)]
+    async fn on_remove_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
+        let settings = self.settings.to_settings();
+
+        let mut new_list = settings.split_tunnel.apps.clone();
+        new_list.remove(&path);
+
+        self.set_split_tunnel_paths(
+            tx,
+            "remove_split_tunnel_app response",
+            settings,
+            ExcludedPathsUpdate::SetPaths(new_list),
+        )
+        .await;
+    }
+
+    #[cfg(windows)]
+    async fn on_clear_split_tunnel_apps(&mut self, tx: ResponseTx<(), Error>) {
+        let settings = self.settings.to_settings();
+        let new_list = HashSet::new();
+        self.set_split_tunnel_paths(
+            tx,
+            "clear_split_tunnel_apps response",
+            settings,
+            ExcludedPathsUpdate::SetPaths(new_list),
+        )
+        .await;
+    }
+
+    #[cfg(windows)]
+    async fn on_set_split_tunnel_state(&mut self, tx: ResponseTx<(), Error>, state: bool) {
+        let settings = self.settings.to_settings();
+        self.set_split_tunnel_paths(
+            tx,
+            "set_split_tunnel_state response",
+            settings,
+            ExcludedPathsUpdate::SetState(state),
+        )
+        .await;
+    }
+
+    #[cfg(windows)]
+    async fn on_set_split_tunnel_keep_exclusions_on_reconnect(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        keep_exclusions: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_split_tunnel_keep_exclusions_on_reconnect(keep_exclusions)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(
+                    tx,
+                    Ok(()),
+                    "set_split_tunnel_keep_exclusions_on_reconnect response",
+                );
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(
+                    tx,
+                    Err(e),
+                    "set_split_tunnel_keep_exclusions_on_reconnect response",
+                );
+            }
+        }
+    }
+
+    /// Reinstalls the excluded apps in the split tunnel driver before reconnecting, unless they
+    /// should be kept as they are.
+    #[cfg(windows)]
+    fn reapply_split_tunnel_apps(&mut self) {
+        let tunnel_list = match excluded_apps_on_reconnect(&self.settings.split_tunnel) {
+            Some(tunnel_list) => tunnel_list,
+            None => return,
+        };
+        let (result_tx, result_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::SetExcludedApps(result_tx, tunnel_list));
+        tokio::spawn(async move {
+            if let Ok(Err(error)) = result_rx.await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to reapply excluded apps list")
+                );
+            }
+        });
+    }
+
+    #[cfg(windows)]
+    async fn on_use_wireguard_nt(&mut self, tx: ResponseTx<(), Error>, state: bool) {
+        let save_result = self
+            .settings
+            .set_use_wireguard_nt(state)
+            .await
+            .map_err(Error::SettingsError);
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "use_wireguard_nt response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        if self.should_reconnect_for(ReconnectTrigger::WireguardOptions) {
+                            log::info!("Initiating tunnel restart");
+                            self.reconnect_tunnel();
+                        }
+                    }
+                }
+            }
+            Err(error) => {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to save settings")
+                );
+                Self::oneshot_send(tx, Err(error), "use_wireguard_nt response");
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    async fn on_check_volumes(&mut self, tx: ResponseTx<(), Error>) {
+        if self.volume_update_tx.unbounded_send(()).is_ok() {
+            let _ = tx.send(Ok(()));
+        }
+    }
+
+    async fn on_update_relay_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        update: RelaySettingsUpdate,
+    ) {
+        let save_result = self.settings.update_relay_settings(update).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.relay_selector
+                        .set_config(new_selector_config(&self.settings));
+                    if self.should_reconnect_for(ReconnectTrigger::RelaySettings) {
+                        log::info!("Initiating tunnel restart because the relay settings changed");
+                        self.reconnect_for_relay_change().await;
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "update_relay_settings response");
+            }
+        }
+    }
+
+    async fn on_set_allow_lan(&mut self, tx: ResponseTx<(), settings::Error>, allow_lan: bool) {
+        let save_result = self.settings.set_allow_lan(allow_lan).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allow_lan response");
+            }
+        }
+    }
+
+    async fn on_set_show_beta_releases(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        enabled: bool,
+    ) {
+        let save_result = self.settings.set_show_beta_releases(enabled).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_show_beta_releases response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    let mut handle = self.version_updater_handle.clone();
+                    handle.set_show_beta_releases(enabled).await;
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_show_beta_releases response");
+            }
+        }
+    }
+
+    async fn on_set_block_when_disconnected(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        block_when_disconnected: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_block_when_disconnected(block_when_disconnected)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_block_when_disconnected response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
+                        block_when_disconnected,
+                    ));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_block_when_disconnected response");
+            }
+        }
+    }
+
+    async fn on_set_block_ipv6(&mut self, tx: ResponseTx<(), settings::Error>, block_ipv6: bool) {
+        let save_result = self.settings.set_block_ipv6(block_ipv6).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_block_ipv6 response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::BlockIpv6(
+                        self.settings.should_block_ipv6(),
+                    ));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_block_ipv6 response");
+            }
+        }
     }
 
     #[cfg(target_os = "linux")]
-    fn on_remove_split_tunnel_process(
+    async fn on_set_allow_excluded_when_blocked(
         &mut self,
-        tx: ResponseTx<(), split_tunnel::Error>,
-        pid: i32,
+        tx: ResponseTx<(), settings::Error>,
+        allow_excluded: bool,
     ) {
-        let result = self.exclude_pids.remove(pid).map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to remove PID"));
-            error
-        });
-        Self::oneshot_send(tx, result, "remove_split_tunnel_process response");
+        let save_result = self
+            .settings
+            .set_allow_excluded_when_blocked(allow_excluded)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allow_excluded_when_blocked response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::AllowExcludedWhenBlocked(
+                        allow_excluded,
+                    ));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allow_excluded_when_blocked response");
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    async fn on_set_linux_routing_options(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        options: LinuxRoutingOptions,
+    ) {
+        let save_result = self.settings.set_linux_routing_options(options).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_linux_routing_options response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::LinuxRoutingOptions(options));
+                    if self.should_reconnect_for(ReconnectTrigger::RoutingOptions) {
+                        log::info!("Initiating tunnel restart because the routing options changed");
+                        self.reconnect_tunnel();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_linux_routing_options response");
+            }
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    async fn on_set_allowed_hosts(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        hosts: Vec<AllowedHost>,
+    ) {
+        match self.settings.set_allowed_hosts(hosts.clone()).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_allowed_hosts response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.allowed_hosts.set_hosts(hosts);
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_allowed_hosts response");
+            }
+        }
+    }
+
+    async fn on_set_on_demand_rules(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        rules: OnDemandRules,
+    ) {
+        let save_result = self.settings.set_on_demand_rules(rules).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_on_demand_rules response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.apply_on_demand_rules().await;
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_on_demand_rules response");
+            }
+        }
+    }
+
+    async fn on_set_reconnect_policy(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        trigger: ReconnectTrigger,
+        policy: ReconnectPolicy,
+    ) {
+        match self.settings.set_reconnect_policy(trigger, policy).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_reconnect_policy response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_reconnect_policy response");
+            }
+        }
+    }
+
+    async fn on_set_on_unverified_connection(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        policy: UnverifiedPolicy,
+    ) {
+        match self.settings.set_on_unverified_connection(policy).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_on_unverified_connection response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_on_unverified_connection response");
+            }
+        }
+    }
+
+    async fn on_set_on_account_expiry(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        policy: ExpiryPolicy,
+    ) {
+        match self.settings.set_on_account_expiry(policy).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_on_account_expiry response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    let tunnel_state = self.tunnel_state.clone();
+                    self.update_account_expiry_check(&tunnel_state);
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_on_account_expiry response");
+            }
+        }
+    }
+
+    async fn on_set_connect_deadline_to_block(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        deadline: Option<Duration>,
+    ) {
+        match self.settings.set_connect_deadline_to_block(deadline).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_connect_deadline_to_block response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    // An attempt in progress gets the full new deadline.
+                    self.reset_connect_deadline();
+                    if matches!(self.tunnel_state, TunnelState::Connecting { .. }) {
+                        self.start_connect_deadline();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_connect_deadline_to_block response");
+            }
+        }
+    }
+
+    async fn on_set_api_client_tag(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        tag: Option<String>,
+    ) {
+        match self.settings.set_api_client_tag(tag).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_api_client_tag response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    Self::apply_api_client_tag(
+                        &self.api_handle,
+                        self.settings.api_client_tag.as_deref(),
+                    );
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_api_client_tag response");
+            }
+        }
+    }
+
+    async fn on_set_api_concurrency_limit(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        limit: Option<u32>,
+    ) {
+        match self.settings.set_api_concurrency_limit(limit).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_api_concurrency_limit response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    Self::apply_api_concurrency_limit(&self.api_handle, limit);
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_api_concurrency_limit response");
+            }
+        }
+    }
+
+    async fn on_set_api_bind_interface(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        address: Option<IpAddr>,
+    ) {
+        match self.settings.set_api_bind_interface(address).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_api_bind_interface response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    Self::apply_api_bind_interface(&self.api_handle, self.api_bind_address());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_api_bind_interface response");
+            }
+        }
     }
 
-    #[cfg(target_os = "linux")]
-    fn on_clear_split_tunnel_processes(&mut self, tx: ResponseTx<(), split_tunnel::Error>) {
-        let result = self.exclude_pids.clear().map_err(|error| {
-            log::error!("{}", error.display_chain_with_msg("Unable to clear PIDs"));
-            error
-        });
-        Self::oneshot_send(tx, result, "clear_split_tunnel_processes response");
+    fn on_get_relay_list_update_interval(&self, tx: oneshot::Sender<Duration>) {
+        Self::oneshot_send(
+            tx,
+            self.settings.relay_list_update_interval,
+            "get_relay_list_update_interval response",
+        );
     }
 
-    /// Update the split app paths in both the settings and tunnel
-    #[cfg(windows)]
-    async fn set_split_tunnel_paths(
+    async fn on_set_relay_list_update_interval(
         &mut self,
-        tx: ResponseTx<(), Error>,
-        response_msg: &'static str,
-        settings: Settings,
-        update: ExcludedPathsUpdate,
+        tx: ResponseTx<(), settings::Error>,
+        interval: Duration,
     ) {
-        let new_list = match update {
-            ExcludedPathsUpdate::SetPaths(ref paths) => {
-                if *paths == settings.split_tunnel.apps {
-                    Self::oneshot_send(tx, Ok(()), response_msg);
-                    return;
+        match self.settings.set_relay_list_update_interval(interval).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_relay_list_update_interval response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.relay_list_updater.set_update_interval(interval).await;
                 }
-                paths.iter()
             }
-            ExcludedPathsUpdate::SetState(_) => settings.split_tunnel.apps.iter(),
-        };
-        let new_state = match update {
-            ExcludedPathsUpdate::SetPaths(_) => settings.split_tunnel.enable_exclusions,
-            ExcludedPathsUpdate::SetState(state) => {
-                if state == settings.split_tunnel.enable_exclusions {
-                    Self::oneshot_send(tx, Ok(()), response_msg);
-                    return;
-                }
-                state
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_relay_list_update_interval response");
             }
-        };
-
-        if new_state || new_state != settings.split_tunnel.enable_exclusions {
-            let tunnel_list = if new_state {
-                new_list.map(|s| OsString::from(s)).collect()
-            } else {
-                vec![]
-            };
+        }
+    }
 
-            let (result_tx, result_rx) = oneshot::channel();
-            self.send_tunnel_command(TunnelCommand::SetExcludedApps(result_tx, tunnel_list));
-            let daemon_tx = self.tx.clone();
+    fn apply_api_client_tag(api_handle: &mullvad_api::rest::MullvadRestHandle, tag: Option<&str>) {
+        if let Err(error) = api_handle.service().set_user_agent_tag(tag) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set the API client tag")
+            );
+        }
+    }
 
-            tokio::spawn(async move {
-                match result_rx.await {
-                    Ok(Ok(_)) => (),
-                    Ok(Err(error)) => {
-                        log::error!(
-                            "{}",
-                            error.display_chain_with_msg("Failed to set excluded apps list")
-                        );
-                        Self::oneshot_send(tx, Err(Error::SplitTunnelError(error)), response_msg);
-                        return;
-                    }
-                    Err(_) => {
-                        log::error!("The tunnel failed to return a result");
-                        return;
-                    }
-                }
+    fn apply_api_concurrency_limit(
+        api_handle: &mullvad_api::rest::MullvadRestHandle,
+        limit: Option<u32>,
+    ) {
+        let limit = limit.map(|limit| usize::try_from(limit).unwrap_or(usize::MAX));
+        if let Err(error) = api_handle.service().set_concurrency_limit(limit) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set the API concurrency limit")
+            );
+        }
+    }
 
-                let _ = daemon_tx.send(InternalDaemonEvent::ExcludedPathsEvent(update, tx));
-            });
+    /// Returns the address to bind API sockets to. Nothing is bound while connected, since API
+    /// traffic is then sent through the tunnel.
+    fn api_bind_address(&self) -> Option<IpAddr> {
+        if self.tunnel_state.is_connected() {
+            None
         } else {
-            let _ = self
-                .tx
-                .send(InternalDaemonEvent::ExcludedPathsEvent(update, tx));
+            self.settings.api_bind_interface
         }
     }
 
-    #[cfg(windows)]
-    async fn on_add_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
-        let settings = self.settings.to_settings();
-
-        let mut new_list = settings.split_tunnel.apps.clone();
-        new_list.insert(path);
-
-        self.set_split_tunnel_paths(
-            tx,
-            "add_split_tunnel_app response",
-            settings,
-            ExcludedPathsUpdate::SetPaths(new_list),
-        )
-        .await;
+    fn apply_api_bind_interface(
+        api_handle: &mullvad_api::rest::MullvadRestHandle,
+        address: Option<IpAddr>,
+    ) {
+        if let Err(error) = api_handle.service().set_bind_address(address) {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set the API bind address")
+            );
+        }
     }
 
-    #[cfg(windows)]
-    async fn on_remove_split_tunnel_app(&mut self, tx: ResponseTx<(), Error>, path: PathBuf) {
-        let settings = self.settings.to_settings();
+    async fn on_set_api_bridge(&mut self, tx: ResponseTx<(), Error>, hostname: Option<String>) {
+        if let Some(hostname) = &hostname {
+            if !self.relay_selector.is_bridge(hostname) {
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::UnknownBridge(hostname.clone())),
+                    "set_api_bridge response",
+                );
+                return;
+            }
+        }
 
-        let mut new_list = settings.split_tunnel.apps.clone();
-        new_list.remove(&path);
+        match self.settings.set_api_bridge(hostname.clone()).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_api_bridge response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    *self.api_bridge.lock().unwrap() = hostname;
+                    if let Err(error) = self.api_handle.service().next_api_endpoint().await {
+                        log::error!("Failed to rotate API endpoint: {}", error);
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(Error::SettingsError(e)), "set_api_bridge response");
+            }
+        }
+    }
 
-        self.set_split_tunnel_paths(
-            tx,
-            "remove_split_tunnel_app response",
-            settings,
-            ExcludedPathsUpdate::SetPaths(new_list),
-        )
-        .await;
+    async fn on_set_backup_relay_settings(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        relay_settings: Option<RelaySettings>,
+    ) {
+        let clear = relay_settings.is_none();
+        match self
+            .settings
+            .set_backup_relay_settings(relay_settings)
+            .await
+        {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_backup_relay_settings response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if clear {
+                        self.reset_backup_relay_settings();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_backup_relay_settings response");
+            }
+        }
     }
 
-    #[cfg(windows)]
-    async fn on_clear_split_tunnel_apps(&mut self, tx: ResponseTx<(), Error>) {
-        let settings = self.settings.to_settings();
-        let new_list = HashSet::new();
-        self.set_split_tunnel_paths(
-            tx,
-            "clear_split_tunnel_apps response",
-            settings,
-            ExcludedPathsUpdate::SetPaths(new_list),
-        )
-        .await;
+    async fn on_set_reconnect_on_relay_removal(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        reconnect_on_relay_removal: bool,
+    ) {
+        let save_result = self
+            .settings
+            .set_reconnect_on_relay_removal(reconnect_on_relay_removal)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_reconnect_on_relay_removal response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_reconnect_on_relay_removal response");
+            }
+        }
     }
 
-    #[cfg(windows)]
-    async fn on_set_split_tunnel_state(&mut self, tx: ResponseTx<(), Error>, state: bool) {
-        let settings = self.settings.to_settings();
-        self.set_split_tunnel_paths(
-            tx,
-            "set_split_tunnel_state response",
-            settings,
-            ExcludedPathsUpdate::SetState(state),
-        )
-        .await;
+    async fn on_set_connection_retry_backoff(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        backoff: ConnectionRetryBackoff,
+    ) {
+        let save_result = self.settings.set_connection_retry_backoff(backoff).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_connection_retry_backoff response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.send_tunnel_command(TunnelCommand::ConnectionRetryBackoff(backoff));
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_connection_retry_backoff response");
+            }
+        }
     }
 
-    #[cfg(windows)]
-    async fn on_use_wireguard_nt(&mut self, tx: ResponseTx<(), Error>, state: bool) {
+    async fn on_set_route_change_reconnect(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        route_change_reconnect: RouteChangeReconnect,
+    ) {
         let save_result = self
             .settings
-            .set_use_wireguard_nt(state)
-            .await
-            .map_err(Error::SettingsError);
+            .set_route_change_reconnect(route_change_reconnect)
+            .await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "use_wireguard_nt response");
+                Self::oneshot_send(tx, Ok(()), "set_route_change_reconnect response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
-                        log::info!("Initiating tunnel restart");
-                        self.reconnect_tunnel();
-                    }
+                    self.send_tunnel_command(TunnelCommand::RouteChangeReconnect(
+                        route_change_reconnect,
+                    ));
                 }
             }
-            Err(error) => {
-                log::error!(
-                    "{}",
-                    error.display_chain_with_msg("Unable to save settings")
-                );
-                Self::oneshot_send(tx, Err(error), "use_wireguard_nt response");
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_route_change_reconnect response");
             }
         }
     }
 
-    #[cfg(windows)]
-    async fn on_check_volumes(&mut self, tx: ResponseTx<(), Error>) {
-        if self.volume_update_tx.unbounded_send(()).is_ok() {
-            let _ = tx.send(Ok(()));
+    async fn on_set_protocol_fallback_threshold(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        threshold: Option<u32>,
+    ) {
+        let save_result = self
+            .settings
+            .set_protocol_fallback_threshold(threshold)
+            .await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_protocol_fallback_threshold response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if threshold.is_none() {
+                        self.reset_protocol_fallback();
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_protocol_fallback_threshold response");
+            }
         }
     }
 
-    async fn on_update_relay_settings(
+    async fn on_set_deprioritize_failing_relays(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        update: RelaySettingsUpdate,
+        deprioritize_failing_relays: bool,
     ) {
-        let save_result = self.settings.update_relay_settings(update).await;
-        match save_result {
+        match self
+            .settings
+            .set_deprioritize_failing_relays(deprioritize_failing_relays)
+            .await
+        {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "update_relay_settings response");
+                Self::oneshot_send(tx, Ok(()), "set_deprioritize_failing_relays response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     self.relay_selector
                         .set_config(new_selector_config(&self.settings));
-                    log::info!("Initiating tunnel restart because the relay settings changed");
-                    self.reconnect_tunnel();
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "update_relay_settings response");
+                Self::oneshot_send(tx, Err(e), "set_deprioritize_failing_relays response");
             }
         }
     }
 
-    async fn on_set_allow_lan(&mut self, tx: ResponseTx<(), settings::Error>, allow_lan: bool) {
-        let save_result = self.settings.set_allow_lan(allow_lan).await;
-        match save_result {
+    async fn on_set_retry_same_relay(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        retry_same_relay: bool,
+    ) {
+        match self.settings.set_retry_same_relay(retry_same_relay).await {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_allow_lan response");
+                Self::oneshot_send(tx, Ok(()), "set_retry_same_relay response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::AllowLan(allow_lan));
+                    self.relay_selector
+                        .set_config(new_selector_config(&self.settings));
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_allow_lan response");
+                Self::oneshot_send(tx, Err(e), "set_retry_same_relay response");
             }
         }
     }
 
-    async fn on_set_show_beta_releases(
+    async fn on_set_openvpn_endpoint_preference(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
-        enabled: bool,
+        preference: Vec<TransportPort>,
     ) {
-        let save_result = self.settings.set_show_beta_releases(enabled).await;
-        match save_result {
+        match self
+            .settings
+            .set_openvpn_endpoint_preference(preference)
+            .await
+        {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_show_beta_releases response");
+                Self::oneshot_send(tx, Ok(()), "set_openvpn_endpoint_preference response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    let mut handle = self.version_updater_handle.clone();
-                    handle.set_show_beta_releases(enabled).await;
+                    self.relay_selector
+                        .set_config(new_selector_config(&self.settings));
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_show_beta_releases response");
+                Self::oneshot_send(tx, Err(e), "set_openvpn_endpoint_preference response");
             }
         }
     }
 
-    async fn on_set_block_when_disconnected(
+    async fn on_set_selection_telemetry_path(
         &mut self,
-        tx: ResponseTx<(), settings::Error>,
-        block_when_disconnected: bool,
+        tx: ResponseTx<(), Error>,
+        path: Option<PathBuf>,
     ) {
+        if let Some(path) = &path {
+            if let Err(error) = self.selection_telemetry.validate_path(path).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Invalid selection telemetry path")
+                );
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::SelectionTelemetryPathError(error)),
+                    "set_selection_telemetry_path response",
+                );
+                return;
+            }
+        }
+
         let save_result = self
             .settings
-            .set_block_when_disconnected(block_when_disconnected)
+            .set_selection_telemetry_path(path.clone())
             .await;
         match save_result {
             Ok(settings_changed) => {
-                Self::oneshot_send(tx, Ok(()), "set_block_when_disconnected response");
+                Self::oneshot_send(tx, Ok(()), "set_selection_telemetry_path response");
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    self.send_tunnel_command(TunnelCommand::BlockWhenDisconnected(
-                        block_when_disconnected,
-                    ));
+                    self.selection_telemetry.set_path(path);
                 }
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_block_when_disconnected response");
+                Self::oneshot_send(
+                    tx,
+                    Err(Error::SettingsError(e)),
+                    "set_selection_telemetry_path response",
+                );
             }
         }
     }
@@ -2082,10 +5228,12 @@ where
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     if let Some(TunnelType::OpenVpn) = self.get_connected_tunnel_type() {
-                        log::info!(
-                            "Initiating tunnel restart because the OpenVPN mssfix setting changed"
-                        );
-                        self.reconnect_tunnel();
+                        if self.should_reconnect_for(ReconnectTrigger::Mtu) {
+                            log::info!(
+                                "Initiating tunnel restart because the OpenVPN mssfix setting changed"
+                            );
+                            self.reconnect_tunnel();
+                        }
                     }
                 }
             }
@@ -2111,7 +5259,9 @@ where
                     if let Err(error) = self.api_handle.service().next_api_endpoint().await {
                         log::error!("Failed to rotate API endpoint: {}", error);
                     }
-                    self.reconnect_tunnel();
+                    if self.should_reconnect_for(ReconnectTrigger::BridgeSettings) {
+                        self.reconnect_tunnel();
+                    }
                 };
                 Self::oneshot_send(tx, Ok(()), "set_bridge_settings");
             }
@@ -2138,7 +5288,9 @@ where
                         .notify_settings(self.settings.to_settings());
                     self.relay_selector
                         .set_config(new_selector_config(&self.settings));
-                    self.reconnect_tunnel();
+                    if self.should_reconnect_for(ReconnectTrigger::Obfuscation) {
+                        self.reconnect_tunnel();
+                    }
                 }
                 Self::oneshot_send(tx, Ok(()), "set_obfuscation_settings");
             }
@@ -2164,8 +5316,10 @@ where
                         .notify_settings(self.settings.to_settings());
                     self.relay_selector
                         .set_config(new_selector_config(&self.settings));
-                    log::info!("Initiating tunnel restart because bridge state changed");
-                    self.reconnect_tunnel();
+                    if self.should_reconnect_for(ReconnectTrigger::BridgeSettings) {
+                        log::info!("Initiating tunnel restart because bridge state changed");
+                        self.reconnect_tunnel();
+                    }
                 }
                 Ok(())
             }
@@ -2188,8 +5342,15 @@ where
                 if settings_changed {
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
-                    log::info!("Initiating tunnel restart because the enable IPv6 setting changed");
-                    self.reconnect_tunnel();
+                    self.send_tunnel_command(TunnelCommand::BlockIpv6(
+                        self.settings.should_block_ipv6(),
+                    ));
+                    if self.should_reconnect_for(ReconnectTrigger::Ipv6) {
+                        log::info!(
+                            "Initiating tunnel restart because the enable IPv6 setting changed"
+                        );
+                        self.reconnect_tunnel();
+                    }
                 }
             }
             Err(e) => {
@@ -2199,21 +5360,106 @@ where
         }
     }
 
+    fn on_test_dns_servers(&self, tx: ResponseTx<Vec<DnsTestResult>, Error>, servers: Vec<IpAddr>) {
+        #[cfg(not(target_os = "android"))]
+        let tunnel_command_tx = self.tunnel_command_tx.clone();
+        tokio::spawn(async move {
+            // Allow the queries through the firewall while they are in flight
+            #[cfg(not(target_os = "android"))]
+            {
+                let (allowed_tx, allowed_rx) = oneshot::channel();
+                let _ = tunnel_command_tx
+                    .unbounded_send(TunnelCommand::AllowDnsProbe(servers.clone(), allowed_tx));
+                let _ = allowed_rx.await;
+            }
+
+            let results = dns::test_servers(&servers).await;
+
+            #[cfg(not(target_os = "android"))]
+            let _ = tunnel_command_tx.unbounded_send(TunnelCommand::RevokeDnsProbe(servers));
+
+            Self::oneshot_send(tx, Ok(results), "test_dns_servers response");
+        });
+    }
+
+    async fn on_run_leak_test(&mut self, tx: ResponseTx<LeakTestReport, Error>) {
+        let endpoint = match &self.tunnel_state {
+            TunnelState::Connected { endpoint, .. } => endpoint.clone(),
+            _ => {
+                Self::oneshot_send(tx, Err(Error::TunnelNotConnected), "run_leak_test response");
+                return;
+            }
+        };
+        // Traffic exits through the exit relay, or the custom endpoint if no relay was selected
+        let exit_ips = match &self.last_generated_relays {
+            Some(LastSelectedRelays::WireGuard { wg_exit: relay, .. }) => {
+                Self::relay_addresses(relay)
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(LastSelectedRelays::OpenVpn { relay, .. }) => Self::relay_addresses(relay),
+            None => vec![endpoint.endpoint.address.ip()],
+        };
+        // The address that traffic leaving the device outside the tunnel is sent to
+        #[cfg(any(target_os = "macos", windows))]
+        let relay_address = endpoint
+            .obfuscation
+            .map(|obfuscation| obfuscation.endpoint)
+            .or_else(|| endpoint.proxy.map(|proxy| proxy.endpoint))
+            .or(endpoint.entry_endpoint)
+            .unwrap_or(endpoint.endpoint)
+            .address
+            .ip();
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let interface_rx = {
+            let (interface_tx, interface_rx) = oneshot::channel();
+            self.send_tunnel_command(TunnelCommand::GetTunnelInterface(interface_tx));
+            interface_rx
+        };
+
+        let resolvers = dns::addresses_from_options(&self.settings.tunnel_options.dns_options)
+            .unwrap_or_default();
+        let ipv6 = self.settings.tunnel_options.generic.enable_ipv6;
+        let rest_service = self.api_runtime.rest_handle().await;
+        tokio::spawn(async move {
+            let tunnel = leak_test::TunnelInfo {
+                resolvers,
+                ipv6,
+                exit_ips,
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                interface: interface_rx.await.ok().flatten(),
+                #[cfg(any(target_os = "macos", windows))]
+                relay_address,
+            };
+            let report = leak_test::run(rest_service, tunnel).await;
+            if report.passed() {
+                log::info!("Leak test passed");
+            } else {
+                log::warn!("Leak test did not pass: {:?}", report);
+            }
+            Self::oneshot_send(tx, Ok(report), "run_leak_test response");
+        });
+    }
+
+    fn relay_addresses(relay: &Relay) -> Vec<IpAddr> {
+        std::iter::once(IpAddr::V4(relay.ipv4_addr_in))
+            .chain(relay.ipv6_addr_in.map(IpAddr::V6))
+            .collect()
+    }
+
     async fn on_set_dns_options(
         &mut self,
         tx: ResponseTx<(), settings::Error>,
         dns_options: DnsOptions,
     ) {
+        let previous_options = self.settings.tunnel_options.dns_options.clone();
         let save_result = self.settings.set_dns_options(dns_options.clone()).await;
         match save_result {
             Ok(settings_changed) => {
                 Self::oneshot_send(tx, Ok(()), "set_dns_options response");
                 if settings_changed {
-                    let settings = self.settings.to_settings();
-                    let resolvers =
-                        dns::addresses_from_options(&settings.tunnel_options.dns_options);
-                    self.event_listener.notify_settings(settings);
-                    self.send_tunnel_command(TunnelCommand::Dns(resolvers));
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    send_dns_update(&self.tunnel_command_tx, &previous_options, &dns_options);
                 }
             }
             Err(e) => {
@@ -2236,8 +5482,68 @@ where
                     self.event_listener
                         .notify_settings(self.settings.to_settings());
                     if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        if self.should_reconnect_for(ReconnectTrigger::Mtu) {
+                            log::info!(
+                                "Initiating tunnel restart because the WireGuard MTU setting changed"
+                            );
+                            self.reconnect_tunnel();
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_mtu response");
+            }
+        }
+    }
+
+    async fn on_set_wireguard_keepalive(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        interval: Option<u16>,
+    ) {
+        let save_result = self.settings.set_wireguard_keepalive(interval).await;
+        match save_result {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_wireguard_keepalive response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    if let Some(TunnelType::Wireguard) = self.get_connected_tunnel_type() {
+                        if self.should_reconnect_for(ReconnectTrigger::WireguardOptions) {
+                            log::info!(
+                                "Initiating tunnel restart because the WireGuard keepalive setting \
+                                 changed"
+                            );
+                            self.reconnect_tunnel();
+                        }
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
+                Self::oneshot_send(tx, Err(e), "set_wireguard_keepalive response");
+            }
+        }
+    }
+
+    async fn on_set_port_forwarding(
+        &mut self,
+        tx: ResponseTx<(), settings::Error>,
+        port_forwarding: bool,
+    ) {
+        match self.settings.set_port_forwarding(port_forwarding).await {
+            Ok(settings_changed) => {
+                Self::oneshot_send(tx, Ok(()), "set_port_forwarding response");
+                if settings_changed {
+                    self.event_listener
+                        .notify_settings(self.settings.to_settings());
+                    self.relay_selector
+                        .set_config(new_selector_config(&self.settings));
+                    if self.should_reconnect_for(ReconnectTrigger::RelaySettings) {
                         log::info!(
-                            "Initiating tunnel restart because the WireGuard MTU setting changed"
+                            "Initiating tunnel restart because the port forwarding constraint changed"
                         );
                         self.reconnect_tunnel();
                     }
@@ -2245,7 +5551,7 @@ where
             }
             Err(e) => {
                 log::error!("{}", e.display_chain_with_msg("Unable to save settings"));
-                Self::oneshot_send(tx, Err(e), "set_wireguard_mtu response");
+                Self::oneshot_send(tx, Err(e), "set_port_forwarding response");
             }
         }
     }
@@ -2284,6 +5590,17 @@ where
         }
     }
 
+    fn on_set_key_rotation_enabled(&self, tx: ResponseTx<(), Error>, enabled: bool) {
+        let manager = self.account_manager.clone();
+        tokio::spawn(async move {
+            let result = manager
+                .set_rotation_enabled(enabled)
+                .await
+                .map_err(Error::KeyRotationError);
+            Self::oneshot_send(tx, result, "set_key_rotation_enabled response");
+        });
+    }
+
     async fn on_rotate_wireguard_key(&self, tx: ResponseTx<(), Error>) {
         let manager = self.account_manager.clone();
         tokio::spawn(async move {
@@ -2296,6 +5613,20 @@ where
         });
     }
 
+    fn on_is_rotating_key(&self, tx: oneshot::Sender<bool>) {
+        let manager = self.account_manager.clone();
+        tokio::spawn(async move {
+            let is_rotating = manager.is_rotating_key().await.unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to check for key rotation")
+                );
+                false
+            });
+            Self::oneshot_send(tx, is_rotating, "is_rotating_key response");
+        });
+    }
+
     async fn on_get_wireguard_key(&self, tx: ResponseTx<Option<PublicKey>, Error>) {
         let result = if let Ok(Some(config)) = self.account_manager.data().await {
             Ok(Some(config.device.wg_data.get_public_key()))
@@ -2305,10 +5636,35 @@ where
         Self::oneshot_send(tx, result, "get_wireguard_key response");
     }
 
+    async fn on_get_wireguard_key_fingerprint(&self, tx: ResponseTx<Option<String>, Error>) {
+        let result = if let Ok(Some(config)) = self.account_manager.data().await {
+            Ok(Some(config.device.wg_data.get_public_key().fingerprint()))
+        } else {
+            Err(Error::NoAccountToken)
+        };
+        Self::oneshot_send(tx, result, "get_wireguard_key_fingerprint response");
+    }
+
     fn on_get_settings(&self, tx: oneshot::Sender<Settings>) {
         Self::oneshot_send(tx, self.settings.to_settings(), "get_settings response");
     }
 
+    fn on_get_non_default_settings(&self, tx: oneshot::Sender<serde_json::Value>) {
+        Self::oneshot_send(
+            tx,
+            settings::non_default_settings(&self.settings),
+            "get_non_default_settings response",
+        );
+    }
+
+    fn on_validate_settings(&self, tx: ResponseTx<Vec<settings::SettingsIssue>, Error>) {
+        Self::oneshot_send(
+            tx,
+            Ok(settings::validate_settings(&self.settings)),
+            "validate_settings response",
+        );
+    }
+
     fn oneshot_send<T>(tx: oneshot::Sender<T>, t: T, msg: &'static str) {
         if tx.send(t).is_err() {
             log::warn!("Unable to send {} to the daemon command sender", msg);
@@ -2382,6 +5738,11 @@ where
             log::debug!("Target state {:?} => {:?}", *self.target_state, new_state);
 
             self.target_state.set(new_state).await;
+            self.auto_connect_suppressed = None;
+            self.connect_trigger = match new_state {
+                TargetState::Secured => ConnectTrigger::ClientCommand,
+                TargetState::Unsecured => ConnectTrigger::NotConnected,
+            };
 
             match *self.target_state {
                 TargetState::Secured => self.connect_tunnel(),
@@ -2408,6 +5769,132 @@ where
         }
     }
 
+    /// Reconnects the tunnel after the relay settings changed. If only the exit relay of a
+    /// connected multihop tunnel changes, just the exit peer is replaced and the entry hop stays
+    /// up.
+    async fn reconnect_for_relay_change(&mut self) {
+        if *self.target_state != TargetState::Secured {
+            return;
+        }
+        let (parameters, selection, relays) = match self.exit_only_tunnel_parameters().await {
+            Some(exit_only) => exit_only,
+            None => {
+                self.connect_tunnel();
+                return;
+            }
+        };
+        log::debug!("Only the exit relay changed. Keeping the entry hop");
+
+        // If the exit peer cannot be replaced, the tunnel state machine reconnects by itself and
+        // new relays are selected for that connection attempt.
+        let (result_tx, result_rx) = oneshot::channel();
+        self.send_tunnel_command(TunnelCommand::SetExitPeer(Box::new(parameters), result_tx));
+        if !result_rx.await.unwrap_or(false) {
+            return;
+        }
+
+        self.selection_telemetry
+            .record_selection(
+                &selection.exit_relay,
+                selection.entry_relay.as_ref(),
+                &selection.endpoint,
+            )
+            .await;
+        self.pending_relay_outcome = std::iter::once(&selection.exit_relay)
+            .chain(selection.entry_relay.as_ref())
+            .cloned()
+            .collect();
+        self.set_last_generated_relays(relays);
+    }
+
+    /// Selects relays for the current relay settings, and returns tunnel parameters for them if
+    /// they differ from the last generated relays in the exit relay only. The selection is not
+    /// remembered by the relay selector.
+    async fn exit_only_tunnel_parameters(
+        &mut self,
+    ) -> Option<(TunnelParameters, NormalSelectedRelay, LastSelectedRelays)> {
+        if self.get_connected_tunnel_type() != Some(TunnelType::Wireguard)
+            || self.relay_override.is_some()
+            || self.protocol_fallback_active
+            || self.backup_relay_settings_active
+        {
+            return None;
+        }
+        let (entry, exit, obfuscator) = match &self.last_generated_relays {
+            Some(LastSelectedRelays::WireGuard {
+                wg_entry: Some(entry),
+                wg_exit,
+                obfuscator,
+            }) => (
+                entry.hostname.clone(),
+                wg_exit.hostname.clone(),
+                obfuscator.as_ref().map(|relay| relay.hostname.clone()),
+            ),
+            _ => return None,
+        };
+
+        let config = self.relay_selector.get_config();
+        let (constraints, obfuscator_selection) = match self.relay_selector.preview_relay(&config) {
+            Ok(SelectionOutcome {
+                relay: SelectedRelay::Normal(constraints),
+                bridge: None,
+                obfuscator,
+                ..
+            }) => (constraints, obfuscator),
+            _ => return None,
+        };
+        let new_entry = constraints.entry_relay.as_ref()?;
+        let new_obfuscator = obfuscator_selection
+            .as_ref()
+            .map(|obfuscator| obfuscator.relay.hostname.clone());
+        if new_entry.hostname != entry
+            || new_obfuscator != obfuscator
+            || constraints.exit_relay.hostname == exit
+        {
+            return None;
+        }
+
+        let data = match self.account_manager.data().await {
+            Ok(Some(data)) => data,
+            _ => return None,
+        };
+        let (parameters, relays) = self
+            .create_tunnel_parameters(
+                &constraints.exit_relay,
+                &constraints.entry_relay,
+                constraints.endpoint.clone(),
+                None,
+                obfuscator_selection,
+                data,
+            )
+            .await
+            .map_err(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to generate tunnel parameters")
+                );
+            })
+            .ok()?;
+        Some((parameters, constraints, relays))
+    }
+
+    /// Returns whether a change to the settings covered by `trigger` should reconnect the tunnel
+    /// now. Deferred changes are picked up when the tunnel connects the next time.
+    fn should_reconnect_for(&self, trigger: ReconnectTrigger) -> bool {
+        match self.settings.reconnect_policies.get(trigger) {
+            ReconnectPolicy::Immediate => true,
+            ReconnectPolicy::Deferred => {
+                if *self.target_state == TargetState::Secured {
+                    log::info!(
+                        "Not reconnecting for changed {}. The change applies on the next connection",
+                        trigger
+                    );
+                }
+                false
+            }
+        }
+    }
+
     fn get_connected_tunnel_type(&self) -> Option<TunnelType> {
         if let TunnelState::Connected {
             endpoint: TunnelEndpoint { tunnel_type, .. },
@@ -2497,6 +5984,13 @@ where
             tx: self.tx.clone(),
         }
     }
+
+    #[cfg(windows)]
+    pub fn resume_handle(&self) -> DaemonResumeHandle {
+        DaemonResumeHandle {
+            tx: self.tx.clone(),
+        }
+    }
 }
 
 pub struct DaemonShutdownHandle {
@@ -2509,6 +6003,20 @@ impl DaemonShutdownHandle {
     }
 }
 
+/// Used to notify the daemon that the machine resumed from sleep, on platforms where power events
+/// are received outside of the daemon.
+#[cfg(windows)]
+pub struct DaemonResumeHandle {
+    tx: DaemonEventSender,
+}
+
+#[cfg(windows)]
+impl DaemonResumeHandle {
+    pub fn notify_resumed(&self) {
+        let _ = self.tx.send(InternalDaemonEvent::SystemResumed);
+    }
+}
+
 struct MullvadTunnelParametersGenerator {
     tx: DaemonEventSender,
 }
@@ -2541,6 +6049,20 @@ impl TunnelParametersGenerator for MullvadTunnelParametersGenerator {
     }
 }
 
+/// A scheduled reconnect of a tunnel that has been paused.
+#[cfg(not(target_os = "android"))]
+struct PauseJob {
+    abort_handle: AbortHandle,
+    resume_at: DateTime<Utc>,
+}
+
+/// An account creation in progress. Whichever of the job and [DaemonCommand::CancelAccountCreation]
+/// sets `claimed` first decides whether the new account is logged in to.
+struct AccountCreationJob {
+    abort_handle: AbortHandle,
+    claimed: Arc<AtomicBool>,
+}
+
 /// Contains all relays that were selected last time when tunnel parameters were generated.
 enum LastSelectedRelays {
     /// Represents all relays generated for a WireGuard tunnel.
@@ -2560,12 +6082,67 @@ enum LastSelectedRelays {
     OpenVpn { relay: Relay, bridge: Option<Relay> },
 }
 
+/// Converts a failed API request into an error, telling network errors caused by API access being
+/// paused or the device being offline apart from other REST errors.
+fn map_api_error(
+    error: mullvad_api::rest::Error,
+    api_availability: &ApiAvailabilityHandle,
+) -> Error {
+    if error.is_network_error() {
+        let state = api_availability.get_state();
+        if state.is_offline() {
+            return Error::ApiOffline;
+        }
+        if state.is_background_paused() {
+            return Error::ApiPaused;
+        }
+    }
+    Error::RestError(error)
+}
+
+/// Replaces proxy credentials and the secrets of custom tunnel endpoints in `config`.
+fn redact_selector_config(mut config: SelectorConfig) -> SelectorConfig {
+    use crate::settings::REDACTED_VALUE;
+    use mullvad_types::ConnectionConfig;
+    use talpid_types::net::openvpn::ProxySettings;
+
+    if let BridgeSettings::Custom(proxy) = &mut config.bridge_settings {
+        match proxy {
+            ProxySettings::Local(_) => (),
+            ProxySettings::Remote(remote) => {
+                if let Some(auth) = &mut remote.auth {
+                    auth.username = REDACTED_VALUE.to_owned();
+                    auth.password = REDACTED_VALUE.to_owned();
+                }
+            }
+            ProxySettings::Shadowsocks(shadowsocks) => {
+                shadowsocks.password = REDACTED_VALUE.to_owned();
+            }
+        }
+    }
+    if let RelaySettings::CustomTunnelEndpoint(endpoint) = &mut config.relay_settings {
+        match &mut endpoint.config {
+            ConnectionConfig::OpenVpn(openvpn_config) => {
+                openvpn_config.username = REDACTED_VALUE.to_owned();
+                openvpn_config.password = REDACTED_VALUE.to_owned();
+            }
+            ConnectionConfig::Wireguard(wireguard_config) => {
+                wireguard_config.tunnel.private_key = wireguard::PrivateKey::from([0u8; 32]);
+            }
+        }
+    }
+    config
+}
+
 fn new_selector_config(settings: &Settings) -> SelectorConfig {
     SelectorConfig {
         relay_settings: settings.get_relay_settings(),
         bridge_state: settings.get_bridge_state(),
         bridge_settings: settings.bridge_settings.clone(),
         obfuscation_settings: settings.obfuscation_settings.clone(),
+        deprioritize_failing_relays: settings.deprioritize_failing_relays,
+        retry_same_relay: settings.retry_same_relay,
+        openvpn_endpoint_preference: settings.openvpn_endpoint_preference.clone(),
     }
 }
 
@@ -2605,3 +6182,75 @@ pub fn bump_filehandle_limit() {
         );
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_dns_update_does_not_reconnect() {
+        let (tunnel_command_tx, mut tunnel_command_rx) = mpsc::unbounded();
+        let mut options = DnsOptions::default();
+        options.default_options.block_ads = true;
+
+        send_dns_update(&tunnel_command_tx, &DnsOptions::default(), &options);
+        match tunnel_command_rx.try_next() {
+            Ok(Some(TunnelCommand::Dns(Some(resolvers)))) => {
+                assert_eq!(resolvers, vec!["100.64.0.1".parse::<IpAddr>().unwrap()])
+            }
+            _ => panic!("Expected the new resolvers to be sent"),
+        }
+        // Nothing else, such as a reconnect, is requested
+        assert!(tunnel_command_rx.try_next().is_err());
+
+        send_dns_update(&tunnel_command_tx, &options, &options);
+        assert!(tunnel_command_rx.try_next().is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_excluded_apps_not_resent_on_reconnect() {
+        let apps: HashSet<PathBuf> = [PathBuf::from(r"C:\app.exe")].into_iter().collect();
+        let settings = SplitTunnelSettings {
+            enable_exclusions: true,
+            apps: apps.clone(),
+            keep_exclusions_on_reconnect: true,
+        };
+
+        assert_eq!(excluded_apps_on_reconnect(&settings), None);
+        assert_eq!(
+            excluded_apps_after_update(&settings, &ExcludedPathsUpdate::SetPaths(apps.clone())),
+            None
+        );
+        assert_eq!(
+            excluded_apps_after_update(&settings, &ExcludedPathsUpdate::SetState(true)),
+            None
+        );
+
+        let reapplying = SplitTunnelSettings {
+            keep_exclusions_on_reconnect: false,
+            ..settings
+        };
+        assert_eq!(
+            excluded_apps_on_reconnect(&reapplying),
+            Some(vec![OsString::from(r"C:\app.exe")])
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_reconnect_keeps_exclusions_by_default() {
+        let settings = SplitTunnelSettings {
+            enable_exclusions: true,
+            apps: [PathBuf::from(r"C:\app.exe")].into_iter().collect(),
+            ..SplitTunnelSettings::default()
+        };
+        assert_eq!(excluded_apps_on_reconnect(&settings), None);
+
+        let settings: SplitTunnelSettings =
+            serde_json::from_str(r#"{"enable_exclusions": true, "apps": ["C:\\app.exe"]}"#)
+                .unwrap();
+        assert!(settings.keep_exclusions_on_reconnect);
+        assert_eq!(excluded_apps_on_reconnect(&settings), None);
+    }
+}
@@ -22,6 +22,9 @@ pub enum Error {
     SetLoggerError(#[error(source)] log::SetLoggerError),
 }
 
+/// Name of the daemon log file in the log directory.
+pub const DAEMON_LOG_FILENAME: &str = "daemon.log";
+
 pub const WARNING_SILENCED_CRATES: &[&str] = &["netlink_proto"];
 pub const SILENCED_CRATES: &[&str] = &[
     "h2",
@@ -16,8 +16,6 @@ mod shutdown;
 #[cfg(windows)]
 mod system_service;
 
-const DAEMON_LOG_FILENAME: &str = "daemon.log";
-
 fn main() {
     let config = cli::get_config();
     let log_dir = init_logging(config).unwrap_or_else(|error| {
@@ -45,7 +43,9 @@ fn main() {
 
 fn init_logging(config: &cli::Config) -> Result<Option<PathBuf>, String> {
     let log_dir = get_log_dir(config)?;
-    let log_file = log_dir.as_ref().map(|dir| dir.join(DAEMON_LOG_FILENAME));
+    let log_file = log_dir
+        .as_ref()
+        .map(|dir| dir.join(logging::DAEMON_LOG_FILENAME));
 
     logging::init_logger(
         config.log_level,
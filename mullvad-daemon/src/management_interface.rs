@@ -13,10 +13,11 @@ use mullvad_paths;
 use mullvad_types::settings::DnsOptions;
 use mullvad_types::{
     account::AccountToken,
+    location::GeoIpLocation,
     relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
     relay_list::RelayList,
     settings::Settings,
-    states::{TargetState, TunnelState},
+    states::{AutoConnectSuppressedReason, ConnectionReadyInfo, TargetState, TunnelState},
     version,
     wireguard::{RotationInterval, RotationIntervalError},
 };
@@ -95,6 +96,19 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(types::TunnelState::from(state)))
     }
 
+    async fn get_auto_connect_suppressed(
+        &self,
+        _: Request<()>,
+    ) -> ServiceResult<types::AutoConnectSuppressed> {
+        log::debug!("get_auto_connect_suppressed");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetAutoConnectSuppressed(tx))?;
+        match self.wait_for_result(rx).await? {
+            Some(reason) => Ok(Response::new(types::AutoConnectSuppressed::from(reason))),
+            None => Err(Status::not_found("auto-connect was not suppressed")),
+        }
+    }
+
     // Control the daemon and receive events
     //
 
@@ -144,6 +158,14 @@ impl ManagementService for ManagementServiceImpl {
         Ok(Response::new(version))
     }
 
+    async fn get_metrics(&self, _: Request<()>) -> ServiceResult<String> {
+        log::debug!("get_metrics");
+        let (tx, rx) = oneshot::channel();
+        self.send_command_to_daemon(DaemonCommand::GetMetrics(tx))?;
+        let metrics = self.wait_for_result(rx).await?;
+        Ok(Response::new(metrics))
+    }
+
     async fn get_version_info(&self, _: Request<()>) -> ServiceResult<types::AppVersionInfo> {
         log::debug!("get_version_info");
 
@@ -443,12 +465,12 @@ impl ManagementService for ManagementServiceImpl {
                     }),
                 })
             })
-            .map_err(|error: RestError| {
+            .map_err(|error| {
                 log::error!(
                     "Unable to get account data from API: {}",
                     error.display_chain()
                 );
-                map_rest_error(error)
+                map_daemon_error(error)
             })
     }
 
@@ -916,6 +938,68 @@ impl EventListener for ManagementInterfaceEventBroadcaster {
             )),
         })
     }
+
+    fn notify_session_label(&self, label: Option<String>) {
+        log::debug!("Broadcasting session label");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::SessionLabel(label.unwrap_or_default())),
+        })
+    }
+
+    fn notify_protocol_fallback(&self, active: bool) {
+        log::debug!("Broadcasting protocol fallback state");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ProtocolFallback(active)),
+        })
+    }
+
+    fn notify_backup_relay_settings(&self, active: bool) {
+        log::debug!("Broadcasting backup relay settings state");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::BackupRelaySettings(active)),
+        })
+    }
+
+    fn notify_endpoint_changed(&self, location: GeoIpLocation) {
+        log::debug!("Broadcasting endpoint change");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::EndpointChanged(
+                types::GeoIpLocation::from(location),
+            )),
+        })
+    }
+
+    fn notify_auto_connect_suppressed(&self, reason: AutoConnectSuppressedReason) {
+        log::debug!("Broadcasting suppressed auto-connect");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::AutoConnectSuppressed(
+                types::AutoConnectSuppressed::from(reason),
+            )),
+        })
+    }
+
+    fn notify_settings_write_failed(&self, error: String) {
+        log::debug!("Broadcasting settings write failure");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::SettingsWriteFailed(error)),
+        })
+    }
+
+    fn notify_post_upgrade_complete(&self) {
+        log::debug!("Broadcasting post-upgrade complete event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::PostUpgradeComplete(())),
+        })
+    }
+
+    fn notify_connection_ready(&self, info: ConnectionReadyInfo) {
+        log::debug!("Broadcasting connection ready event");
+        self.notify(types::DaemonEvent {
+            event: Some(daemon_event::Event::ConnectionReady(
+                types::ConnectionReadyInfo::from(info),
+            )),
+        })
+    }
 }
 
 impl ManagementInterfaceEventBroadcaster {
@@ -932,17 +1016,27 @@ fn map_daemon_error(error: crate::Error) -> Status {
 
     match error {
         DaemonError::RestError(error) => map_rest_error(error),
+        DaemonError::ApiPaused | DaemonError::ApiOffline => Status::unavailable(error.to_string()),
         DaemonError::SettingsError(error) => map_settings_error(error),
         DaemonError::AlreadyLoggedIn => Status::already_exists(error.to_string()),
+        DaemonError::AccountCreationCancelled => Status::cancelled(error.to_string()),
         DaemonError::LoginError(error) => map_device_error(error),
+        DaemonError::TooManyDevices(ref devices) => {
+            mullvad_management_interface::too_many_devices_status(
+                error.to_string(),
+                types::DeviceList::from(devices.clone()),
+            )
+        }
         DaemonError::LogoutError(error) => map_device_error(error),
         DaemonError::KeyRotationError(error) => map_device_error(error),
         DaemonError::ListDevicesError(error) => map_device_error(error),
         DaemonError::RemoveDeviceError(error) => map_device_error(error),
         DaemonError::UpdateDeviceError(error) => map_device_error(error),
+        DaemonError::DeviceMigrationError(error) => map_device_error(error),
         #[cfg(windows)]
         DaemonError::SplitTunnelError(error) => map_split_tunnel_error(error),
         DaemonError::AccountHistory(error) => map_account_history_error(error),
+        DaemonError::EmptyRelayList => Status::invalid_argument(error.to_string()),
         DaemonError::NoAccountToken | DaemonError::NoAccountTokenHistory => {
             Status::unauthenticated(error.to_string())
         }
@@ -1009,6 +1103,28 @@ fn map_settings_error(error: settings::Error) -> Status {
         settings::Error::SerializeError(..) | settings::Error::ParseError(..) => {
             Status::new(Code::Internal, error.to_string())
         }
+        settings::Error::InvalidProxyAuth
+        | settings::Error::InvalidWireguardKeepalive
+        | settings::Error::InvalidConnectionRetryBackoff
+        | settings::Error::InvalidRouteChangeDebounce
+        | settings::Error::InvalidProtocolFallbackThreshold
+        | settings::Error::InvalidAccountHistoryLimit
+        | settings::Error::InvalidApiClientTag
+        | settings::Error::InvalidApiConcurrencyLimit(_)
+        | settings::Error::InvalidApiBindInterface(_)
+        | settings::Error::ApiBindInterfaceNotLocal(..)
+        | settings::Error::InvalidRelayListUpdateInterval(..)
+        | settings::Error::InvalidConnectDeadline => {
+            Status::new(Code::InvalidArgument, error.to_string())
+        }
+        #[cfg(target_os = "linux")]
+        settings::Error::InvalidLinuxRoutingOptions => {
+            Status::new(Code::InvalidArgument, error.to_string())
+        }
+        #[cfg(windows)]
+        settings::Error::BlockIpv6Unsupported => {
+            Status::new(Code::Unimplemented, error.to_string())
+        }
     }
 }
 
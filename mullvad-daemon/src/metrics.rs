@@ -0,0 +1,195 @@
+//! Counters describing the daemon, rendered in the Prometheus text exposition format.
+//!
+//! The metric names are stable and may be relied upon by scrapers:
+//!
+//! - `mullvad_tunnel_state{state}`: 1 for the current tunnel state and 0 for all others.
+//! - `mullvad_tunnel_state_transitions_total{state}`: Number of times each state was entered.
+//! - `mullvad_tunnel_tx_bytes_total`: Bytes sent through the current tunnel. Only present while
+//!   connected through a WireGuard tunnel.
+//! - `mullvad_tunnel_rx_bytes_total`: Bytes received through the current tunnel. Only present
+//!   while connected through a WireGuard tunnel.
+//! - `mullvad_reconnects_total`: Number of connection attempts not started from the disconnected
+//!   state, i.e. reconnects and retries.
+//! - `mullvad_key_rotations_total`: Number of WireGuard key rotations.
+//! - `mullvad_api_errors_total`: Number of API requests that failed or received an error
+//!   response.
+
+use mullvad_types::states::TunnelState;
+use std::{collections::BTreeMap, fmt::Write};
+use talpid_core::tunnel::wireguard::TrafficStats;
+
+#[cfg(not(target_os = "android"))]
+const STATES: &[&str] = &[
+    "disconnected",
+    "paused",
+    "connecting",
+    "connected",
+    "disconnecting",
+    "error",
+];
+#[cfg(target_os = "android")]
+const STATES: &[&str] = &[
+    "disconnected",
+    "connecting",
+    "connected",
+    "disconnecting",
+    "error",
+];
+
+#[derive(Debug, Default, Clone)]
+pub struct Metrics {
+    state_transitions: BTreeMap<&'static str, u64>,
+    reconnects: u64,
+    key_rotations: u64,
+}
+
+impl Metrics {
+    pub fn record_state_transition(&mut self, previous: &TunnelState, new: &TunnelState) {
+        *self.state_transitions.entry(state_name(new)).or_default() += 1;
+        if matches!(new, TunnelState::Connecting { .. }) && !previous.is_disconnected() {
+            self.reconnects += 1;
+        }
+    }
+
+    pub fn record_key_rotation(&mut self) {
+        self.key_rotations += 1;
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(
+        &self,
+        state: &TunnelState,
+        traffic: Option<TrafficStats>,
+        api_errors: u64,
+    ) -> String {
+        let mut output = String::new();
+        let current_state = state_name(state);
+
+        write_header(
+            &mut output,
+            "mullvad_tunnel_state",
+            "gauge",
+            "Whether the tunnel is in the given state.",
+        );
+        for state in STATES {
+            let _ = writeln!(
+                output,
+                "mullvad_tunnel_state{{state=\"{}\"}} {}",
+                state,
+                u8::from(*state == current_state)
+            );
+        }
+
+        write_header(
+            &mut output,
+            "mullvad_tunnel_state_transitions_total",
+            "counter",
+            "Number of times the tunnel entered the given state.",
+        );
+        for state in STATES {
+            let _ = writeln!(
+                output,
+                "mullvad_tunnel_state_transitions_total{{state=\"{}\"}} {}",
+                state,
+                self.state_transitions.get(state).copied().unwrap_or(0)
+            );
+        }
+
+        if let Some(traffic) = traffic {
+            write_counter(
+                &mut output,
+                "mullvad_tunnel_tx_bytes_total",
+                "Bytes sent through the current tunnel.",
+                traffic.tx_bytes,
+            );
+            write_counter(
+                &mut output,
+                "mullvad_tunnel_rx_bytes_total",
+                "Bytes received through the current tunnel.",
+                traffic.rx_bytes,
+            );
+        }
+
+        write_counter(
+            &mut output,
+            "mullvad_reconnects_total",
+            "Number of connection attempts not started from the disconnected state.",
+            self.reconnects,
+        );
+        write_counter(
+            &mut output,
+            "mullvad_key_rotations_total",
+            "Number of WireGuard key rotations.",
+            self.key_rotations,
+        );
+        write_counter(
+            &mut output,
+            "mullvad_api_errors_total",
+            "Number of API requests that failed or received an error response.",
+            api_errors,
+        );
+
+        output
+    }
+}
+
+fn state_name(state: &TunnelState) -> &'static str {
+    match state {
+        TunnelState::Disconnected => "disconnected",
+        #[cfg(not(target_os = "android"))]
+        TunnelState::Paused { .. } => "paused",
+        TunnelState::Connecting { .. } => "connecting",
+        TunnelState::Connected { .. } => "connected",
+        TunnelState::Disconnecting(_) => "disconnecting",
+        TunnelState::Error(_) => "error",
+    }
+}
+
+fn write_header(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+fn write_counter(output: &mut String, name: &str, help: &str, value: u64) {
+    write_header(output, name, "counter", help);
+    let _ = writeln!(output, "{} {}", name, value);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use talpid_types::tunnel::ActionAfterDisconnect;
+
+    #[test]
+    fn test_render_metrics() {
+        let mut metrics = Metrics::default();
+        metrics.record_state_transition(
+            &TunnelState::Disconnected,
+            &TunnelState::Disconnecting(ActionAfterDisconnect::Nothing),
+        );
+        metrics.record_state_transition(
+            &TunnelState::Disconnecting(ActionAfterDisconnect::Nothing),
+            &TunnelState::Disconnected,
+        );
+        metrics.record_key_rotation();
+
+        let output = metrics.render(
+            &TunnelState::Disconnected,
+            Some(TrafficStats {
+                tx_bytes: 10,
+                rx_bytes: 20,
+            }),
+            3,
+        );
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert!(lines.contains(&"mullvad_tunnel_state{state=\"disconnected\"} 1"));
+        assert!(lines.contains(&"mullvad_tunnel_state{state=\"connected\"} 0"));
+        assert!(lines.contains(&"mullvad_tunnel_state_transitions_total{state=\"disconnected\"} 1"));
+        assert!(lines.contains(&"mullvad_tunnel_tx_bytes_total 10"));
+        assert!(lines.contains(&"mullvad_tunnel_rx_bytes_total 20"));
+        assert!(lines.contains(&"mullvad_reconnects_total 0"));
+        assert!(lines.contains(&"mullvad_key_rotations_total 1"));
+        assert!(lines.contains(&"mullvad_api_errors_total 3"));
+    }
+}
@@ -60,7 +60,7 @@ pub async fn migrate_formats(settings_dir: &Path, settings: &mut serde_json::Val
         .await
         .map_err(Error::ReadHistoryError)?;
 
-    if is_format_v3(&bytes) {
+    if is_format_v3(&bytes) || is_format_v4(&bytes) {
         return Ok(());
     }
     write_format_v3(file, migrate_formats_inner(&bytes, settings)?).await
@@ -91,6 +91,18 @@ fn is_format_v3(bytes: &[u8]) -> bool {
     }
 }
 
+/// The current format: a list of recently used accounts with the time they were last used.
+fn is_format_v4(bytes: &[u8]) -> bool {
+    #[derive(Deserialize)]
+    struct AccountEntry {
+        #[allow(dead_code)]
+        account: AccountToken,
+        #[allow(dead_code)]
+        last_used: String,
+    }
+    serde_json::from_slice::<'_, Vec<AccountEntry>>(bytes).is_ok()
+}
+
 async fn write_format_v3(mut file: File, token: Option<AccountToken>) -> Result<()> {
     file.set_len(0).await.map_err(Error::WriteHistoryError)?;
     file.seek(io::SeekFrom::Start(0))
@@ -170,6 +182,8 @@ mod test {
 ]"#;
     pub const ACCOUNT_HISTORY_V2_EMPTY: &str = r#"[]"#;
     pub const ACCOUNT_HISTORY_V3: &str = r#"123456"#;
+    pub const ACCOUNT_HISTORY_V4: &str =
+        r#"[{"account":"123456","last_used":"2022-01-01T00:00:00Z"}]"#;
 
     pub const OLD_SETTINGS: &str = r#"
 {
@@ -328,6 +342,14 @@ mod test {
 "#;
 
     // Test whether the current format is parsed correctly
+    #[test]
+    fn test_v4() {
+        assert!(!super::is_format_v4(ACCOUNT_HISTORY_V1.as_bytes()));
+        assert!(!super::is_format_v4(ACCOUNT_HISTORY_V2.as_bytes()));
+        assert!(!super::is_format_v4(ACCOUNT_HISTORY_V3.as_bytes()));
+        assert!(super::is_format_v4(ACCOUNT_HISTORY_V4.as_bytes()));
+    }
+
     #[test]
     fn test_v3() {
         assert!(!super::is_format_v3(ACCOUNT_HISTORY_V1.as_bytes()));
@@ -26,35 +26,45 @@ pub(crate) fn generate_device(
     daemon_tx: DaemonEventSender,
 ) {
     tokio::spawn(async move {
-        let wg_data: Option<WireguardData> = migration_data.wg_data.and_then(|data| {
-            serde_json::from_value(data)
-                .map(Some)
-                .unwrap_or_else(|error| {
-                    log::error!(
-                        "{}",
-                        error.display_chain_with_msg("Failed to parse WireGuard data")
-                    );
-                    None
-                })
-        });
-
-        let api_handle = rest_handle.availability.clone();
-        let service = DeviceService::new(rest_handle, api_handle);
-        let result = match (migration_data.token, wg_data) {
-            (token, Some(wg_data)) => {
-                log::info!("Creating a new device cache from previous settings");
-                cache_from_wireguard_key(service, token, wg_data).await
-            }
-            (token, None) => {
-                log::info!("Generating a new device for the account");
-                cache_from_account(service, token).await
-            }
-        };
+        let result = generate(migration_data, rest_handle).await;
         let _ = daemon_tx.send(InternalDaemonEvent::DeviceMigrationEvent(result));
         migration_complete.set_complete();
+        let _ = daemon_tx.send(InternalDaemonEvent::MigrationComplete);
     });
 }
 
+/// Matches the WireGuard key in `migration_data` against the devices on the account, or generates
+/// a new device if there is no matching one.
+pub(crate) async fn generate(
+    migration_data: MigrationData,
+    rest_handle: mullvad_api::rest::MullvadRestHandle,
+) -> Result<PrivateAccountAndDevice, device::Error> {
+    let wg_data: Option<WireguardData> = migration_data.wg_data.and_then(|data| {
+        serde_json::from_value(data)
+            .map(Some)
+            .unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to parse WireGuard data")
+                );
+                None
+            })
+    });
+
+    let api_handle = rest_handle.availability.clone();
+    let service = DeviceService::new(rest_handle, api_handle);
+    match (migration_data.token, wg_data) {
+        (token, Some(wg_data)) => {
+            log::info!("Creating a new device cache from previous settings");
+            cache_from_wireguard_key(service, token, wg_data).await
+        }
+        (token, None) => {
+            log::info!("Generating a new device for the account");
+            cache_from_account(service, token).await
+        }
+    }
+}
+
 async fn cache_from_wireguard_key(
     service: DeviceService,
     account_token: AccountToken,
@@ -0,0 +1,147 @@
+//! Append-only log of the settings migrations that have been applied on this install.
+
+use super::{Error, Result};
+use mullvad_types::settings::MigrationRecord;
+use std::path::Path;
+use tokio::{
+    fs,
+    io::{self, AsyncWriteExt},
+};
+
+const MIGRATION_HISTORY_FILE: &str = "migration-history.json";
+
+/// Maximum number of records kept in the log. The oldest records are discarded first.
+const MAX_RECORDS: usize = 32;
+
+/// Keeps track of the settings version while the migrations are run, producing a record for each
+/// migration that changed it.
+pub struct MigrationRecorder {
+    version: u32,
+    records: Vec<MigrationRecord>,
+}
+
+impl MigrationRecorder {
+    pub fn new(settings: &serde_json::Value) -> Self {
+        Self {
+            version: settings_version(settings),
+            records: vec![],
+        }
+    }
+
+    /// Records a migration if the settings version changed since the last call.
+    pub fn observe(&mut self, settings: &serde_json::Value) {
+        let new_version = settings_version(settings);
+        if new_version != self.version {
+            self.records.push(MigrationRecord {
+                from_version: self.version,
+                to_version: new_version,
+                timestamp: chrono::Utc::now(),
+            });
+            self.version = new_version;
+        }
+    }
+
+    pub fn into_records(self) -> Vec<MigrationRecord> {
+        self.records
+    }
+}
+
+/// Returns the version of the settings. The first version of the settings lacked a version field.
+fn settings_version(settings: &serde_json::Value) -> u32 {
+    settings
+        .get("settings_version")
+        .and_then(|version| version.as_u64())
+        .map(|version| version as u32)
+        .unwrap_or(1)
+}
+
+/// Reads all recorded migrations, oldest first.
+pub async fn load(settings_dir: &Path) -> Result<Vec<MigrationRecord>> {
+    let path = settings_dir.join(MIGRATION_HISTORY_FILE);
+    let bytes = match fs::read(&path).await {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(error) => return Err(Error::ReadMigrationHistoryError(error)),
+    };
+    serde_json::from_slice(&bytes).map_err(Error::ParseMigrationHistoryError)
+}
+
+/// Appends `new_records` to the log, discarding the oldest records if the log grows too large.
+pub async fn append(settings_dir: &Path, new_records: Vec<MigrationRecord>) -> Result<()> {
+    if new_records.is_empty() {
+        return Ok(());
+    }
+
+    let mut records = load(settings_dir).await?;
+    append_bounded(&mut records, new_records);
+
+    let buffer = serde_json::to_string_pretty(&records).map_err(Error::SerializeError)?;
+
+    let path = settings_dir.join(MIGRATION_HISTORY_FILE);
+    let mut options = fs::OpenOptions::new();
+    #[cfg(unix)]
+    {
+        options.mode(0o600);
+    }
+    let mut file = options
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(&path)
+        .await
+        .map_err(Error::WriteMigrationHistoryError)?;
+    file.write_all(&buffer.into_bytes())
+        .await
+        .map_err(Error::WriteMigrationHistoryError)?;
+    file.sync_data()
+        .await
+        .map_err(Error::WriteMigrationHistoryError)
+}
+
+fn append_bounded(records: &mut Vec<MigrationRecord>, new_records: Vec<MigrationRecord>) {
+    records.extend(new_records);
+    if records.len() > MAX_RECORDS {
+        let excess = records.len() - MAX_RECORDS;
+        records.drain(..excess);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(from_version: u32) -> MigrationRecord {
+        MigrationRecord {
+            from_version,
+            to_version: from_version + 1,
+            timestamp: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_recorder() {
+        let mut settings = serde_json::json!({});
+        let mut recorder = MigrationRecorder::new(&settings);
+
+        settings["settings_version"] = serde_json::json!(2);
+        recorder.observe(&settings);
+        recorder.observe(&settings);
+        settings["settings_version"] = serde_json::json!(3);
+        recorder.observe(&settings);
+
+        let records = recorder.into_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].from_version, records[0].to_version), (1, 2));
+        assert_eq!((records[1].from_version, records[1].to_version), (2, 3));
+    }
+
+    #[test]
+    fn test_append_bounded() {
+        let mut records: Vec<_> = (0..MAX_RECORDS as u32).map(record).collect();
+        append_bounded(&mut records, vec![record(100), record(101)]);
+
+        assert_eq!(records.len(), MAX_RECORDS);
+        assert_eq!(records[0].from_version, 2);
+        assert_eq!(records[MAX_RECORDS - 1].from_version, 101);
+    }
+}
@@ -31,6 +31,7 @@
 //! 1. Implement the migration and add adequate tests.
 //! 1. Add to the changelog: "Settings format updated to `vY`"
 
+use mullvad_types::settings::MigrationRecord;
 use std::{
     path::Path,
     sync::{
@@ -38,6 +39,7 @@ use std::{
         Arc,
     },
 };
+use talpid_types::ErrorExt;
 use tokio::{
     fs,
     io::{self, AsyncWriteExt},
@@ -45,6 +47,7 @@ use tokio::{
 
 mod account_history;
 mod device;
+mod history;
 mod v1;
 mod v2;
 mod v3;
@@ -86,6 +89,15 @@ pub enum Error {
     #[error(display = "Failed to parse account history")]
     ParseHistoryError,
 
+    #[error(display = "Failed to read the migration history")]
+    ReadMigrationHistoryError(#[error(source)] io::Error),
+
+    #[error(display = "Failed to parse the migration history")]
+    ParseMigrationHistoryError(#[error(source)] serde_json::Error),
+
+    #[error(display = "Failed to write the migration history")]
+    WriteMigrationHistoryError(#[error(source)] io::Error),
+
     #[cfg(windows)]
     #[error(display = "Failed to restore Windows update backup")]
     WinMigrationError(#[error(source)] windows::Error),
@@ -139,16 +151,22 @@ pub(crate) async fn migrate_all(
     }
 
     let old_settings = settings.clone();
+    let mut recorder = history::MigrationRecorder::new(&settings);
 
     v1::migrate(&mut settings)?;
+    recorder.observe(&settings);
     v2::migrate(&mut settings)?;
+    recorder.observe(&settings);
     v3::migrate(&mut settings)?;
+    recorder.observe(&settings);
     v4::migrate(&mut settings)?;
+    recorder.observe(&settings);
 
     account_history::migrate_location(cache_dir, settings_dir).await;
     account_history::migrate_formats(settings_dir, &mut settings).await?;
 
     let migration_data = v5::migrate(&mut settings).await?;
+    recorder.observe(&settings);
 
     if settings == old_settings {
         // Nothing changed
@@ -176,9 +194,21 @@ pub(crate) async fn migrate_all(
 
     log::debug!("Migrated settings. Wrote settings to {}", path.display());
 
+    if let Err(error) = history::append(settings_dir, recorder.into_records()).await {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to record migration history")
+        );
+    }
+
     Ok(migration_data)
 }
 
+/// Returns all settings migrations that have been recorded on this install, oldest first.
+pub(crate) async fn migration_history(settings_dir: &Path) -> Result<Vec<MigrationRecord>> {
+    history::load(settings_dir).await
+}
+
 pub(crate) fn migrate_device(
     migration_data: MigrationData,
     rest_handle: mullvad_api::rest::MullvadRestHandle,
@@ -194,6 +224,15 @@ pub(crate) fn migrate_device(
     migration_complete
 }
 
+/// Runs the device migration again, such as when it failed at startup. Unlike [migrate_device],
+/// the resulting device is returned rather than sent to the daemon.
+pub(crate) async fn retry_migrate_device(
+    migration_data: MigrationData,
+    rest_handle: mullvad_api::rest::MullvadRestHandle,
+) -> std::result::Result<crate::device::PrivateAccountAndDevice, crate::device::Error> {
+    device::generate(migration_data, rest_handle).await
+}
+
 #[cfg(windows)]
 mod windows {
     use std::{ffi::OsStr, io, os::windows::ffi::OsStrExt, path::Path, ptr};
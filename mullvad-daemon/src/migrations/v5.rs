@@ -48,6 +48,7 @@ pub enum SelectedObfuscation {
 
 // ======================================================
 
+#[derive(Clone)]
 pub(crate) struct MigrationData {
     pub token: AccountToken,
     pub wg_data: Option<serde_json::Value>,
@@ -0,0 +1,129 @@
+//! Reports how much memory and how many handles the daemon process uses. Metrics that cannot be
+//! obtained on the current platform are reported as `None`.
+
+/// Resource usage of the daemon process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeStats {
+    /// Resident set size in bytes.
+    pub resident_memory: Option<u64>,
+    /// Number of open file descriptors, or of open handles on Windows.
+    pub open_files: Option<u64>,
+}
+
+impl RuntimeStats {
+    pub fn collect() -> Self {
+        RuntimeStats {
+            resident_memory: imp::resident_memory(),
+            open_files: imp::open_files(),
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod imp {
+    use std::fs;
+
+    pub fn resident_memory() -> Option<u64> {
+        // The second field is the number of resident pages.
+        let statm = fs::read_to_string("/proc/self/statm").ok()?;
+        let pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+        // SAFETY: `sysconf` has no preconditions.
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        if page_size <= 0 {
+            return None;
+        }
+        Some(pages * page_size as u64)
+    }
+
+    pub fn open_files() -> Option<u64> {
+        // Reading the directory opens a descriptor of its own.
+        let count = fs::read_dir("/proc/self/fd").ok()?.count() as u64;
+        Some(count.saturating_sub(1))
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod imp {
+    use std::{fs, mem};
+
+    pub fn resident_memory() -> Option<u64> {
+        // SAFETY: `proc_taskinfo` only contains integers, for which zero is a valid value.
+        let mut info: libc::proc_taskinfo = unsafe { mem::zeroed() };
+        let size = mem::size_of::<libc::proc_taskinfo>() as libc::c_int;
+        // SAFETY: `info` is a valid buffer of `size` bytes.
+        let written = unsafe {
+            libc::proc_pidinfo(
+                libc::getpid(),
+                libc::PROC_PIDTASKINFO,
+                0,
+                &mut info as *mut _ as *mut libc::c_void,
+                size,
+            )
+        };
+        if written != size {
+            return None;
+        }
+        Some(info.pti_resident_size)
+    }
+
+    pub fn open_files() -> Option<u64> {
+        // Reading the directory opens a descriptor of its own.
+        let count = fs::read_dir("/dev/fd").ok()?.count() as u64;
+        Some(count.saturating_sub(1))
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::mem;
+    use winapi::um::{
+        processthreadsapi::{GetCurrentProcess, GetProcessHandleCount},
+        psapi::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+    };
+
+    pub fn resident_memory() -> Option<u64> {
+        // SAFETY: `PROCESS_MEMORY_COUNTERS` only contains integers, for which zero is a valid
+        // value.
+        let mut counters: PROCESS_MEMORY_COUNTERS = unsafe { mem::zeroed() };
+        let size = mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32;
+        // SAFETY: `counters` is a valid buffer of `size` bytes, and the pseudo handle returned by
+        // `GetCurrentProcess` does not need to be closed.
+        if unsafe { GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, size) } == 0 {
+            return None;
+        }
+        Some(counters.WorkingSetSize as u64)
+    }
+
+    pub fn open_files() -> Option<u64> {
+        let mut count = 0;
+        // SAFETY: `count` is a valid pointer.
+        if unsafe { GetProcessHandleCount(GetCurrentProcess(), &mut count) } == 0 {
+            return None;
+        }
+        Some(u64::from(count))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collect() {
+        const MEMORY_SIZE: usize = 64 * 1024 * 1024;
+        const FILES: u64 = 8;
+
+        // Every byte is written, so the memory is resident while the stats are collected.
+        let memory = vec![1u8; MEMORY_SIZE];
+        let files = (0..FILES)
+            .map(|_| std::fs::File::open(std::env::current_exe().unwrap()).unwrap())
+            .collect::<Vec<_>>();
+
+        let stats = RuntimeStats::collect();
+        assert!(stats.resident_memory.unwrap() >= MEMORY_SIZE as u64);
+        assert!(stats.open_files.unwrap() >= FILES);
+
+        drop(files);
+        drop(memory);
+    }
+}
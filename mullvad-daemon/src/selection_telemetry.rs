@@ -0,0 +1,378 @@
+//! Optional, local-only log of relay selections and their connection outcomes.
+//!
+//! Records are appended as JSON lines to a file chosen by the user. The file must be located
+//! directly in the log or cache directory of the daemon, since the daemon runs with elevated
+//! privileges. Nothing is ever sent over the network.
+
+use chrono::{DateTime, Utc};
+use mullvad_types::{endpoint::MullvadEndpoint, relay_list::Relay};
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+use talpid_types::{
+    net::{TransportProtocol, TunnelType},
+    ErrorExt,
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+/// The telemetry file is rotated once it grows beyond this size.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// Suffix appended to the path of the rotated telemetry file.
+const ROTATED_FILE_SUFFIX: &str = ".1";
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Outcome {
+    Selected,
+    Connected,
+    Failed,
+}
+
+#[derive(Debug, Serialize)]
+struct Record<'a> {
+    timestamp: DateTime<Utc>,
+    outcome: Outcome,
+    relay: &'a str,
+    entry_relay: Option<&'a str>,
+    tunnel_type: TunnelType,
+    protocol: TransportProtocol,
+    /// Time from selecting the relay until the outcome, in milliseconds.
+    duration_ms: Option<u64>,
+}
+
+struct PendingSelection {
+    relay: String,
+    entry_relay: Option<String>,
+    tunnel_type: TunnelType,
+    protocol: TransportProtocol,
+    selected_at: Instant,
+}
+
+impl PendingSelection {
+    fn record(&self, outcome: Outcome, duration_ms: Option<u64>) -> Record<'_> {
+        Record {
+            timestamp: Utc::now(),
+            outcome,
+            relay: &self.relay,
+            entry_relay: self.entry_relay.as_deref(),
+            tunnel_type: self.tunnel_type,
+            protocol: self.protocol,
+            duration_ms,
+        }
+    }
+}
+
+/// Writes relay selection telemetry to a file, if one is configured.
+pub struct SelectionTelemetry {
+    path: Option<PathBuf>,
+    /// Directories that the telemetry file may be located in.
+    allowed_dirs: Vec<PathBuf>,
+    pending: Option<PendingSelection>,
+}
+
+impl SelectionTelemetry {
+    /// Creates a writer for the file at `path`. The path is ignored unless it names a file
+    /// directly in one of `allowed_dirs`.
+    pub async fn new(path: Option<PathBuf>, allowed_dirs: Vec<PathBuf>) -> Self {
+        let path = match path {
+            Some(path) if is_allowed_path(&path, &allowed_dirs).await => Some(path),
+            Some(path) => {
+                log::warn!(
+                    "Ignoring selection telemetry path outside of the allowed directories: {}",
+                    path.display()
+                );
+                None
+            }
+            None => None,
+        };
+        SelectionTelemetry {
+            path,
+            allowed_dirs,
+            pending: None,
+        }
+    }
+
+    /// Checks that `path` is located directly in one of the allowed directories, and that the
+    /// telemetry file can be created and appended to.
+    pub async fn validate_path(&self, path: &Path) -> io::Result<()> {
+        if !path.is_absolute() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "selection telemetry path must be absolute",
+            ));
+        }
+        if !is_allowed_path(path, &self.allowed_dirs).await {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "selection telemetry file must be located in the log or cache directory",
+            ));
+        }
+        open(path).await.map(|_| ())
+    }
+
+    pub fn set_path(&mut self, path: Option<PathBuf>) {
+        self.path = path;
+        self.pending = None;
+    }
+
+    /// Record that a relay was selected. A previous selection that never connected is recorded
+    /// as failed.
+    pub async fn record_selection(
+        &mut self,
+        relay: &Relay,
+        entry_relay: Option<&Relay>,
+        endpoint: &MullvadEndpoint,
+    ) {
+        if self.path.is_none() {
+            return;
+        }
+        self.record_outcome(false).await;
+
+        let (tunnel_type, protocol) = match endpoint {
+            MullvadEndpoint::OpenVpn(endpoint) => (TunnelType::OpenVpn, endpoint.protocol),
+            MullvadEndpoint::Wireguard(_) => (TunnelType::Wireguard, TransportProtocol::Udp),
+        };
+        let pending = PendingSelection {
+            relay: relay.hostname.clone(),
+            entry_relay: entry_relay.map(|relay| relay.hostname.clone()),
+            tunnel_type,
+            protocol,
+            selected_at: Instant::now(),
+        };
+        self.write(&pending.record(Outcome::Selected, None)).await;
+        self.pending = Some(pending);
+    }
+
+    /// Record whether the most recently selected relay could be connected to.
+    pub async fn record_outcome(&mut self, success: bool) {
+        if let Some(pending) = self.pending.take() {
+            let outcome = if success {
+                Outcome::Connected
+            } else {
+                Outcome::Failed
+            };
+            let duration_ms = pending.selected_at.elapsed().as_millis() as u64;
+            self.write(&pending.record(outcome, Some(duration_ms)))
+                .await;
+        }
+    }
+
+    /// Forget the most recent selection without recording an outcome, such as when the user
+    /// disconnects before the tunnel is up.
+    pub fn discard_selection(&mut self) {
+        self.pending = None;
+    }
+
+    async fn write(&self, record: &Record<'_>) {
+        if let Some(path) = &self.path {
+            if let Err(error) = append(path, record).await {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to write selection telemetry")
+                );
+            }
+        }
+    }
+}
+
+/// Returns whether `path` names a file directly in one of `allowed_dirs`. Symbolic links in the
+/// directory part of the path are resolved before comparing.
+async fn is_allowed_path(path: &Path, allowed_dirs: &[PathBuf]) -> bool {
+    let (parent, file_name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => (parent, file_name),
+        _ => return false,
+    };
+    if file_name.to_string_lossy().ends_with(ROTATED_FILE_SUFFIX) {
+        // The rotated file would clash with another telemetry file
+        return false;
+    }
+    let parent = match fs::canonicalize(parent).await {
+        Ok(parent) => parent,
+        Err(_) => return false,
+    };
+    for dir in allowed_dirs {
+        if let Ok(dir) = fs::canonicalize(dir).await {
+            if dir == parent {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+async fn append(path: &Path, record: &Record<'_>) -> io::Result<()> {
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+
+    match fs::symlink_metadata(path).await {
+        Ok(metadata) if !metadata.is_file() => return Err(not_a_file_error()),
+        Ok(metadata) if metadata.len() + line.len() as u64 > MAX_FILE_SIZE => {
+            fs::rename(path, rotated_path(path)).await?;
+        }
+        Ok(_) => (),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+        Err(error) => return Err(error),
+    }
+
+    let mut file = open(path).await?;
+    file.write_all(&line).await?;
+    file.flush().await
+}
+
+/// Opens the telemetry file for appending. Symbolic links are not followed, and anything but a
+/// regular file is rejected.
+async fn open(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    options.mode(0o600).custom_flags(libc::O_NOFOLLOW);
+    #[cfg(windows)]
+    options.custom_flags(winapi::um::winbase::FILE_FLAG_OPEN_REPARSE_POINT);
+    let file = options.open(path).await?;
+    if !file.metadata().await?.is_file() {
+        return Err(not_a_file_error());
+    }
+    Ok(file)
+}
+
+fn not_a_file_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "selection telemetry path is not a regular file",
+    )
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = OsString::from(path.as_os_str());
+    rotated.push(ROTATED_FILE_SUFFIX);
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "mullvad-selection-telemetry-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn test_record() -> Record<'static> {
+        Record {
+            timestamp: Utc::now(),
+            outcome: Outcome::Selected,
+            relay: "se9-wireguard",
+            entry_relay: None,
+            tunnel_type: TunnelType::Wireguard,
+            protocol: TransportProtocol::Udp,
+            duration_ms: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejects_path_outside_allowed_dirs() {
+        let dir = temp_dir("outside");
+        let allowed_dir = dir.join("log");
+        let other_dir = dir.join("other");
+        std::fs::create_dir(&allowed_dir).unwrap();
+        std::fs::create_dir(&other_dir).unwrap();
+
+        let telemetry = SelectionTelemetry::new(
+            Some(other_dir.join("telemetry.jsonl")),
+            vec![allowed_dir.clone()],
+        )
+        .await;
+        assert!(telemetry.path.is_none());
+
+        let error = telemetry
+            .validate_path(&other_dir.join("telemetry.jsonl"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        // Files in subdirectories of an allowed directory are rejected as well
+        std::fs::create_dir(allowed_dir.join("nested")).unwrap();
+        assert!(telemetry
+            .validate_path(&allowed_dir.join("nested").join("telemetry.jsonl"))
+            .await
+            .is_err());
+        assert!(telemetry
+            .validate_path(&allowed_dir.join("telemetry.jsonl.1"))
+            .await
+            .is_err());
+        assert!(telemetry
+            .validate_path(Path::new("telemetry.jsonl"))
+            .await
+            .is_err());
+
+        telemetry
+            .validate_path(&allowed_dir.join("telemetry.jsonl"))
+            .await
+            .expect("Path in the allowed directory was rejected");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_does_not_follow_symlinks() {
+        let dir = temp_dir("symlinks");
+        let allowed_dir = dir.join("log");
+        let other_dir = dir.join("other");
+        std::fs::create_dir(&allowed_dir).unwrap();
+        std::fs::create_dir(&other_dir).unwrap();
+        let target = other_dir.join("target");
+        std::fs::write(&target, "").unwrap();
+
+        let telemetry = SelectionTelemetry::new(None, vec![allowed_dir.clone()]).await;
+
+        // A symlink to a file outside of the allowed directory
+        let link = allowed_dir.join("telemetry.jsonl");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(telemetry.validate_path(&link).await.is_err());
+        assert!(append(&link, &test_record()).await.is_err());
+        assert_eq!(std::fs::metadata(&target).unwrap().len(), 0);
+
+        // A symlink to a directory outside of the allowed directory
+        let dir_link = allowed_dir.join("linked");
+        std::os::unix::fs::symlink(&other_dir, &dir_link).unwrap();
+        let error = telemetry
+            .validate_path(&dir_link.join("telemetry.jsonl"))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rotation_at_size_limit() {
+        let dir = temp_dir("rotation");
+        let path = dir.join("telemetry.jsonl");
+
+        append(&path, &test_record()).await.unwrap();
+        assert!(!rotated_path(&path).exists());
+
+        std::fs::write(&path, vec![b'x'; MAX_FILE_SIZE as usize]).unwrap();
+        append(&path, &test_record()).await.unwrap();
+
+        assert_eq!(
+            std::fs::metadata(rotated_path(&path)).unwrap().len(),
+            MAX_FILE_SIZE
+        );
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 1);
+        assert!(contents.contains("se9-wireguard"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,17 +1,39 @@
 #[cfg(not(target_os = "android"))]
 use futures::TryFutureExt;
+use mullvad_api::rest::MAX_CONCURRENCY_LIMIT;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use mullvad_types::settings::AllowedHost;
 use mullvad_types::{
-    relay_constraints::{BridgeSettings, BridgeState, ObfuscationSettings, RelaySettingsUpdate},
-    settings::{DnsOptions, Settings},
+    relay_constraints::{
+        BridgeSettings, BridgeState, Constraint, ObfuscationSettings, RelayConstraintsUpdate,
+        RelaySettings, RelaySettingsUpdate, SelectedObfuscation, TransportPort,
+    },
+    relay_list::{MAX_RELAY_LIST_UPDATE_INTERVAL, MIN_RELAY_LIST_UPDATE_INTERVAL},
+    settings::{
+        DnsOptions, DnsState, ExpiryPolicy, OnDemandRules, ReconnectPolicy, ReconnectTrigger,
+        Settings, UnverifiedPolicy,
+    },
     wireguard::RotationInterval,
 };
-#[cfg(target_os = "windows")]
+#[cfg(any(target_os = "windows", target_os = "linux"))]
 use std::collections::HashSet;
 use std::{
-    ops::Deref,
+    fmt,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    ops::{Deref, RangeInclusive},
     path::{Path, PathBuf},
+    time::Duration,
+};
+#[cfg(target_os = "linux")]
+use talpid_types::net::LinuxRoutingOptions;
+use talpid_types::{
+    net::{
+        openvpn::{ProxySettings, RemoteProxySettings},
+        TransportProtocol, TunnelType,
+    },
+    tunnel::{ConnectionRetryBackoff, RouteChangeReconnect},
+    ErrorExt,
 };
-use talpid_types::ErrorExt;
 use tokio::{
     fs,
     io::{self, AsyncWriteExt},
@@ -19,6 +41,36 @@ use tokio::{
 
 const SETTINGS_FILE: &str = "settings.json";
 
+/// Number of times to try writing the settings before reverting the change.
+const SAVE_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry of a failed settings write. Doubled for every retry.
+const SAVE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Settings keys whose values are never included in [`non_default_settings`].
+const REDACTED_KEYS: &[&str] = &["private_key", "username", "password"];
+pub(crate) const REDACTED_VALUE: &str = "[REDACTED]";
+
+/// Allowed WireGuard persistent keepalive intervals, in seconds.
+const WIREGUARD_KEEPALIVE_RANGE: RangeInclusive<u16> = 1..=3600;
+
+/// Upper bound for the delay between connection attempts, in seconds.
+const MAX_CONNECTION_RETRY_DELAY_SECS: u32 = 600;
+
+/// Upper bound for how long the device must be offline before the tunnel is disconnected, in
+/// seconds.
+const MAX_ROUTE_CHANGE_DEBOUNCE_SECS: u32 = 300;
+
+/// Allowed number of accounts to remember in the account history.
+const ACCOUNT_HISTORY_LIMIT_RANGE: RangeInclusive<u32> = 1..=20;
+
+/// Maximum length of the identifier appended to the User-Agent of API requests.
+const MAX_API_CLIENT_TAG_LEN: usize = 64;
+
+/// Routing tables that are reserved by the kernel: unspecified, default, main and local.
+#[cfg(target_os = "linux")]
+const RESERVED_ROUTING_TABLES: [u32; 4] = [0, 253, 254, 255];
+
 #[derive(err_derive::Error, Debug)]
 #[error(no_from)]
 pub enum Error {
@@ -40,17 +92,89 @@ pub enum Error {
 
     #[error(display = "Unable to set settings file permissions")]
     SetPermissions(#[error(source)] io::Error),
+
+    #[error(display = "Proxy credentials must be non-empty and must not contain line breaks")]
+    InvalidProxyAuth,
+
+    #[error(display = "WireGuard keepalive interval must be between 1 and 3600 seconds")]
+    InvalidWireguardKeepalive,
+
+    #[error(
+        display = "Connection retry delays must not exceed 600 seconds, the maximum delay must not be smaller than the base delay, and the base delay must be at least 1 second if there is a maximum delay"
+    )]
+    InvalidConnectionRetryBackoff,
+
+    #[error(display = "The offline debounce interval must not exceed 300 seconds")]
+    InvalidRouteChangeDebounce,
+
+    #[error(display = "The protocol fallback threshold must be at least 1")]
+    InvalidProtocolFallbackThreshold,
+
+    #[error(display = "The account history limit must be between 1 and 20")]
+    InvalidAccountHistoryLimit,
+
+    #[error(
+        display = "The API client tag must be between 1 and 64 characters long and may only contain ASCII letters, digits, '-', '_', '.' and '/'"
+    )]
+    InvalidApiClientTag,
+
+    #[error(display = "The API concurrency limit must be between 1 and {}", _0)]
+    InvalidApiConcurrencyLimit(usize),
+
+    #[error(display = "{} cannot be used as the API bind address", _0)]
+    InvalidApiBindInterface(IpAddr),
+
+    #[error(display = "{} is not an address of this machine", _0)]
+    ApiBindInterfaceNotLocal(IpAddr, #[error(source)] io::Error),
+
+    #[error(
+        display = "The relay list update interval must be between {} and {} seconds",
+        _0,
+        _1
+    )]
+    InvalidRelayListUpdateInterval(u64, u64),
+
+    #[error(display = "The connect deadline must be greater than zero")]
+    InvalidConnectDeadline,
+
+    #[cfg(target_os = "linux")]
+    #[error(display = "The fwmark must be non-zero and the routing table must not be reserved")]
+    InvalidLinuxRoutingOptions,
+
+    #[cfg(windows)]
+    #[error(display = "Blocking all IPv6 traffic is not supported on Windows")]
+    BlockIpv6Unsupported,
 }
 
 #[derive(Debug)]
 pub struct SettingsPersister {
     settings: Settings,
+    /// The settings that were last written to disk.
+    saved_settings: Settings,
+    /// Description of the last change that could not be saved, if it has not been taken yet.
+    write_failure: Option<String>,
+    /// The `settings_version` of the settings file before it was migrated.
+    file_version: Option<u32>,
     path: PathBuf,
 }
 
 impl SettingsPersister {
-    /// Loads user settings from file. If it fails, it returns the defaults.
-    pub async fn load(settings_dir: &Path) -> Self {
+    /// Returns the `settings_version` stored in the settings file, without parsing or migrating
+    /// the rest of the settings. Returns `None` if there is no file or no readable version.
+    pub async fn read_file_version(settings_dir: &Path) -> Option<u32> {
+        let bytes = fs::read(settings_dir.join(SETTINGS_FILE)).await.ok()?;
+        Self::file_version_from_bytes(&bytes)
+    }
+
+    fn file_version_from_bytes(bytes: &[u8]) -> Option<u32> {
+        let settings: serde_json::Value = serde_json::from_slice(bytes).ok()?;
+        let version = settings.get("settings_version")?.as_u64()?;
+        u32::try_from(version).ok()
+    }
+
+    /// Loads user settings from file. If it fails, it returns the defaults. `file_version` is the
+    /// version read by [`SettingsPersister::read_file_version`] before the settings were migrated.
+    pub async fn load(settings_dir: &Path, file_version: Option<u32>) -> Self {
         let path = settings_dir.join(SETTINGS_FILE);
         let (mut settings, mut should_save) = match Self::load_from_file(&path).await {
             Ok(value) => value,
@@ -78,7 +202,13 @@ impl SettingsPersister {
             should_save |= Self::update_field(&mut settings.show_beta_releases, true);
         }
 
-        let mut persister = SettingsPersister { settings, path };
+        let mut persister = SettingsPersister {
+            saved_settings: settings.clone(),
+            settings,
+            write_failure: None,
+            file_version,
+            path,
+        };
 
         if should_save {
             if let Err(error) = persister.save().await {
@@ -113,11 +243,46 @@ impl SettingsPersister {
         serde_json::from_slice(bytes).map_err(Error::ParseError)
     }
 
-    /// Serializes the settings and saves them to the file it was loaded from.
+    /// Returns the `settings_version` of the settings file as it was found when the daemon
+    /// started, before any migration. `None` if there was no file or it had no version.
+    pub fn file_version(&self) -> Option<u32> {
+        self.file_version
+    }
+
+    /// Serializes the settings and saves them to the file it was loaded from. The settings are
+    /// written to a temporary file first, which then replaces the settings file, so that the file
+    /// always contains either the old or the new settings.
     async fn save(&mut self) -> Result<(), Error> {
         log::debug!("Writing settings to {}", self.path.display());
 
         let buffer = serde_json::to_string_pretty(&self.settings).map_err(Error::SerializeError)?;
+        let temp_path = self.path.with_extension("json.tmp");
+        if let Err(error) = Self::write_file(&temp_path, buffer.as_bytes()).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(error);
+        }
+        if let Err(error) = fs::rename(&temp_path, &self.path).await {
+            let _ = fs::remove_file(&temp_path).await;
+            return Err(Error::WriteError(self.path.display().to_string(), error));
+        }
+
+        // The new settings are in place, even if the directory entry is not yet persisted
+        self.saved_settings = self.settings.clone();
+        #[cfg(unix)]
+        if let Some(dir) = self.path.parent() {
+            let result = async { fs::File::open(dir).await?.sync_all().await }.await;
+            if let Err(error) = result {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to sync the settings directory")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `contents` to the file at `path` and waits until it has reached the disk.
+    async fn write_file(path: &Path, contents: &[u8]) -> Result<(), Error> {
         let mut options = fs::OpenOptions::new();
         #[cfg(unix)]
         {
@@ -127,12 +292,12 @@ impl SettingsPersister {
             .create(true)
             .write(true)
             .truncate(true)
-            .open(&self.path)
+            .open(path)
             .await
-            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
-        file.write_all(&buffer.into_bytes())
+            .map_err(|e| Error::WriteError(path.display().to_string(), e))?;
+        file.write_all(contents)
             .await
-            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
+            .map_err(|e| Error::WriteError(path.display().to_string(), e))?;
 
         #[cfg(unix)]
         {
@@ -153,9 +318,40 @@ impl SettingsPersister {
 
         file.sync_all()
             .await
-            .map_err(|e| Error::WriteError(self.path.display().to_string(), e))?;
+            .map_err(|e| Error::WriteError(path.display().to_string(), e))
+    }
 
-        Ok(())
+    /// Saves the settings, retrying writes that fail. If the settings still cannot be saved, the
+    /// unsaved changes are reverted so that the settings in use match those on disk.
+    async fn save_or_revert(&mut self) -> Result<(), Error> {
+        let mut delay = SAVE_RETRY_DELAY;
+        let mut attempt = 1;
+        loop {
+            let error = match self.save().await {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            let is_io_error = matches!(error, Error::WriteError(..) | Error::SetPermissions(_));
+            if !is_io_error || attempt >= SAVE_ATTEMPTS {
+                log::warn!("Reverting settings change that could not be saved");
+                self.settings = self.saved_settings.clone();
+                self.write_failure = Some(error.display_chain());
+                return Err(error);
+            }
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to save settings. Retrying")
+            );
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// Returns a description of the last settings change that was reverted because it could not
+    /// be saved, unless it has already been returned.
+    pub fn take_write_failure(&mut self) -> Option<String> {
+        self.write_failure.take()
     }
 
     /// Resets default settings
@@ -199,6 +395,17 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_port_forwarding(&mut self, port_forwarding: bool) -> Result<bool, Error> {
+        if let RelaySettings::CustomTunnelEndpoint(_) = self.settings.get_relay_settings() {
+            return Ok(false);
+        }
+        self.update_relay_settings(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+            port_forwarding: Some(port_forwarding),
+            ..Default::default()
+        }))
+        .await
+    }
+
     pub async fn set_allow_lan(&mut self, allow_lan: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.allow_lan, allow_lan);
         self.update(should_save).await
@@ -215,6 +422,263 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_block_ipv6(&mut self, block_ipv6: bool) -> Result<bool, Error> {
+        #[cfg(windows)]
+        if block_ipv6 {
+            return Err(Error::BlockIpv6Unsupported);
+        }
+        let should_save = Self::update_field(&mut self.settings.block_ipv6, block_ipv6);
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_allow_excluded_when_blocked(
+        &mut self,
+        allow_excluded_when_blocked: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.allow_excluded_when_blocked,
+            allow_excluded_when_blocked,
+        );
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_split_tunnel_cgroups(
+        &mut self,
+        cgroups: HashSet<PathBuf>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.split_tunnel_cgroups, cgroups);
+        self.update(should_save).await
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub async fn set_allowed_hosts(&mut self, hosts: Vec<AllowedHost>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.allowed_hosts, hosts);
+        self.update(should_save).await
+    }
+
+    #[cfg(target_os = "linux")]
+    pub async fn set_linux_routing_options(
+        &mut self,
+        options: LinuxRoutingOptions,
+    ) -> Result<bool, Error> {
+        if options.fwmark == Some(0)
+            || options
+                .routing_table
+                .map(|table| RESERVED_ROUTING_TABLES.contains(&table))
+                .unwrap_or(false)
+        {
+            return Err(Error::InvalidLinuxRoutingOptions);
+        }
+        let should_save = Self::update_field(&mut self.settings.linux_routing_options, options);
+        self.update(should_save).await
+    }
+
+    pub async fn set_connection_retry_backoff(
+        &mut self,
+        backoff: ConnectionRetryBackoff,
+    ) -> Result<bool, Error> {
+        // A zero base delay is never doubled, so it would disable the backoff
+        if backoff.max_delay_secs < backoff.base_delay_secs
+            || backoff.max_delay_secs > MAX_CONNECTION_RETRY_DELAY_SECS
+            || (backoff.max_delay_secs > 0 && backoff.base_delay_secs == 0)
+        {
+            return Err(Error::InvalidConnectionRetryBackoff);
+        }
+        let should_save = Self::update_field(&mut self.settings.connection_retry_backoff, backoff);
+        self.update(should_save).await
+    }
+
+    pub async fn set_route_change_reconnect(
+        &mut self,
+        route_change_reconnect: RouteChangeReconnect,
+    ) -> Result<bool, Error> {
+        if route_change_reconnect.debounce_secs > MAX_ROUTE_CHANGE_DEBOUNCE_SECS {
+            return Err(Error::InvalidRouteChangeDebounce);
+        }
+        let should_save = Self::update_field(
+            &mut self.settings.route_change_reconnect,
+            route_change_reconnect,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_on_demand_rules(&mut self, rules: OnDemandRules) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.on_demand, rules);
+        self.update(should_save).await
+    }
+
+    pub async fn set_reconnect_policy(
+        &mut self,
+        trigger: ReconnectTrigger,
+        policy: ReconnectPolicy,
+    ) -> Result<bool, Error> {
+        let should_save = self.settings.reconnect_policies.set(trigger, policy);
+        self.update(should_save).await
+    }
+
+    pub async fn set_backup_relay_settings(
+        &mut self,
+        relay_settings: Option<RelaySettings>,
+    ) -> Result<bool, Error> {
+        let should_save =
+            Self::update_field(&mut self.settings.backup_relay_settings, relay_settings);
+        self.update(should_save).await
+    }
+
+    pub async fn set_on_unverified_connection(
+        &mut self,
+        policy: UnverifiedPolicy,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.on_unverified_connection, policy);
+        self.update(should_save).await
+    }
+
+    pub async fn set_on_account_expiry(&mut self, policy: ExpiryPolicy) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.on_account_expiry, policy);
+        self.update(should_save).await
+    }
+
+    pub async fn set_protocol_fallback_threshold(
+        &mut self,
+        threshold: Option<u32>,
+    ) -> Result<bool, Error> {
+        if threshold == Some(0) {
+            return Err(Error::InvalidProtocolFallbackThreshold);
+        }
+        let should_save =
+            Self::update_field(&mut self.settings.protocol_fallback_threshold, threshold);
+        self.update(should_save).await
+    }
+
+    pub async fn set_api_bridge(&mut self, api_bridge: Option<String>) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.api_bridge, api_bridge);
+        self.update(should_save).await
+    }
+
+    pub async fn set_api_client_tag(&mut self, tag: Option<String>) -> Result<bool, Error> {
+        if let Some(tag) = &tag {
+            let is_valid = !tag.is_empty()
+                && tag.len() <= MAX_API_CLIENT_TAG_LEN
+                && tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+            if !is_valid {
+                return Err(Error::InvalidApiClientTag);
+            }
+        }
+        let should_save = Self::update_field(&mut self.settings.api_client_tag, tag);
+        self.update(should_save).await
+    }
+
+    pub async fn set_api_concurrency_limit(&mut self, limit: Option<u32>) -> Result<bool, Error> {
+        if let Some(limit) = limit {
+            if limit == 0 || limit as usize > MAX_CONCURRENCY_LIMIT {
+                return Err(Error::InvalidApiConcurrencyLimit(MAX_CONCURRENCY_LIMIT));
+            }
+        }
+        let should_save = Self::update_field(&mut self.settings.api_concurrency_limit, limit);
+        self.update(should_save).await
+    }
+
+    pub async fn set_api_bind_interface(&mut self, address: Option<IpAddr>) -> Result<bool, Error> {
+        if let Some(address) = address {
+            if address.is_unspecified() || address.is_loopback() {
+                return Err(Error::InvalidApiBindInterface(address));
+            }
+            // Binding only succeeds if the address is assigned to a local interface.
+            UdpSocket::bind(SocketAddr::new(address, 0))
+                .map_err(|error| Error::ApiBindInterfaceNotLocal(address, error))?;
+        }
+        let should_save = Self::update_field(&mut self.settings.api_bind_interface, address);
+        self.update(should_save).await
+    }
+
+    pub async fn set_relay_list_update_interval(
+        &mut self,
+        interval: Duration,
+    ) -> Result<bool, Error> {
+        if interval < MIN_RELAY_LIST_UPDATE_INTERVAL || interval > MAX_RELAY_LIST_UPDATE_INTERVAL {
+            return Err(Error::InvalidRelayListUpdateInterval(
+                MIN_RELAY_LIST_UPDATE_INTERVAL.as_secs(),
+                MAX_RELAY_LIST_UPDATE_INTERVAL.as_secs(),
+            ));
+        }
+        let should_save =
+            Self::update_field(&mut self.settings.relay_list_update_interval, interval);
+        self.update(should_save).await
+    }
+
+    pub async fn set_connect_deadline_to_block(
+        &mut self,
+        deadline: Option<Duration>,
+    ) -> Result<bool, Error> {
+        if deadline == Some(Duration::ZERO) {
+            return Err(Error::InvalidConnectDeadline);
+        }
+        let should_save =
+            Self::update_field(&mut self.settings.connect_deadline_to_block, deadline);
+        self.update(should_save).await
+    }
+
+    pub async fn set_account_history_limit(&mut self, limit: u32) -> Result<bool, Error> {
+        if !ACCOUNT_HISTORY_LIMIT_RANGE.contains(&limit) {
+            return Err(Error::InvalidAccountHistoryLimit);
+        }
+        let should_save = Self::update_field(&mut self.settings.account_history_limit, limit);
+        self.update(should_save).await
+    }
+
+    pub async fn set_reconnect_on_relay_removal(
+        &mut self,
+        reconnect_on_relay_removal: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.reconnect_on_relay_removal,
+            reconnect_on_relay_removal,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_retry_same_relay(&mut self, retry_same_relay: bool) -> Result<bool, Error> {
+        let should_save = Self::update_field(&mut self.settings.retry_same_relay, retry_same_relay);
+        self.update(should_save).await
+    }
+
+    pub async fn set_openvpn_endpoint_preference(
+        &mut self,
+        openvpn_endpoint_preference: Vec<TransportPort>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.openvpn_endpoint_preference,
+            openvpn_endpoint_preference,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_deprioritize_failing_relays(
+        &mut self,
+        deprioritize_failing_relays: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.deprioritize_failing_relays,
+            deprioritize_failing_relays,
+        );
+        self.update(should_save).await
+    }
+
+    pub async fn set_selection_telemetry_path(
+        &mut self,
+        selection_telemetry_path: Option<PathBuf>,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.selection_telemetry_path,
+            selection_telemetry_path,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_auto_connect(&mut self, auto_connect: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(&mut self.settings.auto_connect, auto_connect);
         self.update(should_save).await
@@ -248,6 +712,24 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    pub async fn set_wireguard_keepalive(&mut self, interval: Option<u16>) -> Result<bool, Error> {
+        if let Some(interval) = interval {
+            if !WIREGUARD_KEEPALIVE_RANGE.contains(&interval) {
+                return Err(Error::InvalidWireguardKeepalive);
+            }
+        }
+        let should_save = Self::update_field(
+            &mut self
+                .settings
+                .tunnel_options
+                .wireguard
+                .options
+                .wireguard_keepalive,
+            interval,
+        );
+        self.update(should_save).await
+    }
+
     pub async fn set_wireguard_rotation_interval(
         &mut self,
         interval: Option<RotationInterval>,
@@ -272,6 +754,15 @@ impl SettingsPersister {
         &mut self,
         bridge_settings: BridgeSettings,
     ) -> Result<bool, Error> {
+        if let BridgeSettings::Custom(ProxySettings::Remote(RemoteProxySettings {
+            auth: Some(auth),
+            ..
+        })) = &bridge_settings
+        {
+            if !auth.is_valid() {
+                return Err(Error::InvalidProxyAuth);
+            }
+        }
         let should_save = Self::update_field(&mut self.settings.bridge_settings, bridge_settings);
         self.update(should_save).await
     }
@@ -297,6 +788,18 @@ impl SettingsPersister {
         self.update(should_save).await
     }
 
+    #[cfg(windows)]
+    pub async fn set_split_tunnel_keep_exclusions_on_reconnect(
+        &mut self,
+        keep_exclusions: bool,
+    ) -> Result<bool, Error> {
+        let should_save = Self::update_field(
+            &mut self.settings.split_tunnel.keep_exclusions_on_reconnect,
+            keep_exclusions,
+        );
+        self.update(should_save).await
+    }
+
     #[cfg(windows)]
     pub async fn set_use_wireguard_nt(&mut self, state: bool) -> Result<bool, Error> {
         let should_save = Self::update_field(
@@ -334,13 +837,166 @@ impl SettingsPersister {
 
     async fn update(&mut self, should_save: bool) -> Result<bool, Error> {
         if should_save {
-            self.save().await.map(|_| true)
+            self.save_or_revert().await.map(|_| true)
         } else {
             Ok(false)
         }
     }
 }
 
+/// Returns only the settings that differ from [`Settings::default`], as a JSON object. Values of
+/// sensitive fields are redacted.
+pub fn non_default_settings(settings: &Settings) -> serde_json::Value {
+    let to_value = |settings: &Settings| {
+        serde_json::to_value(settings).unwrap_or_else(|error| {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to serialize settings")
+            );
+            serde_json::Value::Null
+        })
+    };
+    let current = to_value(settings);
+    let default = to_value(&Settings::default());
+
+    let mut changed = json_diff(current, &default)
+        .unwrap_or_else(|| serde_json::Value::Object(serde_json::Map::new()));
+    redact(&mut changed);
+    changed
+}
+
+/// Returns the parts of `current` that differ from `default`. Objects are compared key by key,
+/// while any other values are returned whole if they differ.
+fn json_diff(current: serde_json::Value, default: &serde_json::Value) -> Option<serde_json::Value> {
+    use serde_json::Value;
+
+    match (current, default) {
+        (Value::Object(current), Value::Object(default)) => {
+            let changed: serde_json::Map<String, Value> = current
+                .into_iter()
+                .filter_map(|(key, value)| {
+                    let diff = match default.get(&key) {
+                        Some(default_value) => json_diff(value, default_value),
+                        None => Some(value),
+                    };
+                    diff.map(|diff| (key, diff))
+                })
+                .collect();
+            if changed.is_empty() {
+                None
+            } else {
+                Some(Value::Object(changed))
+            }
+        }
+        (current, default) if current == *default => None,
+        (current, _) => Some(current),
+    }
+}
+
+fn redact(value: &mut serde_json::Value) {
+    use serde_json::Value;
+
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if REDACTED_KEYS.contains(&key.as_str()) {
+                    *value = Value::String(REDACTED_VALUE.to_owned());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => (),
+    }
+}
+
+/// A contradiction in the settings that prevents some or all connections from being established.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsIssue {
+    /// Obfuscation is always used, but only OpenVPN relays may be selected.
+    ObfuscationRequiresWireguard,
+    /// Bridges are always used, but only WireGuard relays may be selected.
+    BridgeRequiresOpenVpn,
+    /// Bridges are always used, but only OpenVPN over UDP may be used.
+    BridgeRequiresTcp,
+    /// Multihop is enabled, but only OpenVPN relays may be selected.
+    MultihopRequiresWireguard,
+    /// Custom DNS is enabled, but no DNS servers are specified.
+    NoCustomDnsServers,
+}
+
+impl fmt::Display for SettingsIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            SettingsIssue::ObfuscationRequiresWireguard => {
+                "Obfuscation is only supported by WireGuard. Set the tunnel protocol to WireGuard or any, or disable obfuscation"
+            }
+            SettingsIssue::BridgeRequiresOpenVpn => {
+                "Bridges are only supported by OpenVPN. Set the tunnel protocol to OpenVPN or any, or set the bridge state to auto or off"
+            }
+            SettingsIssue::BridgeRequiresTcp => {
+                "Bridges require OpenVPN over TCP. Change the OpenVPN transport protocol, or set the bridge state to auto or off"
+            }
+            SettingsIssue::MultihopRequiresWireguard => {
+                "Multihop is only supported by WireGuard. Set the tunnel protocol to WireGuard or any, or disable multihop"
+            }
+            SettingsIssue::NoCustomDnsServers => {
+                "Custom DNS is enabled without any DNS servers. Add a DNS server, or disable custom DNS"
+            }
+        };
+        f.write_str(description)
+    }
+}
+
+/// Checks the settings for contradicting options. The settings are not modified.
+pub fn validate_settings(settings: &Settings) -> Vec<SettingsIssue> {
+    let mut issues = vec![];
+
+    if let RelaySettings::Normal(constraints) = &settings.relay_settings {
+        match constraints.tunnel_protocol {
+            Constraint::Only(TunnelType::OpenVpn) => {
+                if settings.obfuscation_settings.selected_obfuscation
+                    == SelectedObfuscation::Udp2Tcp
+                {
+                    issues.push(SettingsIssue::ObfuscationRequiresWireguard);
+                }
+                if constraints.wireguard_constraints.use_multihop {
+                    issues.push(SettingsIssue::MultihopRequiresWireguard);
+                }
+                if matches!(
+                    settings.bridge_state,
+                    BridgeState::On | BridgeState::OnDemand { .. }
+                ) {
+                    if let Constraint::Only(TransportPort {
+                        protocol: TransportProtocol::Udp,
+                        ..
+                    }) = constraints.openvpn_constraints.port
+                    {
+                        issues.push(SettingsIssue::BridgeRequiresTcp);
+                    }
+                }
+            }
+            Constraint::Only(TunnelType::Wireguard) => {
+                if matches!(
+                    settings.bridge_state,
+                    BridgeState::On | BridgeState::OnDemand { .. }
+                ) {
+                    issues.push(SettingsIssue::BridgeRequiresOpenVpn);
+                }
+            }
+            Constraint::Any => (),
+        }
+    }
+
+    let dns_options = &settings.tunnel_options.dns_options;
+    if dns_options.state == DnsState::Custom && dns_options.custom_options.addresses.is_empty() {
+        issues.push(SettingsIssue::NoCustomDnsServers);
+    }
+
+    issues
+}
+
 impl Deref for SettingsPersister {
     type Target = Settings;
 
@@ -351,9 +1007,92 @@ impl Deref for SettingsPersister {
 
 #[cfg(test)]
 mod test {
-    use super::SettingsPersister;
-    use mullvad_types::settings::SettingsVersion;
+    use super::{
+        non_default_settings, validate_settings, Error, SettingsIssue, SettingsPersister,
+        SETTINGS_FILE,
+    };
+    use mullvad_types::{
+        relay_constraints::{
+            BridgeSettings, BridgeState, Constraint, RelayConstraints, RelaySettings,
+        },
+        settings::{Settings, SettingsVersion},
+    };
     use serde_json;
+    use std::net::IpAddr;
+    use talpid_types::net::{
+        openvpn::{ProxyAuth, ProxySettings, RemoteProxySettings},
+        TunnelType,
+    };
+    use talpid_types::tunnel::ConnectionRetryBackoff;
+
+    #[test]
+    fn test_non_default_settings() {
+        assert_eq!(
+            non_default_settings(&Settings::default()),
+            serde_json::json!({})
+        );
+
+        let mut settings = Settings::default();
+        settings.allow_lan = true;
+        settings.bridge_settings =
+            BridgeSettings::Custom(ProxySettings::Remote(RemoteProxySettings {
+                address: "192.0.2.1:1080".parse().unwrap(),
+                auth: Some(ProxyAuth {
+                    username: "user".to_owned(),
+                    password: "secret".to_owned(),
+                }),
+            }));
+
+        assert_eq!(
+            non_default_settings(&settings),
+            serde_json::json!({
+                "allow_lan": true,
+                "bridge_settings": {
+                    "custom": {
+                        "remote": {
+                            "address": "192.0.2.1:1080",
+                            "auth": {
+                                "username": "[REDACTED]",
+                                "password": "[REDACTED]",
+                            },
+                        },
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_settings() {
+        assert_eq!(validate_settings(&Settings::default()), vec![]);
+
+        let mut settings = Settings::default();
+        settings.bridge_state = BridgeState::On;
+        settings.relay_settings = RelaySettings::Normal(RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..Default::default()
+        });
+        assert_eq!(
+            validate_settings(&settings),
+            vec![SettingsIssue::BridgeRequiresOpenVpn]
+        );
+        settings.bridge_state = BridgeState::OnDemand { threshold: 3 };
+        assert_eq!(
+            validate_settings(&settings),
+            vec![SettingsIssue::BridgeRequiresOpenVpn]
+        );
+
+        let mut constraints = RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+            ..Default::default()
+        };
+        constraints.wireguard_constraints.use_multihop = true;
+        settings.relay_settings = RelaySettings::Normal(constraints);
+        assert_eq!(
+            validate_settings(&settings),
+            vec![SettingsIssue::MultihopRequiresWireguard]
+        );
+    }
 
     #[test]
     #[should_panic]
@@ -430,4 +1169,105 @@ mod test {
 
         let _ = SettingsPersister::load_from_bytes(settings).unwrap();
     }
+
+    fn persister_in_temp_dir(name: &str) -> SettingsPersister {
+        let dir = std::env::temp_dir().join(format!(
+            "mullvad-settings-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        SettingsPersister {
+            settings: Settings::default(),
+            saved_settings: Settings::default(),
+            write_failure: None,
+            file_version: None,
+            path: dir.join(SETTINGS_FILE),
+        }
+    }
+
+    #[test]
+    fn test_file_version_from_bytes() {
+        let version = |bytes: &str| SettingsPersister::file_version_from_bytes(bytes.as_bytes());
+
+        assert_eq!(
+            version(r#"{ "settings_version": 2, "relay_settings": 1 }"#),
+            Some(2)
+        );
+        assert_eq!(version(r#"{ "account_token": "1234" }"#), None);
+        assert_eq!(version(r#"{ "settings_version": "v5" }"#), None);
+        assert_eq!(version("not json"), None);
+    }
+
+    #[tokio::test]
+    async fn test_save_replaces_settings_file() {
+        let mut persister = persister_in_temp_dir("save");
+        std::fs::write(&persister.path, "{}").unwrap();
+
+        assert!(persister.set_allow_lan(true).await.unwrap());
+
+        let saved: Settings =
+            serde_json::from_str(&std::fs::read_to_string(&persister.path).unwrap()).unwrap();
+        assert!(saved.allow_lan);
+        assert!(!persister.path.with_extension("json.tmp").exists());
+        std::fs::remove_dir_all(persister.path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_save_failure_reverts_settings() {
+        let mut persister = persister_in_temp_dir("revert");
+        // The settings file cannot be replaced by the temporary file if it is a directory
+        std::fs::create_dir(&persister.path).unwrap();
+        std::fs::write(persister.path.join("keep"), "").unwrap();
+
+        assert!(persister.set_allow_lan(true).await.is_err());
+
+        assert!(!persister.allow_lan);
+        assert!(persister.take_write_failure().is_some());
+        assert!(persister.path.is_dir());
+        assert!(!persister.path.with_extension("json.tmp").exists());
+        std::fs::remove_dir_all(persister.path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_api_bind_interface_rejects_non_interface_addresses() {
+        let mut persister = persister_in_temp_dir("bind");
+
+        for address in ["0.0.0.0", "::", "127.0.0.1", "::1"] {
+            let address: IpAddr = address.parse().unwrap();
+            assert!(matches!(
+                persister.set_api_bind_interface(Some(address)).await,
+                Err(Error::InvalidApiBindInterface(rejected)) if rejected == address
+            ));
+        }
+        assert_eq!(persister.api_bind_interface, None);
+        std::fs::remove_dir_all(persister.path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_set_connection_retry_backoff_bounds() {
+        let mut persister = persister_in_temp_dir("backoff");
+        let backoff = |base_delay_secs, max_delay_secs| ConnectionRetryBackoff {
+            base_delay_secs,
+            max_delay_secs,
+        };
+
+        for invalid in [backoff(0, 10), backoff(10, 5), backoff(1, 601)] {
+            assert!(matches!(
+                persister.set_connection_retry_backoff(invalid).await,
+                Err(Error::InvalidConnectionRetryBackoff)
+            ));
+        }
+        assert_eq!(
+            persister.connection_retry_backoff,
+            ConnectionRetryBackoff::default()
+        );
+
+        for valid in [backoff(1, 600), backoff(5, 5), backoff(0, 0)] {
+            persister.set_connection_retry_backoff(valid).await.unwrap();
+            assert_eq!(persister.connection_retry_backoff, valid);
+        }
+        std::fs::remove_dir_all(persister.path.parent().unwrap()).unwrap();
+    }
 }
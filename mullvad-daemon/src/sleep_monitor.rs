@@ -0,0 +1,38 @@
+//! Notifies the daemon when the machine resumes from sleep, so that the tunnel can be checked.
+//!
+//! On Windows, power events are delivered to the system service instead. See
+//! [`crate::DaemonResumeHandle`]. Other platforms have no sleep notifications, and this is a
+//! no-op there.
+
+use crate::DaemonEventSender;
+
+#[cfg(target_os = "linux")]
+pub fn spawn(daemon_tx: DaemonEventSender) {
+    use crate::InternalDaemonEvent;
+    use talpid_core::mpsc::Sender;
+    use talpid_types::ErrorExt;
+
+    std::thread::spawn(move || {
+        let should_continue_tx = daemon_tx.clone();
+        let result = talpid_dbus::logind::watch_sleep(
+            move |going_to_sleep| {
+                if going_to_sleep {
+                    log::debug!("System is going to sleep");
+                } else {
+                    log::info!("System resumed from sleep");
+                    let _ = daemon_tx.send(InternalDaemonEvent::SystemResumed);
+                }
+            },
+            move || !should_continue_tx.is_closed(),
+        );
+        if let Err(error) = result {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Unable to listen for sleep notifications")
+            );
+        }
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn spawn(_daemon_tx: DaemonEventSender) {}
@@ -0,0 +1,113 @@
+//! Counts events that indicate an unstable connection, such as reconnects and failures.
+
+use mullvad_types::states::TunnelState;
+use std::time::SystemTime;
+
+/// Number of events since the daemon started or since the counters were reset. The counters
+/// saturate instead of overflowing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StabilityCounters {
+    /// Number of connection attempts not started from the disconnected state, i.e. reconnects
+    /// and retries.
+    pub reconnects: u64,
+    /// Number of times the tunnel entered the error state.
+    pub connection_failures: u64,
+    /// Number of WireGuard key rotations that failed.
+    pub key_rotation_failures: u64,
+    /// When the counting started.
+    pub since: SystemTime,
+}
+
+pub struct StabilityTracker {
+    reconnects: u64,
+    connection_failures: u64,
+    /// The number of key rotation failures reported by the account manager when the counters
+    /// were last reset.
+    key_rotation_failures_at_reset: u64,
+    since: SystemTime,
+}
+
+impl StabilityTracker {
+    pub fn new() -> Self {
+        Self {
+            reconnects: 0,
+            connection_failures: 0,
+            key_rotation_failures_at_reset: 0,
+            since: SystemTime::now(),
+        }
+    }
+
+    pub fn record_state_transition(&mut self, previous: &TunnelState, new: &TunnelState) {
+        match new {
+            TunnelState::Connecting { .. } if !previous.is_disconnected() => {
+                self.reconnects = self.reconnects.saturating_add(1);
+            }
+            TunnelState::Error(_) => {
+                self.connection_failures = self.connection_failures.saturating_add(1);
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns the counters. `key_rotation_failures` is the total number of failed key rotations
+    /// reported by the account manager.
+    pub fn counters(&self, key_rotation_failures: u64) -> StabilityCounters {
+        StabilityCounters {
+            reconnects: self.reconnects,
+            connection_failures: self.connection_failures,
+            key_rotation_failures: key_rotation_failures
+                .saturating_sub(self.key_rotation_failures_at_reset),
+            since: self.since,
+        }
+    }
+
+    /// Sets all counters to zero. `key_rotation_failures` is the total number of failed key
+    /// rotations reported by the account manager.
+    pub fn reset(&mut self, key_rotation_failures: u64) {
+        *self = Self {
+            key_rotation_failures_at_reset: key_rotation_failures,
+            ..Self::new()
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use talpid_types::{
+        net::{Endpoint, TransportProtocol, TunnelEndpoint, TunnelType},
+        tunnel::{ErrorState, ErrorStateCause},
+    };
+
+    #[test]
+    fn test_stability_counters() {
+        let connecting = TunnelState::Connecting {
+            endpoint: TunnelEndpoint {
+                endpoint: Endpoint::new(Ipv4Addr::LOCALHOST, 51820, TransportProtocol::Udp),
+                tunnel_type: TunnelType::Wireguard,
+                proxy: None,
+                obfuscation: None,
+                entry_endpoint: None,
+            },
+            location: None,
+        };
+        let error = TunnelState::Error(ErrorState::new(ErrorStateCause::IsOffline, None));
+
+        let mut tracker = StabilityTracker::new();
+        tracker.record_state_transition(&TunnelState::Disconnected, &connecting);
+        tracker.record_state_transition(&connecting, &error);
+        tracker.record_state_transition(&error, &connecting);
+
+        let counters = tracker.counters(3);
+        assert_eq!(counters.reconnects, 1);
+        assert_eq!(counters.connection_failures, 1);
+        assert_eq!(counters.key_rotation_failures, 3);
+
+        tracker.reset(3);
+        let counters = tracker.counters(4);
+        assert_eq!(counters.reconnects, 0);
+        assert_eq!(counters.connection_failures, 0);
+        assert_eq!(counters.key_rotation_failures, 1);
+    }
+}
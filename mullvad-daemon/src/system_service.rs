@@ -1,5 +1,5 @@
 use crate::cli;
-use mullvad_daemon::{runtime::new_runtime_builder, DaemonShutdownHandle};
+use mullvad_daemon::{runtime::new_runtime_builder, DaemonResumeHandle, DaemonShutdownHandle};
 use std::{
     env,
     ffi::{OsStr, OsString},
@@ -115,11 +115,13 @@ pub fn handle_service_main(_arguments: Vec<OsString>) {
     let result = runtime.block_on(crate::create_daemon(log_dir));
     let result = if let Ok(daemon) = result {
         let shutdown_handle = daemon.shutdown_handle();
+        let resume_handle = daemon.resume_handle();
 
         // Register monitor that translates `ServiceControl` to Daemon events
         start_event_monitor(
             persistent_service_status.clone(),
             shutdown_handle,
+            resume_handle,
             event_rx,
             clean_shutdown.clone(),
         );
@@ -158,6 +160,7 @@ pub fn handle_service_main(_arguments: Vec<OsString>) {
 fn start_event_monitor(
     mut persistent_service_status: PersistentServiceStatus,
     shutdown_handle: DaemonShutdownHandle,
+    resume_handle: DaemonResumeHandle,
     event_rx: mpsc::Receiver<ServiceControl>,
     clean_shutdown: Arc<AtomicBool>,
 ) -> thread::JoinHandle<()> {
@@ -179,6 +182,7 @@ fn start_event_monitor(
                     }
                     PowerEventParam::ResumeAutomatic | PowerEventParam::ResumeSuspend => {
                         hibernation_detector.register_resume();
+                        resume_handle.notify_resumed();
                     }
                     _ => (),
                 },
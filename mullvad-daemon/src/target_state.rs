@@ -13,6 +13,7 @@ const TARGET_START_STATE_FILE: &str = "target-start-state.json";
 /// Persists the target state to a file, which is only removed if the instance is dropped cleanly.
 pub struct PersistentTargetState {
     state: TargetState,
+    cached_state: Option<TargetState>,
     cache_path: PathBuf,
     locked: bool,
 }
@@ -21,41 +22,10 @@ impl PersistentTargetState {
     /// Initialize using the current target state (if there is one)
     pub async fn new(cache_dir: &Path) -> Self {
         let cache_path = cache_dir.join(TARGET_START_STATE_FILE);
-        let mut update_cache = false;
-        let state = match fs::read_to_string(&cache_path).await {
-            Ok(content) => serde_json::from_str(&content)
-                .map(|state| {
-                    log::info!(
-                        "Loaded cached target state \"{}\" from {}",
-                        state,
-                        cache_path.display()
-                    );
-                    state
-                })
-                .unwrap_or_else(|error| {
-                    log::error!(
-                        "{}",
-                        error.display_chain_with_msg("Failed to parse cached target tunnel state")
-                    );
-                    update_cache = true;
-                    TargetState::Secured
-                }),
-            Err(error) => {
-                if error.kind() == io::ErrorKind::NotFound {
-                    log::debug!("No cached target state to load");
-                    DEFAULT_TARGET_STATE
-                } else {
-                    log::error!(
-                        "{}",
-                        error.display_chain_with_msg("Failed to read cached target tunnel state")
-                    );
-                    update_cache = true;
-                    TargetState::Secured
-                }
-            }
-        };
+        let (cached_state, update_cache) = Self::load(&cache_path).await;
         let state = PersistentTargetState {
-            state,
+            state: cached_state.unwrap_or(DEFAULT_TARGET_STATE),
+            cached_state,
             cache_path,
             locked: false,
         };
@@ -65,11 +35,14 @@ impl PersistentTargetState {
         state
     }
 
-    /// Override the current target state, if there is one
+    /// Override the current target state, if there is one. The cached state is still read first,
+    /// so that it can be queried with [`Self::cached_state`].
     pub async fn force(cache_dir: &Path, state: TargetState) -> Self {
         let cache_path = cache_dir.join(TARGET_START_STATE_FILE);
+        let (cached_state, _) = Self::load(&cache_path).await;
         let state = PersistentTargetState {
             state,
+            cached_state,
             cache_path,
             locked: false,
         };
@@ -77,6 +50,55 @@ impl PersistentTargetState {
         state
     }
 
+    /// Returns the target state that was left in the cache by the previous instance, if it did
+    /// not shut down cleanly.
+    pub fn cached_state(&self) -> Option<TargetState> {
+        self.cached_state
+    }
+
+    /// Reads the cached target state. Returns whether the cache needs to be rewritten, which is
+    /// the case if it exists but cannot be read.
+    async fn load(cache_path: &Path) -> (Option<TargetState>, bool) {
+        let mut update_cache = false;
+        let state = match fs::read_to_string(cache_path).await {
+            Ok(content) => Some(
+                serde_json::from_str(&content)
+                    .map(|state| {
+                        log::info!(
+                            "Loaded cached target state \"{}\" from {}",
+                            state,
+                            cache_path.display()
+                        );
+                        state
+                    })
+                    .unwrap_or_else(|error| {
+                        log::error!(
+                            "{}",
+                            error.display_chain_with_msg(
+                                "Failed to parse cached target tunnel state"
+                            )
+                        );
+                        update_cache = true;
+                        TargetState::Secured
+                    }),
+            ),
+            Err(error) => {
+                if error.kind() == io::ErrorKind::NotFound {
+                    log::debug!("No cached target state to load");
+                    None
+                } else {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read cached target tunnel state")
+                    );
+                    update_cache = true;
+                    Some(TargetState::Secured)
+                }
+            }
+        };
+        (state, update_cache)
+    }
+
     pub async fn set(&mut self, new_state: TargetState) {
         if new_state != self.state {
             self.state = new_state;
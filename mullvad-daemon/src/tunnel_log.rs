@@ -0,0 +1,96 @@
+//! Reads the most recent output of the tunnel process (OpenVPN or WireGuard) from the log
+//! directory, with secrets redacted.
+
+use regex::Regex;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use talpid_core::tunnel::{OPENVPN_LOG_FILENAME, WIREGUARD_LOG_FILENAME};
+use tokio::fs;
+
+const REDACTED: &str = "[REDACTED]";
+
+lazy_static::lazy_static! {
+    /// Matches WireGuard keys, account numbers and OpenVPN auth tokens.
+    static ref SECRET_RE: Regex = Regex::new(
+        r"(?P<key>[A-Za-z0-9+/]{43}=)|(?P<account>\d{16})|(?P<token>(?i)auth-token\S*\s+)\S+"
+    )
+    .unwrap();
+}
+
+/// Returns the last `count` lines written by the most recently used tunnel process.
+pub async fn read_recent_lines(log_dir: &Path, count: usize) -> io::Result<Vec<String>> {
+    match most_recent_log(log_dir).await? {
+        Some(path) => read_last_lines(&path, count).await,
+        None => Ok(vec![]),
+    }
+}
+
+/// Returns the last `count` lines of the file at `path`, with secrets redacted.
+pub async fn read_last_lines(path: &Path, count: usize) -> io::Result<Vec<String>> {
+    let contents = fs::read(path).await?;
+    let contents = String::from_utf8_lossy(&contents);
+
+    let lines: Vec<&str> = contents.lines().collect();
+    let skip = lines.len().saturating_sub(count);
+    Ok(lines[skip..].iter().map(|line| redact(line)).collect())
+}
+
+async fn most_recent_log(log_dir: &Path) -> io::Result<Option<PathBuf>> {
+    let mut most_recent: Option<(SystemTime, PathBuf)> = None;
+    for filename in [OPENVPN_LOG_FILENAME, WIREGUARD_LOG_FILENAME] {
+        let path = log_dir.join(filename);
+        let modified = match fs::metadata(&path).await {
+            Ok(metadata) => metadata.modified()?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error),
+        };
+        if most_recent
+            .as_ref()
+            .map(|(most_recent, _)| modified > *most_recent)
+            .unwrap_or(true)
+        {
+            most_recent = Some((modified, path));
+        }
+    }
+    Ok(most_recent.map(|(_, path)| path))
+}
+
+/// Replaces WireGuard keys, account numbers and OpenVPN auth tokens in `line`.
+pub fn redact(line: &str) -> String {
+    SECRET_RE
+        .replace_all(line, |captures: &regex::Captures<'_>| {
+            match captures.name("token") {
+                Some(prefix) => format!("{}{}", prefix.as_str(), REDACTED),
+                None => REDACTED.to_owned(),
+            }
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::redact;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(
+            redact("peer BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4= added"),
+            "peer [REDACTED] added"
+        );
+        assert_eq!(
+            redact("Logged in with 1234567890123456"),
+            "Logged in with [REDACTED]"
+        );
+        assert_eq!(
+            redact("PUSH: auth-token SESS_ID_abcdef,ifconfig"),
+            "PUSH: auth-token [REDACTED]"
+        );
+        assert_eq!(
+            redact("Initialization Sequence Completed"),
+            "Initialization Sequence Completed"
+        );
+    }
+}
@@ -4,6 +4,9 @@ pub const PRODUCT_VERSION: &str = include_str!(concat!(env!("OUT_DIR"), "/produc
 /// Contains the date of the git commit this was built from
 pub const COMMIT_DATE: &str = include_str!(concat!(env!("OUT_DIR"), "/git-commit-date.txt"));
 
+/// Contains the hash of the git commit this was built from, or is empty if it is unknown
+pub const COMMIT_HASH: &str = include_str!(concat!(env!("OUT_DIR"), "/git-commit-hash.txt"));
+
 pub fn is_beta_version() -> bool {
     PRODUCT_VERSION.contains("beta")
 }
@@ -21,6 +21,9 @@ use talpid_types::ErrorExt;
 use tokio::fs::{self, File};
 
 const VERSION_INFO_FILENAME: &str = "version-info.json";
+/// Stores a random number identifying this installation, used to decide whether a version that is
+/// being rolled out in stages has been released to it.
+const INSTALL_ID_FILENAME: &str = "install-id";
 
 lazy_static::lazy_static! {
     static ref APP_VERSION: ParsedAppVersion = ParsedAppVersion::from_str(PRODUCT_VERSION).unwrap();
@@ -103,6 +106,7 @@ pub(crate) struct VersionUpdater {
     rx: Option<mpsc::Receiver<VersionUpdaterCommand>>,
     availability_handle: ApiAvailabilityHandle,
     internal_done_tx: Option<oneshot::Sender<AppVersionInfo>>,
+    install_id: u64,
 }
 
 #[derive(Clone)]
@@ -158,6 +162,7 @@ impl VersionUpdater {
         let cache_path = cache_dir.join(VERSION_INFO_FILENAME);
         let (tx, rx) = mpsc::channel(1);
         let platform_version = talpid_platform_metadata::short_version();
+        let install_id = load_or_create_install_id(&cache_dir);
 
         (
             Self {
@@ -170,6 +175,7 @@ impl VersionUpdater {
                 rx: Some(rx),
                 availability_handle,
                 internal_done_tx: None,
+                install_id,
             },
             VersionUpdaterHandle { tx },
         )
@@ -273,9 +279,19 @@ impl VersionUpdater {
         &mut self,
         response: mullvad_api::AppVersionResponse,
     ) -> AppVersionInfo {
+        let rollout = response
+            .latest_stable_rollout
+            .filter(|rollout| *rollout < 1.0);
+        let in_rollout_cohort = match (rollout, &response.latest_stable) {
+            (Some(rollout), Some(latest_stable)) => {
+                rollout_position(self.install_id, latest_stable) < rollout
+            }
+            _ => true,
+        };
+
         let suggested_upgrade = Self::suggested_upgrade(
             &*APP_VERSION,
-            &response.latest_stable,
+            &response.latest_stable.clone().filter(|_| in_rollout_cohort),
             &response.latest_beta,
             self.show_beta_releases || is_beta_version(),
         );
@@ -285,6 +301,8 @@ impl VersionUpdater {
             latest_stable: response.latest_stable.unwrap_or_else(|| "".to_owned()),
             latest_beta: response.latest_beta,
             suggested_upgrade,
+            latest_stable_rolling_out: rollout.is_some(),
+            in_rollout_cohort,
         }
     }
 
@@ -357,18 +375,19 @@ impl VersionUpdater {
                                 .last_app_version_info
                                 .clone()
                             {
+                                let latest_stable = Some(&last_app_version_info.latest_stable)
+                                    .filter(|_| last_app_version_info.in_rollout_cohort)
+                                    .cloned();
                                 let suggested_upgrade = Self::suggested_upgrade(
                                     &*APP_VERSION,
-                                    &Some(last_app_version_info.latest_stable.clone()),
+                                    &latest_stable,
                                     &last_app_version_info.latest_beta,
                                     self.show_beta_releases || is_beta_version(),
                                 );
 
                                 self.update_version_info(AppVersionInfo {
-                                    supported: last_app_version_info.supported,
-                                    latest_stable: last_app_version_info.latest_stable,
-                                    latest_beta: last_app_version_info.latest_beta,
                                     suggested_upgrade,
+                                    ..last_app_version_info
                                 }).await;
                             }
                         }
@@ -421,6 +440,46 @@ impl VersionUpdater {
     }
 }
 
+/// Returns a number in `[0, 1)` that decides whether `version` has been released to the
+/// installation identified by `install_id`. A version has been released to the installation once
+/// its rollout fraction exceeds this number. The number is different for each version, so that
+/// the same installations do not always receive new versions last.
+fn rollout_position(install_id: u64, version: &str) -> f32 {
+    // 64-bit FNV-1a, which unlike the standard library hasher is stable across builds
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let hash = install_id
+        .to_le_bytes()
+        .iter()
+        .chain(version.as_bytes())
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+        });
+    // Use the upper 24 bits, which an f32 can represent exactly
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Reads the installation ID from the cache directory, or creates a new one if there is none.
+fn load_or_create_install_id(cache_dir: &Path) -> u64 {
+    let path = cache_dir.join(INSTALL_ID_FILENAME);
+    if let Some(install_id) = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| content.trim().parse().ok())
+    {
+        return install_id;
+    }
+
+    let install_id = rand::random();
+    if let Err(error) = std::fs::write(&path, install_id.to_string()) {
+        log::error!(
+            "{}",
+            error.display_chain_with_msg("Failed to save installation ID")
+        );
+    }
+    install_id
+}
+
 async fn try_load_cache(cache_dir: &Path) -> Result<AppVersionInfo, Error> {
     let path = cache_dir.join(VERSION_INFO_FILENAME);
     log::debug!("Loading version check cache from {}", path.display());
@@ -454,6 +513,16 @@ pub async fn load_cache(cache_dir: &Path) -> Option<AppVersionInfo> {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_rollout_position() {
+        for install_id in [0, 1, u64::MAX] {
+            let position = rollout_position(install_id, "2022.1");
+            assert!((0.0..1.0).contains(&position));
+            assert_eq!(position, rollout_position(install_id, "2022.1"));
+        }
+        assert_ne!(rollout_position(1, "2022.1"), rollout_position(1, "2022.2"));
+    }
+
     #[test]
     fn test_version_upgrade_suggestions() {
         let latest_stable = Some("2020.4".to_string());
@@ -0,0 +1,69 @@
+//! Notifies the daemon of the Wi-Fi networks the machine is connected to, so that on-demand rules
+//! can be applied.
+//!
+//! On Linux, the networks are obtained from NetworkManager. Other platforms have no access to the
+//! SSID, and this is a no-op there.
+
+use crate::DaemonEventSender;
+
+#[cfg(target_os = "linux")]
+pub fn spawn(daemon_tx: DaemonEventSender) {
+    use crate::InternalDaemonEvent;
+    use talpid_core::mpsc::Sender;
+    use talpid_dbus::network_manager::NetworkManager;
+    use talpid_types::ErrorExt;
+
+    std::thread::spawn(move || {
+        let network_manager = match NetworkManager::new() {
+            Ok(network_manager) => network_manager,
+            Err(error) => {
+                log::warn!(
+                    "{}",
+                    error.display_chain_with_msg("Unable to connect to NetworkManager")
+                );
+                return;
+            }
+        };
+
+        // The networks are queried on a separate thread, since D-Bus calls should not be made
+        // from within the signal handler.
+        let (changed_tx, changed_rx) = std::sync::mpsc::channel();
+        let should_continue_tx = daemon_tx.clone();
+        std::thread::spawn(move || {
+            let network_manager = match NetworkManager::new() {
+                Ok(network_manager) => network_manager,
+                Err(_) => return,
+            };
+            loop {
+                match network_manager.wifi_ssids() {
+                    Ok(ssids) => {
+                        let _ = daemon_tx.send(InternalDaemonEvent::WifiNetworksChanged(ssids));
+                    }
+                    Err(error) => log::debug!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to obtain Wi-Fi networks")
+                    ),
+                }
+                if changed_rx.recv().is_err() {
+                    break;
+                }
+            }
+        });
+
+        let result = network_manager.watch_state(
+            move || {
+                let _ = changed_tx.send(());
+            },
+            move || !should_continue_tx.is_closed(),
+        );
+        if let Err(error) = result {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Unable to listen for network changes")
+            );
+        }
+    });
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn spawn(_daemon_tx: DaemonEventSender) {}
@@ -93,7 +93,7 @@ impl DaemonInterface {
 
         block_on(rx)
             .map_err(|_| Error::NoResponse)?
-            .map_err(Error::RpcError)
+            .map_err(Error::from)
     }
 
     pub fn get_account_history(&self) -> Result<Option<AccountToken>> {
@@ -124,6 +124,7 @@ impl<'env> FromJava<'env> for RelayConstraintsUpdate {
             tunnel_protocol: None,
             openvpn_constraints: None,
             wireguard_constraints: None,
+            port_forwarding: None,
         }
     }
 }
@@ -8,9 +8,10 @@ use jnix::{
 use mullvad_daemon::EventListener;
 use mullvad_types::{
     device::{DeviceEvent, RemoveDeviceEvent},
+    location::GeoIpLocation,
     relay_list::RelayList,
     settings::Settings,
-    states::TunnelState,
+    states::{AutoConnectSuppressedReason, ConnectionReadyInfo, TunnelState},
     version::AppVersionInfo,
 };
 use std::{sync::mpsc, thread};
@@ -71,6 +72,38 @@ impl EventListener for JniEventListener {
     fn notify_remove_device_event(&self, event: RemoveDeviceEvent) {
         let _ = self.0.send(Event::RemoveDeviceEvent(event));
     }
+
+    fn notify_session_label(&self, _label: Option<String>) {
+        // Session labels are not shown by the Android app
+    }
+
+    fn notify_protocol_fallback(&self, _active: bool) {
+        // The protocol fallback is not available on Android
+    }
+
+    fn notify_backup_relay_settings(&self, _active: bool) {
+        // Backup relay settings are not available on Android
+    }
+
+    fn notify_endpoint_changed(&self, _location: GeoIpLocation) {
+        // The Android app shows the location of the tunnel state
+    }
+
+    fn notify_auto_connect_suppressed(&self, _reason: AutoConnectSuppressedReason) {
+        // The Android app asks the user to log in when no device is logged in
+    }
+
+    fn notify_settings_write_failed(&self, _error: String) {
+        // The Android app receives the reverted settings when it requests them
+    }
+
+    fn notify_post_upgrade_complete(&self) {
+        // The Android app does not wait for the post-upgrade migration
+    }
+
+    fn notify_connection_ready(&self, _info: ConnectionReadyInfo) {
+        // The Android app shows notifications based on the tunnel state
+    }
 }
 
 struct JniEventHandler<'env> {
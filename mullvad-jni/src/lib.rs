@@ -114,6 +114,9 @@ impl From<Result<(), daemon_interface::Error>> for LoginResult {
                         _ => LoginResult::OtherError,
                     }
                 }
+                daemon_interface::Error::OtherError(mullvad_daemon::Error::TooManyDevices(_)) => {
+                    LoginResult::MaxDevicesReached
+                }
                 daemon_interface::Error::RpcError(_) => LoginResult::RpcError,
                 _ => LoginResult::OtherError,
             },
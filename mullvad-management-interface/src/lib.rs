@@ -1,6 +1,7 @@
 pub mod types;
 
 use parity_tokio_ipc::Endpoint as IpcEndpoint;
+use prost::Message;
 #[cfg(unix)]
 use std::{env, fs, os::unix::fs::PermissionsExt};
 use std::{
@@ -19,6 +20,24 @@ pub type ManagementServiceClient =
     types::management_service_client::ManagementServiceClient<Channel>;
 pub use types::management_service_server::{ManagementService, ManagementServiceServer};
 
+/// Returns a status reporting that an account has too many devices. `devices` are included in the
+/// details of the status, so that clients can offer to revoke one of them.
+pub fn too_many_devices_status(message: impl Into<String>, devices: types::DeviceList) -> Status {
+    Status::with_details(
+        Code::ResourceExhausted,
+        message,
+        devices.encode_to_vec().into(),
+    )
+}
+
+/// Returns the devices included in a status created by [`too_many_devices_status`].
+pub fn devices_from_status(status: &Status) -> Option<types::DeviceList> {
+    if status.code() != Code::ResourceExhausted || status.details().is_empty() {
+        return None;
+    }
+    types::DeviceList::decode(status.details()).ok()
+}
+
 #[cfg(unix)]
 lazy_static::lazy_static! {
     static ref MULLVAD_MANAGEMENT_SOCKET_GROUP: Option<String> = env::var("MULLVAD_MANAGEMENT_SOCKET_GROUP")
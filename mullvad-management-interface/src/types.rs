@@ -24,6 +24,36 @@ impl From<mullvad_types::location::GeoIpLocation> for GeoIpLocation {
     }
 }
 
+impl From<mullvad_types::states::ConnectionReadyInfo> for ConnectionReadyInfo {
+    fn from(info: mullvad_types::states::ConnectionReadyInfo) -> Self {
+        ConnectionReadyInfo {
+            endpoint: Some(TunnelEndpoint::from(info.endpoint)),
+            country: info.country,
+            city: info.city.unwrap_or_default(),
+            hostname: info.hostname.unwrap_or_default(),
+            ipv4: info.ipv4.map(|ip| ip.to_string()).unwrap_or_default(),
+            ipv6: info.ipv6.map(|ip| ip.to_string()).unwrap_or_default(),
+            verified: info.verified,
+        }
+    }
+}
+
+impl From<mullvad_types::states::AutoConnectSuppressedReason> for AutoConnectSuppressed {
+    fn from(reason: mullvad_types::states::AutoConnectSuppressedReason) -> Self {
+        use mullvad_types::states::AutoConnectSuppressedReason;
+
+        let reason = match reason {
+            AutoConnectSuppressedReason::NoDevice => auto_connect_suppressed::Reason::NoDevice,
+            AutoConnectSuppressedReason::MigrationPending => {
+                auto_connect_suppressed::Reason::MigrationPending
+            }
+        };
+        AutoConnectSuppressed {
+            reason: i32::from(reason),
+        }
+    }
+}
+
 impl From<talpid_types::net::TunnelEndpoint> for TunnelEndpoint {
     fn from(endpoint: talpid_types::net::TunnelEndpoint) -> Self {
         use talpid_types::net;
@@ -96,7 +126,16 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
 
         let state = match state {
             MullvadTunnelState::Disconnected => {
-                tunnel_state::State::Disconnected(tunnel_state::Disconnected {})
+                tunnel_state::State::Disconnected(tunnel_state::Disconnected { paused_until: None })
+            }
+            #[cfg(not(target_os = "android"))]
+            MullvadTunnelState::Paused { resume_at } => {
+                tunnel_state::State::Disconnected(tunnel_state::Disconnected {
+                    paused_until: Some(Timestamp {
+                        seconds: resume_at.timestamp(),
+                        nanos: 0,
+                    }),
+                })
             }
             MullvadTunnelState::Connecting { endpoint, location } => {
                 tunnel_state::State::Connecting(tunnel_state::Connecting {
@@ -162,6 +201,14 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                             talpid_tunnel::ErrorStateCause::SplitTunnelError => {
                                 i32::from(Cause::SplitTunnelError)
                             }
+                            #[cfg(not(target_os = "android"))]
+                            talpid_tunnel::ErrorStateCause::TunnelUnverified => {
+                                i32::from(Cause::TunnelUnverified)
+                            }
+                            #[cfg(not(target_os = "android"))]
+                            talpid_tunnel::ErrorStateCause::ConnectDeadlineExceeded => {
+                                i32::from(Cause::ConnectDeadlineExceeded)
+                            }
                         },
                         blocking_error: error_state.block_failure().map(map_firewall_error),
                         auth_fail_reason: if let talpid_tunnel::ErrorStateCause::AuthFailed(
@@ -183,6 +230,12 @@ impl From<mullvad_types::states::TunnelState> for TunnelState {
                             talpid_tunnel::ParameterGenerationError::NoMatchingBridgeRelay => {
                                 i32::from(GenerationError::NoMatchingBridgeRelay)
                             }
+                            talpid_tunnel::ParameterGenerationError::NoMatchingPortForwardingRelay => {
+                                i32::from(GenerationError::NoMatchingPortForwardingRelay)
+                            }
+                            talpid_tunnel::ParameterGenerationError::NoMatchingObfuscatorRelay => {
+                                i32::from(GenerationError::NoMatchingObfuscatorRelay)
+                            }
                             talpid_tunnel::ParameterGenerationError::NoWireguardKey => {
                                 i32::from(GenerationError::NoWireguardKey)
                             }
@@ -280,11 +333,15 @@ impl From<mullvad_types::wireguard::PublicKey> for PublicKey {
 
 impl From<mullvad_types::version::AppVersionInfo> for AppVersionInfo {
     fn from(version_info: mullvad_types::version::AppVersionInfo) -> Self {
+        let suggested_upgrade_rolling_out = version_info.suggested_upgrade_rolling_out();
         Self {
             supported: version_info.supported,
             latest_stable: version_info.latest_stable,
             latest_beta: version_info.latest_beta,
             suggested_upgrade: version_info.suggested_upgrade.unwrap_or_default(),
+            latest_stable_rolling_out: version_info.latest_stable_rolling_out,
+            in_rollout_cohort: version_info.in_rollout_cohort,
+            suggested_upgrade_rolling_out,
         }
     }
 }
@@ -353,6 +410,17 @@ impl From<talpid_types::net::IpVersion> for IpVersion {
     }
 }
 
+impl From<mullvad_types::relay_constraints::JurisdictionGroup> for JurisdictionGroup {
+    fn from(group: mullvad_types::relay_constraints::JurisdictionGroup) -> Self {
+        use mullvad_types::relay_constraints::JurisdictionGroup as MullvadJurisdictionGroup;
+        match group {
+            MullvadJurisdictionGroup::FiveEyes => Self::FiveEyes,
+            MullvadJurisdictionGroup::NineEyes => Self::NineEyes,
+            MullvadJurisdictionGroup::FourteenEyes => Self::FourteenEyes,
+        }
+    }
+}
+
 impl From<IpVersion> for IpVersionConstraint {
     fn from(version: IpVersion) -> Self {
         Self {
@@ -434,6 +502,11 @@ impl From<&mullvad_types::settings::Settings> for Settings {
         #[cfg(not(windows))]
         let split_tunnel = None;
 
+        #[cfg(target_os = "linux")]
+        let tunnel_fwmark = settings.linux_routing_options.fwmark_or_default();
+        #[cfg(not(target_os = "linux"))]
+        let tunnel_fwmark = 0;
+
         Self {
             relay_settings: Some(RelaySettings::from(settings.get_relay_settings())),
             bridge_settings: Some(BridgeSettings::from(settings.bridge_settings.clone())),
@@ -445,6 +518,7 @@ impl From<&mullvad_types::settings::Settings> for Settings {
             show_beta_releases: settings.show_beta_releases,
             obfuscation_settings: Some(ObfuscationSettings::from(&settings.obfuscation_settings)),
             split_tunnel,
+            tunnel_fwmark,
         }
     }
 }
@@ -452,12 +526,15 @@ impl From<&mullvad_types::settings::Settings> for Settings {
 impl From<mullvad_types::relay_constraints::BridgeState> for BridgeState {
     fn from(state: mullvad_types::relay_constraints::BridgeState) -> Self {
         use mullvad_types::relay_constraints::BridgeState;
+        let (state, on_demand_threshold) = match state {
+            BridgeState::Auto => (bridge_state::State::Auto, 0),
+            BridgeState::On => (bridge_state::State::On, 0),
+            BridgeState::Off => (bridge_state::State::Off, 0),
+            BridgeState::OnDemand { threshold } => (bridge_state::State::OnDemand, threshold),
+        };
         Self {
-            state: i32::from(match state {
-                BridgeState::Auto => bridge_state::State::Auto,
-                BridgeState::On => bridge_state::State::On,
-                BridgeState::Off => bridge_state::State::Off,
-            }),
+            state: i32::from(state),
+            on_demand_threshold,
         }
     }
 }
@@ -580,6 +657,15 @@ impl From<mullvad_types::relay_constraints::RelaySettings> for RelaySettings {
                             .entry_location
                             .option()
                             .map(RelayLocation::from),
+                        multihop_same_country: constraints
+                            .wireguard_constraints
+                            .multihop_same_country,
+                        multihop_avoided_jurisdictions: constraints
+                            .wireguard_constraints
+                            .multihop_avoided_jurisdictions
+                            .iter()
+                            .map(|group| i32::from(JurisdictionGroup::from(*group)))
+                            .collect(),
                     }),
 
                     openvpn_constraints: Some(OpenvpnConstraints {
@@ -589,6 +675,7 @@ impl From<mullvad_types::relay_constraints::RelaySettings> for RelaySettings {
                             .option()
                             .map(TransportPort::from),
                     }),
+                    port_forwarding: constraints.port_forwarding,
                 })
             }
         };
@@ -641,6 +728,13 @@ impl From<&mullvad_types::settings::TunnelOptions> for TunnelOptions {
                 use_wireguard_nt: options.wireguard.options.use_wireguard_nt,
                 #[cfg(not(windows))]
                 use_wireguard_nt: false,
+                keepalive: u32::from(
+                    options
+                        .wireguard
+                        .options
+                        .wireguard_keepalive
+                        .unwrap_or_default(),
+                ),
             }),
             generic: Some(tunnel_options::GenericOptions {
                 enable_ipv6: options.generic.enable_ipv6,
@@ -659,6 +753,7 @@ impl From<mullvad_types::relay_list::RelayListCountry> for RelayListCountry {
             name: country.name,
             code: country.code,
             cities: Vec::with_capacity(country.cities.len()),
+            name_language: mullvad_types::relay_list::LOCATION_NAME_LANGUAGE.to_owned(),
         };
 
         for city in country.cities.into_iter() {
@@ -668,6 +763,7 @@ impl From<mullvad_types::relay_list::RelayListCountry> for RelayListCountry {
                 latitude: city.latitude,
                 longitude: city.longitude,
                 relays: city.relays.into_iter().map(Relay::from).collect(),
+                name_language: mullvad_types::relay_list::LOCATION_NAME_LANGUAGE.to_owned(),
             });
         }
 
@@ -721,6 +817,7 @@ impl From<mullvad_types::relay_list::Relay> for Relay {
                     })
                     .collect(),
             }),
+            port_forwarding: relay.port_forwarding,
             bridges: Some(RelayBridges {
                 shadowsocks: relay
                     .bridges
@@ -805,6 +902,25 @@ impl TryFrom<&WireguardConstraints> for mullvad_types::relay_constraints::Wiregu
             None => None,
         };
 
+        let multihop_avoided_jurisdictions = constraints
+            .multihop_avoided_jurisdictions
+            .iter()
+            .map(|group| match JurisdictionGroup::from_i32(*group) {
+                Some(JurisdictionGroup::FiveEyes) => {
+                    Ok(mullvad_constraints::JurisdictionGroup::FiveEyes)
+                }
+                Some(JurisdictionGroup::NineEyes) => {
+                    Ok(mullvad_constraints::JurisdictionGroup::NineEyes)
+                }
+                Some(JurisdictionGroup::FourteenEyes) => {
+                    Ok(mullvad_constraints::JurisdictionGroup::FourteenEyes)
+                }
+                None => Err(FromProtobufTypeError::InvalidArgument(
+                    "invalid jurisdiction group",
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
         Ok(mullvad_constraints::WireguardConstraints {
             port: if constraints.port == 0 {
                 Constraint::Any
@@ -818,6 +934,8 @@ impl TryFrom<&WireguardConstraints> for mullvad_types::relay_constraints::Wiregu
                 .clone()
                 .map(Constraint::<mullvad_types::relay_constraints::LocationConstraint>::from)
                 .unwrap_or(Constraint::Any),
+            multihop_same_country: constraints.multihop_same_country,
+            multihop_avoided_jurisdictions,
         })
     }
 }
@@ -902,6 +1020,7 @@ impl TryFrom<RelaySettings> for mullvad_types::relay_constraints::RelaySettings
                         tunnel_protocol,
                         wireguard_constraints,
                         openvpn_constraints,
+                        port_forwarding: settings.port_forwarding,
                     },
                 ))
             }
@@ -992,6 +1111,9 @@ impl TryFrom<RelaySettingsUpdate> for mullvad_types::relay_constraints::RelaySet
                         tunnel_protocol,
                         wireguard_constraints,
                         openvpn_constraints,
+                        port_forwarding: settings
+                            .port_forwarding
+                            .map(|update| update.port_forwarding),
                     },
                 ))
             }
@@ -1127,6 +1249,7 @@ impl TryFrom<ConnectionConfig> for mullvad_types::ConnectionConfig {
                             public_key,
                             allowed_ips,
                             endpoint,
+                            persistent_keepalive: None,
                         },
                         exit_peer: None,
                         ipv4_gateway,
@@ -1303,6 +1426,13 @@ impl TryFrom<BridgeState> for mullvad_types::relay_constraints::BridgeState {
             Some(bridge_state::State::Off) => {
                 Ok(mullvad_types::relay_constraints::BridgeState::Off)
             }
+            Some(bridge_state::State::OnDemand) => {
+                let threshold = match state.on_demand_threshold {
+                    0 => mullvad_types::relay_constraints::BridgeState::DEFAULT_ON_DEMAND_THRESHOLD,
+                    threshold => threshold,
+                };
+                Ok(mullvad_types::relay_constraints::BridgeState::OnDemand { threshold })
+            }
             None => Err(FromProtobufTypeError::InvalidArgument(
                 "invalid bridge state",
             )),
@@ -1352,6 +1482,15 @@ impl TryFrom<TunnelOptions> for mullvad_types::settings::TunnelOptions {
                     } else {
                         None
                     },
+                    wireguard_keepalive: if wireguard_options.keepalive != 0 {
+                        Some(u16::try_from(wireguard_options.keepalive).map_err(|_| {
+                            FromProtobufTypeError::InvalidArgument(
+                                "invalid WireGuard keepalive interval",
+                            )
+                        })?)
+                    } else {
+                        None
+                    },
                     #[cfg(windows)]
                     use_wireguard_nt: wireguard_options.use_wireguard_nt,
                 },
@@ -12,12 +12,13 @@ use mullvad_types::{
         Match, ObfuscationSettings, OpenVpnConstraints, Providers, RelayConstraints, RelaySettings,
         SelectedObfuscation, Set, TransportPort, Udp2TcpObfuscationSettings, WireguardConstraints,
     },
-    relay_list::{Relay, RelayList, Udp2TcpEndpointData},
+    relay_list::{ProtocolCounts, ProviderInfo, Relay, RelayList, Udp2TcpEndpointData},
     CustomTunnelEndpoint,
 };
-use parking_lot::{Mutex, MutexGuard};
+use parking_lot::Mutex;
 use rand::{self, seq::SliceRandom, Rng};
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     io,
     net::{IpAddr, SocketAddr},
     path::Path,
@@ -32,9 +33,13 @@ use talpid_types::{
     ErrorExt,
 };
 
-use self::matcher::{RelayMatcher, TunnelMatcher, WireguardMatcher};
+use self::{
+    matcher::{RelayMatcher, TunnelMatcher, WireguardMatcher},
+    relay_stats::RelayStats,
+};
 
 mod matcher;
+mod relay_stats;
 pub mod updater;
 
 const DATE_TIME_FORMAT_STR: &str = "%Y-%m-%d %H:%M:%S%.3f";
@@ -45,6 +50,8 @@ const WIREGUARD_EXIT_CONSTRAINTS: WireguardMatcher = WireguardMatcher {
     peer: None,
     port: Constraint::Only(DEFAULT_WIREGUARD_PORT),
     ip_version: Constraint::Only(IpVersion::V4),
+    same_country_as_peer: false,
+    avoided_jurisdictions_with_peer: Vec::new(),
 };
 
 const UDP2TCP_PORTS: [u16; 3] = [80, 443, 5001];
@@ -66,6 +73,9 @@ pub enum Error {
     #[error(display = "No relays matching current constraints")]
     NoRelay,
 
+    #[error(display = "No relays with port forwarding matching current constraints")]
+    NoPortForwardingRelay,
+
     #[error(display = "No bridges matching current constraints")]
     NoBridge,
 
@@ -209,18 +219,71 @@ impl ParsedRelays {
     }
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct SelectorConfig {
     pub relay_settings: RelaySettings,
     pub bridge_state: BridgeState,
     pub bridge_settings: BridgeSettings,
     pub obfuscation_settings: ObfuscationSettings,
+    /// Temporarily lower the weight of relays that could recently not be connected to.
+    pub deprioritize_failing_relays: bool,
+    /// Retry the previously selected relays once before selecting new ones.
+    pub retry_same_relay: bool,
+    /// OpenVPN transports to cycle through on successive retries, if the port is not constrained.
+    /// The default ordering is used if this is empty.
+    pub openvpn_endpoint_preference: Vec<TransportPort>,
+}
+
+/// Relays that would be selected with a given config. See [RelaySelector::preview_relay].
+#[derive(Debug, Clone)]
+pub struct SelectionOutcome {
+    pub relay: SelectedRelay,
+    pub bridge: Option<SelectedBridge>,
+    pub obfuscator: Option<SelectedObfuscator>,
+    /// Number of active relays that match the relay constraints. This is `None` for custom
+    /// tunnel endpoints.
+    pub matching_relay_count: Option<usize>,
+}
+
+/// Whether the relay list in use is signed, and the result of verifying the signature. See
+/// [RelaySelector::relay_list_signature_info].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayListSignatureInfo {
+    /// Whether relay list signatures are verified by this build. If not, the list is only
+    /// protected by the TLS connection to the API it was downloaded from.
+    pub verification_available: bool,
+    /// Whether the relay list has a signature.
+    pub signed: bool,
+    /// Whether the signature is valid. This is `None` unless the relay list is signed and the
+    /// signature was verified.
+    pub valid: Option<bool>,
+    /// When the relay list was signed.
+    pub signed_at: Option<SystemTime>,
 }
 
+type RelaySelection = (
+    SelectedRelay,
+    Option<SelectedBridge>,
+    Option<SelectedObfuscator>,
+);
+
 #[derive(Clone)]
 pub struct RelaySelector {
     config: Arc<Mutex<SelectorConfig>>,
     parsed_relays: Arc<Mutex<ParsedRelays>>,
+    /// The cached or downloaded relays while an ephemeral relay list is in use.
+    overridden_relays: Arc<Mutex<Option<ParsedRelays>>>,
+    relay_stats: Arc<Mutex<RelayStats>>,
+    /// Relays returned by the last call to [RelaySelector::get_relay].
+    last_selection: Arc<Mutex<Option<RelaySelection>>>,
+    /// Copy of [SelectorConfig::openvpn_endpoint_preference], which is read while `config` may
+    /// already be locked.
+    openvpn_endpoint_preference: Arc<Mutex<Vec<TransportPort>>>,
+    /// Copy of the selected obfuscation in [SelectorConfig::obfuscation_settings], which is read
+    /// while `config` may already be locked.
+    selected_obfuscation: Arc<Mutex<SelectedObfuscation>>,
+    /// Hostnames of bridge relays that are not used for tunnel connections.
+    excluded_bridges: Arc<Mutex<HashSet<String>>>,
 }
 
 impl RelaySelector {
@@ -243,14 +306,143 @@ impl RelaySelector {
                 .format(DATE_TIME_FORMAT_STR)
         );
 
+        let relay_stats = RelayStats::load(cache_dir, config.deprioritize_failing_relays);
+        let openvpn_endpoint_preference = config.openvpn_endpoint_preference.clone();
+        let selected_obfuscation = config.obfuscation_settings.selected_obfuscation;
+
         RelaySelector {
             config: Arc::new(Mutex::new(config)),
             parsed_relays: Arc::new(Mutex::new(unsynchronized_parsed_relays)),
+            relay_stats: Arc::new(Mutex::new(relay_stats)),
+            last_selection: Arc::new(Mutex::new(None)),
+            openvpn_endpoint_preference: Arc::new(Mutex::new(openvpn_endpoint_preference)),
+            selected_obfuscation: Arc::new(Mutex::new(selected_obfuscation)),
+            excluded_bridges: Arc::new(Mutex::new(HashSet::new())),
+            overridden_relays: Arc::new(Mutex::new(None)),
         }
     }
 
+    /// Returns the config that is currently used to select relays.
+    pub fn get_config(&self) -> SelectorConfig {
+        self.config.lock().clone()
+    }
+
     pub fn set_config(&mut self, config: SelectorConfig) {
+        self.relay_stats
+            .lock()
+            .set_enabled(config.deprioritize_failing_relays);
+        *self.openvpn_endpoint_preference.lock() = config.openvpn_endpoint_preference.clone();
+        *self.selected_obfuscation.lock() = config.obfuscation_settings.selected_obfuscation;
         *self.config.lock() = config;
+        // The previous selection may no longer match the constraints
+        *self.last_selection.lock() = None;
+    }
+
+    /// Returns whether a WireGuard entry and exit relay can be selected with the current relay
+    /// constraints if multihop were enabled. This is always `false` for custom tunnel endpoints and
+    /// if the tunnel protocol is constrained to OpenVPN.
+    pub fn can_use_multihop(&self) -> bool {
+        let constraints = match &self.config.lock().relay_settings {
+            RelaySettings::Normal(constraints) => constraints.clone(),
+            RelaySettings::CustomTunnelEndpoint(_) => return false,
+        };
+        if constraints.tunnel_protocol == Constraint::Only(TunnelType::OpenVpn) {
+            return false;
+        }
+        let wireguard_constraints = WireguardConstraints {
+            use_multihop: true,
+            ..constraints.wireguard_constraints
+        };
+        self.get_wireguard_endpoint(
+            &constraints.location,
+            &constraints.providers,
+            constraints.port_forwarding,
+            &wireguard_constraints,
+            0,
+        )
+        .is_ok()
+    }
+
+    /// Returns the tunnel type that the first connection attempt would use with the current relay
+    /// settings. Unlike [RelaySelector::get_relay], this does not select any relays.
+    pub fn resolve_tunnel_type(&self) -> TunnelType {
+        let constraints = match &self.config.lock().relay_settings {
+            RelaySettings::Normal(constraints) => constraints.clone(),
+            RelaySettings::CustomTunnelEndpoint(custom_relay) => return custom_relay.tunnel_type(),
+        };
+        match constraints.tunnel_protocol {
+            Constraint::Only(tunnel_type) => tunnel_type,
+            Constraint::Any => {
+                let (_port, _protocol, tunnel_type) = self.preferred_tunnel_constraints(
+                    0,
+                    &constraints.location,
+                    &constraints.providers,
+                );
+                tunnel_type
+            }
+        }
+    }
+
+    /// Records whether a tunnel could be established to `relay`. This is only used if
+    /// [SelectorConfig::deprioritize_failing_relays] is enabled.
+    pub fn record_connection_result(&self, relay: &Relay, success: bool) {
+        self.relay_stats
+            .lock()
+            .record(&relay.hostname, success, SystemTime::now());
+    }
+
+    /// Selects relays from `relay_list` instead of the cached or downloaded relays until
+    /// [RelaySelector::clear_ephemeral_relay_list] is called. The list is only kept in memory.
+    pub fn set_ephemeral_relay_list(&self, relay_list: RelayList) {
+        {
+            let mut overridden_relays = self.overridden_relays.lock();
+            let mut parsed_relays = self.parsed_relays.lock();
+            let previous = std::mem::replace(
+                &mut *parsed_relays,
+                ParsedRelays::from_relay_list(relay_list, SystemTime::now()),
+            );
+            if overridden_relays.is_none() {
+                *overridden_relays = Some(previous);
+            }
+        }
+        *self.last_selection.lock() = None;
+    }
+
+    /// Goes back to selecting from the cached or downloaded relays. Returns the relay list that is
+    /// used again, or `None` if no ephemeral relay list was in use.
+    pub fn clear_ephemeral_relay_list(&self) -> Option<RelayList> {
+        let locations = {
+            let relays = self.overridden_relays.lock().take()?;
+            let mut parsed_relays = self.parsed_relays.lock();
+            *parsed_relays = relays;
+            parsed_relays.locations().clone()
+        };
+        *self.last_selection.lock() = None;
+        Some(locations)
+    }
+
+    /// Returns whether relays are selected from an ephemeral relay list.
+    pub fn is_using_ephemeral_relay_list(&self) -> bool {
+        self.overridden_relays.lock().is_some()
+    }
+
+    /// Returns whether the relay list in use is signed and whether its signature is valid.
+    ///
+    /// The API does not sign relay lists yet, and neither downloaded, cached nor bundled lists
+    /// are verified when they are loaded. So this always reports that verification is
+    /// unavailable.
+    pub fn relay_list_signature_info(&self) -> RelayListSignatureInfo {
+        RelayListSignatureInfo {
+            verification_available: false,
+            signed: false,
+            valid: None,
+            signed_at: None,
+        }
+    }
+
+    /// Returns when the relay list in use was last updated.
+    pub fn last_updated(&self) -> SystemTime {
+        self.parsed_relays.lock().last_updated()
     }
 
     /// Returns all countries and cities. The cities in the object returned does not have any
@@ -259,26 +451,106 @@ impl RelaySelector {
         self.parsed_relays.lock().locations().clone()
     }
 
-    /// Returns a random relay and relay endpoint matching the current constraints.
-    pub fn get_relay(
+    /// Returns a random relay and relay endpoint matching the current constraints. If
+    /// [SelectorConfig::retry_same_relay] is enabled, the first retry uses the previous selection.
+    pub fn get_relay(&self, retry_attempt: u32) -> Result<RelaySelection, Error> {
+        let config = self.config.lock();
+        if config.retry_same_relay && retry_attempt == 1 {
+            if let Some(selection) = self.last_selection.lock().clone() {
+                log::debug!("Retrying the previously selected relay");
+                return Ok(selection);
+            }
+        }
+        let selection = self.select_relay(&config, retry_attempt)?;
+        *self.last_selection.lock() = Some(selection.clone());
+        Ok(selection)
+    }
+
+    /// Returns a random relay and relay endpoint like [RelaySelector::get_relay], but with the
+    /// tunnel protocol constrained to `tunnel_type` regardless of the relay settings. Custom
+    /// tunnel endpoints are returned unchanged. The selection is not retried by
+    /// [SelectorConfig::retry_same_relay].
+    pub fn get_relay_with_tunnel_type(
         &self,
         retry_attempt: u32,
-    ) -> Result<
-        (
-            SelectedRelay,
-            Option<SelectedBridge>,
-            Option<SelectedObfuscator>,
-        ),
-        Error,
-    > {
-        let config = self.config.lock();
+        tunnel_type: TunnelType,
+    ) -> Result<RelaySelection, Error> {
+        let mut config = self.config.lock().clone();
+        if let RelaySettings::Normal(ref mut constraints) = config.relay_settings {
+            constraints.tunnel_protocol = Constraint::Only(tunnel_type);
+        }
+        let selection = self.select_relay(&config, retry_attempt)?;
+        *self.last_selection.lock() = None;
+        Ok(selection)
+    }
+
+    /// Returns a random relay and relay endpoint matching `config` rather than the current config.
+    /// The previous selection is not reused and is cleared.
+    pub fn get_relay_with_config(
+        &self,
+        config: &SelectorConfig,
+        retry_attempt: u32,
+    ) -> Result<RelaySelection, Error> {
+        let selection = self.select_relay(config, retry_attempt)?;
+        *self.last_selection.lock() = None;
+        Ok(selection)
+    }
+
+    /// Returns the relays that the first connection attempt would use with `config`, without
+    /// changing the current config or the previous selection.
+    pub fn preview_relay(&self, config: &SelectorConfig) -> Result<SelectionOutcome, Error> {
+        let (relay, bridge, obfuscator) = self.select_relay(config, 0)?;
+        let matching_relay_count = match &config.relay_settings {
+            RelaySettings::Normal(constraints) => {
+                let matcher = RelayMatcher::from(constraints.clone());
+                let count = self
+                    .parsed_relays
+                    .lock()
+                    .relays()
+                    .iter()
+                    .filter(|relay| relay.active && matcher.filter_matching_relay(relay).is_some())
+                    .count();
+                Some(count)
+            }
+            RelaySettings::CustomTunnelEndpoint(_) => None,
+        };
+        Ok(SelectionOutcome {
+            relay,
+            bridge,
+            obfuscator,
+            matching_relay_count,
+        })
+    }
+
+    fn select_relay(
+        &self,
+        config: &SelectorConfig,
+        retry_attempt: u32,
+    ) -> Result<RelaySelection, Error> {
         match &config.relay_settings {
             RelaySettings::CustomTunnelEndpoint(custom_relay) => {
                 Ok((SelectedRelay::Custom(custom_relay.clone()), None, None))
             }
             RelaySettings::Normal(constraints) => {
-                let relay =
-                    self.get_tunnel_endpoint(&constraints, config.bridge_state, retry_attempt)?;
+                let bridge_state = Self::effective_bridge_state(config.bridge_state, retry_attempt);
+                let relay = self
+                    .get_tunnel_endpoint(&constraints, bridge_state, retry_attempt)
+                    .map_err(|error| match error {
+                        Error::NoRelay if constraints.port_forwarding => {
+                            // Only blame port forwarding if a relay matches the other constraints
+                            let mut constraints = constraints.clone();
+                            constraints.port_forwarding = false;
+                            match self.get_tunnel_endpoint(
+                                &constraints,
+                                bridge_state,
+                                retry_attempt,
+                            ) {
+                                Ok(_) => Error::NoPortForwardingRelay,
+                                Err(_) => Error::NoRelay,
+                            }
+                        }
+                        error => error,
+                    })?;
                 let bridge = match relay.endpoint {
                     MullvadEndpoint::OpenVpn(endpoint)
                         if endpoint.protocol == TransportProtocol::Tcp =>
@@ -288,7 +560,7 @@ impl RelaySelector {
                             .location
                             .as_ref()
                             .expect("Relay has no location set");
-                        self.get_bridge_for(&config, location, retry_attempt)?
+                        self.get_bridge_for(config, bridge_state, location, retry_attempt)?
                     }
                     _ => None,
                 };
@@ -297,7 +569,7 @@ impl RelaySelector {
                         let obfuscator_relay =
                             relay.entry_relay.as_ref().unwrap_or(&relay.exit_relay);
                         self.get_obfuscator_inner(
-                            &config,
+                            config,
                             obfuscator_relay,
                             &endpoint,
                             retry_attempt,
@@ -317,11 +589,51 @@ impl RelaySelector {
         relay_constraints: &RelayConstraints,
         bridge_state: BridgeState,
         retry_attempt: u32,
+    ) -> Result<NormalSelectedRelay, Error> {
+        let wireguard_constraints = &relay_constraints.wireguard_constraints;
+        if !wireguard_constraints.use_multihop
+            || wireguard_constraints
+                .multihop_avoided_jurisdictions
+                .is_empty()
+        {
+            return self.get_tunnel_endpoint_for_protocol(
+                relay_constraints,
+                bridge_state,
+                retry_attempt,
+            );
+        }
+
+        // Avoiding jurisdictions is only a preference
+        self.get_tunnel_endpoint_for_protocol(relay_constraints, bridge_state, retry_attempt)
+            .or_else(|_| {
+                log::debug!(
+                    "No multihop relays avoid the jurisdiction groups {:?}. Ignoring them",
+                    wireguard_constraints.multihop_avoided_jurisdictions
+                );
+                let mut relay_constraints = relay_constraints.clone();
+                relay_constraints
+                    .wireguard_constraints
+                    .multihop_avoided_jurisdictions
+                    .clear();
+                self.get_tunnel_endpoint_for_protocol(
+                    &relay_constraints,
+                    bridge_state,
+                    retry_attempt,
+                )
+            })
+    }
+
+    fn get_tunnel_endpoint_for_protocol(
+        &self,
+        relay_constraints: &RelayConstraints,
+        bridge_state: BridgeState,
+        retry_attempt: u32,
     ) -> Result<NormalSelectedRelay, Error> {
         match relay_constraints.tunnel_protocol {
             Constraint::Only(TunnelType::OpenVpn) => self.get_openvpn_endpoint(
                 &relay_constraints.location,
                 &relay_constraints.providers,
+                relay_constraints.port_forwarding,
                 relay_constraints.openvpn_constraints.clone(),
                 bridge_state,
                 retry_attempt,
@@ -330,6 +642,7 @@ impl RelaySelector {
             Constraint::Only(TunnelType::Wireguard) => self.get_wireguard_endpoint(
                 &relay_constraints.location,
                 &relay_constraints.providers,
+                relay_constraints.port_forwarding,
                 &relay_constraints.wireguard_constraints,
                 retry_attempt,
             ),
@@ -367,12 +680,127 @@ impl RelaySelector {
         Some(Coordinates::midpoint(&matching_locations))
     }
 
+    /// Returns the providers of all active relays, sorted by name. The selectable relays are
+    /// counted using the current relay constraints, except for the provider constraint.
+    pub fn get_providers(&self) -> Vec<ProviderInfo> {
+        let matcher = match &self.config.lock().relay_settings {
+            RelaySettings::Normal(constraints) => {
+                let mut matcher = RelayMatcher::from(constraints.clone());
+                matcher.providers = Constraint::Any;
+                Some(matcher)
+            }
+            RelaySettings::CustomTunnelEndpoint(_) => None,
+        };
+
+        let parsed_relays = self.parsed_relays.lock();
+        let mut providers: BTreeMap<&str, ProviderInfo> = BTreeMap::new();
+        for relay in parsed_relays.relays().iter().filter(|relay| relay.active) {
+            let provider =
+                providers
+                    .entry(relay.provider.as_str())
+                    .or_insert_with(|| ProviderInfo {
+                        name: relay.provider.clone(),
+                        relay_count: 0,
+                        selectable_relay_count: 0,
+                        owned: true,
+                    });
+            provider.relay_count += 1;
+            provider.owned &= relay.owned;
+            let is_selectable = match &matcher {
+                Some(matcher) => matcher.filter_matching_relay(relay).is_some(),
+                None => false,
+            };
+            if is_selectable {
+                provider.selectable_relay_count += 1;
+            }
+        }
+        providers.into_values().collect()
+    }
+
+    /// Returns the number of active relays in each country and city, in relay list order.
+    /// Only relays that match the current tunnel protocol and provider constraints are counted.
+    /// Relays that are excluded when selecting a whole country are not counted for the country.
+    pub fn get_relay_counts_by_location(&self) -> Vec<(LocationConstraint, usize)> {
+        let matcher = match &self.config.lock().relay_settings {
+            RelaySettings::Normal(constraints) => {
+                let mut matcher = RelayMatcher::from(constraints.clone());
+                matcher.location = Constraint::Any;
+                Some(matcher)
+            }
+            RelaySettings::CustomTunnelEndpoint(_) => None,
+        };
+
+        let parsed_relays = self.parsed_relays.lock();
+        let mut city_counts: HashMap<(&str, &str), (usize, usize)> = HashMap::new();
+        for relay in parsed_relays.relays().iter().filter(|relay| relay.active) {
+            let is_usable = match &matcher {
+                Some(matcher) => matcher.filter_matching_relay(relay).is_some(),
+                None => !relay.tunnels.is_empty(),
+            };
+            if let (true, Some(location)) = (is_usable, &relay.location) {
+                let (city_count, country_count) = city_counts
+                    .entry((location.country_code.as_str(), location.city_code.as_str()))
+                    .or_default();
+                *city_count += 1;
+                if relay.include_in_country {
+                    *country_count += 1;
+                }
+            }
+        }
+
+        let mut counts = Vec::new();
+        for country in &parsed_relays.locations().countries {
+            let country_index = counts.len();
+            counts.push((LocationConstraint::Country(country.code.clone()), 0));
+            for city in &country.cities {
+                let (city_count, country_count) = city_counts
+                    .get(&(country.code.as_str(), city.code.as_str()))
+                    .copied()
+                    .unwrap_or_default();
+                counts[country_index].1 += country_count;
+                counts.push((
+                    LocationConstraint::City(country.code.clone(), city.code.clone()),
+                    city_count,
+                ));
+            }
+        }
+        counts
+    }
+
+    /// Returns the number of active relays that support WireGuard and OpenVPN respectively. Only
+    /// relays that match the current location and provider constraints are counted. All relays
+    /// are counted when a custom tunnel endpoint is used.
+    pub fn get_relay_counts_by_protocol(&self) -> ProtocolCounts {
+        let (location, providers) = match &self.config.lock().relay_settings {
+            RelaySettings::Normal(constraints) => {
+                (constraints.location.clone(), constraints.providers.clone())
+            }
+            RelaySettings::CustomTunnelEndpoint(_) => (Constraint::Any, Constraint::Any),
+        };
+
+        let parsed_relays = self.parsed_relays.lock();
+        let mut counts = ProtocolCounts::default();
+        for relay in parsed_relays.relays() {
+            if !relay.active || !location.matches(relay) || !providers.matches(relay) {
+                continue;
+            }
+            if !relay.tunnels.wireguard.is_empty() {
+                counts.wireguard += 1;
+            }
+            if !relay.tunnels.openvpn.is_empty() {
+                counts.openvpn += 1;
+            }
+        }
+        counts
+    }
+
     /// Returns an OpenVpn endpoint, should only ever be used when the user has specified the tunnel
     /// protocol as only OpenVPN.
     fn get_openvpn_endpoint(
         &self,
         location: &Constraint<LocationConstraint>,
         providers: &Constraint<Providers>,
+        port_forwarding: bool,
         openvpn_constraints: OpenVpnConstraints,
         bridge_state: BridgeState,
         retry_attempt: u32,
@@ -380,6 +808,7 @@ impl RelaySelector {
         let mut relay_matcher = RelayMatcher {
             location: location.clone(),
             providers: providers.clone(),
+            port_forwarding,
             tunnel: openvpn_constraints,
         };
 
@@ -395,7 +824,7 @@ impl RelaySelector {
         let mut preferred_relay_matcher = relay_matcher.clone();
 
         let (preferred_port, preferred_protocol) =
-            Self::preferred_openvpn_constraints(retry_attempt);
+            self.preferred_openvpn_constraints(retry_attempt);
         let should_try_preferred = match &mut preferred_relay_matcher.tunnel.port {
             any @ Constraint::Any => {
                 *any = Constraint::Only(TransportPort {
@@ -432,10 +861,17 @@ impl RelaySelector {
             tunnel: WIREGUARD_EXIT_CONSTRAINTS.clone().into(),
             ..entry_matcher.clone()
         };
+        // Ports are forwarded on the exit relay
+        entry_matcher.port_forwarding = false;
+        exit_matcher.tunnel.same_country_as_peer = entry_matcher.tunnel.same_country_as_peer;
+        exit_matcher.tunnel.avoided_jurisdictions_with_peer =
+            entry_matcher.tunnel.avoided_jurisdictions_with_peer.clone();
 
         let (exit_relay, entry_relay, exit_endpoint, mut entry_endpoint) =
             if entry_matcher.location.is_subset(&exit_matcher.location) {
-                let (entry_relay, entry_endpoint) = self.get_entry_endpoint(&entry_matcher)?;
+                let entry_relays = self.matching_relays_with_peer(&entry_matcher, &exit_matcher);
+                let (entry_relay, entry_endpoint) =
+                    self.pick_entry_endpoint(&entry_matcher, &entry_relays)?;
                 exit_matcher.set_peer(entry_relay.clone());
                 let exit_result = self.get_tunnel_endpoint_internal(&exit_matcher)?;
                 (
@@ -445,7 +881,8 @@ impl RelaySelector {
                     entry_endpoint,
                 )
             } else {
-                let exit_result = self.get_tunnel_endpoint_internal(&exit_matcher)?;
+                let exit_relays = self.matching_relays_with_peer(&exit_matcher, &entry_matcher);
+                let exit_result = self.pick_tunnel_endpoint(&exit_matcher, &exit_relays)?;
 
                 entry_matcher.set_peer(exit_result.exit_relay.clone());
                 let (entry_relay, entry_endpoint) = self.get_entry_endpoint(&entry_matcher)?;
@@ -480,12 +917,14 @@ impl RelaySelector {
         &self,
         location: &Constraint<LocationConstraint>,
         providers: &Constraint<Providers>,
+        port_forwarding: bool,
         wireguard_constraints: &WireguardConstraints,
         retry_attempt: u32,
     ) -> Result<NormalSelectedRelay, Error> {
         let mut entry_relay_matcher = RelayMatcher {
             location: location.clone(),
             providers: providers.clone(),
+            port_forwarding,
             tunnel: wireguard_constraints.clone().into(),
         };
 
@@ -523,19 +962,32 @@ impl RelaySelector {
                 .wireguard_constraints
                 .entry_location
                 .clone(),
+            port_forwarding: false,
             ..matcher.clone()
         }
         .to_wireguard_matcher();
 
+        let pick_entry_first = relay_constraints
+            .wireguard_constraints
+            .entry_location
+            .is_subset(&matcher.location);
+
         // Pick the entry relay first if its location constraint is a subset of the exit location.
         if relay_constraints.wireguard_constraints.use_multihop {
             matcher.tunnel.wireguard = WIREGUARD_EXIT_CONSTRAINTS.clone().into();
-            if relay_constraints
+            matcher.tunnel.wireguard.same_country_as_peer = relay_constraints
                 .wireguard_constraints
-                .entry_location
-                .is_subset(&matcher.location)
-            {
-                if let Ok((entry_relay, entry_endpoint)) = self.get_entry_endpoint(&entry_matcher) {
+                .multihop_same_country;
+            matcher.tunnel.wireguard.avoided_jurisdictions_with_peer = relay_constraints
+                .wireguard_constraints
+                .multihop_avoided_jurisdictions
+                .clone();
+            if pick_entry_first {
+                let exit_matcher = matcher.clone().to_wireguard_matcher();
+                let entry_relays = self.matching_relays_with_peer(&entry_matcher, &exit_matcher);
+                if let Ok((entry_relay, entry_endpoint)) =
+                    self.pick_entry_endpoint(&entry_matcher, &entry_relays)
+                {
                     matcher.tunnel.wireguard.peer = Some(entry_relay.clone());
                     selected_entry_relay = Some(entry_relay);
                     selected_entry_endpoint = Some(entry_endpoint);
@@ -543,18 +995,20 @@ impl RelaySelector {
             }
         }
 
-        let mut selected_relay = self.get_tunnel_endpoint_internal(&matcher)?;
+        let exit_relays =
+            if relay_constraints.wireguard_constraints.use_multihop && !pick_entry_first {
+                self.matching_relays_with_peer(&matcher, &entry_matcher)
+            } else {
+                self.matching_relays(&matcher)
+            };
+        let mut selected_relay = self.pick_tunnel_endpoint(&matcher, &exit_relays)?;
 
         // Pick the entry relay last if its location constraint is NOT a subset of the exit
         // location.
         if matches!(selected_relay.endpoint, MullvadEndpoint::Wireguard(..))
             && relay_constraints.wireguard_constraints.use_multihop
         {
-            if !relay_constraints
-                .wireguard_constraints
-                .entry_location
-                .is_subset(&matcher.location)
-            {
+            if !pick_entry_first {
                 entry_matcher.tunnel.peer = Some(selected_relay.exit_relay.clone());
                 if let Ok((entry_relay, entry_endpoint)) = self.get_entry_endpoint(&entry_matcher) {
                     selected_entry_relay = Some(entry_relay);
@@ -673,7 +1127,7 @@ impl RelaySelector {
                     });
                 } else if openvpn_constraints.port.is_any() {
                     let (preferred_port, preferred_protocol) =
-                        Self::preferred_openvpn_constraints(retry_attempt);
+                        self.preferred_openvpn_constraints(retry_attempt);
                     openvpn_constraints.port = Constraint::Only(TransportPort {
                         protocol: preferred_protocol,
                         port: preferred_port,
@@ -697,17 +1151,18 @@ impl RelaySelector {
         &self,
         matcher: &RelayMatcher<WireguardMatcher>,
     ) -> Result<(Relay, MullvadWireguardEndpoint), Error> {
-        let matching_relays: Vec<Relay> = self
-            .parsed_relays
-            .lock()
-            .relays()
-            .iter()
-            .filter(|relay| relay.active)
-            .filter_map(|relay| matcher.filter_matching_relay(relay))
-            .collect();
+        self.pick_entry_endpoint(matcher, &self.matching_relays(matcher))
+    }
 
+    /// Picks a random entry relay and endpoint out of `matching_relays`, which must have been
+    /// filtered using `matcher`.
+    fn pick_entry_endpoint(
+        &self,
+        matcher: &RelayMatcher<WireguardMatcher>,
+        matching_relays: &[Relay],
+    ) -> Result<(Relay, MullvadWireguardEndpoint), Error> {
         let relay = self
-            .pick_random_relay(&matching_relays)
+            .pick_random_relay(matching_relays)
             .map(|relay| relay.clone())
             .ok_or(Error::NoRelay)?;
         let endpoint = matcher
@@ -729,7 +1184,8 @@ impl RelaySelector {
 
     fn get_bridge_for(
         &self,
-        config: &MutexGuard<'_, SelectorConfig>,
+        config: &SelectorConfig,
+        bridge_state: BridgeState,
         location: &mullvad_types::location::Location,
         retry_attempt: u32,
     ) -> Result<Option<SelectedBridge>, Error> {
@@ -741,10 +1197,14 @@ impl RelaySelector {
                     // FIXME: This is temporary while talpid-core only supports TCP proxies
                     transport_protocol: Constraint::Only(TransportProtocol::Tcp),
                 };
-                match config.bridge_state {
+                match bridge_state {
                     BridgeState::On => {
                         let (settings, relay) = self
-                            .get_proxy_settings(&bridge_constraints, Some(location))
+                            .get_proxy_settings(
+                                &bridge_constraints,
+                                Some(location),
+                                &self.excluded_bridges.lock(),
+                            )
                             .ok_or(Error::NoBridge)?;
                         Ok(Some(SelectedBridge::Normal(NormalSelectedBridge {
                             settings,
@@ -752,19 +1212,23 @@ impl RelaySelector {
                         })))
                     }
                     BridgeState::Auto if Self::should_use_bridge(retry_attempt) => Ok(self
-                        .get_proxy_settings(&bridge_constraints, Some(location))
+                        .get_proxy_settings(
+                            &bridge_constraints,
+                            Some(location),
+                            &self.excluded_bridges.lock(),
+                        )
                         .map(|(settings, relay)| {
                             SelectedBridge::Normal(NormalSelectedBridge { settings, relay })
                         })),
-                    BridgeState::Auto | BridgeState::Off => Ok(None),
+                    BridgeState::Auto | BridgeState::Off | BridgeState::OnDemand { .. } => Ok(None),
                 }
             }
-            BridgeSettings::Custom(bridge_settings) => match config.bridge_state {
+            BridgeSettings::Custom(bridge_settings) => match bridge_state {
                 BridgeState::On => Ok(Some(SelectedBridge::Custom(bridge_settings.clone()))),
                 BridgeState::Auto if Self::should_use_bridge(retry_attempt) => {
                     Ok(Some(SelectedBridge::Custom(bridge_settings.clone())))
                 }
-                BridgeState::Auto | BridgeState::Off => Ok(None),
+                BridgeState::Auto | BridgeState::Off | BridgeState::OnDemand { .. } => Ok(None),
             },
         }
     }
@@ -791,10 +1255,94 @@ impl RelaySelector {
             },
         };
 
-        self.get_proxy_settings(&constraints, near_location)
+        self.get_proxy_settings(&constraints, near_location, &HashSet::new())
             .map(|(settings, _relay)| settings)
     }
 
+    /// Returns a bridge on the relay with the given hostname, or `None` if there is no such active
+    /// bridge relay.
+    pub fn get_bridge_by_hostname(&self, hostname: &str) -> Option<ProxySettings> {
+        let constraints = InternalBridgeConstraints {
+            location: Constraint::Any,
+            providers: Constraint::Any,
+            transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+        };
+        let relay = self
+            .parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .filter(|relay| relay.active && relay.hostname == hostname)
+            .find_map(|relay| Self::matching_bridge_relay(relay, &constraints))?;
+        self.pick_random_bridge(&relay)
+    }
+
+    /// Returns the hostnames of the active bridge relays that match the bridge constraints. This
+    /// is empty if a custom bridge is used.
+    fn get_bridge_hostnames(&self) -> Vec<String> {
+        let constraints = match &self.config.lock().bridge_settings {
+            BridgeSettings::Normal(settings) => InternalBridgeConstraints {
+                location: settings.location.clone(),
+                providers: settings.providers.clone(),
+                transport_protocol: Constraint::Only(TransportProtocol::Tcp),
+            },
+            BridgeSettings::Custom(_) => return vec![],
+        };
+        self.parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .filter(|relay| relay.active)
+            .filter_map(|relay| Self::matching_bridge_relay(relay, &constraints))
+            .map(|relay| relay.hostname)
+            .collect()
+    }
+
+    /// Stops using the bridge relay with the given hostname for tunnel connections. Once every
+    /// bridge has been excluded, all but `hostname` may be used again. Returns `false` if there is
+    /// no other bridge to use.
+    pub fn exclude_bridge(&self, hostname: &str) -> bool {
+        let bridges = self.get_bridge_hostnames();
+        if !bridges.iter().any(|bridge| bridge != hostname) {
+            return false;
+        }
+        let mut excluded_bridges = self.excluded_bridges.lock();
+        excluded_bridges.insert(hostname.to_owned());
+        if bridges
+            .iter()
+            .all(|bridge| excluded_bridges.contains(bridge))
+        {
+            // Every bridge has been skipped, so start over from this one
+            excluded_bridges.clear();
+            excluded_bridges.insert(hostname.to_owned());
+        }
+        true
+    }
+
+    /// Allows all bridges excluded with [Self::exclude_bridge] to be used again.
+    pub fn clear_excluded_bridges(&self) {
+        self.excluded_bridges.lock().clear();
+    }
+
+    /// Returns whether the relay list contains a bridge relay with the given hostname.
+    pub fn is_bridge(&self, hostname: &str) -> bool {
+        self.parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .any(|relay| relay.hostname == hostname && !relay.bridges.shadowsocks.is_empty())
+    }
+
+    /// Resolves [BridgeState::OnDemand] to [BridgeState::On] once `threshold` attempts have
+    /// failed, and to [BridgeState::Off] before that. Other states are returned unchanged.
+    fn effective_bridge_state(bridge_state: BridgeState, retry_attempt: u32) -> BridgeState {
+        match bridge_state {
+            BridgeState::OnDemand { threshold } if retry_attempt >= threshold => BridgeState::On,
+            BridgeState::OnDemand { .. } => BridgeState::Off,
+            bridge_state => bridge_state,
+        }
+    }
+
     fn should_use_bridge(retry_attempt: u32) -> bool {
         // shouldn't use a bridge for the first 3 times
         retry_attempt > 3 &&
@@ -809,13 +1357,14 @@ impl RelaySelector {
         &self,
         constraints: &InternalBridgeConstraints,
         location: Option<T>,
+        excluded_hostnames: &HashSet<String>,
     ) -> Option<(ProxySettings, Relay)> {
         let mut matching_relays: Vec<Relay> = self
             .parsed_relays
             .lock()
             .relays()
             .iter()
-            .filter(|relay| relay.active)
+            .filter(|relay| relay.active && !excluded_hostnames.contains(&relay.hostname))
             .filter_map(|relay| Self::matching_bridge_relay(relay, constraints))
             .collect();
 
@@ -854,7 +1403,7 @@ impl RelaySelector {
 
     fn get_obfuscator_inner(
         &self,
-        config: &MutexGuard<'_, SelectorConfig>,
+        config: &SelectorConfig,
         relay: &Relay,
         endpoint: &MullvadWireguardEndpoint,
         retry_attempt: u32,
@@ -950,6 +1499,15 @@ impl RelaySelector {
         location_constraint: &Constraint<LocationConstraint>,
         providers_constraint: &Constraint<Providers>,
     ) -> (Constraint<u16>, TransportProtocol, TunnelType) {
+        // Only WireGuard tunnels can be obfuscated
+        if *self.selected_obfuscation.lock() == SelectedObfuscation::Udp2Tcp {
+            return (
+                Self::preferred_wireguard_port(retry_attempt),
+                TransportProtocol::Udp,
+                TunnelType::Wireguard,
+            );
+        }
+
         #[cfg(target_os = "windows")]
         {
             let location_supports_openvpn =
@@ -961,7 +1519,7 @@ impl RelaySelector {
                 });
             if location_supports_openvpn {
                 let (preferred_port, preferred_protocol) =
-                    Self::preferred_openvpn_constraints(retry_attempt);
+                    self.preferred_openvpn_constraints(retry_attempt);
                 return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
             }
         }
@@ -976,7 +1534,7 @@ impl RelaySelector {
         // constraints
         if !location_supports_wireguard {
             let (preferred_port, preferred_protocol) =
-                Self::preferred_openvpn_constraints(retry_attempt);
+                self.preferred_openvpn_constraints(retry_attempt);
             return (preferred_port, preferred_protocol, TunnelType::OpenVpn);
         }
 
@@ -996,7 +1554,7 @@ impl RelaySelector {
             ),
             _ => {
                 let (preferred_port, preferred_protocol) =
-                    Self::preferred_openvpn_constraints(retry_attempt - 2);
+                    self.preferred_openvpn_constraints(retry_attempt - 2);
                 (preferred_port, preferred_protocol, TunnelType::OpenVpn)
             }
         }
@@ -1012,7 +1570,16 @@ impl RelaySelector {
         }
     }
 
-    fn preferred_openvpn_constraints(retry_attempt: u32) -> (Constraint<u16>, TransportProtocol) {
+    fn preferred_openvpn_constraints(
+        &self,
+        retry_attempt: u32,
+    ) -> (Constraint<u16>, TransportProtocol) {
+        let preference = self.openvpn_endpoint_preference.lock();
+        if !preference.is_empty() {
+            let transport = preference[retry_attempt as usize % preference.len()];
+            return (transport.port, transport.protocol);
+        }
+
         // Prefer UDP by default. But if that has failed a couple of times, then try TCP port
         // 443, which works for many with UDP problems. After that, just alternate
         // between protocols.
@@ -1035,16 +1602,17 @@ impl RelaySelector {
         &self,
         matcher: &RelayMatcher<T>,
     ) -> Result<NormalSelectedRelay, Error> {
-        let matching_relays: Vec<Relay> = self
-            .parsed_relays
-            .lock()
-            .relays()
-            .iter()
-            .filter(|relay| relay.active)
-            .filter_map(|relay| matcher.filter_matching_relay(relay))
-            .collect();
+        self.pick_tunnel_endpoint(matcher, &self.matching_relays(matcher))
+    }
 
-        self.pick_random_relay(&matching_relays)
+    /// Picks a random relay endpoint out of `matching_relays`, which must have been filtered
+    /// using `matcher`.
+    fn pick_tunnel_endpoint<T: TunnelMatcher>(
+        &self,
+        matcher: &RelayMatcher<T>,
+        matching_relays: &[Relay],
+    ) -> Result<NormalSelectedRelay, Error> {
+        self.pick_random_relay(matching_relays)
             .and_then(|selected_relay| {
                 let endpoint = matcher.mullvad_endpoint(&selected_relay);
                 let addr_in = endpoint
@@ -1057,6 +1625,44 @@ impl RelaySelector {
             .ok_or(Error::NoRelay)
     }
 
+    /// Returns the active relays matching `matcher`, with only the matching endpoints included.
+    fn matching_relays<T: TunnelMatcher>(&self, matcher: &RelayMatcher<T>) -> Vec<Relay> {
+        self.parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .filter(|relay| relay.active)
+            .filter_map(|relay| matcher.filter_matching_relay(relay))
+            .collect()
+    }
+
+    /// Returns the relays matching `matcher` that can be paired with at least one relay matching
+    /// `peer_matcher` as the other multihop relay. The WireGuard endpoints of relays without such
+    /// a peer are removed, as are relays left without endpoints. Picking the first relay out of
+    /// these ensures that the other one can be selected afterwards.
+    fn matching_relays_with_peer<T: TunnelMatcher>(
+        &self,
+        matcher: &RelayMatcher<T>,
+        peer_matcher: &RelayMatcher<WireguardMatcher>,
+    ) -> Vec<Relay> {
+        let peers = self.matching_relays(peer_matcher);
+        self.matching_relays(matcher)
+            .into_iter()
+            .filter_map(|mut relay| {
+                let has_peer = peers
+                    .iter()
+                    .any(|peer| peer_matcher.tunnel.is_valid_peer_pair(peer, &relay));
+                if !has_peer {
+                    relay.tunnels.wireguard.clear();
+                }
+                if relay.tunnels.wireguard.is_empty() && relay.tunnels.openvpn.is_empty() {
+                    return None;
+                }
+                Some(relay)
+            })
+            .collect()
+    }
+
     fn matching_bridge_relay(
         relay: &Relay,
         constraints: &InternalBridgeConstraints,
@@ -1083,7 +1689,9 @@ impl RelaySelector {
     /// Picks a relay using [Self::pick_random_relay_fn], using the `weight` member of each relay
     /// as the weight function.
     fn pick_random_relay<'a>(&self, relays: &'a [Relay]) -> Option<&'a Relay> {
-        self.pick_random_relay_fn(relays, |_index, relay| relay.weight)
+        let relay_stats = self.relay_stats.lock();
+        let now = SystemTime::now();
+        self.pick_random_relay_fn(relays, |_index, relay| relay_stats.weight(relay, now))
     }
 
     /// Pick a random relay from the given slice. Will return `None` if the given slice is empty.
@@ -1169,32 +1777,32 @@ impl RelaySelector {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SelectedBridge {
     Normal(NormalSelectedBridge),
     Custom(ProxySettings),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NormalSelectedBridge {
     pub settings: ProxySettings,
     pub relay: Relay,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum SelectedRelay {
     Normal(NormalSelectedRelay),
     Custom(CustomTunnelEndpoint),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NormalSelectedRelay {
     pub exit_relay: Relay,
     pub endpoint: MullvadEndpoint,
     pub entry_relay: Option<Relay>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SelectedObfuscator {
     pub config: ObfuscatorConfig,
     pub relay: Relay,
@@ -1226,13 +1834,13 @@ impl NormalSelectedRelay {
 mod test {
     use super::*;
     use mullvad_types::{
-        relay_constraints::{BridgeConstraints, RelayConstraints},
+        relay_constraints::{BridgeConstraints, JurisdictionGroup, RelayConstraints},
         relay_list::{
             OpenVpnEndpointData, Relay, RelayBridges, RelayListCity, RelayListCountry,
-            RelayObfuscators, RelayTunnels, WireguardEndpointData,
+            RelayObfuscators, RelayTunnels, ShadowsocksEndpointData, WireguardEndpointData,
         },
     };
-    use talpid_types::net::wireguard::PublicKey;
+    use talpid_types::net::{openvpn::LocalProxySettings, wireguard::PublicKey};
 
     lazy_static::lazy_static! {
         static ref RELAYS: RelayList = RelayList {
@@ -1275,6 +1883,7 @@ mod test {
                                         udp2tcp: vec![],
                                     },
                                     location: None,
+                                    port_forwarding: false,
                                 },
                                 Relay {
                                     hostname: "se10-wireguard".to_string(),
@@ -1303,6 +1912,7 @@ mod test {
                                         udp2tcp: vec![],
                                     },
                                     location: None,
+                                    port_forwarding: false,
                                 },
                                 Relay {
                                     hostname: "se-got-001".to_string(),
@@ -1337,6 +1947,7 @@ mod test {
                                         udp2tcp: vec![],
                                     },
                                     location: None,
+                                    port_forwarding: false,
                                 },
                                 Relay {
                                     hostname: "se11-wireguard-filtered".to_string(),
@@ -1365,6 +1976,7 @@ mod test {
                                         udp2tcp: vec![],
                                     },
                                     location: None,
+                                    port_forwarding: false,
                                 },
                                 Relay {
                                     hostname: "se-got-010-filtered".to_string(),
@@ -1389,6 +2001,7 @@ mod test {
                                         udp2tcp: vec![],
                                     },
                                     location: None,
+                                    port_forwarding: false,
                                 }
                             ],
                         },
@@ -1415,7 +2028,16 @@ mod test {
                     ..Default::default()
                 },
                 bridge_state: BridgeState::Auto,
+                deprioritize_failing_relays: false,
+                retry_same_relay: false,
+                openvpn_endpoint_preference: vec![],
             })),
+            relay_stats: Arc::new(Mutex::new(RelayStats::in_memory(false))),
+            last_selection: Arc::new(Mutex::new(None)),
+            openvpn_endpoint_preference: Arc::new(Mutex::new(vec![])),
+            selected_obfuscation: Arc::new(Mutex::new(SelectedObfuscation::Off)),
+            excluded_bridges: Arc::new(Mutex::new(HashSet::new())),
+            overridden_relays: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -1499,6 +2121,27 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_preferred_tunnel_protocol_with_obfuscation() {
+        let mut relay_selector = new_relay_selector();
+        let mut config = relay_selector.get_config();
+        config.obfuscation_settings.selected_obfuscation = SelectedObfuscation::Udp2Tcp;
+        relay_selector.set_config(config);
+
+        let relay_constraints = RelayConstraints {
+            tunnel_protocol: Constraint::Any,
+            ..RelayConstraints::default()
+        };
+        for attempt in 0..10 {
+            let preferred =
+                relay_selector.preferred_constraints(&relay_constraints, BridgeState::Off, attempt);
+            assert_eq!(
+                preferred.tunnel_protocol,
+                Constraint::Only(TunnelType::Wireguard)
+            );
+        }
+    }
+
     #[test]
     fn test_wg_entry_hostname_collision() {
         let relay_selector = new_relay_selector();
@@ -1679,6 +2322,438 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_wg_multihop_same_country() {
+        let mut relay_list = RELAYS.clone();
+        let mut norwegian_relay = relay_list.countries[0].cities[0].relays[0].clone();
+        norwegian_relay.hostname = "no-osl-wg-001".to_string();
+        relay_list.countries.push(RelayListCountry {
+            name: "Norway".to_string(),
+            code: "no".to_string(),
+            cities: vec![RelayListCity {
+                name: "Oslo".to_string(),
+                code: "osl".to_string(),
+                latitude: 59.91273,
+                longitude: 10.74609,
+                relays: vec![norwegian_relay],
+            }],
+        });
+        let relay_selector = new_relay_selector();
+        *relay_selector.parsed_relays.lock() =
+            ParsedRelays::from_relay_list(relay_list, SystemTime::now());
+
+        let mut relay_constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+        relay_constraints.wireguard_constraints.use_multihop = true;
+        relay_constraints
+            .wireguard_constraints
+            .multihop_same_country = true;
+
+        for _ in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0)
+                .expect("Failed to select relays in the same country");
+            let entry_relay = result.entry_relay.expect("Expected an entry relay");
+            assert_ne!(entry_relay.hostname, result.exit_relay.hostname);
+            assert_eq!(
+                entry_relay.location.unwrap().country_code,
+                result.exit_relay.location.unwrap().country_code
+            );
+        }
+
+        // The Norwegian relay has no partner in its country, so it must never be picked first
+        relay_constraints.location = Constraint::Any;
+        for _ in 0..50 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0)
+                .expect("Failed to select relays in the same country");
+            assert_ne!(result.exit_relay.hostname, "no-osl-wg-001");
+            assert_ne!(result.entry_relay.unwrap().hostname, "no-osl-wg-001");
+        }
+
+        // There is only a single relay in Norway
+        relay_constraints.location =
+            Constraint::Only(LocationConstraint::Country("no".to_string()));
+        assert!(relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0)
+            .is_err());
+
+        relay_constraints
+            .wireguard_constraints
+            .multihop_same_country = false;
+        assert!(relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_wg_multihop_avoided_jurisdictions() {
+        let mut relay_list = RELAYS.clone();
+        for (country_name, country_code, city_name, city_code) in [
+            ("Norway", "no", "Oslo", "osl"),
+            ("Switzerland", "ch", "Zurich", "zrh"),
+        ] {
+            let mut relay = relay_list.countries[0].cities[0].relays[0].clone();
+            relay.hostname = format!("{}-{}-wg-001", country_code, city_code);
+            relay_list.countries.push(RelayListCountry {
+                name: country_name.to_string(),
+                code: country_code.to_string(),
+                cities: vec![RelayListCity {
+                    name: city_name.to_string(),
+                    code: city_code.to_string(),
+                    latitude: 0.0,
+                    longitude: 0.0,
+                    relays: vec![relay],
+                }],
+            });
+        }
+        let relay_selector = new_relay_selector();
+        *relay_selector.parsed_relays.lock() =
+            ParsedRelays::from_relay_list(relay_list, SystemTime::now());
+
+        let mut relay_constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        };
+        relay_constraints.wireguard_constraints.use_multihop = true;
+        relay_constraints
+            .wireguard_constraints
+            .multihop_avoided_jurisdictions = vec![JurisdictionGroup::FourteenEyes];
+
+        // Sweden and Norway are both in the Fourteen Eyes group
+        for _ in 0..10 {
+            let result = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0)
+                .expect("Failed to select relays");
+            let entry_relay = result.entry_relay.expect("Expected an entry relay");
+            assert_eq!(entry_relay.hostname, "ch-zrh-wg-001");
+        }
+
+        // Fall back to relays in the same group if no other pair matches
+        relay_constraints.wireguard_constraints.entry_location =
+            Constraint::Only(LocationConstraint::Country("no".to_string()));
+        let result = relay_selector
+            .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0)
+            .expect("Failed to fall back to relays in the same jurisdiction group");
+        let entry_relay = result.entry_relay.expect("Expected an entry relay");
+        assert_eq!(entry_relay.hostname, "no-osl-wg-001");
+    }
+
+    #[test]
+    fn test_can_use_multihop() {
+        let relay_selector = new_relay_selector();
+        assert!(relay_selector.can_use_multihop());
+
+        // The only relay cannot be used as both the entry and the exit relay
+        let location = LocationConstraint::Hostname(
+            "se".to_string(),
+            "got".to_string(),
+            "se9-wireguard".to_string(),
+        );
+        let mut constraints = RelayConstraints {
+            location: Constraint::Only(location.clone()),
+            ..RelayConstraints::default()
+        };
+        constraints.wireguard_constraints.entry_location = Constraint::Only(location);
+        relay_selector.config.lock().relay_settings = RelaySettings::Normal(constraints);
+        assert!(!relay_selector.can_use_multihop());
+
+        relay_selector.config.lock().relay_settings = RelaySettings::Normal(RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+            ..RelayConstraints::default()
+        });
+        assert!(!relay_selector.can_use_multihop());
+    }
+
+    #[test]
+    fn test_resolve_tunnel_type() {
+        let relay_selector = new_relay_selector();
+        relay_selector.config.lock().relay_settings = RelaySettings::Normal(RelayConstraints {
+            tunnel_protocol: Constraint::Any,
+            ..RelayConstraints::default()
+        });
+        #[cfg(not(target_os = "windows"))]
+        assert_eq!(relay_selector.resolve_tunnel_type(), TunnelType::Wireguard);
+        #[cfg(target_os = "windows")]
+        assert_eq!(relay_selector.resolve_tunnel_type(), TunnelType::OpenVpn);
+
+        relay_selector.config.lock().relay_settings = RelaySettings::Normal(RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+            ..RelayConstraints::default()
+        });
+        assert_eq!(relay_selector.resolve_tunnel_type(), TunnelType::OpenVpn);
+    }
+
+    #[test]
+    fn test_get_relay_with_tunnel_type() {
+        let relay_selector = new_relay_selector();
+        relay_selector.config.lock().relay_settings = RelaySettings::Normal(RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
+            ..RelayConstraints::default()
+        });
+
+        for attempt in 0..10 {
+            match relay_selector.get_relay_with_tunnel_type(attempt, TunnelType::OpenVpn) {
+                Ok((SelectedRelay::Normal(relay), _, _)) => {
+                    assert!(matches!(relay.endpoint, MullvadEndpoint::OpenVpn(_)))
+                }
+                result => panic!("Expected an OpenVPN relay, got {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_excluded_bridges() {
+        let mut relays = RELAYS.clone();
+        let city = &mut relays.countries[0].cities[0];
+        for hostname in &["se-got-br-001", "se-got-br-002"] {
+            let mut bridge = city.relays[2].clone();
+            bridge.hostname = hostname.to_string();
+            bridge.tunnels = RelayTunnels {
+                openvpn: vec![],
+                wireguard: vec![],
+            };
+            bridge.bridges.shadowsocks = vec![ShadowsocksEndpointData {
+                port: 443,
+                cipher: "aes-256-gcm".to_string(),
+                password: "mullvad".to_string(),
+                protocol: TransportProtocol::Tcp,
+            }];
+            city.relays.push(bridge);
+        }
+
+        let relay_selector = new_relay_selector();
+        *relay_selector.parsed_relays.lock() =
+            ParsedRelays::from_relay_list(relays, SystemTime::now());
+        {
+            let mut config = relay_selector.config.lock();
+            config.relay_settings = RelaySettings::Normal(RelayConstraints {
+                tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+                ..RelayConstraints::default()
+            });
+            config.bridge_state = BridgeState::On;
+        }
+        let selected_bridge = || match relay_selector.get_relay(0) {
+            Ok((_, Some(SelectedBridge::Normal(bridge)), _)) => bridge.relay.hostname,
+            result => panic!("Expected a bridge, got {:?}", result),
+        };
+
+        assert!(relay_selector.exclude_bridge("se-got-br-001"));
+        for _ in 0..10 {
+            assert_eq!(selected_bridge(), "se-got-br-002");
+        }
+
+        // Excluding every bridge starts over from the last one
+        assert!(relay_selector.exclude_bridge("se-got-br-002"));
+        for _ in 0..10 {
+            assert_eq!(selected_bridge(), "se-got-br-001");
+        }
+
+        // A reconnect makes every bridge available again
+        relay_selector.clear_excluded_bridges();
+        assert!((0..100).any(|_| selected_bridge() == "se-got-br-002"));
+    }
+
+    #[test]
+    fn test_get_relay_with_config() {
+        let relay_selector = new_relay_selector();
+        let mut config = relay_selector.get_config();
+        config.relay_settings = RelaySettings::Normal(RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Hostname(
+                "se".to_string(),
+                "got".to_string(),
+                "se10-wireguard".to_string(),
+            )),
+            ..RelayConstraints::default()
+        });
+
+        for attempt in 0..10 {
+            match relay_selector.get_relay_with_config(&config, attempt) {
+                Ok((SelectedRelay::Normal(relay), _, _)) => {
+                    assert_eq!(relay.exit_relay.hostname, "se10-wireguard")
+                }
+                result => panic!("Expected a normal relay, got {:?}", result),
+            }
+        }
+        assert!(relay_selector.last_selection.lock().is_none());
+    }
+
+    #[test]
+    fn test_bridge_on_demand() {
+        const THRESHOLD: u32 = 2;
+
+        let relay_selector = new_relay_selector();
+        let mut config = relay_selector.get_config();
+        config.relay_settings = RelaySettings::Normal(RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_owned())),
+            tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+            ..RelayConstraints::default()
+        });
+        config.bridge_settings = BridgeSettings::Custom(ProxySettings::Local(LocalProxySettings {
+            port: 1080,
+            peer: "1.2.3.4:443".parse().unwrap(),
+        }));
+        config.bridge_state = BridgeState::OnDemand {
+            threshold: THRESHOLD,
+        };
+
+        for attempt in 0..THRESHOLD {
+            let (_, bridge, _) = relay_selector
+                .get_relay_with_config(&config, attempt)
+                .expect("Failed to select relay");
+            assert!(
+                bridge.is_none(),
+                "Expected a direct connection on attempt {}",
+                attempt
+            );
+        }
+        for attempt in THRESHOLD..THRESHOLD + 4 {
+            let (relay, bridge, _) = relay_selector
+                .get_relay_with_config(&config, attempt)
+                .expect("Failed to select relay");
+            assert!(
+                matches!(bridge, Some(SelectedBridge::Custom(_))),
+                "Expected a bridge on attempt {}",
+                attempt
+            );
+            match relay {
+                SelectedRelay::Normal(relay) => assert_eq!(
+                    relay.endpoint.to_endpoint().protocol,
+                    TransportProtocol::Tcp
+                ),
+                SelectedRelay::Custom(_) => panic!("Expected a normal relay"),
+            }
+        }
+
+        let (_, bridge, _) = relay_selector
+            .get_relay_with_config(&config, 0)
+            .expect("Failed to select relay");
+        assert!(bridge.is_none());
+    }
+
+    #[test]
+    fn test_retry_same_relay() {
+        const ATTEMPTS: usize = 20;
+
+        let relay_selector = new_relay_selector();
+        relay_selector.config.lock().retry_same_relay = true;
+
+        let exit_hostname = |retry_attempt| match relay_selector.get_relay(retry_attempt).unwrap() {
+            (SelectedRelay::Normal(relay), _, _) => relay.exit_relay.hostname,
+            (SelectedRelay::Custom(_), _, _) => unreachable!("Expected a normal relay"),
+        };
+
+        for _ in 0..ATTEMPTS {
+            let first = exit_hostname(0);
+            assert_eq!(exit_hostname(1), first);
+        }
+    }
+
+    #[test]
+    fn test_openvpn_endpoint_preference() {
+        let mut relay_selector = new_relay_selector();
+        let mut config = relay_selector.get_config();
+        config.openvpn_endpoint_preference = vec![
+            TransportPort {
+                protocol: TransportProtocol::Udp,
+                port: Constraint::Only(1194),
+            },
+            TransportPort {
+                protocol: TransportProtocol::Tcp,
+                port: Constraint::Only(443),
+            },
+        ];
+        relay_selector.set_config(config);
+
+        let relay_constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+            ..RelayConstraints::default()
+        };
+        let expected = [
+            (TransportProtocol::Udp, 1194),
+            (TransportProtocol::Tcp, 443),
+            (TransportProtocol::Udp, 1194),
+            (TransportProtocol::Tcp, 443),
+        ];
+        for (retry_attempt, (protocol, port)) in expected.into_iter().enumerate() {
+            let result = relay_selector
+                .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, retry_attempt as u32)
+                .expect("Failed to get OpenVPN relay");
+            match result.endpoint {
+                MullvadEndpoint::OpenVpn(endpoint) => {
+                    assert_eq!(endpoint.protocol, protocol);
+                    assert_eq!(endpoint.address.port(), port);
+                }
+                _ => panic!("Expected an OpenVPN endpoint"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_deprioritize_failing_relays() {
+        const ATTEMPTS: usize = 1000;
+
+        let relay_selector = new_relay_selector();
+        relay_selector.relay_stats.lock().set_enabled(true);
+
+        let relay_constraints = RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("se".to_string())),
+            ..WIREGUARD_SINGLEHOP_CONSTRAINTS
+        };
+        let count_selections = |hostname: &str| {
+            (0..ATTEMPTS)
+                .filter(|_| {
+                    relay_selector
+                        .get_tunnel_endpoint(&relay_constraints, BridgeState::Off, 0)
+                        .unwrap()
+                        .exit_relay
+                        .hostname
+                        == hostname
+                })
+                .count()
+        };
+
+        let now = SystemTime::now();
+        let long_ago = now - time::Duration::from_secs(24 * 60 * 60);
+        {
+            let mut relay_stats = relay_selector.relay_stats.lock();
+            for _ in 0..5 {
+                // se9 keeps failing
+                relay_stats.record("se9-wireguard", false, now);
+                // se10 failed a long time ago, but has since recovered
+                relay_stats.record("se10-wireguard", false, long_ago);
+            }
+        }
+
+        let failing_count = count_selections("se9-wireguard");
+        assert!(
+            failing_count < ATTEMPTS / 3,
+            "A failing relay was selected {} out of {} times",
+            failing_count,
+            ATTEMPTS
+        );
+
+        let relay_stats = relay_selector.relay_stats.lock();
+        let recovered_relay = relay_selector
+            .parsed_relays
+            .lock()
+            .relays()
+            .iter()
+            .find(|relay| relay.hostname == "se10-wireguard")
+            .unwrap()
+            .clone();
+        assert_eq!(
+            relay_stats.weight(&recovered_relay, now),
+            relay_stats.weight(&RELAYS.countries[0].cities[0].relays[2], now),
+            "A recovered relay should have the same weight as a relay that never failed"
+        );
+    }
+
     #[test]
     fn test_selecting_any_relay_will_consider_multihop() {
         let relay_constraints = RelayConstraints {
@@ -1718,11 +2793,14 @@ mod test {
             port: Constraint::Any,
             ip_version: Constraint::Any,
             entry_location: Constraint::Any,
+            multihop_same_country: false,
+            multihop_avoided_jurisdictions: Vec::new(),
         },
         tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
         openvpn_constraints: OpenVpnConstraints {
             port: Constraint::Any,
         },
+        port_forwarding: false,
     };
 
     const WIREGUARD_SINGLEHOP_CONSTRAINTS: RelayConstraints = RelayConstraints {
@@ -1733,11 +2811,14 @@ mod test {
             port: Constraint::Any,
             ip_version: Constraint::Any,
             entry_location: Constraint::Any,
+            multihop_same_country: false,
+            multihop_avoided_jurisdictions: Vec::new(),
         },
         tunnel_protocol: Constraint::Only(TunnelType::Wireguard),
         openvpn_constraints: OpenVpnConstraints {
             port: Constraint::Any,
         },
+        port_forwarding: false,
     };
 
     #[test]
@@ -1751,6 +2832,157 @@ mod test {
         // TODO: Verify that neither endpoint is using obfuscation for retry attempt 0
     }
 
+    #[test]
+    fn test_selecting_port_forwarding_relay() {
+        let relay_selector = new_relay_selector();
+        let mut config = relay_selector.get_config();
+        config.relay_settings = RelaySettings::Normal(RelayConstraints {
+            port_forwarding: true,
+            ..RelayConstraints::default()
+        });
+
+        let result = relay_selector.get_relay_with_config(&config, 0);
+        assert!(matches!(result, Err(Error::NoPortForwardingRelay)));
+
+        // No relay matches even without port forwarding
+        config.relay_settings = RelaySettings::Normal(RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Country("xx".to_string())),
+            port_forwarding: true,
+            ..RelayConstraints::default()
+        });
+        let result = relay_selector.get_relay_with_config(&config, 0);
+        assert!(matches!(result, Err(Error::NoRelay)));
+        config.relay_settings = RelaySettings::Normal(RelayConstraints {
+            port_forwarding: true,
+            ..RelayConstraints::default()
+        });
+
+        let mut relay_list = RELAYS.clone();
+        for relay in &mut relay_list.countries[0].cities[0].relays {
+            relay.port_forwarding = relay.hostname == "se-got-001";
+        }
+        *relay_selector.parsed_relays.lock() =
+            ParsedRelays::from_relay_list(relay_list, SystemTime::now());
+
+        let (relay, _, _) = relay_selector
+            .get_relay_with_config(&config, 0)
+            .expect("Failed to get relay when port forwarding is required");
+        match relay {
+            SelectedRelay::Normal(relay) => assert_eq!(relay.exit_relay.hostname, "se-got-001"),
+            SelectedRelay::Custom(_) => panic!("Expected a normal relay"),
+        }
+    }
+
+    #[test]
+    fn test_relay_counts_by_location() {
+        let mut relay_selector = new_relay_selector();
+        let country = LocationConstraint::Country("se".to_string());
+        let city = LocationConstraint::City("se".to_string(), "got".to_string());
+
+        // Relays without any valid endpoints are not counted
+        assert_eq!(
+            relay_selector.get_relay_counts_by_location(),
+            vec![(country.clone(), 3), (city.clone(), 3)]
+        );
+
+        let mut config = relay_selector.config.lock().clone();
+        config.relay_settings = RelaySettings::Normal(RelayConstraints {
+            tunnel_protocol: Constraint::Only(TunnelType::OpenVpn),
+            ..Default::default()
+        });
+        relay_selector.set_config(config.clone());
+        assert_eq!(
+            relay_selector.get_relay_counts_by_location(),
+            vec![(country.clone(), 1), (city.clone(), 1)]
+        );
+
+        config.relay_settings = RelaySettings::Normal(RelayConstraints {
+            providers: Constraint::Only(
+                Providers::new(std::iter::once("unknown".to_string())).unwrap(),
+            ),
+            ..Default::default()
+        });
+        relay_selector.set_config(config);
+        assert_eq!(
+            relay_selector.get_relay_counts_by_location(),
+            vec![(country, 0), (city, 0)]
+        );
+    }
+
+    #[test]
+    fn test_preview_relay() {
+        let relay_selector = new_relay_selector();
+        let original_config = relay_selector.get_config();
+
+        let mut config = original_config.clone();
+        config.relay_settings = RelaySettings::Normal(RelayConstraints {
+            location: Constraint::Only(LocationConstraint::Hostname(
+                "se".to_string(),
+                "got".to_string(),
+                "se9-wireguard".to_string(),
+            )),
+            ..RelayConstraints::default()
+        });
+        let outcome = relay_selector
+            .preview_relay(&config)
+            .expect("Failed to preview relay");
+        match outcome.relay {
+            SelectedRelay::Normal(relay) => assert_eq!(relay.exit_relay.hostname, "se9-wireguard"),
+            SelectedRelay::Custom(_) => panic!("Expected a normal relay"),
+        }
+        assert_eq!(outcome.matching_relay_count, Some(1));
+
+        assert_eq!(
+            relay_selector.get_config().relay_settings,
+            original_config.relay_settings
+        );
+        assert!(relay_selector.last_selection.lock().is_none());
+    }
+
+    #[test]
+    fn test_get_providers() {
+        let mut relay_selector = new_relay_selector();
+        let expected = vec![ProviderInfo {
+            name: "31173".to_string(),
+            relay_count: 5,
+            selectable_relay_count: 3,
+            owned: true,
+        }];
+        assert_eq!(relay_selector.get_providers(), expected);
+
+        // The provider constraint does not affect the counts
+        let mut config = relay_selector.config.lock().clone();
+        if let RelaySettings::Normal(ref mut constraints) = config.relay_settings {
+            constraints.providers =
+                Constraint::Only(Providers::new(std::iter::once("unknown".to_string())).unwrap());
+        }
+        relay_selector.set_config(config);
+        assert_eq!(relay_selector.get_providers(), expected);
+    }
+
+    #[test]
+    fn test_get_relay_counts_by_protocol() {
+        let mut relay_selector = new_relay_selector();
+        // Relays without valid endpoints are not counted
+        assert_eq!(
+            relay_selector.get_relay_counts_by_protocol(),
+            ProtocolCounts {
+                wireguard: 2,
+                openvpn: 1,
+            }
+        );
+
+        let mut config = relay_selector.config.lock().clone();
+        if let RelaySettings::Normal(ref mut constraints) = config.relay_settings {
+            constraints.location = Constraint::Only(LocationConstraint::Country("no".to_owned()));
+        }
+        relay_selector.set_config(config);
+        assert_eq!(
+            relay_selector.get_relay_counts_by_protocol(),
+            ProtocolCounts::default()
+        );
+    }
+
     #[test]
     fn test_selecting_wg_endpoint_with_udp2tcp_obfuscation() {
         let relay_selector = new_relay_selector();
@@ -1878,4 +3110,20 @@ mod test {
             .get_tunnel_endpoint(&constraints, BridgeState::Off, 0)
             .expect_err("Successfully selected a relay that should be filtered");
     }
+
+    #[test]
+    fn test_ephemeral_relay_list() {
+        let relay_selector = new_relay_selector();
+        relay_selector.set_ephemeral_relay_list(RelayList::empty());
+        relay_selector.set_ephemeral_relay_list(RelayList::empty());
+        assert!(relay_selector.is_using_ephemeral_relay_list());
+        assert!(relay_selector.get_relay(0).is_err());
+
+        let restored = relay_selector
+            .clear_ephemeral_relay_list()
+            .expect("no ephemeral relay list was in use");
+        assert_eq!(restored.countries.len(), RELAYS.countries.len());
+        assert!(relay_selector.get_relay(0).is_ok());
+        assert!(relay_selector.clear_ephemeral_relay_list().is_none());
+    }
 }
@@ -1,8 +1,8 @@
 use mullvad_types::{
     endpoint::{MullvadEndpoint, MullvadWireguardEndpoint},
     relay_constraints::{
-        Constraint, LocationConstraint, Match, OpenVpnConstraints, Providers, RelayConstraints,
-        WireguardConstraints,
+        Constraint, JurisdictionGroup, LocationConstraint, Match, OpenVpnConstraints, Providers,
+        RelayConstraints, WireguardConstraints,
     },
     relay_list::{Relay, RelayTunnels, WireguardEndpointData},
 };
@@ -14,6 +14,8 @@ use talpid_types::net::{all_of_the_internet, wireguard, IpVersion, TunnelType};
 pub struct RelayMatcher<T: TunnelMatcher> {
     pub location: Constraint<LocationConstraint>,
     pub providers: Constraint<Providers>,
+    /// Only match relays where port forwarding is available.
+    pub port_forwarding: bool,
     pub tunnel: T,
 }
 
@@ -22,6 +24,7 @@ impl From<RelayConstraints> for RelayMatcher<AnyTunnelMatcher> {
         Self {
             location: constraints.location,
             providers: constraints.providers,
+            port_forwarding: constraints.port_forwarding,
             tunnel: AnyTunnelMatcher {
                 wireguard: constraints.wireguard_constraints.into(),
                 openvpn: constraints.openvpn_constraints,
@@ -37,6 +40,7 @@ impl RelayMatcher<AnyTunnelMatcher> {
             tunnel: self.tunnel.wireguard,
             location: self.location,
             providers: self.providers,
+            port_forwarding: self.port_forwarding,
         }
     }
 }
@@ -54,6 +58,9 @@ impl<T: TunnelMatcher> RelayMatcher<T> {
         if !self.location.matches(relay) || !self.providers.matches(relay) {
             return None;
         }
+        if self.port_forwarding && !relay.port_forwarding {
+            return None;
+        }
 
         self.tunnel.filter_matching_endpoints(relay)
     }
@@ -169,9 +176,40 @@ pub struct WireguardMatcher {
     pub peer: Option<Relay>,
     pub port: Constraint<u16>,
     pub ip_version: Constraint<IpVersion>,
+    /// Only match relays in the same country as `peer`, if one is set.
+    pub same_country_as_peer: bool,
+    /// Only match relays that are not in the same one of these groups as `peer`, if one is set.
+    pub avoided_jurisdictions_with_peer: Vec<JurisdictionGroup>,
 }
 
 impl WireguardMatcher {
+    /// Returns whether `relay` and `peer` may be used together as the entry and exit relays,
+    /// in either order.
+    pub fn is_valid_peer_pair(&self, peer: &Relay, relay: &Relay) -> bool {
+        if peer.hostname == relay.hostname {
+            return false;
+        }
+        let country_code = |relay: &Relay| {
+            relay
+                .location
+                .as_ref()
+                .map(|location| location.country_code.clone())
+        };
+        if self.same_country_as_peer && country_code(peer) != country_code(relay) {
+            return false;
+        }
+        if let (Some(peer_country), Some(country)) = (country_code(peer), country_code(relay)) {
+            if self
+                .avoided_jurisdictions_with_peer
+                .iter()
+                .any(|group| group.contains(&peer_country) && group.contains(&country))
+            {
+                return false;
+            }
+        }
+        true
+    }
+
     fn wg_data_to_endpoint(
         &self,
         relay: &Relay,
@@ -183,6 +221,7 @@ impl WireguardMatcher {
             public_key: data.public_key,
             endpoint: SocketAddr::new(host, port),
             allowed_ips: all_of_the_internet(),
+            persistent_keepalive: None,
         };
         Some(MullvadEndpoint::Wireguard(MullvadWireguardEndpoint {
             peer: peer_config,
@@ -243,6 +282,8 @@ impl From<WireguardConstraints> for WireguardMatcher {
             peer: None,
             port: constraints.port,
             ip_version: constraints.ip_version,
+            same_country_as_peer: constraints.multihop_same_country,
+            avoided_jurisdictions_with_peer: constraints.multihop_avoided_jurisdictions,
         }
     }
 }
@@ -261,13 +302,10 @@ impl Match<WireguardEndpointData> for WireguardMatcher {
 
 impl TunnelMatcher for WireguardMatcher {
     fn filter_matching_endpoints(&self, relay: &Relay) -> Option<Relay> {
-        if self
-            .peer
-            .as_ref()
-            .map(|peer_relay| peer_relay.hostname == relay.hostname)
-            .unwrap_or(false)
-        {
-            return None;
+        if let Some(peer_relay) = &self.peer {
+            if !self.is_valid_peer_pair(peer_relay, relay) {
+                return None;
+            }
         }
 
         let tunnels = relay
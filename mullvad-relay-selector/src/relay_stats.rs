@@ -0,0 +1,155 @@
+//! Keeps track of how often connecting to each relay has failed recently, so that relays which do
+//! not work for this user can be deprioritized. Failures decay over time, so a relay that failed
+//! once is eventually weighted normally again.
+
+use mullvad_types::relay_list::Relay;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use talpid_types::ErrorExt;
+
+const RELAY_STATS_FILENAME: &str = "relay-stats.json";
+
+/// The failure score of a relay is halved this often.
+const FAILURE_HALF_LIFE: Duration = Duration::from_secs(30 * 60);
+
+/// Entries whose failure score has decayed below this are forgotten.
+const MIN_FAILURE_SCORE: f64 = 0.01;
+
+/// Relay weights are scaled up by this much before being reduced by the failure score, so that
+/// relays with a small weight can still be deprioritized.
+const WEIGHT_SCALE: f64 = 1000.0;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RelayStat {
+    attempts: u64,
+    successes: u64,
+    /// Roughly the number of recent failures. Decays with [FAILURE_HALF_LIFE].
+    failure_score: f64,
+    updated: SystemTime,
+}
+
+impl RelayStat {
+    fn decayed_failure_score(&self, now: SystemTime) -> f64 {
+        let elapsed = now.duration_since(self.updated).unwrap_or_default();
+        self.failure_score * 0.5f64.powf(elapsed.as_secs_f64() / FAILURE_HALF_LIFE.as_secs_f64())
+    }
+}
+
+/// Per-relay connection statistics, persisted in the cache directory.
+pub struct RelayStats {
+    enabled: bool,
+    path: Option<PathBuf>,
+    stats: HashMap<String, RelayStat>,
+}
+
+impl RelayStats {
+    /// Loads the statistics from `cache_dir`. Returns empty statistics if there are none.
+    pub fn load(cache_dir: &Path, enabled: bool) -> Self {
+        let path = cache_dir.join(RELAY_STATS_FILENAME);
+        let stats = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents).unwrap_or_else(|error| {
+                log::error!(
+                    "{}",
+                    error.display_chain_with_msg("Failed to parse relay statistics")
+                );
+                HashMap::new()
+            }),
+            Err(error) => {
+                if error.kind() != io::ErrorKind::NotFound {
+                    log::error!(
+                        "{}",
+                        error.display_chain_with_msg("Failed to read relay statistics")
+                    );
+                }
+                HashMap::new()
+            }
+        };
+        RelayStats {
+            enabled,
+            path: Some(path),
+            stats,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn in_memory(enabled: bool) -> Self {
+        RelayStats {
+            enabled,
+            path: None,
+            stats: HashMap::new(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Records whether connecting to the relay with the given hostname succeeded. Nothing is
+    /// recorded unless weighting by success rate is enabled.
+    pub fn record(&mut self, hostname: &str, success: bool, now: SystemTime) {
+        if !self.enabled {
+            return;
+        }
+        let stat = self
+            .stats
+            .entry(hostname.to_owned())
+            .or_insert_with(|| RelayStat {
+                attempts: 0,
+                successes: 0,
+                failure_score: 0.0,
+                updated: now,
+            });
+        let failure_score = stat.decayed_failure_score(now);
+        stat.attempts += 1;
+        if success {
+            stat.successes += 1;
+            stat.failure_score = failure_score / 2.0;
+        } else {
+            stat.failure_score = failure_score + 1.0;
+        }
+        stat.updated = now;
+
+        self.stats
+            .retain(|_, stat| stat.decayed_failure_score(now) >= MIN_FAILURE_SCORE);
+        self.save();
+    }
+
+    /// Returns the weight of `relay` adjusted for its recent failures. If weighting by success
+    /// rate is disabled, this is simply the weight of the relay.
+    pub fn weight(&self, relay: &Relay, now: SystemTime) -> u64 {
+        if !self.enabled || relay.weight == 0 {
+            return relay.weight;
+        }
+        let weight = relay.weight as f64 * WEIGHT_SCALE * self.weight_factor(&relay.hostname, now);
+        (weight.round() as u64).max(1)
+    }
+
+    /// Returns a factor in `(0, 1]` that the weight of the relay should be multiplied by.
+    fn weight_factor(&self, hostname: &str, now: SystemTime) -> f64 {
+        match self.stats.get(hostname) {
+            Some(stat) => 1.0 / (1.0 + stat.decayed_failure_score(now)),
+            None => 1.0,
+        }
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let result = serde_json::to_vec(&self.stats)
+            .map_err(io::Error::from)
+            .and_then(|contents| fs::write(path, contents));
+        if let Err(error) = result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to write relay statistics")
+            );
+        }
+    }
+}
@@ -18,30 +18,47 @@ use tokio::fs::File;
 
 /// How often the updater should wake up to check the cache of the in-memory cache of relays.
 /// This check is very cheap. The only reason to not have it very often is because if downloading
-/// constantly fails it will try very often and fill the logs etc.
+/// constantly fails it will try very often and fill the logs etc. The updater wakes up more often
+/// if the update interval is shorter than this.
 const UPDATE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 15);
-/// How old the cached relays need to be to trigger an update
-const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 const EXPONENTIAL_BACKOFF_INITIAL: Duration = Duration::from_secs(16);
 const EXPONENTIAL_BACKOFF_FACTOR: u32 = 8;
 
+enum UpdaterCommand {
+    /// Download a new relay list.
+    Update,
+    /// Set how old the cached relays need to be to trigger an update.
+    SetUpdateInterval(Duration),
+}
+
 #[derive(Clone)]
 pub struct RelayListUpdaterHandle {
-    tx: mpsc::Sender<()>,
+    tx: mpsc::Sender<UpdaterCommand>,
 }
 
 impl RelayListUpdaterHandle {
     pub async fn update(&mut self) {
+        self.send_command(UpdaterCommand::Update).await
+    }
+
+    /// Changes the update interval. The next update is rescheduled according to the new
+    /// interval.
+    pub async fn set_update_interval(&mut self, interval: Duration) {
+        self.send_command(UpdaterCommand::SetUpdateInterval(interval))
+            .await
+    }
+
+    async fn send_command(&mut self, command: UpdaterCommand) {
         if let Err(error) = self
             .tx
-            .send(())
+            .send(command)
             .await
             .map_err(|_| Error::DownloaderShutDown)
         {
             log::error!(
                 "{}",
-                error.display_chain_with_msg("Unable to send update command to relay list updater")
+                error.display_chain_with_msg("Unable to send command to relay list updater")
             );
         }
     }
@@ -51,8 +68,10 @@ pub struct RelayListUpdater {
     api_client: RelayListProxy,
     cache_path: PathBuf,
     parsed_relays: Arc<Mutex<ParsedRelays>>,
+    overridden_relays: Arc<Mutex<Option<ParsedRelays>>>,
     on_update: Box<dyn Fn(&RelayList) + Send + 'static>,
     last_check: SystemTime,
+    update_interval: Duration,
     api_availability: ApiAvailabilityHandle,
 }
 
@@ -61,6 +80,7 @@ impl RelayListUpdater {
         selector: super::RelaySelector,
         api_handle: MullvadRestHandle,
         cache_dir: &Path,
+        update_interval: Duration,
         on_update: impl Fn(&RelayList) + Send + 'static,
     ) -> RelayListUpdaterHandle {
         let (tx, cmd_rx) = mpsc::channel(1);
@@ -70,8 +90,10 @@ impl RelayListUpdater {
             api_client,
             cache_path: cache_dir.join(super::RELAYS_FILENAME),
             parsed_relays: selector.parsed_relays.clone(),
+            overridden_relays: selector.overridden_relays.clone(),
             on_update: Box::new(on_update),
             last_check: UNIX_EPOCH,
+            update_interval,
             api_availability,
         };
 
@@ -80,17 +102,19 @@ impl RelayListUpdater {
         RelayListUpdaterHandle { tx }
     }
 
-    async fn run(mut self, mut cmd_rx: mpsc::Receiver<()>) {
+    async fn run(mut self, mut cmd_rx: mpsc::Receiver<UpdaterCommand>) {
         let mut download_future = Box::pin(Fuse::terminated());
         loop {
-            let next_check = tokio::time::sleep(UPDATE_CHECK_INTERVAL).fuse();
+            let next_check =
+                tokio::time::sleep(std::cmp::min(UPDATE_CHECK_INTERVAL, self.update_interval))
+                    .fuse();
             tokio::pin!(next_check);
 
             futures::select! {
                 _check_update = next_check => {
                     if download_future.is_terminated() && self.should_update() {
-                        let tag = self.parsed_relays.lock().tag().map(|tag| tag.to_string());
-                        download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag).fuse());
+                        let tag = self.with_downloaded_relays(|relays| relays.tag().map(|tag| tag.to_string()));
+                        download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag, self.update_interval).fuse());
                         self.last_check = SystemTime::now();
                     }
                 },
@@ -101,11 +125,20 @@ impl RelayListUpdater {
 
                 cmd = cmd_rx.next() => {
                     match cmd {
-                        Some(()) => {
-                            let tag = self.parsed_relays.lock().tag().map(|tag| tag.to_string());
-                            download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag).fuse());
+                        Some(UpdaterCommand::Update) => {
+                            let tag = self.with_downloaded_relays(|relays| relays.tag().map(|tag| tag.to_string()));
+                            download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag, self.update_interval).fuse());
                             self.last_check = SystemTime::now();
                         },
+                        Some(UpdaterCommand::SetUpdateInterval(interval)) => {
+                            log::debug!("Relay list update interval set to {:?}", interval);
+                            self.update_interval = interval;
+                            if download_future.is_terminated() && self.should_update() {
+                                let tag = self.with_downloaded_relays(|relays| relays.tag().map(|tag| tag.to_string()));
+                                download_future = Box::pin(Self::download_relay_list(self.api_availability.clone(), self.api_client.clone(), tag, self.update_interval).fuse());
+                                self.last_check = SystemTime::now();
+                            }
+                        },
                         None => {
                             log::trace!("Relay list updater shutting down");
                             return;
@@ -135,11 +168,21 @@ impl RelayListUpdater {
         }
     }
 
-    /// Returns true if the current parsed_relays is older than UPDATE_INTERVAL
+    /// Calls `f` with the cached or downloaded relays, which are not in use while an ephemeral
+    /// relay list is.
+    fn with_downloaded_relays<T>(&self, f: impl FnOnce(&ParsedRelays) -> T) -> T {
+        match &*self.overridden_relays.lock() {
+            Some(relays) => f(relays),
+            None => f(&self.parsed_relays.lock()),
+        }
+    }
+
+    /// Returns true if the current parsed_relays is older than the update interval
     fn should_update(&mut self) -> bool {
-        let last_check = std::cmp::max(self.parsed_relays.lock().last_updated(), self.last_check);
+        let last_updated = self.with_downloaded_relays(|relays| relays.last_updated());
+        let last_check = std::cmp::max(last_updated, self.last_check);
         match SystemTime::now().duration_since(last_check) {
-            Ok(duration) => duration >= UPDATE_INTERVAL,
+            Ok(duration) => duration >= self.update_interval,
             // If the clock is skewed we have no idea by how much or when the last update
             // actually was, better download again to get in sync and get a `last_updated`
             // timestamp corresponding to the new time.
@@ -151,6 +194,7 @@ impl RelayListUpdater {
         api_handle: ApiAvailabilityHandle,
         proxy: RelayListProxy,
         tag: Option<String>,
+        update_interval: Duration,
     ) -> impl Future<Output = Result<Option<RelayList>, mullvad_api::Error>> + 'static {
         let download_futures = move || {
             let available = api_handle.wait_background();
@@ -163,7 +207,7 @@ impl RelayListUpdater {
 
         let exponential_backoff =
             ExponentialBackoff::new(EXPONENTIAL_BACKOFF_INITIAL, EXPONENTIAL_BACKOFF_FACTOR)
-                .max_delay(UPDATE_INTERVAL * 2);
+                .max_delay(update_interval * 2);
 
         let download_future = retry_future(
             download_futures,
@@ -187,6 +231,12 @@ impl RelayListUpdater {
             new_parsed_relays.relays().len()
         );
 
+        let mut overridden_relays = self.overridden_relays.lock();
+        if let Some(relays) = overridden_relays.as_mut() {
+            log::debug!("Not using the downloaded relays while an ephemeral relay list is in use");
+            *relays = new_parsed_relays;
+            return Ok(());
+        }
         let mut parsed_relays = self.parsed_relays.lock();
         *parsed_relays = new_parsed_relays;
         (self.on_update)(parsed_relays.locations());
@@ -18,6 +18,10 @@ enum AuthFailedInner {
     Unknown(String, String),
 }
 
+/// An `AUTH_FAILED` reason stating that the account has expired, in the format sent by the
+/// relays.
+pub const EXPIRED_ACCOUNT_REASON: &str = "[EXPIRED_ACCOUNT] This account has no time left";
+
 // These strings should match up with gui/packages/desktop/src/renderer/lib/auth-failure.js
 const INVALID_ACCOUNT_MSG: &str = "You've logged in with an account number that is not valid. Please log out and try another one.";
 const EXPIRED_ACCOUNT_MSG: &str = "You have no more VPN time left on this account. Please log in on our website to buy more credit.";
@@ -53,6 +57,13 @@ impl<'a> From<&'a str> for AuthFailed {
     }
 }
 
+impl AuthFailed {
+    /// Returns whether authentication failed because the account has no time left.
+    pub fn is_expired_account(&self) -> bool {
+        matches!(self.reason, AuthFailedInner::ExpiredAccount)
+    }
+}
+
 impl fmt::Display for AuthFailed {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use self::AuthFailedInner::*;
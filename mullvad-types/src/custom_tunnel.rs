@@ -6,7 +6,7 @@ use std::{
     fmt, io,
     net::{IpAddr, SocketAddr, ToSocketAddrs},
 };
-use talpid_types::net::{openvpn, wireguard, Endpoint, TunnelParameters};
+use talpid_types::net::{openvpn, wireguard, Endpoint, TunnelParameters, TunnelType};
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -39,6 +39,13 @@ impl CustomTunnelEndpoint {
         }
     }
 
+    pub fn tunnel_type(&self) -> TunnelType {
+        match &self.config {
+            ConnectionConfig::OpenVpn(_) => TunnelType::OpenVpn,
+            ConnectionConfig::Wireguard(_) => TunnelType::Wireguard,
+        }
+    }
+
     pub fn to_tunnel_parameters(
         &self,
         tunnel_options: TunnelOptions,
@@ -190,6 +190,9 @@ pub struct RelayConstraints {
     pub wireguard_constraints: WireguardConstraints,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub openvpn_constraints: OpenVpnConstraints,
+    /// Only select relays where port forwarding is available.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub port_forwarding: bool,
 }
 
 #[cfg(target_os = "android")]
@@ -201,6 +204,7 @@ impl Default for RelayConstraints {
             providers: Constraint::default(),
             wireguard_constraints: WireguardConstraints::default(),
             openvpn_constraints: OpenVpnConstraints::default(),
+            port_forwarding: false,
         }
     }
 }
@@ -219,6 +223,7 @@ impl RelayConstraints {
             openvpn_constraints: update
                 .openvpn_constraints
                 .unwrap_or_else(|| self.openvpn_constraints.clone()),
+            port_forwarding: update.port_forwarding.unwrap_or(self.port_forwarding),
         }
     }
 }
@@ -250,9 +255,13 @@ impl fmt::Display for RelayConstraints {
         }
         write!(f, " using ")?;
         match self.providers {
-            Constraint::Any => write!(f, "any provider"),
-            Constraint::Only(ref constraint) => constraint.fmt(f),
+            Constraint::Any => write!(f, "any provider")?,
+            Constraint::Only(ref constraint) => constraint.fmt(f)?,
         }
+        if self.port_forwarding {
+            write!(f, " with port forwarding")?;
+        }
+        Ok(())
     }
 }
 
@@ -430,6 +439,51 @@ pub struct WireguardConstraints {
     pub ip_version: Constraint<IpVersion>,
     pub use_multihop: bool,
     pub entry_location: Constraint<LocationConstraint>,
+    /// Require the entry and exit relays to be in the same country when using multihop.
+    pub multihop_same_country: bool,
+    /// Prefer multihop entry and exit relays that are not both in one of these groups. Relays
+    /// in the same group are still selected if no other pair matches the constraints.
+    pub multihop_avoided_jurisdictions: Vec<JurisdictionGroup>,
+}
+
+/// Groups of countries that are known to share intelligence with each other.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JurisdictionGroup {
+    FiveEyes,
+    NineEyes,
+    FourteenEyes,
+}
+
+const FIVE_EYES_COUNTRIES: &[&str] = &["au", "ca", "gb", "nz", "us"];
+const NINE_EYES_COUNTRIES: &[&str] = &["au", "ca", "dk", "fr", "gb", "nl", "no", "nz", "us"];
+const FOURTEEN_EYES_COUNTRIES: &[&str] = &[
+    "au", "be", "ca", "de", "dk", "es", "fr", "gb", "it", "nl", "no", "nz", "se", "us",
+];
+
+impl JurisdictionGroup {
+    /// Returns the codes of the countries in the group.
+    pub fn country_codes(&self) -> &'static [&'static str] {
+        match self {
+            JurisdictionGroup::FiveEyes => FIVE_EYES_COUNTRIES,
+            JurisdictionGroup::NineEyes => NINE_EYES_COUNTRIES,
+            JurisdictionGroup::FourteenEyes => FOURTEEN_EYES_COUNTRIES,
+        }
+    }
+
+    pub fn contains(&self, country_code: &str) -> bool {
+        self.country_codes().contains(&country_code)
+    }
+}
+
+impl fmt::Display for JurisdictionGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JurisdictionGroup::FiveEyes => write!(f, "Five Eyes"),
+            JurisdictionGroup::NineEyes => write!(f, "Nine Eyes"),
+            JurisdictionGroup::FourteenEyes => write!(f, "Fourteen Eyes"),
+        }
+    }
 }
 
 impl fmt::Display for WireguardConstraints {
@@ -445,9 +499,16 @@ impl fmt::Display for WireguardConstraints {
         }
         if self.use_multihop {
             match &self.entry_location {
-                Constraint::Any => write!(f, " (via any location)"),
-                Constraint::Only(location) => write!(f, " (via {})", location),
+                Constraint::Any => write!(f, " (via any location")?,
+                Constraint::Only(location) => write!(f, " (via {}", location)?,
+            }
+            if self.multihop_same_country {
+                write!(f, " in the same country")?;
             }
+            for group in &self.multihop_avoided_jurisdictions {
+                write!(f, ", avoiding {} pairs", group)?;
+            }
+            write!(f, ")")
         } else {
             Ok(())
         }
@@ -488,6 +549,32 @@ impl fmt::Display for SelectedObfuscation {
     }
 }
 
+impl SelectedObfuscation {
+    /// Every variant. [`SelectedObfuscation::is_protocol`] must be updated along with this.
+    const ALL: [SelectedObfuscation; 3] = [
+        SelectedObfuscation::Auto,
+        SelectedObfuscation::Off,
+        SelectedObfuscation::Udp2Tcp,
+    ];
+
+    /// Returns whether this selects a specific obfuscation protocol.
+    pub fn is_protocol(&self) -> bool {
+        match self {
+            SelectedObfuscation::Auto | SelectedObfuscation::Off => false,
+            SelectedObfuscation::Udp2Tcp => true,
+        }
+    }
+
+    /// Returns all obfuscation protocols that are supported by this build.
+    pub fn protocols() -> Vec<SelectedObfuscation> {
+        Self::ALL
+            .iter()
+            .copied()
+            .filter(SelectedObfuscation::is_protocol)
+            .collect()
+    }
+}
+
 #[derive(Default, Debug, Clone, Eq, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub struct Udp2TcpObfuscationSettings {
@@ -542,19 +629,29 @@ pub enum BridgeState {
     Auto,
     On,
     Off,
+    /// Connect directly at first, and use a bridge once `threshold` consecutive attempts to
+    /// connect have failed. The next connection that is not a retry is attempted directly again.
+    OnDemand {
+        threshold: u32,
+    },
+}
+
+impl BridgeState {
+    /// The number of failed attempts before a bridge is used if no threshold is specified for
+    /// [BridgeState::OnDemand].
+    pub const DEFAULT_ON_DEMAND_THRESHOLD: u32 = 3;
 }
 
 impl fmt::Display for BridgeState {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                BridgeState::Auto => "auto",
-                BridgeState::On => "on",
-                BridgeState::Off => "off",
+        match self {
+            BridgeState::Auto => write!(f, "auto"),
+            BridgeState::On => write!(f, "on"),
+            BridgeState::Off => write!(f, "off"),
+            BridgeState::OnDemand { threshold } => {
+                write!(f, "on-demand (after {} failed attempts)", threshold)
             }
-        )
+        }
     }
 }
 
@@ -603,6 +700,28 @@ impl RelaySettingsUpdate {
     }
 }
 
+/// Relay constraints that are used instead of the saved ones for a single connection. Constraints
+/// that are `None` are taken from the saved relay settings.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RelayConstraintOverride {
+    pub location: Option<Constraint<LocationConstraint>>,
+    pub tunnel_protocol: Option<Constraint<TunnelType>>,
+}
+
+impl RelayConstraintOverride {
+    /// Returns the relay settings that result from applying the override to `relay_settings`.
+    pub fn apply(&self, relay_settings: &RelaySettings) -> RelaySettings {
+        relay_settings
+            .clone()
+            .merge(RelaySettingsUpdate::Normal(RelayConstraintsUpdate {
+                location: self.location.clone(),
+                tunnel_protocol: self.tunnel_protocol,
+                ..Default::default()
+            }))
+    }
+}
+
 /// Used in [`RelaySettings`] to change relay constraints in the daemon.
 #[derive(Debug, Default, Deserialize, Serialize)]
 #[cfg_attr(target_os = "android", derive(FromJava))]
@@ -618,4 +737,6 @@ pub struct RelayConstraintsUpdate {
     pub wireguard_constraints: Option<WireguardConstraints>,
     #[cfg_attr(target_os = "android", jnix(default))]
     pub openvpn_constraints: Option<OpenVpnConstraints>,
+    #[cfg_attr(target_os = "android", jnix(default))]
+    pub port_forwarding: Option<bool>,
 }
@@ -8,12 +8,23 @@ use serde::{Deserialize, Serialize};
 use std::{
     fmt,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
 };
 use talpid_types::net::{
     openvpn::{ProxySettings, ShadowsocksProxySettings},
     wireguard, Endpoint, TransportProtocol,
 };
 
+/// How old the cached relay list needs to be before a new one is downloaded, by default.
+pub const DEFAULT_RELAY_LIST_UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Shortest allowed interval between relay list updates.
+pub const MIN_RELAY_LIST_UPDATE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Longest allowed interval between relay list updates.
+pub const MAX_RELAY_LIST_UPDATE_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Language tag of the country and city names in the relay list. The API only provides English
+/// names.
+pub const LOCATION_NAME_LANGUAGE: &str = "en";
+
 /// Stores a list of relays for each country obtained from the API using
 /// `mullvad_api::RelayListProxy`. This can also be passed to frontends.
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -39,6 +50,7 @@ impl RelayList {
 #[cfg_attr(target_os = "android", derive(IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 pub struct RelayListCountry {
+    /// Display name of the country, in [`LOCATION_NAME_LANGUAGE`].
     pub name: String,
     pub code: CountryCode,
     pub cities: Vec<RelayListCity>,
@@ -49,6 +61,7 @@ pub struct RelayListCountry {
 #[cfg_attr(target_os = "android", derive(IntoJava))]
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 pub struct RelayListCity {
+    /// Display name of the city, in [`LOCATION_NAME_LANGUAGE`].
     pub name: String,
     pub code: CityCode,
     #[cfg_attr(target_os = "android", jnix(skip))]
@@ -88,6 +101,30 @@ pub struct Relay {
     pub obfuscators: RelayObfuscators,
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub location: Option<Location>,
+    /// Whether port forwarding is available on the relay.
+    #[serde(default)]
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub port_forwarding: bool,
+}
+
+/// Summary of the relays hosted by a single provider.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProviderInfo {
+    pub name: String,
+    /// Number of active relays hosted by the provider.
+    pub relay_count: usize,
+    /// Number of active relays hosted by the provider that match the current relay constraints,
+    /// disregarding the provider constraint.
+    pub selectable_relay_count: usize,
+    /// Whether all relays hosted by the provider are owned by Mullvad.
+    pub owned: bool,
+}
+
+/// Number of active relays that support each tunnel protocol.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProtocolCounts {
+    pub wireguard: usize,
+    pub openvpn: usize,
 }
 
 /// Provides protocol-specific information about a [`Relay`].
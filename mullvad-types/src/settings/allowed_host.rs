@@ -0,0 +1,18 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use talpid_types::net::TransportProtocol;
+
+/// A host that the daemon should be able to reach in every tunnel state. The hostname is
+/// resolved by the daemon, and every address that it resolves to is allowed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AllowedHost {
+    pub hostname: String,
+    pub port: u16,
+    pub protocol: TransportProtocol,
+}
+
+impl fmt::Display for AllowedHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}/{}", self.hostname, self.port, self.protocol)
+    }
+}
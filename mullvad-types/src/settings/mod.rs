@@ -2,18 +2,27 @@ use crate::{
     relay_constraints::{
         BridgeConstraints, BridgeSettings, BridgeState, Constraint, LocationConstraint,
         ObfuscationSettings, RelayConstraints, RelaySettings, RelaySettingsUpdate,
-        SelectedObfuscation,
+        SelectedObfuscation, TransportPort,
     },
+    relay_list::DEFAULT_RELAY_LIST_UPDATE_INTERVAL,
     wireguard,
 };
+use chrono::{DateTime, Utc};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-#[cfg(target_os = "windows")]
-use std::{collections::HashSet, path::PathBuf};
-use talpid_types::net::{self, openvpn, GenericTunnelOptions};
+#[cfg(any(windows, target_os = "linux"))]
+use std::collections::HashSet;
+use std::{net::IpAddr, path::PathBuf, time::Duration};
+use talpid_types::{
+    net::{self, openvpn, GenericTunnelOptions},
+    tunnel::{ConnectionRetryBackoff, RouteChangeReconnect},
+};
 
+mod allowed_host;
 mod dns;
+mod on_demand;
+mod reconnect_policy;
 
 /// The version used by the current version of the code. Should always be the
 /// latest version that exists in `SettingsVersion`.
@@ -59,6 +68,54 @@ impl Serialize for SettingsVersion {
     }
 }
 
+/// A settings migration that has been applied to the settings file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct MigrationRecord {
+    /// Settings version before the migration. The first version lacked an explicit version, and
+    /// is reported as `1`.
+    pub from_version: u32,
+    /// Settings version after the migration.
+    pub to_version: u32,
+    /// When the migration was applied.
+    pub timestamp: DateTime<Utc>,
+}
+
+/// What to do when the tunnel has connected but no request could be sent through it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnverifiedPolicy {
+    /// Keep the tunnel up, but report the connection as unverified.
+    Keep,
+    /// Reconnect the tunnel.
+    Reconnect,
+    /// Disconnect the tunnel and block all traffic until the user reconnects.
+    Block,
+}
+
+impl Default for UnverifiedPolicy {
+    fn default() -> Self {
+        UnverifiedPolicy::Keep
+    }
+}
+
+/// What to do when the account is found to have expired while the tunnel is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExpiryPolicy {
+    /// Keep trying to connect.
+    Retry,
+    /// Disconnect the tunnel.
+    Disconnect,
+    /// Stop trying to connect and block all traffic until the user reconnects.
+    Block,
+}
+
+impl Default for ExpiryPolicy {
+    fn default() -> Self {
+        ExpiryPolicy::Retry
+    }
+}
+
 /// Mullvad daemon settings.
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 #[serde(default)]
@@ -78,6 +135,11 @@ pub struct Settings {
     /// the firewall to not allow any traffic in or out.
     #[cfg_attr(target_os = "android", jnix(skip))]
     pub block_when_disconnected: bool,
+    /// Block all IPv6 traffic in the firewall whenever IPv6 is disabled in the tunnel options.
+    /// This applies to the connecting, connected and error states, and to the disconnected state
+    /// if `block_when_disconnected` is enabled. Not supported on Windows.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub block_ipv6: bool,
     /// If the daemon should connect the VPN tunnel directly on start or not.
     pub auto_connect: bool,
     /// Options that should be applied to tunnels of a specific type regardless of where the relays
@@ -85,6 +147,86 @@ pub struct Settings {
     pub tunnel_options: TunnelOptions,
     /// Whether to notify users of beta updates.
     pub show_beta_releases: bool,
+    /// File that relay selection and connection outcomes are written to, as JSON lines. It must
+    /// be located directly in the log or cache directory. Nothing is recorded when this is unset.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub selection_telemetry_path: Option<PathBuf>,
+    /// Temporarily lower the weight of relays that could recently not be connected to.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub deprioritize_failing_relays: bool,
+    /// Retry the previously selected relays once before selecting new ones.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub retry_same_relay: bool,
+    /// OpenVPN transports to try on successive retries. The default ordering is used if this is
+    /// empty.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub openvpn_endpoint_preference: Vec<TransportPort>,
+    /// Reconnect to another relay if the exit relay in use is removed from the relay list.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub reconnect_on_relay_removal: bool,
+    /// Maximum number of recently used accounts to remember.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub account_history_limit: u32,
+    /// Rules that set the target state depending on the Wi-Fi network.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub on_demand: OnDemandRules,
+    /// Hostname of the bridge relay to always reach the API through, if it is available.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_bridge: Option<String>,
+    /// How long to wait between consecutive attempts to connect the tunnel.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub connection_retry_backoff: ConnectionRetryBackoff,
+    /// Whether and how soon the tunnel is disconnected when the device goes offline.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub route_change_reconnect: RouteChangeReconnect,
+    /// Number of failed attempts to connect using WireGuard after which OpenVPN is used instead
+    /// until the next manual connect. The fallback is disabled if this is `None`.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub protocol_fallback_threshold: Option<u32>,
+    /// Identifier appended to the User-Agent of API requests.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_client_tag: Option<String>,
+    /// Maximum number of API requests that are in flight at the same time. Unlimited if `None`.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_concurrency_limit: Option<u32>,
+    /// Local address to bind the sockets used to reach the API to. The OS chooses the source
+    /// address if `None`.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub api_bind_interface: Option<IpAddr>,
+    /// How old the cached relay list needs to be before a new one is downloaded.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub relay_list_update_interval: Duration,
+    /// Whether changes to each group of settings reconnect the tunnel immediately.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub reconnect_policies: ReconnectPolicies,
+    /// What to do when the connectivity of a newly connected tunnel could not be verified.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub on_unverified_connection: UnverifiedPolicy,
+    /// What to do when the account expires while the tunnel is secured.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub on_account_expiry: ExpiryPolicy,
+    /// How long the tunnel may try to connect before all traffic is blocked. Retries continue
+    /// indefinitely if `None`.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub connect_deadline_to_block: Option<Duration>,
+    /// Relay settings to use when no relay matches `relay_settings`.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    pub backup_relay_settings: Option<RelaySettings>,
+    /// Hosts that the daemon may reach outside the tunnel while connecting or blocking. The
+    /// hostnames are resolved again periodically.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub allowed_hosts: Vec<AllowedHost>,
+    /// Whether processes excluded from the tunnel may still communicate while all other traffic
+    /// is blocked.
+    #[cfg(target_os = "linux")]
+    pub allow_excluded_when_blocked: bool,
+    /// Firewall mark and routing table used by the tunnel.
+    #[cfg(target_os = "linux")]
+    pub linux_routing_options: net::LinuxRoutingOptions,
+    /// `net_cls` cgroups whose processes are excluded from the tunnel, relative to the root of
+    /// the hierarchy.
+    #[cfg(target_os = "linux")]
+    pub split_tunnel_cgroups: HashSet<PathBuf>,
     /// Split tunneling settings
     #[cfg(windows)]
     pub split_tunnel: SplitTunnelSettings,
@@ -94,12 +236,32 @@ pub struct Settings {
 }
 
 #[cfg(windows)]
-#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
 pub struct SplitTunnelSettings {
     /// Toggles split tunneling on or off
     pub enable_exclusions: bool,
     /// List of applications to exclude from the tunnel.
     pub apps: HashSet<PathBuf>,
+    /// Keep the exclusions installed in the split tunnel driver when reconnecting, instead of
+    /// reapplying them.
+    #[serde(default = "default_keep_exclusions_on_reconnect")]
+    pub keep_exclusions_on_reconnect: bool,
+}
+
+#[cfg(windows)]
+fn default_keep_exclusions_on_reconnect() -> bool {
+    true
+}
+
+#[cfg(windows)]
+impl Default for SplitTunnelSettings {
+    fn default() -> Self {
+        SplitTunnelSettings {
+            enable_exclusions: false,
+            apps: HashSet::new(),
+            keep_exclusions_on_reconnect: default_keep_exclusions_on_reconnect(),
+        }
+    }
 }
 
 impl Default for Settings {
@@ -117,9 +279,38 @@ impl Default for Settings {
             bridge_state: BridgeState::Auto,
             allow_lan: false,
             block_when_disconnected: false,
+            block_ipv6: false,
             auto_connect: false,
             tunnel_options: TunnelOptions::default(),
             show_beta_releases: false,
+            selection_telemetry_path: None,
+            deprioritize_failing_relays: false,
+            retry_same_relay: false,
+            openvpn_endpoint_preference: vec![],
+            reconnect_on_relay_removal: false,
+            account_history_limit: 1,
+            on_demand: OnDemandRules::default(),
+            api_bridge: None,
+            connection_retry_backoff: ConnectionRetryBackoff::default(),
+            route_change_reconnect: RouteChangeReconnect::default(),
+            protocol_fallback_threshold: None,
+            api_client_tag: None,
+            api_concurrency_limit: None,
+            api_bind_interface: None,
+            relay_list_update_interval: DEFAULT_RELAY_LIST_UPDATE_INTERVAL,
+            reconnect_policies: ReconnectPolicies::default(),
+            on_unverified_connection: UnverifiedPolicy::default(),
+            on_account_expiry: ExpiryPolicy::default(),
+            connect_deadline_to_block: None,
+            backup_relay_settings: None,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_hosts: vec![],
+            #[cfg(target_os = "linux")]
+            allow_excluded_when_blocked: true,
+            #[cfg(target_os = "linux")]
+            linux_routing_options: net::LinuxRoutingOptions::default(),
+            #[cfg(target_os = "linux")]
+            split_tunnel_cgroups: HashSet::new(),
             #[cfg(windows)]
             split_tunnel: SplitTunnelSettings::default(),
             settings_version: CURRENT_SETTINGS_VERSION,
@@ -165,6 +356,12 @@ impl Settings {
         }
     }
 
+    /// Returns whether the firewall should block all IPv6 traffic. This is never the case on
+    /// Windows, where the firewall does not support it.
+    pub fn should_block_ipv6(&self) -> bool {
+        !cfg!(windows) && self.block_ipv6 && !self.tunnel_options.generic.enable_ipv6
+    }
+
     pub fn get_settings_version(&self) -> SettingsVersion {
         self.settings_version
     }
@@ -188,7 +385,10 @@ pub struct TunnelOptions {
     pub dns_options: DnsOptions,
 }
 
+pub use allowed_host::AllowedHost;
 pub use dns::{CustomDnsOptions, DefaultDnsOptions, DnsOptions, DnsState};
+pub use on_demand::{OnDemandRule, OnDemandRules};
+pub use reconnect_policy::{ReconnectPolicies, ReconnectPolicy, ReconnectTrigger};
 
 #[cfg(target_os = "android")]
 pub use dns::AndroidDnsOptions;
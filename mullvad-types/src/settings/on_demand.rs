@@ -0,0 +1,73 @@
+use crate::states::TargetState;
+use serde::{Deserialize, Serialize};
+
+/// Rules that set the target state when the device joins a Wi-Fi network.
+///
+/// The rules are only evaluated when the set of connected Wi-Fi networks changes, so a target
+/// state set manually stays in effect until the next network change.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OnDemandRules {
+    pub enabled: bool,
+    /// Rules for specific networks. The first rule that matches a connected network is used.
+    pub rules: Vec<OnDemandRule>,
+    /// Target state to use on Wi-Fi networks that no rule matches. The target state is left
+    /// unchanged if this is `None`.
+    pub untrusted_wifi: Option<TargetState>,
+}
+
+/// Target state to use when connected to the Wi-Fi network `ssid`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OnDemandRule {
+    pub ssid: String,
+    pub target_state: TargetState,
+}
+
+impl OnDemandRules {
+    /// Returns the target state that should be used while connected to the Wi-Fi networks in
+    /// `ssids`, or `None` if the target state should be left unchanged. Wired networks are not
+    /// matched by any rule.
+    pub fn target_state(&self, ssids: &[String]) -> Option<TargetState> {
+        if !self.enabled || ssids.is_empty() {
+            return None;
+        }
+        self.rules
+            .iter()
+            .find(|rule| ssids.contains(&rule.ssid))
+            .map(|rule| rule.target_state)
+            .or(self.untrusted_wifi)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_on_demand_target_state() {
+        let rules = OnDemandRules {
+            enabled: true,
+            rules: vec![OnDemandRule {
+                ssid: "home".to_owned(),
+                target_state: TargetState::Unsecured,
+            }],
+            untrusted_wifi: Some(TargetState::Secured),
+        };
+
+        assert_eq!(
+            rules.target_state(&["home".to_owned()]),
+            Some(TargetState::Unsecured)
+        );
+        assert_eq!(
+            rules.target_state(&["cafe".to_owned()]),
+            Some(TargetState::Secured)
+        );
+        assert_eq!(rules.target_state(&[]), None);
+
+        let disabled = OnDemandRules {
+            enabled: false,
+            ..rules
+        };
+        assert_eq!(disabled.target_state(&["cafe".to_owned()]), None);
+    }
+}
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt};
+
+/// Groups of settings whose changes reconnect a tunnel that is connecting or connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectTrigger {
+    /// Relay constraints.
+    RelaySettings,
+    /// Bridge settings and bridge state.
+    BridgeSettings,
+    Obfuscation,
+    Ipv6,
+    /// WireGuard MTU and OpenVPN mssfix.
+    Mtu,
+    /// Other WireGuard options, such as the keepalive interval and allowed IPs.
+    WireguardOptions,
+    /// Firewall mark and routing table on Linux.
+    RoutingOptions,
+}
+
+impl fmt::Display for ReconnectTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ReconnectTrigger::RelaySettings => "relay settings",
+            ReconnectTrigger::BridgeSettings => "bridge settings",
+            ReconnectTrigger::Obfuscation => "obfuscation settings",
+            ReconnectTrigger::Ipv6 => "IPv6 setting",
+            ReconnectTrigger::Mtu => "MTU settings",
+            ReconnectTrigger::WireguardOptions => "WireGuard options",
+            ReconnectTrigger::RoutingOptions => "routing options",
+        };
+        f.write_str(name)
+    }
+}
+
+/// When a settings change is applied to a tunnel that is connecting or connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectPolicy {
+    /// Reconnect the tunnel immediately.
+    Immediate,
+    /// Keep the current tunnel. The change is applied the next time the tunnel connects.
+    Deferred,
+}
+
+/// Policies for each [`ReconnectTrigger`]. Triggers without a policy reconnect immediately.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ReconnectPolicies(BTreeMap<ReconnectTrigger, ReconnectPolicy>);
+
+impl ReconnectPolicies {
+    pub fn get(&self, trigger: ReconnectTrigger) -> ReconnectPolicy {
+        self.0
+            .get(&trigger)
+            .copied()
+            .unwrap_or(ReconnectPolicy::Immediate)
+    }
+
+    /// Sets the policy for `trigger`. Returns whether the policy changed.
+    pub fn set(&mut self, trigger: ReconnectTrigger, policy: ReconnectPolicy) -> bool {
+        let previous = self.get(trigger);
+        if policy == ReconnectPolicy::Immediate {
+            self.0.remove(&trigger);
+        } else {
+            self.0.insert(trigger, policy);
+        }
+        previous != policy
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_set_reconnect_policy() {
+        let mut policies = ReconnectPolicies::default();
+        assert_eq!(
+            policies.get(ReconnectTrigger::Mtu),
+            ReconnectPolicy::Immediate
+        );
+
+        assert!(policies.set(ReconnectTrigger::Mtu, ReconnectPolicy::Deferred));
+        assert!(!policies.set(ReconnectTrigger::Mtu, ReconnectPolicy::Deferred));
+        assert_eq!(
+            policies.get(ReconnectTrigger::Mtu),
+            ReconnectPolicy::Deferred
+        );
+        assert_eq!(
+            policies.get(ReconnectTrigger::Ipv6),
+            ReconnectPolicy::Immediate
+        );
+
+        assert!(policies.set(ReconnectTrigger::Mtu, ReconnectPolicy::Immediate));
+        assert_eq!(policies, ReconnectPolicies::default());
+    }
+}
@@ -1,10 +1,15 @@
 use crate::location::GeoIpLocation;
+#[cfg(not(target_os = "android"))]
+use chrono::{DateTime, Utc};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
+use std::{
+    fmt,
+    net::{Ipv4Addr, Ipv6Addr},
+};
 use talpid_types::{
-    net::TunnelEndpoint,
+    net::{ObfuscationType, TransportProtocol, TunnelEndpoint},
     tunnel::{ActionAfterDisconnect, ErrorState},
 };
 
@@ -27,6 +32,55 @@ impl fmt::Display for TargetState {
     }
 }
 
+/// What caused the daemon to connect the tunnel in the current session.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectTrigger {
+    /// The target state is unsecured.
+    NotConnected,
+    /// `auto_connect` was enabled when the daemon started.
+    AutoConnect,
+    /// The target state was secured when the daemon last stopped, for example because it crashed.
+    PersistedTargetState,
+    /// A client asked the daemon to connect or to resume a pause.
+    ClientCommand,
+    /// An on-demand rule matched the Wi-Fi network.
+    OnDemandRule,
+}
+
+impl fmt::Display for ConnectTrigger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectTrigger::NotConnected => "not connected".fmt(f),
+            ConnectTrigger::AutoConnect => "auto-connect".fmt(f),
+            ConnectTrigger::PersistedTargetState => "persisted target state".fmt(f),
+            ConnectTrigger::ClientCommand => "client command".fmt(f),
+            ConnectTrigger::OnDemandRule => "on-demand rule".fmt(f),
+        }
+    }
+}
+
+/// Why the daemon did not connect at startup even though `auto_connect` is enabled.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoConnectSuppressedReason {
+    /// No device is logged in.
+    NoDevice,
+    /// No device is logged in, but the post-upgrade migration may still create one.
+    MigrationPending,
+}
+
+impl fmt::Display for AutoConnectSuppressedReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoConnectSuppressedReason::NoDevice => "no device is logged in".fmt(f),
+            AutoConnectSuppressedReason::MigrationPending => {
+                "the post-upgrade migration has not completed".fmt(f)
+            }
+        }
+    }
+}
+
 /// Represents the state the client tunnel is in.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -35,6 +89,11 @@ impl fmt::Display for TargetState {
 #[cfg_attr(target_os = "android", jnix(package = "net.mullvad.mullvadvpn.model"))]
 pub enum TunnelState {
     Disconnected,
+    /// The tunnel has been disconnected temporarily and will be reconnected at `resume_at`.
+    #[cfg(not(target_os = "android"))]
+    Paused {
+        resume_at: DateTime<Utc>,
+    },
     Connecting {
         endpoint: TunnelEndpoint,
         location: Option<GeoIpLocation>,
@@ -64,11 +123,205 @@ impl TunnelState {
         }
     }
 
-    /// Returns true if the tunnel state is in the disconnected state.
+    /// Returns true if the tunnel state is in the disconnected or paused state.
     pub fn is_disconnected(&self) -> bool {
         match self {
             TunnelState::Disconnected => true,
+            #[cfg(not(target_os = "android"))]
+            TunnelState::Paused { .. } => true,
             _ => false,
         }
     }
 }
+
+/// Details about the tunnel that is currently connected.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActiveTunnelInfo {
+    /// The endpoint of the tunnel, as reported by the connected state.
+    pub endpoint: TunnelEndpoint,
+    /// Hostname of the exit relay. This is not set for custom tunnel endpoints.
+    pub exit_hostname: Option<String>,
+    /// Hostname of the WireGuard entry relay, if multihop is used.
+    pub entry_hostname: Option<String>,
+    /// Hostname of the OpenVPN bridge, if one is used.
+    pub bridge_hostname: Option<String>,
+    /// Hostname of the relay running the obfuscation server, if obfuscation is used.
+    pub obfuscator_hostname: Option<String>,
+    /// MTU of the WireGuard tunnel, if it has been set explicitly.
+    pub mtu: Option<u16>,
+    /// The mssfix value of the OpenVPN tunnel, if it has been set explicitly.
+    pub mssfix: Option<u16>,
+    /// Data channel cipher of the OpenVPN tunnel.
+    pub openvpn_cipher: Option<String>,
+    /// Type of obfuscation used to reach the WireGuard relay, if any.
+    pub obfuscation_type: Option<ObfuscationType>,
+    /// Transport protocol used by the obfuscation, if any.
+    pub obfuscation_protocol: Option<TransportProtocol>,
+    /// Whether IPv6 is enabled in the tunnel.
+    pub enable_ipv6: bool,
+}
+
+/// Result of probing the network for a captive portal.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptivePortalStatus {
+    /// The probe reached its destination without being intercepted.
+    NoPortal,
+    /// The probe was redirected or required authentication, most likely by a captive portal.
+    PortalDetected,
+    /// The probe failed in a way that does not say whether a portal is present.
+    Indeterminate,
+}
+
+impl fmt::Display for CaptivePortalStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptivePortalStatus::NoPortal => "no portal".fmt(f),
+            CaptivePortalStatus::PortalDetected => "portal detected".fmt(f),
+            CaptivePortalStatus::Indeterminate => "indeterminate".fmt(f),
+        }
+    }
+}
+
+/// Describes what can be reached through the firewall in the current tunnel state.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct BlockedCapabilities {
+    /// Whether devices on the local network can be reached.
+    pub lan: bool,
+    /// Whether DNS queries can be resolved.
+    pub dns: bool,
+    /// Whether hosts on the internet can be reached. The API may be reachable even if this is
+    /// false.
+    pub internet: bool,
+}
+
+impl BlockedCapabilities {
+    /// Derives the capabilities from the tunnel state and the settings that affect the firewall
+    /// policy.
+    pub fn new(state: &TunnelState, allow_lan: bool, block_when_disconnected: bool) -> Self {
+        let blocked = match state {
+            TunnelState::Disconnected => block_when_disconnected,
+            #[cfg(not(target_os = "android"))]
+            TunnelState::Paused { .. } => block_when_disconnected,
+            TunnelState::Disconnecting(ActionAfterDisconnect::Nothing) => block_when_disconnected,
+            TunnelState::Disconnecting(_) | TunnelState::Connecting { .. } => true,
+            TunnelState::Connected { .. } => false,
+            TunnelState::Error(error_state) => error_state.is_blocking(),
+        };
+        // The LAN is only reachable through the firewall while it is active if it is allowed
+        let firewall_active = blocked || state.is_connected();
+        BlockedCapabilities {
+            lan: allow_lan || !firewall_active,
+            dns: !blocked,
+            internet: !blocked,
+        }
+    }
+}
+
+/// Whether traffic can leak while the device boots, given the current settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootLeakStatus {
+    /// Traffic is blocked from boot until the daemon has started. This is only possible on
+    /// Windows, where the firewall filters remain in place while the daemon is not running.
+    pub blocked_before_daemon_start: bool,
+    /// Traffic is blocked from when the daemon starts until the tunnel is up.
+    pub blocked_until_connected: bool,
+}
+
+impl BootLeakStatus {
+    pub fn new(auto_connect: bool, block_when_disconnected: bool) -> Self {
+        let blocked_until_connected = auto_connect || block_when_disconnected;
+        BootLeakStatus {
+            // The daemon blocks traffic before shutting down if auto-connect is enabled
+            blocked_before_daemon_start: cfg!(windows) && blocked_until_connected,
+            blocked_until_connected,
+        }
+    }
+
+    /// Returns whether no traffic can leak during boot.
+    pub fn is_leak_proof(&self) -> bool {
+        self.blocked_before_daemon_start && self.blocked_until_connected
+    }
+}
+
+/// Describes an established tunnel once its exit location has been looked up.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionReadyInfo {
+    pub endpoint: TunnelEndpoint,
+    pub country: String,
+    pub city: Option<String>,
+    /// Hostname of the exit relay, if known.
+    pub hostname: Option<String>,
+    /// Public IPv4 address of the exit. `None` if it could not be looked up.
+    pub ipv4: Option<Ipv4Addr>,
+    /// Public IPv6 address of the exit. `None` if it could not be looked up.
+    pub ipv6: Option<Ipv6Addr>,
+    /// Whether a request was sent through the tunnel successfully after connecting.
+    pub verified: bool,
+}
+
+impl ConnectionReadyInfo {
+    /// Combines the location of the selected relay with the location returned by GeoIP. The
+    /// country and city of the relay are preferred, since they match the relay list. Returns `None`
+    /// if neither location is known. The connection is verified if the GeoIP location is known.
+    pub fn new(
+        endpoint: TunnelEndpoint,
+        relay_location: Option<GeoIpLocation>,
+        fetched_location: Option<GeoIpLocation>,
+    ) -> Option<Self> {
+        let (ipv4, ipv6) = fetched_location
+            .as_ref()
+            .map(|location| (location.ipv4, location.ipv6))
+            .unwrap_or_default();
+        let verified = fetched_location.is_some();
+        let location = relay_location.or(fetched_location)?;
+        Some(ConnectionReadyInfo {
+            endpoint,
+            country: location.country,
+            city: location.city,
+            hostname: location.hostname,
+            ipv4,
+            ipv6,
+            verified,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_blocked_capabilities() {
+        let disconnected = BlockedCapabilities::new(&TunnelState::Disconnected, false, false);
+        assert!(disconnected.lan && disconnected.dns && disconnected.internet);
+
+        let blocked = BlockedCapabilities::new(&TunnelState::Disconnected, true, true);
+        assert_eq!(
+            blocked,
+            BlockedCapabilities {
+                lan: true,
+                dns: false,
+                internet: false,
+            }
+        );
+
+        let reconnecting = BlockedCapabilities::new(
+            &TunnelState::Disconnecting(ActionAfterDisconnect::Reconnect),
+            false,
+            false,
+        );
+        assert!(!reconnecting.lan && !reconnecting.dns && !reconnecting.internet);
+    }
+
+    #[test]
+    fn test_boot_leak_status() {
+        let unprotected = BootLeakStatus::new(false, false);
+        assert!(!unprotected.blocked_before_daemon_start && !unprotected.blocked_until_connected);
+        assert!(!unprotected.is_leak_proof());
+
+        let auto_connect = BootLeakStatus::new(true, false);
+        assert!(auto_connect.blocked_until_connected);
+        assert_eq!(auto_connect.is_leak_proof(), cfg!(windows));
+    }
+}
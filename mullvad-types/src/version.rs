@@ -1,8 +1,12 @@
+use chrono::{DateTime, Utc};
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::cmp::{Ord, Ordering, PartialOrd};
+use std::{
+    cmp::{Ord, Ordering, PartialOrd},
+    time::Duration,
+};
 
 lazy_static::lazy_static! {
     static ref STABLE_REGEX: Regex = Regex::new(r"^(\d{4})\.(\d+)$").unwrap();
@@ -32,10 +36,45 @@ pub struct AppVersionInfo {
     pub latest_beta: AppVersion,
     /// Whether should update to newer version
     pub suggested_upgrade: Option<AppVersion>,
+    /// True if `latest_stable` is being rolled out in stages and has not yet been released to
+    /// all installations.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub latest_stable_rolling_out: bool,
+    /// True if `latest_stable` has been released to this installation. Versions that are still
+    /// being rolled out to other installations are never suggested as an upgrade.
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default = "default_in_rollout_cohort")]
+    pub in_rollout_cohort: bool,
+}
+
+fn default_in_rollout_cohort() -> bool {
+    true
+}
+
+impl AppVersionInfo {
+    /// Returns true if the suggested upgrade has not yet been released to all installations.
+    pub fn suggested_upgrade_rolling_out(&self) -> bool {
+        self.latest_stable_rolling_out
+            && self.suggested_upgrade.as_ref() == Some(&self.latest_stable)
+    }
 }
 
 pub type AppVersion = String;
 
+/// Information about the running daemon.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct DaemonInfo {
+    /// Version of the daemon
+    pub version: AppVersion,
+    /// Git commit that the daemon was built from, if it is known
+    pub commit: Option<String>,
+    /// When the daemon was started
+    pub started_at: DateTime<Utc>,
+    /// How long the daemon has been running
+    pub uptime: Duration,
+}
+
 /// Parses a version string into a type that can be used for comparisons.
 #[derive(Eq, PartialEq, Debug, Clone)]
 pub enum ParsedAppVersion {
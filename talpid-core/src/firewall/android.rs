@@ -1,4 +1,4 @@
-use super::{FirewallArguments, FirewallPolicy};
+use super::{FirewallArguments, FirewallBackendInfo, FirewallPolicy};
 
 /// Stub error type for Firewall errors on Android.
 #[derive(Debug, err_derive::Error)]
@@ -24,4 +24,12 @@ impl Firewall {
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         Ok(())
     }
+
+    /// Traffic is only restricted by the VPN service on Android.
+    pub fn backend_info() -> FirewallBackendInfo {
+        FirewallBackendInfo {
+            name: "none",
+            version: None,
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use super::{FirewallArguments, FirewallPolicy};
+use super::{is_local_dns_address, FirewallArguments, FirewallBackendInfo, FirewallPolicy};
 use crate::{split_tunnel, tunnel};
 use ipnetwork::IpNetwork;
 use lazy_static::lazy_static;
@@ -11,8 +11,16 @@ use nftnl::{
 use std::{
     env,
     ffi::{CStr, CString},
-    io,
+    fs,
+    io::{self, Read, Seek},
     net::{IpAddr, Ipv4Addr},
+    os::unix::fs::OpenOptionsExt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
 };
 use talpid_types::net::{Endpoint, TransportProtocol};
 
@@ -20,6 +28,11 @@ use talpid_types::net::{Endpoint, TransportProtocol};
 const MANGLE_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_MANGLE;
 const PREROUTING_CHAIN_PRIORITY: i32 = libc::NF_IP_PRI_CONNTRACK + 1;
 
+/// Prefix of the kernel log messages about packets dropped by the firewall.
+const DROP_LOG_PREFIX: &str = "mullvad-drop: ";
+/// How often the kernel log is checked for new messages while dropped packets are logged.
+const DROP_LOG_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// Errors that can happen when interacting with Linux netfilter.
@@ -53,6 +66,10 @@ pub enum Error {
         _0
     )]
     LookupIfaceIndexError(String, #[error(source)] crate::linux::IfaceIndexLookupError),
+
+    /// Unable to read dropped packets from the kernel log.
+    #[error(display = "Unable to read dropped packets from the kernel log")]
+    DropLogError(#[error(source)] io::Error),
 }
 
 lazy_static! {
@@ -96,7 +113,12 @@ enum End {
 }
 
 /// The Linux implementation for the firewall and DNS.
-pub struct Firewall(());
+pub struct Firewall {
+    /// The policy that is currently enforced, if any.
+    policy: Option<FirewallPolicy>,
+    /// Set while packets dropped by the firewall are logged.
+    drop_log_forwarder: Option<DropLogForwarder>,
+}
 
 struct FirewallTables {
     main: Table,
@@ -106,11 +128,14 @@ struct FirewallTables {
 
 impl Firewall {
     pub fn from_args(_args: FirewallArguments) -> Result<Self> {
-        Ok(Firewall(()))
+        Self::new()
     }
 
     pub fn new() -> Result<Self> {
-        Ok(Firewall(()))
+        Ok(Firewall {
+            policy: None,
+            drop_log_forwarder: None,
+        })
     }
 
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
@@ -119,13 +144,35 @@ impl Firewall {
             mangle_v4: Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
             mangle_v6: Table::new(&*MANGLE_TABLE_NAME_V6, ProtoFamily::Ipv6),
         };
-        let batch = PolicyBatch::new(&tables).finalize(&policy)?;
+        let batch =
+            PolicyBatch::new(&tables).finalize(&policy, self.drop_log_forwarder.is_some())?;
         Self::send_and_process(&batch)?;
         Self::apply_kernel_config(&policy);
-        self.verify_tables(&[&TABLE_NAME, &MANGLE_TABLE_NAME_V4, &MANGLE_TABLE_NAME_V6])
+        self.verify_tables(&[&TABLE_NAME, &MANGLE_TABLE_NAME_V4, &MANGLE_TABLE_NAME_V6])?;
+        self.policy = Some(policy);
+        Ok(())
+    }
+
+    /// Enables or disables logging of dropped packets. The packets are logged to the kernel log,
+    /// from where they are forwarded to the log of this process. The current policy is reapplied,
+    /// if there is one.
+    pub fn set_drop_logging(&mut self, enabled: bool) -> Result<()> {
+        if enabled == self.drop_log_forwarder.is_some() {
+            return Ok(());
+        }
+        self.drop_log_forwarder = if enabled {
+            Some(DropLogForwarder::start().map_err(Error::DropLogError)?)
+        } else {
+            None
+        };
+        match self.policy.clone() {
+            Some(policy) => self.apply_policy(policy),
+            None => Ok(()),
+        }
     }
 
     pub fn reset_policy(&mut self) -> Result<()> {
+        self.policy = None;
         let tables = [
             Table::new(&*TABLE_NAME, ProtoFamily::Inet),
             Table::new(&*MANGLE_TABLE_NAME_V4, ProtoFamily::Ipv4),
@@ -145,6 +192,16 @@ impl Firewall {
         Ok(())
     }
 
+    /// nftables is part of the kernel, so the kernel release is used as its version.
+    pub fn backend_info() -> FirewallBackendInfo {
+        FirewallBackendInfo {
+            name: "nftables",
+            version: std::fs::read_to_string("/proc/sys/kernel/osrelease")
+                .ok()
+                .map(|release| release.trim().to_owned()),
+        }
+    }
+
     fn apply_kernel_config(policy: &FirewallPolicy) {
         if *DONT_SET_SRC_VALID_MARK {
             log::debug!("Not setting src_valid_mark");
@@ -314,17 +371,46 @@ impl<'a> PolicyBatch<'a> {
 
     /// Finalize the nftnl message batch by adding every firewall rule needed to satisfy the given
     /// policy.
-    pub fn finalize(mut self, policy: &FirewallPolicy) -> Result<FinalizedBatch> {
+    pub fn finalize(mut self, policy: &FirewallPolicy, log_drops: bool) -> Result<FinalizedBatch> {
         self.add_loopback_rules()?;
+        // Allow the endpoints before blocking IPv6, so that IPv6 relays, API addresses and allowed
+        // hosts remain reachable
+        self.add_allow_policy_endpoint_rules(policy);
+        if policy.blocks_ipv6() {
+            self.add_block_ipv6_rules();
+        }
         self.add_split_tunneling_rules(policy)?;
         self.add_dhcp_client_rules();
         self.add_ndp_rules();
         self.add_policy_specific_rules(policy)?;
+        if log_drops {
+            self.add_drop_log_rules();
+        }
 
         Ok(self.batch.finalize())
     }
 
+    /// Logs packets that reach the end of the filter chains, and are about to be dropped by the
+    /// chain policy, to the kernel log.
+    fn add_drop_log_rules(&mut self) {
+        let log_expr =
+            expr::Log::new(None, Some(DROP_LOG_PREFIX)).expect("Invalid drop log prefix");
+        for chain in &[&self.in_chain, &self.out_chain, &self.forward_chain] {
+            let mut rule = Rule::new(chain);
+            rule.add_expr(&log_expr);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+    }
+
     fn add_split_tunneling_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
+        if let FirewallPolicy::Blocked {
+            allow_excluded: false,
+            ..
+        } = policy
+        {
+            return Ok(());
+        }
+
         // Send select DNS requests in the tunnel
         if let FirewallPolicy::Connected {
             tunnel,
@@ -365,7 +451,7 @@ impl<'a> PolicyBatch<'a> {
             rule.add_expr(&nft_expr!(cmp == split_tunnel::NET_CLS_CLASSID));
             rule.add_expr(&nft_expr!(immediate data split_tunnel::MARK));
             rule.add_expr(&nft_expr!(ct mark set));
-            rule.add_expr(&nft_expr!(immediate data crate::linux::TUNNEL_FW_MARK));
+            rule.add_expr(&nft_expr!(immediate data crate::linux::tunnel_fw_mark()));
             rule.add_expr(&nft_expr!(meta mark set));
             self.batch.add(&rule, nftnl::MsgType::Add);
         }
@@ -416,7 +502,7 @@ impl<'a> PolicyBatch<'a> {
             check_not_iface(&mut prerouting_rule, Direction::In, &tunnel.interface)?;
             prerouting_rule.add_expr(&nft_expr!(ct mark));
             prerouting_rule.add_expr(&nft_expr!(cmp == split_tunnel::MARK));
-            prerouting_rule.add_expr(&nft_expr!(immediate data crate::linux::TUNNEL_FW_MARK));
+            prerouting_rule.add_expr(&nft_expr!(immediate data crate::linux::tunnel_fw_mark()));
             prerouting_rule.add_expr(&nft_expr!(meta mark set));
             if *ADD_COUNTERS {
                 prerouting_rule.add_expr(&nft_expr!(counter));
@@ -440,6 +526,18 @@ impl<'a> PolicyBatch<'a> {
         Ok(())
     }
 
+    /// Drops all IPv6 traffic not already accepted by the loopback and endpoint rules.
+    fn add_block_ipv6_rules(&mut self) {
+        let ipv6_proto = libc::NFPROTO_IPV6 as u8;
+        for chain in &[&self.in_chain, &self.out_chain, &self.forward_chain] {
+            let mut rule = Rule::new(chain);
+            rule.add_expr(&nft_expr!(meta nfproto));
+            rule.add_expr(&nft_expr!(cmp == ipv6_proto));
+            add_verdict(&mut rule, &Verdict::Drop);
+            self.batch.add(&rule, nftnl::MsgType::Add);
+        }
+    }
+
     fn add_dhcp_client_rules(&mut self) {
         use self::TransportProtocol::Udp;
         // Outgoing DHCPv4 request
@@ -551,17 +649,42 @@ impl<'a> PolicyBatch<'a> {
         }
     }
 
-    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
-        let allow_lan = match policy {
+    /// Allows traffic to the endpoints that the policy lets the daemon reach outside the tunnel.
+    fn add_allow_policy_endpoint_rules(&mut self, policy: &FirewallPolicy) {
+        match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
-                tunnel,
-                allow_lan,
                 allowed_endpoint,
+                allowed_hosts,
+                ..
             } => {
                 self.add_allow_tunnel_endpoint_rules(peer_endpoint);
                 self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+                for endpoint in allowed_hosts {
+                    self.add_allow_endpoint_rules(endpoint);
+                }
+            }
+            FirewallPolicy::Connected { peer_endpoint, .. } => {
+                self.add_allow_tunnel_endpoint_rules(peer_endpoint);
+            }
+            FirewallPolicy::Blocked {
+                allowed_endpoint,
+                allowed_hosts,
+                ..
+            } => {
+                self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
+                for endpoint in allowed_hosts {
+                    self.add_allow_endpoint_rules(endpoint);
+                }
+            }
+        }
+    }
 
+    fn add_policy_specific_rules(&mut self, policy: &FirewallPolicy) -> Result<()> {
+        let allow_lan = match policy {
+            FirewallPolicy::Connecting {
+                tunnel, allow_lan, ..
+            } => {
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
                 self.add_drop_dns_rule();
@@ -575,12 +698,11 @@ impl<'a> PolicyBatch<'a> {
                 *allow_lan
             }
             FirewallPolicy::Connected {
-                peer_endpoint,
                 tunnel,
                 allow_lan,
                 dns_servers,
+                ..
             } => {
-                self.add_allow_tunnel_endpoint_rules(peer_endpoint);
                 self.add_allow_dns_rules(tunnel, &dns_servers, TransportProtocol::Udp)?;
                 self.add_allow_dns_rules(tunnel, &dns_servers, TransportProtocol::Tcp)?;
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
@@ -592,12 +714,7 @@ impl<'a> PolicyBatch<'a> {
                 }
                 *allow_lan
             }
-            FirewallPolicy::Blocked {
-                allow_lan,
-                allowed_endpoint,
-            } => {
-                self.add_allow_endpoint_rules(&allowed_endpoint.endpoint);
-
+            FirewallPolicy::Blocked { allow_lan, .. } => {
                 // Important to drop DNS before allowing LAN (to stop DNS leaking to the LAN)
                 self.add_drop_dns_rule();
                 *allow_lan
@@ -624,7 +741,7 @@ impl<'a> PolicyBatch<'a> {
     fn add_allow_tunnel_endpoint_rules(&mut self, endpoint: &Endpoint) {
         let mut prerouting_rule = Rule::new(&self.prerouting_chain);
         check_endpoint(&mut prerouting_rule, End::Src, endpoint);
-        prerouting_rule.add_expr(&nft_expr!(immediate data crate::linux::TUNNEL_FW_MARK));
+        prerouting_rule.add_expr(&nft_expr!(immediate data crate::linux::tunnel_fw_mark()));
         prerouting_rule.add_expr(&nft_expr!(meta mark set));
 
         if *ADD_COUNTERS {
@@ -647,7 +764,7 @@ impl<'a> PolicyBatch<'a> {
         let mut out_rule = Rule::new(&self.out_chain);
         check_endpoint(&mut out_rule, End::Dst, endpoint);
         out_rule.add_expr(&nft_expr!(meta mark));
-        out_rule.add_expr(&nft_expr!(cmp == crate::linux::TUNNEL_FW_MARK));
+        out_rule.add_expr(&nft_expr!(cmp == crate::linux::tunnel_fw_mark()));
         add_verdict(&mut out_rule, &Verdict::Accept);
 
         self.batch.add(&out_rule, nftnl::MsgType::Add);
@@ -867,12 +984,6 @@ impl<'a> PolicyBatch<'a> {
     }
 }
 
-fn is_local_dns_address(tunnel: &tunnel::TunnelMetadata, server: &IpAddr) -> bool {
-    super::is_local_address(server)
-        && server != &tunnel.ipv4_gateway
-        && Some(server) != tunnel.ipv6_gateway.map(IpAddr::from).as_ref()
-}
-
 fn allow_tunnel_dns_rule<'a>(
     chain: &'a Chain<'_>,
     iface: &str,
@@ -1029,3 +1140,59 @@ fn add_verdict(rule: &mut Rule<'_>, verdict: &expr::Verdict) {
     }
     rule.add_expr(verdict);
 }
+
+/// Forwards kernel log messages about dropped packets to the log of this process until it is
+/// dropped.
+struct DropLogForwarder {
+    stop: Arc<AtomicBool>,
+}
+
+impl DropLogForwarder {
+    fn start() -> io::Result<Self> {
+        let mut kmsg = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/kmsg")?;
+        // Skip the messages that were logged before now
+        kmsg.seek(io::SeekFrom::End(0))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        thread::spawn(move || Self::forward(kmsg, &thread_stop));
+        Ok(DropLogForwarder { stop })
+    }
+
+    fn forward(mut kmsg: fs::File, stop: &AtomicBool) {
+        // Every read returns a single record, which is never longer than this
+        let mut buffer = vec![0u8; 8192];
+        while !stop.load(Ordering::Acquire) {
+            match kmsg.read(&mut buffer) {
+                Ok(length) => {
+                    // Records look like "<level>,<sequence>,<timestamp>,<flags>;<message>"
+                    let record = String::from_utf8_lossy(&buffer[..length]);
+                    if let Some(packet) = record
+                        .split_once(';')
+                        .and_then(|(_, message)| message.trim_end().strip_prefix(DROP_LOG_PREFIX))
+                    {
+                        log::info!("Dropped packet: {}", packet);
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(DROP_LOG_POLL_INTERVAL)
+                }
+                // Some records were overwritten before they were read
+                Err(error) if error.raw_os_error() == Some(libc::EPIPE) => (),
+                Err(error) => {
+                    log::error!("Failed to read from the kernel log: {}", error);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for DropLogForwarder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Release);
+    }
+}
@@ -1,9 +1,12 @@
-use super::{FirewallArguments, FirewallPolicy};
+use super::{FirewallArguments, FirewallBackendInfo, FirewallPolicy};
 use ipnetwork::IpNetwork;
 use pfctl::{DropAction, FilterRuleAction, Uid};
 use std::{
     env,
+    io::{self, BufRead, BufReader},
     net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    thread,
 };
 use subslice::SubsliceExt;
 use talpid_types::net;
@@ -20,6 +23,12 @@ pub struct Firewall {
     pf: pfctl::PfCtl,
     pf_was_enabled: Option<bool>,
     rule_logging: RuleLogging,
+    /// Logging policy selected through `TALPID_FIREWALL_DEBUG`.
+    default_rule_logging: RuleLogging,
+    /// The policy that is currently enforced, if any.
+    policy: Option<FirewallPolicy>,
+    /// Set while packets dropped by the firewall are logged.
+    drop_log_forwarder: Option<DropLogForwarder>,
 }
 
 impl Firewall {
@@ -43,16 +52,61 @@ impl Firewall {
             pf: pfctl::PfCtl::new()?,
             pf_was_enabled: None,
             rule_logging,
+            default_rule_logging: rule_logging,
+            policy: None,
+            drop_log_forwarder: None,
         })
     }
 
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<()> {
         self.enable()?;
         self.add_anchor()?;
-        self.set_rules(policy)
+        self.set_rules(policy.clone())?;
+        self.policy = Some(policy);
+        Ok(())
+    }
+
+    /// pf is versioned with the OS.
+    pub fn backend_info() -> FirewallBackendInfo {
+        let version = duct::cmd!("/usr/bin/sw_vers", "-productVersion")
+            .stderr_null()
+            .read()
+            .ok()
+            .map(|version| format!("macOS {}", version.trim()));
+        FirewallBackendInfo {
+            name: "pf",
+            version,
+        }
+    }
+
+    /// Enables or disables logging of dropped packets to `pflog0`, in addition to what is logged
+    /// according to `TALPID_FIREWALL_DEBUG`. While enabled, everything logged to `pflog0` is
+    /// forwarded to the log of this process. The current policy is reapplied, if there is one.
+    pub fn set_drop_logging(&mut self, enabled: bool) -> Result<()> {
+        if !enabled {
+            self.drop_log_forwarder = None;
+        } else if self.drop_log_forwarder.is_none() {
+            match DropLogForwarder::start() {
+                Ok(forwarder) => self.drop_log_forwarder = Some(forwarder),
+                Err(error) => log::error!("Failed to read packets logged to pflog0: {}", error),
+            }
+        }
+
+        self.rule_logging = match (self.default_rule_logging, enabled) {
+            (RuleLogging::None, true) => RuleLogging::Drop,
+            (RuleLogging::Pass, true) => RuleLogging::All,
+            (default_rule_logging, _) => default_rule_logging,
+        };
+        log::trace!("Firewall debug log policy: {:?}", self.rule_logging);
+
+        match self.policy.clone() {
+            Some(policy) => self.set_rules(policy),
+            None => Ok(()),
+        }
     }
 
     pub fn reset_policy(&mut self) -> Result<()> {
+        self.policy = None;
         // Implemented this way to not early return on an error.
         // We always want all three methods to run, and then return
         // the first error it encounterd, if any.
@@ -65,6 +119,12 @@ impl Firewall {
         let mut new_filter_rules = vec![];
 
         new_filter_rules.append(&mut self.get_allow_loopback_rules()?);
+        // Allow the endpoints before blocking IPv6, so that IPv6 relays, API addresses and allowed
+        // hosts remain reachable
+        new_filter_rules.append(&mut self.get_allow_policy_endpoint_rules(&policy)?);
+        if policy.blocks_ipv6() {
+            new_filter_rules.push(self.get_block_ipv6_rule()?);
+        }
         new_filter_rules.append(&mut self.get_allow_dhcp_client_rules()?);
         new_filter_rules.append(&mut self.get_allow_ndp_rules()?);
         new_filter_rules.append(&mut self.get_policy_specific_rules(&policy)?);
@@ -109,23 +169,54 @@ impl Firewall {
         Ok(redirect_rules)
     }
 
-    fn get_policy_specific_rules(
-        &mut self,
+    /// Produces rules that allow traffic to the endpoints that the policy lets the daemon reach
+    /// outside the tunnel.
+    fn get_allow_policy_endpoint_rules(
+        &self,
         policy: &FirewallPolicy,
     ) -> Result<Vec<pfctl::FilterRule>> {
+        let mut rules = vec![];
         match policy {
             FirewallPolicy::Connecting {
                 peer_endpoint,
-                tunnel,
-                allow_lan,
                 allowed_endpoint,
+                allowed_hosts,
+                ..
             } => {
-                let mut rules = vec![self.get_allow_relay_rule(*peer_endpoint)?];
+                rules.push(self.get_allow_relay_rule(*peer_endpoint)?);
                 rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
+                for endpoint in allowed_hosts {
+                    rules.push(self.get_allowed_endpoint_rule(*endpoint)?);
+                }
+            }
+            FirewallPolicy::Connected { peer_endpoint, .. } => {
+                rules.push(self.get_allow_relay_rule(*peer_endpoint)?);
+            }
+            FirewallPolicy::Blocked {
+                allowed_endpoint,
+                allowed_hosts,
+                ..
+            } => {
+                rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
+                for endpoint in allowed_hosts {
+                    rules.push(self.get_allowed_endpoint_rule(*endpoint)?);
+                }
+            }
+        }
+        Ok(rules)
+    }
 
+    fn get_policy_specific_rules(
+        &mut self,
+        policy: &FirewallPolicy,
+    ) -> Result<Vec<pfctl::FilterRule>> {
+        match policy {
+            FirewallPolicy::Connecting {
+                tunnel, allow_lan, ..
+            } => {
                 // Important to block DNS after allow relay rule (so the relay can operate
                 // over port 53) but before allow LAN (so DNS does not leak to the LAN)
-                rules.append(&mut self.get_block_dns_rules()?);
+                let mut rules = self.get_block_dns_rules()?;
 
                 if let Some(tunnel) = tunnel {
                     rules.push(self.get_allow_tunnel_rule(&tunnel.interface)?);
@@ -137,10 +228,10 @@ impl Firewall {
                 Ok(rules)
             }
             FirewallPolicy::Connected {
-                peer_endpoint,
                 tunnel,
                 allow_lan,
                 dns_servers,
+                ..
             } => {
                 let mut rules = vec![];
 
@@ -148,8 +239,6 @@ impl Firewall {
                     rules.append(&mut self.get_allow_dns_rules_when_connected(&tunnel, *server)?);
                 }
 
-                rules.push(self.get_allow_relay_rule(*peer_endpoint)?);
-
                 // Important to block DNS *before* we allow the tunnel and allow LAN. So DNS
                 // can't leak to the wrong IPs in the tunnel or on the LAN.
                 rules.append(&mut self.get_block_dns_rules()?);
@@ -162,14 +251,8 @@ impl Firewall {
 
                 Ok(rules)
             }
-            FirewallPolicy::Blocked {
-                allow_lan,
-                allowed_endpoint,
-                ..
-            } => {
+            FirewallPolicy::Blocked { allow_lan, .. } => {
                 let mut rules = Vec::new();
-                rules.push(self.get_allowed_endpoint_rule(allowed_endpoint.endpoint)?);
-
                 if *allow_lan {
                     // Important to block DNS before allow LAN (so DNS does not leak to the LAN)
                     rules.append(&mut self.get_block_dns_rules()?);
@@ -188,14 +271,7 @@ impl Firewall {
     ) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = Vec::with_capacity(4);
 
-        let is_local = super::is_local_address(&server)
-            && server != tunnel.ipv4_gateway
-            && !tunnel
-                .ipv6_gateway
-                .map(|ref gateway| &server == gateway)
-                .unwrap_or(false);
-
-        if is_local {
+        if super::is_local_dns_address(tunnel, &server) {
             // Block requests on the tunnel interface
             let block_tunnel_tcp = self
                 .create_rule_builder(FilterRuleAction::Drop(DropAction::Return))
@@ -338,6 +414,15 @@ impl Firewall {
         Ok(vec![lo0_rule])
     }
 
+    /// Produces a rule that drops all IPv6 traffic not already passed by the loopback rule.
+    fn get_block_ipv6_rule(&self) -> Result<pfctl::FilterRule> {
+        Ok(self
+            .create_rule_builder(FilterRuleAction::Drop(DropAction::Drop))
+            .quick(true)
+            .af(pfctl::AddrFamily::Ipv6)
+            .build()?)
+    }
+
     fn get_allow_lan_rules(&self) -> Result<Vec<pfctl::FilterRule>> {
         let mut rules = vec![];
         for net in &*super::ALLOWED_LAN_NETS {
@@ -636,3 +721,36 @@ enum RuleLogging {
     Drop,
     All,
 }
+
+/// Forwards the packets logged to `pflog0` to the log of this process until it is dropped.
+struct DropLogForwarder {
+    tcpdump: Arc<duct::ReaderHandle>,
+}
+
+impl DropLogForwarder {
+    fn start() -> io::Result<Self> {
+        let tcpdump = Arc::new(
+            duct::cmd!("/usr/sbin/tcpdump", "-n", "-e", "-l", "-i", "pflog0")
+                .stderr_null()
+                .reader()?,
+        );
+        let reader = tcpdump.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(&*reader).lines() {
+                match line {
+                    Ok(line) => log::info!("Firewall log: {}", line),
+                    Err(_) => break,
+                }
+            }
+        });
+        Ok(DropLogForwarder { tcpdump })
+    }
+}
+
+impl Drop for DropLogForwarder {
+    fn drop(&mut self) {
+        if let Err(error) = self.tcpdump.kill() {
+            log::error!("Failed to stop reading from pflog0: {}", error);
+        }
+    }
+}
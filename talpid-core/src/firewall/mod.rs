@@ -1,11 +1,8 @@
-#[cfg(unix)]
 use ipnetwork::{IpNetwork, Ipv4Network, Ipv6Network};
-#[cfg(unix)]
 use lazy_static::lazy_static;
 use std::fmt;
 #[cfg(not(target_os = "android"))]
 use std::net::IpAddr;
-#[cfg(unix)]
 use std::net::{Ipv4Addr, Ipv6Addr};
 #[cfg(windows)]
 use std::path::PathBuf;
@@ -29,7 +26,6 @@ mod imp;
 
 pub use self::imp::Error;
 
-#[cfg(unix)]
 lazy_static! {
     /// When "allow local network" is enabled the app will allow traffic to and from these networks.
     pub(crate) static ref ALLOWED_LAN_NETS: [IpNetwork; 6] = [
@@ -83,7 +79,7 @@ const DHCPV6_CLIENT_PORT: u16 = 546;
 #[cfg(all(unix, not(target_os = "android")))]
 const ROOT_UID: u32 = 0;
 
-#[cfg(all(unix, not(target_os = "android")))]
+#[cfg(not(target_os = "android"))]
 /// Returns whether an address belongs to a private subnet.
 pub fn is_local_address(address: &IpAddr) -> bool {
     let address = address.clone();
@@ -93,6 +89,18 @@ pub fn is_local_address(address: &IpAddr) -> bool {
         .any(|net| net.contains(address))
 }
 
+#[cfg(not(target_os = "android"))]
+/// Returns whether a DNS server is reached outside the tunnel. Servers on private subnets are,
+/// unless they are the tunnel gateway.
+pub(crate) fn is_local_dns_address(
+    tunnel: &crate::tunnel::TunnelMetadata,
+    server: &IpAddr,
+) -> bool {
+    is_local_address(server)
+        && server != &tunnel.ipv4_gateway
+        && Some(server) != tunnel.ipv6_gateway.map(IpAddr::from).as_ref()
+}
+
 /// A enum that describes network security strategy
 ///
 /// # Firewall block/allow specification.
@@ -109,8 +117,13 @@ pub enum FirewallPolicy {
         tunnel: Option<crate::tunnel::TunnelMetadata>,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Flag setting if all IPv6 traffic should be blocked, including inside the tunnel.
+        block_ipv6: bool,
         /// Host that should be reachable while connecting.
         allowed_endpoint: AllowedEndpoint,
+        /// Additional hosts that the daemon should be able to reach while connecting.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        allowed_hosts: Vec<Endpoint>,
         /// A process that is allowed to send packets to the relay.
         #[cfg(windows)]
         relay_client: PathBuf,
@@ -124,6 +137,8 @@ pub enum FirewallPolicy {
         tunnel: crate::tunnel::TunnelMetadata,
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Flag setting if all IPv6 traffic should be blocked, including inside the tunnel.
+        block_ipv6: bool,
         /// Servers that are allowed to respond to DNS requests.
         #[cfg(not(target_os = "android"))]
         dns_servers: Vec<IpAddr>,
@@ -136,8 +151,16 @@ pub enum FirewallPolicy {
     Blocked {
         /// Flag setting if communication with LAN networks should be possible.
         allow_lan: bool,
+        /// Flag setting if all IPv6 traffic should be blocked, including inside the tunnel.
+        block_ipv6: bool,
         /// Host that should be reachable while in the blocked state.
         allowed_endpoint: AllowedEndpoint,
+        /// Additional hosts that the daemon should be able to reach while in the blocked state.
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        allowed_hosts: Vec<Endpoint>,
+        /// Flag setting if processes excluded using split tunneling may still communicate.
+        #[cfg(target_os = "linux")]
+        allow_excluded: bool,
         /// Desination port for DNS traffic redirection. Traffic destined to `127.0.0.1:53` will be
         /// redirected to `127.0.0.1:$dns_redirect_port`.
         #[cfg(target_os = "macos")]
@@ -145,6 +168,144 @@ pub enum FirewallPolicy {
     },
 }
 
+/// Traffic that a [FirewallPolicy] lets through outside the tunnel, or while blocking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FirewallException {
+    /// Traffic to the relay that the tunnel is being established or connected to.
+    Relay(Endpoint),
+    /// Traffic to the endpoint used to reach the API.
+    ApiEndpoint(Endpoint),
+    /// Traffic to a host that must be reachable while connecting or blocking.
+    AllowedHost(Endpoint),
+    /// DNS requests to a server. Requests to other servers are blocked.
+    #[cfg(not(target_os = "android"))]
+    DnsServer(IpAddr),
+    /// Traffic to and from local networks.
+    Lan,
+    /// Traffic of processes that are excluded from the tunnel.
+    ExcludedProcesses,
+    /// The only program that may send traffic to the relay.
+    #[cfg(windows)]
+    RelayClient(PathBuf),
+}
+
+impl fmt::Display for FirewallException {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FirewallException::Relay(endpoint) => write!(f, "relay {}", endpoint),
+            FirewallException::ApiEndpoint(endpoint) => write!(f, "API endpoint {}", endpoint),
+            FirewallException::AllowedHost(endpoint) => write!(f, "allowed host {}", endpoint),
+            #[cfg(not(target_os = "android"))]
+            FirewallException::DnsServer(address) => write!(f, "DNS server {}", address),
+            FirewallException::Lan => write!(f, "local networks"),
+            FirewallException::ExcludedProcesses => write!(f, "excluded processes"),
+            #[cfg(windows)]
+            FirewallException::RelayClient(path) => write!(f, "relay client {}", path.display()),
+        }
+    }
+}
+
+impl FirewallPolicy {
+    /// Returns the traffic that the policy lets through outside the tunnel, or while blocking.
+    /// Traffic through the tunnel interface is not included.
+    pub fn exceptions(&self) -> Vec<FirewallException> {
+        let mut exceptions = vec![];
+        match self {
+            FirewallPolicy::Connecting {
+                peer_endpoint,
+                allow_lan,
+                allowed_endpoint,
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                allowed_hosts,
+                #[cfg(windows)]
+                relay_client,
+                ..
+            } => {
+                exceptions.push(FirewallException::Relay(*peer_endpoint));
+                #[cfg(windows)]
+                exceptions.push(FirewallException::RelayClient(relay_client.clone()));
+                exceptions.push(FirewallException::ApiEndpoint(allowed_endpoint.endpoint));
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                exceptions.extend(
+                    allowed_hosts
+                        .iter()
+                        .cloned()
+                        .map(FirewallException::AllowedHost),
+                );
+                if *allow_lan {
+                    exceptions.push(FirewallException::Lan);
+                }
+                #[cfg(any(target_os = "linux", windows))]
+                exceptions.push(FirewallException::ExcludedProcesses);
+            }
+            FirewallPolicy::Connected {
+                peer_endpoint,
+                #[cfg(not(target_os = "android"))]
+                tunnel,
+                allow_lan,
+                #[cfg(not(target_os = "android"))]
+                dns_servers,
+                #[cfg(windows)]
+                relay_client,
+                ..
+            } => {
+                exceptions.push(FirewallException::Relay(*peer_endpoint));
+                #[cfg(windows)]
+                exceptions.push(FirewallException::RelayClient(relay_client.clone()));
+                // Other DNS servers are reached through the tunnel
+                #[cfg(not(target_os = "android"))]
+                exceptions.extend(
+                    dns_servers
+                        .iter()
+                        .filter(|server| is_local_dns_address(tunnel, server))
+                        .cloned()
+                        .map(FirewallException::DnsServer),
+                );
+                if *allow_lan {
+                    exceptions.push(FirewallException::Lan);
+                }
+                #[cfg(any(target_os = "linux", windows))]
+                exceptions.push(FirewallException::ExcludedProcesses);
+            }
+            FirewallPolicy::Blocked {
+                allow_lan,
+                allowed_endpoint,
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                allowed_hosts,
+                #[cfg(target_os = "linux")]
+                allow_excluded,
+                ..
+            } => {
+                exceptions.push(FirewallException::ApiEndpoint(allowed_endpoint.endpoint));
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                exceptions.extend(
+                    allowed_hosts
+                        .iter()
+                        .cloned()
+                        .map(FirewallException::AllowedHost),
+                );
+                if *allow_lan {
+                    exceptions.push(FirewallException::Lan);
+                }
+                #[cfg(target_os = "linux")]
+                if *allow_excluded {
+                    exceptions.push(FirewallException::ExcludedProcesses);
+                }
+            }
+        }
+        exceptions
+    }
+
+    /// Returns whether the policy blocks all IPv6 traffic.
+    pub fn blocks_ipv6(&self) -> bool {
+        match self {
+            FirewallPolicy::Connecting { block_ipv6, .. } => *block_ipv6,
+            FirewallPolicy::Connected { block_ipv6, .. } => *block_ipv6,
+            FirewallPolicy::Blocked { block_ipv6, .. } => *block_ipv6,
+        }
+    }
+}
+
 impl fmt::Display for FirewallPolicy {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -220,6 +381,17 @@ impl fmt::Display for FirewallPolicy {
 /// by manipulating the OS firewall and DNS settings.
 pub struct Firewall {
     inner: imp::Firewall,
+    /// The policy that was last applied successfully, or `None` if no policy is enforced.
+    active_policy: Option<FirewallPolicy>,
+}
+
+/// Describes the implementation used to enforce firewall policies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FirewallBackendInfo {
+    /// Name of the implementation, e.g. `nftables`.
+    pub name: &'static str,
+    /// Version of the implementation, if it could be detected.
+    pub version: Option<String>,
 }
 
 /// Arguments required when first initializing the firewall.
@@ -243,6 +415,7 @@ impl Firewall {
     pub fn from_args(args: FirewallArguments) -> Result<Self, Error> {
         Ok(Firewall {
             inner: imp::Firewall::from_args(args)?,
+            active_policy: None,
         })
     }
 
@@ -250,6 +423,7 @@ impl Firewall {
     pub fn new() -> Result<Self, Error> {
         Ok(Firewall {
             inner: imp::Firewall::new()?,
+            active_policy: None,
         })
     }
 
@@ -257,13 +431,42 @@ impl Firewall {
     /// until this method is called again with another policy, or until `reset_policy` is called.
     pub fn apply_policy(&mut self, policy: FirewallPolicy) -> Result<(), Error> {
         log::info!("Applying firewall policy: {}", policy);
-        self.inner.apply_policy(policy)
+        // A policy that fails to apply does not replace the one that is already enforced
+        self.inner.apply_policy(policy.clone())?;
+        self.active_policy = Some(policy);
+        Ok(())
     }
 
     /// Resets/removes any currently enforced `FirewallPolicy`. Returns the system to the same state
     /// it had before any policy was applied through this `Firewall` instance.
     pub fn reset_policy(&mut self) -> Result<(), Error> {
         log::info!("Resetting firewall policy");
+        self.active_policy = None;
         self.inner.reset_policy()
     }
+
+    /// Returns the traffic that the enforced policy lets through outside the tunnel, or while
+    /// blocking. This is empty if no policy is enforced, in which case nothing is blocked.
+    pub fn exceptions(&self) -> Vec<FirewallException> {
+        self.active_policy
+            .as_ref()
+            .map(FirewallPolicy::exceptions)
+            .unwrap_or_default()
+    }
+
+    /// Returns the implementation used on this platform. This may run external commands.
+    pub fn backend_info() -> FirewallBackendInfo {
+        imp::Firewall::backend_info()
+    }
+
+    /// Enables or disables logging of packets dropped by the firewall to the log of this process.
+    /// This takes effect immediately if a policy is being enforced.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn set_drop_logging(&mut self, enabled: bool) -> Result<(), Error> {
+        log::info!(
+            "{} logging of dropped packets",
+            if enabled { "Enabling" } else { "Disabling" }
+        );
+        self.inner.set_drop_logging(enabled)
+    }
 }
@@ -3,7 +3,7 @@ use crate::{logging::windows::log_sink, tunnel::TunnelMetadata};
 use std::{net::IpAddr, path::Path, ptr};
 
 use self::winfw::*;
-use super::{FirewallArguments, FirewallPolicy, InitialFirewallState};
+use super::{FirewallArguments, FirewallBackendInfo, FirewallPolicy, InitialFirewallState};
 use crate::winnet;
 use talpid_types::{
     net::{AllowedEndpoint, Endpoint},
@@ -103,6 +103,7 @@ impl Firewall {
                 allow_lan,
                 allowed_endpoint,
                 relay_client,
+                ..
             } => {
                 let cfg = &WinFwSettings::new(allow_lan);
 
@@ -120,6 +121,7 @@ impl Firewall {
                 allow_lan,
                 dns_servers,
                 relay_client,
+                ..
             } => {
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_connected_state(&peer_endpoint, &cfg, &tunnel, &dns_servers, &relay_client)
@@ -127,6 +129,7 @@ impl Firewall {
             FirewallPolicy::Blocked {
                 allow_lan,
                 allowed_endpoint,
+                ..
             } => {
                 let cfg = &WinFwSettings::new(allow_lan);
                 self.set_blocked_state(
@@ -142,6 +145,16 @@ impl Firewall {
         Ok(())
     }
 
+    /// WFP is versioned with the OS.
+    pub fn backend_info() -> FirewallBackendInfo {
+        FirewallBackendInfo {
+            name: "WFP",
+            version: talpid_platform_metadata::WindowsVersion::new()
+                .ok()
+                .map(|version| version.windows_version_string()),
+        }
+    }
+
     fn set_connecting_state(
         &mut self,
         endpoint: &Endpoint,
@@ -61,7 +61,7 @@ mod mktemp;
 
 /// Misc utilities for the Linux platform.
 #[cfg(target_os = "linux")]
-mod linux;
+pub mod linux;
 
 /// A pair of functions to monitor and establish connectivity with ICMP
 pub mod ping_monitor;
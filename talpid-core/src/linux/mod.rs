@@ -1,7 +1,9 @@
 use std::{
     ffi::{self, CString},
     fs, io,
+    sync::atomic::{AtomicU32, Ordering},
 };
+use talpid_types::net::LinuxRoutingOptions;
 
 const PROC_SYS_NET_IPV4_CONF_SRC_VALID_MARK: &str = "/proc/sys/net/ipv4/conf/all/src_valid_mark";
 
@@ -28,9 +30,28 @@ pub enum IfaceIndexLookupError {
     InterfaceLookupError(String, #[error(source)] io::Error),
 }
 
-// b"mole" is [ 0x6d, 0x6f 0x6c, 0x65 ]
-pub const TUNNEL_FW_MARK: u32 = 0x6d6f6c65;
-pub const TUNNEL_TABLE_ID: u32 = 0x6d6f6c65;
+pub const DEFAULT_TUNNEL_FW_MARK: u32 = LinuxRoutingOptions::DEFAULT_FWMARK;
+pub const DEFAULT_TUNNEL_TABLE_ID: u32 = LinuxRoutingOptions::DEFAULT_ROUTING_TABLE;
+
+static TUNNEL_FW_MARK: AtomicU32 = AtomicU32::new(DEFAULT_TUNNEL_FW_MARK);
+static TUNNEL_TABLE_ID: AtomicU32 = AtomicU32::new(DEFAULT_TUNNEL_TABLE_ID);
+
+/// Returns the fwmark of traffic that should not be routed through the tunnel.
+pub fn tunnel_fw_mark() -> u32 {
+    TUNNEL_FW_MARK.load(Ordering::Relaxed)
+}
+
+/// Returns the ID of the routing table that routes traffic through the tunnel.
+pub fn tunnel_table_id() -> u32 {
+    TUNNEL_TABLE_ID.load(Ordering::Relaxed)
+}
+
+/// Sets the fwmark and routing table to use. This must only be done while no tunnel is up and the
+/// routing rules have been cleared, since they are not updated.
+pub(crate) fn set_routing_options(options: LinuxRoutingOptions) {
+    TUNNEL_FW_MARK.store(options.fwmark_or_default(), Ordering::Relaxed);
+    TUNNEL_TABLE_ID.store(options.routing_table_or_default(), Ordering::Relaxed);
+}
 
 pub fn set_src_valid_mark_sysctl() -> io::Result<()> {
     fs::write(PROC_SYS_NET_IPV4_CONF_SRC_VALID_MARK, b"1")
@@ -29,7 +29,8 @@ static BASE_ARGUMENTS: &[&[&str]] = &[
     &["--rcvbuf", "1048576"],
     &["--sndbuf", "1048576"],
     &["--fast-io"],
-    &["--data-ciphers-fallback", "AES-256-GCM"],
+    &["--data-ciphers", net::openvpn::DATA_CIPHER],
+    &["--data-ciphers-fallback", net::openvpn::DATA_CIPHER],
     &["--tls-version-min", "1.3"],
     &["--verb", "3"],
     #[cfg(windows)]
@@ -288,7 +289,7 @@ impl OpenVpnCommand {
 
         #[cfg(target_os = "linux")]
         args.extend(
-            ["--mark", &crate::linux::TUNNEL_FW_MARK.to_string()]
+            ["--mark", &crate::linux::tunnel_fw_mark().to_string()]
                 .iter()
                 .map(OsString::from),
         );
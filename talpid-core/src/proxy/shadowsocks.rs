@@ -73,7 +73,7 @@ impl ShadowsocksProxyMonitor {
 
         #[cfg(target_os = "linux")]
         {
-            config.outbound_fwmark = Some(crate::linux::TUNNEL_FW_MARK);
+            config.outbound_fwmark = Some(crate::linux::tunnel_fw_mark());
         }
 
         let srv = local::create(config).await?;
@@ -55,29 +55,37 @@ lazy_static! {
         v6_rule.header.family = AF_INET6 as u8;
         v6_rule
     };
-    static ref NO_FWMARK_RULE_V4: RuleMessage = RuleMessage {
+}
+
+/// Returns the rules that route traffic without the tunnel fwmark through the tunnel table,
+/// followed by the rules that suppress the default route of the main table. The fwmark and table
+/// are read when this is called, since they are configurable.
+fn all_rules() -> [RuleMessage; 4] {
+    rules_for(
+        crate::linux::tunnel_fw_mark(),
+        crate::linux::tunnel_table_id(),
+    )
+}
+
+fn rules_for(fwmark: u32, table_id: u32) -> [RuleMessage; 4] {
+    let no_fwmark_rule_v4 = RuleMessage {
         header: RuleHeader {
             family: AF_INET as u8,
             action: FR_ACT_TO_TBL,
             flags: FIB_RULE_INVERT,
             ..RuleHeader::default()
         },
-        nlas: vec![
-            RuleNla::FwMark(crate::linux::TUNNEL_FW_MARK),
-            RuleNla::Table(crate::linux::TUNNEL_TABLE_ID),
-        ],
+        nlas: vec![RuleNla::FwMark(fwmark), RuleNla::Table(table_id)],
     };
-    static ref NO_FWMARK_RULE_V6: RuleMessage = {
-        let mut v6_rule = NO_FWMARK_RULE_V4.clone();
-        v6_rule.header.family = AF_INET6 as u8;
-        v6_rule
-    };
-    static ref ALL_RULES: [&'static RuleMessage; 4] = [
-        &*NO_FWMARK_RULE_V4,
-        &*NO_FWMARK_RULE_V6,
-        &*SUPPRESS_RULE_V4,
-        &*SUPPRESS_RULE_V6,
-    ];
+    let mut no_fwmark_rule_v6 = no_fwmark_rule_v4.clone();
+    no_fwmark_rule_v6.header.family = AF_INET6 as u8;
+
+    [
+        no_fwmark_rule_v4,
+        no_fwmark_rule_v6,
+        SUPPRESS_RULE_V4.clone(),
+        SUPPRESS_RULE_V6.clone(),
+    ]
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -156,6 +164,14 @@ impl RouteManagerImpl {
         };
 
         monitor.clear_routing_rules().await?;
+        // Rules left behind by a previous instance may use the default fwmark and table, if they
+        // have been configured since.
+        monitor
+            .clear_rules(&rules_for(
+                crate::linux::DEFAULT_TUNNEL_FW_MARK,
+                crate::linux::DEFAULT_TUNNEL_TABLE_ID,
+            ))
+            .await?;
         monitor.add_required_routes(required_routes).await?;
 
         Ok(monitor)
@@ -166,11 +182,11 @@ impl RouteManagerImpl {
 
         self.clear_routing_rules().await?;
 
-        for rule in ALL_RULES
-            .iter()
+        for rule in all_rules()
+            .into_iter()
             .filter(|rule| rule.header.family as u16 == AF_INET || enable_ipv6)
         {
-            let mut req = NetlinkMessage::from(RtnlMessage::NewRule((*rule).clone()));
+            let mut req = NetlinkMessage::from(RtnlMessage::NewRule(rule));
             req.header.flags = NLM_F_REQUEST | NLM_F_ACK | NLM_F_CREATE | NLM_F_REPLACE;
 
             let mut response = self.handle.request(req).map_err(Error::NetlinkError)?;
@@ -185,8 +201,12 @@ impl RouteManagerImpl {
     }
 
     async fn clear_routing_rules(&mut self) -> Result<()> {
+        self.clear_rules(&all_rules()).await
+    }
+
+    async fn clear_rules(&mut self, rules_to_clear: &[RuleMessage]) -> Result<()> {
         let rules = self.get_rules().await?;
-        for rule in &*ALL_RULES {
+        for rule in rules_to_clear {
             let mut matching_rule = None;
 
             // `RTM_DELRULE` is way too picky about which rules are considered the same.
@@ -285,6 +305,18 @@ impl RouteManagerImpl {
         Ok(())
     }
 
+    async fn remove_required_routes(&mut self, routes: HashSet<RequiredRoute>) -> Result<()> {
+        for route in routes {
+            let route = match route.node {
+                NetNode::RealNode(node) => Route::new(node, route.prefix).table(route.table_id),
+            };
+            if self.added_routes.remove(&route) {
+                self.delete_route_if_exists(&route).await?;
+            }
+        }
+        Ok(())
+    }
+
     async fn initialize_link_map(
         handle: &rtnetlink::Handle,
     ) -> Result<BTreeMap<u32, NetworkInterface>> {
@@ -351,6 +383,10 @@ impl RouteManagerImpl {
                 log::debug!("Adding routes: {:?}", routes);
                 let _ = result_tx.send(self.add_required_routes(routes.clone()).await);
             }
+            RouteManagerCommand::RemoveRoutes(routes, result_tx) => {
+                log::debug!("Removing routes: {:?}", routes);
+                let _ = result_tx.send(self.remove_required_routes(routes).await);
+            }
             RouteManagerCommand::CreateRoutingRules(enable_ipv6, result_tx) => {
                 let _ = result_tx.send(self.create_routing_rules(enable_ipv6).await);
             }
@@ -734,7 +770,7 @@ impl RouteManagerImpl {
         if set_mark {
             message
                 .nlas
-                .push(RouteNla::Mark(crate::linux::TUNNEL_FW_MARK));
+                .push(RouteNla::Mark(crate::linux::tunnel_fw_mark()));
         }
         message.header.destination_prefix_length = 8u8 * (octets.len() as u8);
         message.header.flags = RouteFlags::RTM_F_FIB_MATCH;
@@ -101,6 +101,10 @@ impl RouteManagerImpl {
                             let result = self.add_required_routes(routes).await;
                             let _ = result_tx.send(result);
                         },
+                        Some(RouteManagerCommand::RemoveRoutes(routes, result_tx)) => {
+                            let result = self.remove_required_routes(routes).await;
+                            let _ = result_tx.send(result);
+                        },
                         Some(RouteManagerCommand::ClearRoutes) => {
                             self.cleanup_routes().await;
                         },
@@ -167,6 +171,25 @@ impl RouteManagerImpl {
         Ok(())
     }
 
+    async fn remove_required_routes(&mut self, routes: HashSet<RequiredRoute>) -> Result<()> {
+        for route in routes {
+            let is_applied = match route.node {
+                NetNode::DefaultNode => {
+                    self.applied_routes
+                        .retain(|applied_route| applied_route.prefix != route.prefix);
+                    self.default_destinations.remove(&route.prefix)
+                }
+                NetNode::RealNode(node) => {
+                    self.applied_routes.remove(&Route::new(node, route.prefix))
+                }
+            };
+            if is_applied {
+                Self::delete_route(route.prefix).await?;
+            }
+        }
+        Ok(())
+    }
+
     // Retrieves the node that's currently used to reach 0.0.0.0/0
     pub(crate) async fn get_default_node(ip_version: IpVersion) -> Result<Option<Node>> {
         let ip_version_arg = match ip_version {
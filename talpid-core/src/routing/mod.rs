@@ -86,7 +86,7 @@ impl RequiredRoute {
             node: node.into(),
             prefix,
             #[cfg(target_os = "linux")]
-            table_id: u32::from(crate::linux::TUNNEL_TABLE_ID),
+            table_id: crate::linux::tunnel_table_id(),
         }
     }
 
@@ -21,6 +21,9 @@ pub enum Error {
     /// Failure to add routes
     #[error(display = "Failed to add routes")]
     AddRoutesFailed(#[error(source)] winnet::Error),
+    /// Failure to remove routes
+    #[error(display = "Failed to remove routes")]
+    RemoveRoutesFailed,
     /// Failure to clear routes
     #[error(display = "Failed to clear applied routes")]
     ClearRoutesFailed,
@@ -54,11 +57,21 @@ impl RouteManagerHandle {
             .map_err(|_| Error::RouteManagerDown)?;
         response_rx.await.map_err(|_| Error::ManagerChannelDown)?
     }
+
+    /// Removes the given routes, if they were applied by the route manager.
+    pub async fn remove_routes(&self, routes: HashSet<RequiredRoute>) -> Result<()> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.tx
+            .unbounded_send(RouteManagerCommand::RemoveRoutes(routes, response_tx))
+            .map_err(|_| Error::RouteManagerDown)?;
+        response_rx.await.map_err(|_| Error::ManagerChannelDown)?
+    }
 }
 
 #[derive(Debug)]
 pub enum RouteManagerCommand {
     AddRoutes(HashSet<RequiredRoute>, oneshot::Sender<Result<()>>),
+    RemoveRoutes(HashSet<RequiredRoute>, oneshot::Sender<Result<()>>),
     Shutdown,
 }
 
@@ -92,26 +105,20 @@ impl RouteManager {
         while let Some(command) = manage_rx.next().await {
             match command {
                 RouteManagerCommand::AddRoutes(routes, tx) => {
-                    let routes: Vec<_> = routes
-                        .iter()
-                        .map(|route| {
-                            let destination = winnet::WinNetIpNetwork::from(route.prefix);
-                            match &route.node {
-                                NetNode::DefaultNode => {
-                                    winnet::WinNetRoute::through_default_node(destination)
-                                }
-                                NetNode::RealNode(node) => winnet::WinNetRoute::new(
-                                    winnet::WinNetNode::from(node),
-                                    destination,
-                                ),
-                            }
-                        })
-                        .collect();
-
+                    let routes = Self::to_winnet_routes(&routes);
                     let _ = tx.send(
                         winnet::routing_manager_add_routes(&routes).map_err(Error::AddRoutesFailed),
                     );
                 }
+                RouteManagerCommand::RemoveRoutes(routes, tx) => {
+                    let routes = Self::to_winnet_routes(&routes);
+                    let result = if winnet::routing_manager_delete_routes(&routes) {
+                        Ok(())
+                    } else {
+                        Err(Error::RemoveRoutesFailed)
+                    };
+                    let _ = tx.send(result);
+                }
                 RouteManagerCommand::Shutdown => {
                     break;
                 }
@@ -119,6 +126,21 @@ impl RouteManager {
         }
     }
 
+    fn to_winnet_routes(routes: &HashSet<RequiredRoute>) -> Vec<winnet::WinNetRoute> {
+        routes
+            .iter()
+            .map(|route| {
+                let destination = winnet::WinNetIpNetwork::from(route.prefix);
+                match &route.node {
+                    NetNode::DefaultNode => winnet::WinNetRoute::through_default_node(destination),
+                    NetNode::RealNode(node) => {
+                        winnet::WinNetRoute::new(winnet::WinNetNode::from(node), destination)
+                    }
+                }
+            })
+            .collect()
+    }
+
     /// Stops the routing manager and invalidates the route manager - no new default route callbacks
     /// can be added
     pub fn stop(&mut self) {
@@ -1,7 +1,7 @@
 use std::{
     env, fs,
     io::{self, BufRead, BufReader, Write},
-    path::PathBuf,
+    path::{Component, Path, PathBuf},
 };
 use talpid_types::cgroup::{find_net_cls_mount, SPLIT_TUNNEL_CGROUP_NAME};
 
@@ -43,6 +43,10 @@ pub enum Error {
     #[error(display = "Unable to obtain PIDs from cgroup.procs")]
     ListCGroupPids(#[error(source)] io::Error),
 
+    /// The cgroup path is not a relative path to an existing cgroup.
+    #[error(display = "No net_cls cgroup exists at {}", _0)]
+    InvalidCGroupPath(String),
+
     /// Unable to read /proc/mounts
     #[error(display = "Failed to read /proc/mounts")]
     ListMounts(#[error(source)] io::Error),
@@ -158,6 +162,34 @@ impl PidManager {
         result.map_err(Error::ListCGroupPids)
     }
 
+    /// Exclude all processes in a `net_cls` cgroup from the tunnel, including processes that
+    /// join it later. `cgroup` is the path of the cgroup relative to the root of the hierarchy.
+    pub fn add_cgroup(&self, cgroup: &Path) -> Result<(), Error> {
+        fs::write(
+            self.cgroup_classid_path(cgroup)?,
+            NET_CLS_CLASSID.to_string().as_bytes(),
+        )
+        .map_err(Error::SetCGroupClassId)
+    }
+
+    /// Stop excluding the processes in a `net_cls` cgroup from the tunnel.
+    pub fn remove_cgroup(&self, cgroup: &Path) -> Result<(), Error> {
+        // A class ID of zero means that the traffic is unclassified
+        fs::write(self.cgroup_classid_path(cgroup)?, b"0").map_err(Error::SetCGroupClassId)
+    }
+
+    fn cgroup_classid_path(&self, cgroup: &Path) -> Result<PathBuf, Error> {
+        let is_valid = cgroup.components().next().is_some()
+            && cgroup
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        let cgroup_path = self.net_cls_path.join(cgroup);
+        if !is_valid || !cgroup_path.is_dir() {
+            return Err(Error::InvalidCGroupPath(cgroup.display().to_string()));
+        }
+        Ok(cgroup_path.join("net_cls.classid"))
+    }
+
     /// Removes all PIDs from the Cgroup.
     pub fn clear(&self) -> Result<(), Error> {
         // TODO: reuse file handle
@@ -23,8 +23,10 @@ pub mod wireguard;
 /// A module for low level platform specific tunnel device management.
 pub(crate) mod tun_provider;
 
-const OPENVPN_LOG_FILENAME: &str = "openvpn.log";
-const WIREGUARD_LOG_FILENAME: &str = "wireguard.log";
+/// Name of the file in the log directory that OpenVPN output is written to.
+pub const OPENVPN_LOG_FILENAME: &str = "openvpn.log";
+/// Name of the file in the log directory that WireGuard output is written to.
+pub const WIREGUARD_LOG_FILENAME: &str = "wireguard.log";
 
 /// Results from operations in the tunnel module.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -285,6 +287,26 @@ impl TunnelMonitor {
         }
     }
 
+    /// Returns a handle for reading the traffic statistics of the tunnel. Statistics are only
+    /// available for WireGuard tunnels.
+    pub fn traffic_stats_handle(&self) -> Option<wireguard::TrafficStatsHandle> {
+        match &self.monitor {
+            #[cfg(not(target_os = "android"))]
+            InternalTunnelMonitor::OpenVpn(_) => None,
+            InternalTunnelMonitor::Wireguard(monitor) => Some(monitor.traffic_stats_handle()),
+        }
+    }
+
+    /// Returns a handle for replacing the exit peer of the tunnel. Only WireGuard tunnels have an
+    /// exit peer.
+    pub fn exit_peer_handle(&self) -> Option<wireguard::ExitPeerHandle> {
+        match &self.monitor {
+            #[cfg(not(target_os = "android"))]
+            InternalTunnelMonitor::OpenVpn(_) => None,
+            InternalTunnelMonitor::Wireguard(monitor) => Some(monitor.exit_peer_handle()),
+        }
+    }
+
     /// Consumes the monitor and blocks until the tunnel exits or there is an error.
     pub fn wait(self) -> Result<()> {
         self.monitor.wait().map_err(Error::from)
@@ -6,6 +6,7 @@ use std::{
 use talpid_types::net::{obfuscation::ObfuscatorConfig, wireguard, GenericTunnelOptions};
 
 /// Config required to set up a single WireGuard tunnel
+#[derive(Clone)]
 pub struct Config {
     /// Contains tunnel endpoint specific config
     pub tunnel: wireguard::TunnelConfig,
@@ -113,7 +114,7 @@ impl Config {
             ipv6_gateway,
             mtu,
             #[cfg(target_os = "linux")]
-            fwmark: crate::linux::TUNNEL_FW_MARK,
+            fwmark: crate::linux::tunnel_fw_mark(),
             #[cfg(target_os = "linux")]
             enable_ipv6: generic_options.enable_ipv6,
             #[cfg(target_os = "windows")]
@@ -123,7 +124,6 @@ impl Config {
     }
 
     /// Returns a CString with the appropriate config for WireGuard-go
-    // TODO: Consider outputting both overriding and additive configs
     pub fn to_userspace_format(&self) -> CString {
         // the order of insertion matters, public key entry denotes a new peer entry
         let mut wg_conf = WgConfigBuffer::new();
@@ -136,18 +136,45 @@ impl Config {
 
         wg_conf.add("replace_peers", "true");
 
+        self.add_userspace_peers(&mut wg_conf);
+
+        let bytes = wg_conf.into_config();
+        CString::new(bytes).expect("null bytes inside config")
+    }
+
+    /// Returns a CString that updates the peers of a running WireGuard-go tunnel. The peers in
+    /// this config are added or updated and `removed_peers` are removed. Other peers and the
+    /// interface settings are left as they are.
+    pub fn to_userspace_peer_update(&self, removed_peers: &[wireguard::PublicKey]) -> CString {
+        let mut wg_conf = WgConfigBuffer::new();
+        for public_key in removed_peers {
+            wg_conf
+                .add("public_key", public_key.as_bytes().as_ref())
+                .add("remove", "true");
+        }
+
+        self.add_userspace_peers(&mut wg_conf);
+
+        let bytes = wg_conf.into_config();
+        CString::new(bytes).expect("null bytes inside config")
+    }
+
+    fn add_userspace_peers(&self, wg_conf: &mut WgConfigBuffer) {
         for peer in &self.peers {
             wg_conf
                 .add("public_key", peer.public_key.as_bytes().as_ref())
-                .add("endpoint", peer.endpoint.to_string().as_str())
-                .add("replace_allowed_ips", "true");
+                .add("endpoint", peer.endpoint.to_string().as_str());
+            if let Some(interval) = peer.persistent_keepalive {
+                wg_conf.add(
+                    "persistent_keepalive_interval",
+                    interval.to_string().as_str(),
+                );
+            }
+            wg_conf.add("replace_allowed_ips", "true");
             for addr in &peer.allowed_ips {
                 wg_conf.add("allowed_ip", addr.to_string().as_str());
             }
         }
-
-        let bytes = wg_conf.into_config();
-        CString::new(bytes).expect("null bytes inside config")
     }
 }
 
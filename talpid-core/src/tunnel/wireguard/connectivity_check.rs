@@ -393,6 +393,7 @@ impl ConnState {
 mod test {
     use super::*;
     use crate::tunnel::wireguard::{
+        config::Config,
         stats::{self, Stats},
         TunnelError,
     };
@@ -403,6 +404,7 @@ mod test {
         },
         time::{Duration, Instant},
     };
+    use talpid_types::net::wireguard::PublicKey;
 
     /// Test if a newly created ConnState won't have timed out or consider itself connected
     #[test]
@@ -598,6 +600,14 @@ mod test {
         fn get_tunnel_stats(&self) -> Result<stats::StatsMap, TunnelError> {
             (self.on_get_stats)()
         }
+
+        fn update_peers(
+            &self,
+            _config: &Config,
+            _removed_peers: &[PublicKey],
+        ) -> Result<(), TunnelError> {
+            Ok(())
+        }
     }
 
     fn mock_monitor(
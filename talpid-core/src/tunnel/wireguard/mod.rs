@@ -18,14 +18,21 @@ use std::env;
 #[cfg(windows)]
 use std::io;
 use std::{
+    collections::HashSet,
     convert::Infallible,
     net::IpAddr,
     path::Path,
-    sync::{mpsc as sync_mpsc, Arc, Mutex},
+    sync::{mpsc as sync_mpsc, Arc, Mutex, Weak},
 };
 #[cfg(windows)]
 use talpid_types::BoxedError;
-use talpid_types::{net::obfuscation::ObfuscatorConfig, ErrorExt};
+use talpid_types::{
+    net::{
+        obfuscation::ObfuscatorConfig,
+        wireguard::{PublicKey, TunnelParameters},
+    },
+    ErrorExt,
+};
 use tunnel_obfuscation::{
     create_obfuscator, Error as ObfuscationError, Settings as ObfuscationSettings, Udp2TcpSettings,
 };
@@ -41,6 +48,7 @@ pub(crate) mod wireguard_kernel;
 #[cfg(windows)]
 mod wireguard_nt;
 
+pub use self::stats::Stats as TrafficStats;
 use self::wireguard_go::WgGoTunnel;
 
 type Result<T> = std::result::Result<T, Error>;
@@ -73,6 +81,15 @@ pub enum Error {
     #[error(display = "Connectivity monitor failed")]
     ConnectivityMonitorError(#[error(source)] connectivity_check::Error),
 
+    /// Failed to build the config for a new exit peer
+    #[error(display = "Invalid config for the new exit peer")]
+    ExitPeerConfigError(#[error(source)] config::Error),
+
+    /// The exit peer cannot be replaced without also changing the entry peer, or the tunnel is
+    /// not running
+    #[error(display = "Cannot replace the exit peer of the tunnel")]
+    SetExitPeerError,
+
     /// Failed to set up IP interfaces.
     #[cfg(windows)]
     #[error(display = "Failed to set up IP interfaces")]
@@ -99,6 +116,92 @@ pub struct WireguardMonitor {
     close_msg_receiver: sync_mpsc::Receiver<CloseMsg>,
     pinger_stop_sender: sync_mpsc::Sender<()>,
     _obfuscator: Option<ObfuscatorHandle>,
+    /// The config currently applied to the tunnel
+    config: Arc<Mutex<Config>>,
+    route_manager: RouteManagerHandle,
+}
+
+/// Handle for reading the number of bytes sent and received through a running tunnel.
+#[derive(Clone)]
+pub struct TrafficStatsHandle {
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+}
+
+impl TrafficStatsHandle {
+    /// Returns the traffic summed over all peers, or `None` if the tunnel is no longer running.
+    pub fn get(&self) -> Option<TrafficStats> {
+        let tunnel = self.tunnel.upgrade()?;
+        let tunnel = tunnel.lock().ok()?;
+        let stats = tunnel.as_ref()?.get_tunnel_stats().ok()?;
+        Some(
+            stats
+                .values()
+                .fold(TrafficStats::default(), |total, peer| TrafficStats {
+                    tx_bytes: total.tx_bytes + peer.tx_bytes,
+                    rx_bytes: total.rx_bytes + peer.rx_bytes,
+                }),
+        )
+    }
+}
+
+/// Handle for replacing the exit peer of a running multihop tunnel, leaving the entry peer as it
+/// is.
+#[derive(Clone)]
+pub struct ExitPeerHandle {
+    runtime: tokio::runtime::Handle,
+    tunnel: Weak<Mutex<Option<Box<dyn Tunnel>>>>,
+    config: Arc<Mutex<Config>>,
+    route_manager: RouteManagerHandle,
+}
+
+impl ExitPeerHandle {
+    /// Switches the tunnel to the exit peer in `params`. Fails if `params` does not use the same
+    /// entry peer as the running tunnel.
+    pub fn set_exit_peer(&self, params: &TunnelParameters) -> Result<()> {
+        let new_config = Config::from_parameters(params).map_err(Error::ExitPeerConfigError)?;
+        let mut config = self.config.lock().expect("Tunnel config lock poisoned");
+
+        let mut peers = new_config.peers;
+        if peers.len() != 2
+            || config.peers.len() != 2
+            || peers[0].public_key != config.peers[0].public_key
+        {
+            return Err(Error::SetExitPeerError);
+        }
+        // The running entry peer may be reached through a local obfuscator.
+        peers[0].endpoint = config.peers[0].endpoint;
+        let removed_peers = if peers[1].public_key != config.peers[1].public_key {
+            vec![config.peers[1].public_key.clone()]
+        } else {
+            vec![]
+        };
+        let mut updated_config = config.clone();
+        updated_config.peers = peers;
+
+        let tunnel = self.tunnel.upgrade().ok_or(Error::SetExitPeerError)?;
+        let tunnel = tunnel.lock().expect("Tunnel lock poisoned");
+        let tunnel = tunnel.as_ref().ok_or(Error::SetExitPeerError)?;
+        tunnel
+            .update_peers(&updated_config, &removed_peers)
+            .map_err(Error::TunnelError)?;
+
+        // The new exit endpoint must be routed through the entry peer, and the old one no longer.
+        let iface_name = tunnel.get_interface_name();
+        let new_routes: HashSet<_> =
+            WireguardMonitor::get_pre_tunnel_routes(&iface_name, &updated_config).collect();
+        let old_routes: HashSet<_> = WireguardMonitor::get_pre_tunnel_routes(&iface_name, &config)
+            .filter(|route| !new_routes.contains(route))
+            .collect();
+        self.runtime
+            .block_on(async {
+                self.route_manager.add_routes(new_routes).await?;
+                self.route_manager.remove_routes(old_routes).await
+            })
+            .map_err(Error::SetupRoutingError)?;
+
+        *config = updated_config;
+        Ok(())
+    }
 }
 
 /// Simple wrapper that automatically cancels the future which runs an obfuscator.
@@ -146,7 +249,7 @@ fn maybe_create_obfuscator(
                 let settings = Udp2TcpSettings {
                     peer: *endpoint,
                     #[cfg(target_os = "linux")]
-                    fwmark: Some(crate::linux::TUNNEL_FW_MARK),
+                    fwmark: Some(crate::linux::tunnel_fw_mark()),
                 };
                 let obfuscator = runtime
                     .block_on(create_obfuscator(&ObfuscationSettings::Udp2Tcp(settings)))
@@ -224,6 +327,8 @@ impl WireguardMonitor {
             close_msg_receiver,
             pinger_stop_sender: pinger_tx,
             _obfuscator: obfuscator,
+            config: Arc::new(Mutex::new(config.clone())),
+            route_manager: route_manager.clone(),
         };
 
         let gateway = config.ipv4_gateway;
@@ -421,6 +526,23 @@ impl WireguardMonitor {
         ))
     }
 
+    /// Returns a handle for reading the traffic statistics of the tunnel.
+    pub fn traffic_stats_handle(&self) -> TrafficStatsHandle {
+        TrafficStatsHandle {
+            tunnel: Arc::downgrade(&self.tunnel),
+        }
+    }
+
+    /// Returns a handle for replacing the exit peer of the tunnel.
+    pub fn exit_peer_handle(&self) -> ExitPeerHandle {
+        ExitPeerHandle {
+            runtime: self.runtime.clone(),
+            tunnel: Arc::downgrade(&self.tunnel),
+            config: self.config.clone(),
+            route_manager: self.route_manager.clone(),
+        }
+    }
+
     /// Blocks the current thread until tunnel disconnects
     pub fn wait(mut self) -> Result<()> {
         let wait_result = match self.close_msg_receiver.recv() {
@@ -594,6 +716,13 @@ pub(crate) trait Tunnel: Send {
     fn get_interface_name(&self) -> String;
     fn stop(self: Box<Self>) -> std::result::Result<(), TunnelError>;
     fn get_tunnel_stats(&self) -> std::result::Result<stats::StatsMap, TunnelError>;
+    /// Adds or updates the peers in `config` and removes `removed_peers`. Where possible, peers
+    /// that are not changed keep their sessions.
+    fn update_peers(
+        &self,
+        config: &Config,
+        removed_peers: &[PublicKey],
+    ) -> std::result::Result<(), TunnelError>;
 }
 
 /// Errors to be returned from WireGuard implementations, namely implementers of the Tunnel trait
@@ -630,6 +759,15 @@ pub enum TunnelError {
     #[error(display = "Failed to get config of WireGuard tunnel")]
     GetConfigError,
 
+    /// Error whilst trying to update the config of a running WireGuard tunnel
+    #[error(display = "Failed to set config of WireGuard tunnel")]
+    SetConfigError,
+
+    /// The peers of a running tunnel cannot be updated by this WireGuard implementation
+    #[cfg(windows)]
+    #[error(display = "Updating the peers of a running tunnel is not supported by wireguard-nt")]
+    UpdatePeersUnsupported,
+
     /// Failed to duplicate tunnel file descriptor for wireguard-go
     #[cfg(any(target_os = "linux", target_os = "macos", target_os = "android"))]
     #[error(display = "Failed to duplicate tunnel file descriptor for wireguard-go")]
@@ -16,6 +16,7 @@ use std::{
     os::raw::c_char,
     path::Path,
 };
+use talpid_types::net::wireguard;
 #[cfg(windows)]
 use talpid_types::BoxedError;
 use zeroize::Zeroize;
@@ -351,6 +352,17 @@ impl Tunnel for WgGoTunnel {
         result
     }
 
+    fn update_peers(&self, config: &Config, removed_peers: &[wireguard::PublicKey]) -> Result<()> {
+        let wg_config_str = config.to_userspace_peer_update(removed_peers);
+        let status =
+            unsafe { wgSetConfig(self.handle.unwrap(), wg_config_str.as_ptr() as *const i8) };
+        if status != 0 {
+            log::error!("Failed to update peers of wireguard-go tunnel");
+            return Err(TunnelError::SetConfigError);
+        }
+        Ok(())
+    }
+
     fn stop(mut self: Box<Self>) -> Result<()> {
         self.stop_tunnel()
     }
@@ -422,6 +434,10 @@ extern "C" {
     // Returns the file descriptor of the tunnel IPv4 socket.
     fn wgGetConfig(handle: i32) -> *mut std::os::raw::c_char;
 
+    // Applies the given config to a running tunnel. Only the peers and settings in the config are
+    // changed. Peers are removed only if the config says so.
+    fn wgSetConfig(handle: i32, settings: *const i8) -> i32;
+
     // Frees a pointer allocated by the go runtime - useful to free return value of wgGetConfig
     fn wgFreePtr(ptr: *mut c_void);
 
@@ -15,6 +15,7 @@ use netlink_proto::{
     ConnectionHandle, Error as NetlinkError,
 };
 use std::{ffi::CString, net::IpAddr};
+use talpid_types::net::wireguard;
 use tokio_stream::StreamExt;
 
 mod parsers;
@@ -291,6 +292,21 @@ impl WireguardConnection {
 
     pub async fn set_config(&mut self, interface_index: u32, config: &Config) -> Result<(), Error> {
         let message = DeviceMessage::reset_config(self.message_type, interface_index, config);
+        self.set_device(message).await
+    }
+
+    pub async fn update_peers(
+        &mut self,
+        interface_index: u32,
+        config: &Config,
+        removed_peers: &[wireguard::PublicKey],
+    ) -> Result<(), Error> {
+        let message =
+            DeviceMessage::update_peers(self.message_type, interface_index, config, removed_peers);
+        self.set_device(message).await
+    }
+
+    async fn set_device(&mut self, message: DeviceMessage) -> Result<(), Error> {
         let mut netlink_message = NetlinkMessage::from(message);
         netlink_message.header.flags = NLM_F_REQUEST | NLM_F_ACK;
 
@@ -3,6 +3,7 @@ use super::{
     wg_message::DeviceNla,
     Config, Error, Handle, Tunnel, TunnelError, MULLVAD_INTERFACE_NAME,
 };
+use talpid_types::net::wireguard::PublicKey;
 
 pub struct NetlinkTunnel {
     interface_index: u32,
@@ -109,4 +110,21 @@ impl Tunnel for NetlinkTunnel {
 
         result
     }
+
+    fn update_peers(
+        &self,
+        config: &Config,
+        removed_peers: &[PublicKey],
+    ) -> std::result::Result<(), TunnelError> {
+        let mut wg = self.netlink_connections.wg_handle.clone();
+        let interface_index = self.interface_index;
+        self.tokio_handle.block_on(async move {
+            wg.update_peers(interface_index, config, removed_peers)
+                .await
+                .map_err(|err| {
+                    log::error!("Failed to update WireGuard peers: {}", err);
+                    TunnelError::SetConfigError
+                })
+        })
+    }
 }
@@ -10,6 +10,7 @@ use talpid_dbus::{
         WireguardTunnel,
     },
 };
+use talpid_types::net::wireguard::PublicKey;
 
 #[derive(err_derive::Error, Debug)]
 pub enum Error {
@@ -91,6 +92,15 @@ impl Tunnel for NetworkManagerTunnel {
             Ok(Stats::parse_device_message(&device))
         })
     }
+
+    fn update_peers(
+        &self,
+        _config: &Config,
+        _removed_peers: &[PublicKey],
+    ) -> std::result::Result<(), TunnelError> {
+        log::error!("Updating the peers of a NetworkManager tunnel is not supported");
+        Err(TunnelError::SetConfigError)
+    }
 }
 
 fn convert_config_to_dbus(config: &Config) -> DeviceConfig {
@@ -126,6 +136,12 @@ fn convert_config_to_dbus(config: &Config) -> DeviceConfig {
             "public-key".into(),
             Variant(Box::new(peer.public_key.to_base64())),
         );
+        if let Some(interval) = peer.persistent_keepalive {
+            peer_config.insert(
+                "persistent-keepalive".into(),
+                Variant(Box::new(u32::from(interval))),
+            );
+        }
 
         peer_configs.push(peer_config);
     }
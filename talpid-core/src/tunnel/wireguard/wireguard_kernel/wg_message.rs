@@ -11,6 +11,7 @@ use netlink_packet_utils::{
 };
 use nix::sys::{socket::InetAddr, time::TimeSpec};
 use std::{ffi::CString, io::Write, mem, net::IpAddr};
+use talpid_types::net::wireguard;
 
 /// WireGuard netlink constants
 mod constants {
@@ -76,23 +77,16 @@ pub struct DeviceMessage {
 
 impl DeviceMessage {
     pub fn reset_config(message_type: u16, interface_index: u32, config: &Config) -> DeviceMessage {
-        let mut peers = vec![];
-
-        for peer in config.peers.iter() {
-            let peer_endpoint = InetAddr::from_std(&peer.endpoint);
-            let allowed_ips = peer.allowed_ips.iter().map(From::from).collect();
-            peers.push(PeerMessage(vec![
-                PeerNla::PublicKey(*peer.public_key.as_bytes()),
-                PeerNla::Endpoint(peer_endpoint),
-                PeerNla::AllowedIps(allowed_ips),
-                PeerNla::Flags(WGPEER_F_REPLACE_ALLOWEDIPS),
-            ]));
-        }
+        let peers = config
+            .peers
+            .iter()
+            .map(PeerMessage::from_peer_config)
+            .collect();
 
         let nlas = vec![
             DeviceNla::IfIndex(interface_index),
             DeviceNla::ListenPort(0),
-            DeviceNla::Fwmark(crate::linux::TUNNEL_FW_MARK),
+            DeviceNla::Fwmark(crate::linux::tunnel_fw_mark()),
             DeviceNla::PrivateKey(config.tunnel.private_key.to_bytes()),
             DeviceNla::Flags(WGDEVICE_F_REPLACE_PEERS),
             DeviceNla::Peers(peers),
@@ -105,6 +99,34 @@ impl DeviceMessage {
         }
     }
 
+    /// Adds or updates the peers in `config` and removes `removed_peers`, without touching the
+    /// other peers or the device itself.
+    pub fn update_peers(
+        message_type: u16,
+        interface_index: u32,
+        config: &Config,
+        removed_peers: &[wireguard::PublicKey],
+    ) -> DeviceMessage {
+        let peers = removed_peers
+            .iter()
+            .map(|public_key| {
+                PeerMessage(vec![
+                    PeerNla::PublicKey(*public_key.as_bytes()),
+                    PeerNla::Flags(WGPEER_F_REMOVE_ME),
+                ])
+            })
+            .chain(config.peers.iter().map(PeerMessage::from_peer_config))
+            .collect();
+
+        let nlas = vec![DeviceNla::IfIndex(interface_index), DeviceNla::Peers(peers)];
+
+        Self {
+            nlas,
+            message_type,
+            command: WG_CMD_SET_DEVICE,
+        }
+    }
+
     pub fn get_by_name(message_type: u16, name: String) -> Result<Self, Error> {
         let c_name = CString::new(name).map_err(|_| Error::InterfaceNameError)?;
         if c_name.as_bytes_with_nul().len() > libc::IFNAMSIZ {
@@ -290,6 +312,21 @@ impl<'a, T: AsRef<[u8]> + 'a + ?Sized + core::fmt::Debug> Parseable<NlaBuffer<&'
 pub struct PeerMessage(pub Vec<PeerNla>);
 
 impl PeerMessage {
+    fn from_peer_config(peer: &wireguard::PeerConfig) -> Self {
+        let peer_endpoint = InetAddr::from_std(&peer.endpoint);
+        let allowed_ips = peer.allowed_ips.iter().map(From::from).collect();
+        let mut peer_nlas = vec![
+            PeerNla::PublicKey(*peer.public_key.as_bytes()),
+            PeerNla::Endpoint(peer_endpoint),
+            PeerNla::AllowedIps(allowed_ips),
+            PeerNla::Flags(WGPEER_F_REPLACE_ALLOWEDIPS),
+        ];
+        if let Some(interval) = peer.persistent_keepalive {
+            peer_nlas.push(PeerNla::PersistentKeepaliveInterval(interval));
+        }
+        Self(peer_nlas)
+    }
+
     fn parse(payload: &[u8]) -> Result<Self, DecodeError> {
         let mut nlas = vec![];
 
@@ -901,4 +938,45 @@ mod test {
         assert_eq!(message, deserialized_device);
         assert_eq!(payload_buffer, expected_payload);
     }
+
+    #[test]
+    fn update_peers_message() {
+        let removed_peer = wireguard::PrivateKey::new_from_random().public_key();
+        let exit_peer = wireguard::PeerConfig {
+            public_key: wireguard::PrivateKey::new_from_random().public_key(),
+            allowed_ips: vec!["0.0.0.0/0".parse().unwrap()],
+            endpoint: "192.168.40.2:9797".parse().unwrap(),
+            persistent_keepalive: None,
+        };
+        let config = Config {
+            tunnel: wireguard::TunnelConfig {
+                private_key: wireguard::PrivateKey::new_from_random(),
+                addresses: vec![Ipv4Addr::new(10, 64, 0, 2).into()],
+            },
+            peers: vec![exit_peer.clone()],
+            ipv4_gateway: Ipv4Addr::new(10, 64, 0, 1),
+            ipv6_gateway: None,
+            mtu: 1380,
+            fwmark: 0,
+            enable_ipv6: false,
+            obfuscator_config: None,
+        };
+
+        let message = DeviceMessage::update_peers(0, 320, &config, &[removed_peer.clone()]);
+
+        // The device itself must not be reset.
+        assert_eq!(
+            message.nlas,
+            vec![
+                DeviceNla::IfIndex(320),
+                DeviceNla::Peers(vec![
+                    PeerMessage(vec![
+                        PeerNla::PublicKey(*removed_peer.as_bytes()),
+                        PeerNla::Flags(WGPEER_F_REMOVE_ME),
+                    ]),
+                    PeerMessage::from_peer_config(&exit_peer),
+                ]),
+            ]
+        );
+    }
 }
@@ -19,7 +19,7 @@ use std::{
     ptr,
     sync::{Arc, Mutex},
 };
-use talpid_types::{BoxedError, ErrorExt};
+use talpid_types::{net::wireguard, BoxedError, ErrorExt};
 use widestring::{U16CStr, U16CString};
 use winapi::{
     shared::{
@@ -833,12 +833,16 @@ fn serialize_config(config: &Config) -> Result<Vec<MaybeUninit<u8>>> {
     buffer.extend(windows::as_uninit_byte_slice(&header));
 
     for peer in &config.peers {
+        let mut flags = WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT;
+        if peer.persistent_keepalive.is_some() {
+            flags |= WgPeerFlag::HAS_PERSISTENT_KEEPALIVE;
+        }
         let wg_peer = WgPeer {
-            flags: WgPeerFlag::HAS_PUBLIC_KEY | WgPeerFlag::HAS_ENDPOINT,
+            flags,
             reserved: 0,
             public_key: peer.public_key.as_bytes().clone(),
             preshared_key: [0u8; WIREGUARD_KEY_LENGTH],
-            persistent_keepalive: 0,
+            persistent_keepalive: peer.persistent_keepalive.unwrap_or(0),
             endpoint: windows::inet_sockaddr_from_socketaddr(peer.endpoint).into(),
             tx_bytes: 0,
             rx_bytes: 0,
@@ -976,6 +980,16 @@ impl Tunnel for WgNtTunnel {
         self.stop_tunnel();
         Ok(())
     }
+
+    fn update_peers(
+        &self,
+        _config: &Config,
+        _removed_peers: &[wireguard::PublicKey],
+    ) -> std::result::Result<(), super::TunnelError> {
+        // wireguard-nt can only replace the whole config, which resets the sessions of all
+        // peers. Reconnecting is no worse than that.
+        Err(super::TunnelError::UpdatePeersUnsupported)
+    }
 }
 
 #[cfg(test)]
@@ -1006,6 +1020,7 @@ mod tests {
                     public_key: WG_PUBLIC_KEY.clone(),
                     allowed_ips: vec!["1.3.3.0/24".parse().unwrap()],
                     endpoint: "1.2.3.4:1234".parse().unwrap(),
+                    persistent_keepalive: None,
                 }],
                 ipv4_gateway: "0.0.0.0".parse().unwrap(),
                 ipv6_gateway: None,
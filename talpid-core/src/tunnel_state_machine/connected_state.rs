@@ -23,7 +23,7 @@ use talpid_types::{
 #[cfg(windows)]
 use crate::tunnel::TunnelMonitor;
 
-use super::connecting_state::TunnelCloseEvent;
+use super::connecting_state::{ExitPeer, TrafficStats, TunnelCloseEvent};
 
 pub(crate) type TunnelEventsReceiver =
     Fuse<mpsc::UnboundedReceiver<(TunnelEvent, oneshot::Sender<()>)>>;
@@ -34,6 +34,8 @@ pub struct ConnectedStateBootstrap {
     pub tunnel_parameters: TunnelParameters,
     pub tunnel_close_event: TunnelCloseEvent,
     pub tunnel_close_tx: oneshot::Sender<()>,
+    pub traffic_stats: TrafficStats,
+    pub exit_peer: ExitPeer,
 }
 
 /// The tunnel is up and working.
@@ -43,6 +45,8 @@ pub struct ConnectedState {
     tunnel_parameters: TunnelParameters,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    traffic_stats: TrafficStats,
+    exit_peer: ExitPeer,
 }
 
 impl ConnectedState {
@@ -53,6 +57,8 @@ impl ConnectedState {
             tunnel_parameters: bootstrap.tunnel_parameters,
             tunnel_close_event: bootstrap.tunnel_close_event,
             tunnel_close_tx: bootstrap.tunnel_close_tx,
+            traffic_stats: bootstrap.traffic_stats,
+            exit_peer: bootstrap.exit_peer,
         }
     }
 
@@ -110,8 +116,13 @@ impl ConnectedState {
             peer_endpoint: self.tunnel_parameters.get_next_hop_endpoint(),
             tunnel: self.metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            block_ipv6: shared_values.block_ipv6,
             #[cfg(not(target_os = "android"))]
-            dns_servers: self.get_dns_servers(shared_values),
+            dns_servers: self
+                .get_dns_servers(shared_values)
+                .into_iter()
+                .chain(shared_values.dns_probe_servers.keys().cloned())
+                .collect(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(
                 &shared_values.resource_dir,
@@ -215,6 +226,12 @@ impl ConnectedState {
                 let _ = tx.send(());
                 SameState(self.into())
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowHosts(endpoints, tx)) => {
+                shared_values.allowed_hosts = endpoints;
+                let _ = tx.send(());
+                SameState(self.into())
+            }
             Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
                 Ok(true) => {
                     if let Err(error) = self.set_firewall_policy(shared_values) {
@@ -247,9 +264,101 @@ impl ConnectedState {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
+            Some(TunnelCommand::BlockIpv6(block_ipv6)) => {
+                if shared_values.block_ipv6 != block_ipv6 {
+                    shared_values.block_ipv6 = block_ipv6;
+                    if let Err(error) = self.set_firewall_policy(shared_values) {
+                        return self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                        );
+                    }
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::AllowExcludedWhenBlocked(allow_excluded)) => {
+                shared_values.allow_excluded_when_blocked = allow_excluded;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LinuxRoutingOptions(options)) => {
+                shared_values.linux_routing_options = options;
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::AllowDnsProbe(servers, tx)) => {
+                shared_values.allow_dns_probe(servers);
+                let result = self.set_firewall_policy(shared_values);
+                let _ = tx.send(());
+                match result {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::RevokeDnsProbe(servers)) => {
+                shared_values.revoke_dns_probe(servers);
+                match self.set_firewall_policy(shared_values) {
+                    Ok(()) => SameState(self.into()),
+                    Err(error) => self.disconnect(
+                        shared_values,
+                        AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                    ),
+                }
+            }
+            Some(TunnelCommand::ConnectionRetryBackoff(backoff)) => {
+                shared_values.connection_retry_backoff = backoff;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RouteChangeReconnect(route_change_reconnect)) => {
+                shared_values.set_route_change_reconnect(route_change_reconnect);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTrafficStats(tx)) => {
+                let stats = self
+                    .traffic_stats
+                    .lock()
+                    .expect("Traffic stats lock poisoned")
+                    .as_ref()
+                    .and_then(|handle| handle.get());
+                let _ = tx.send(stats);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetDnsServers(tx)) => {
+                let _ = tx.send(self.get_dns_servers(shared_values));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelInterface(tx)) => {
+                let _ = tx.send(Some(self.metadata.interface.clone()));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelParameters(tx)) => {
+                let _ = tx.send(Some(self.tunnel_parameters.clone()));
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetFirewallExceptions(tx)) => {
+                let _ = tx.send(shared_values.firewall.exceptions());
+                SameState(self.into())
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::SetFirewallDropLogging(enabled, result_tx)) => {
+                shared_values.set_firewall_drop_logging(enabled, result_tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RefreshOfflineState(result_tx)) => {
+                shared_values.refresh_offline_state(result_tx);
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
-                if is_offline {
+                if is_offline && !shared_values.disconnect_when_offline() {
+                    log::info!("Keeping the tunnel up even though the device is offline");
+                    SameState(self.into())
+                } else if is_offline {
                     self.disconnect(
                         shared_values,
                         AfterDisconnect::Block(ErrorStateCause::IsOffline),
@@ -261,6 +370,9 @@ impl ConnectedState {
             Some(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            Some(TunnelCommand::SetExitPeer(parameters, result_tx)) => {
+                self.set_exit_peer(*parameters, result_tx, shared_values)
+            }
             Some(TunnelCommand::Disconnect) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Nothing)
             }
@@ -295,6 +407,64 @@ impl ConnectedState {
         }
     }
 
+    fn set_exit_peer(
+        mut self,
+        parameters: TunnelParameters,
+        result_tx: oneshot::Sender<bool>,
+        shared_values: &mut SharedTunnelStateValues,
+    ) -> EventConsequence {
+        // The entry peer is kept, and so is the way of reaching it.
+        let entry = match &self.tunnel_parameters {
+            TunnelParameters::Wireguard(current) if current.connection.exit_peer.is_some() => {
+                Some((
+                    current.connection.peer.endpoint,
+                    current.obfuscation.clone(),
+                ))
+            }
+            _ => None,
+        };
+        let new_parameters = match (entry, parameters) {
+            (Some((endpoint, obfuscation)), TunnelParameters::Wireguard(mut new))
+                if new.connection.exit_peer.is_some() =>
+            {
+                new.connection.peer.endpoint = endpoint;
+                new.obfuscation = obfuscation;
+                new
+            }
+            _ => {
+                let _ = result_tx.send(false);
+                return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+            }
+        };
+
+        let handle = self
+            .exit_peer
+            .lock()
+            .expect("Exit peer handle lock poisoned")
+            .clone();
+        let result = match handle {
+            Some(handle) => handle.set_exit_peer(&new_parameters),
+            None => Err(crate::tunnel::wireguard::Error::SetExitPeerError),
+        };
+        if let Err(error) = result {
+            log::warn!(
+                "{}",
+                error.display_chain_with_msg("Failed to replace the exit peer. Reconnecting")
+            );
+            let _ = result_tx.send(false);
+            return self.disconnect(shared_values, AfterDisconnect::Reconnect(0));
+        }
+
+        log::info!("Replaced the exit peer of the tunnel");
+        let _ = result_tx.send(true);
+        self.tunnel_parameters = TunnelParameters::Wireguard(new_parameters);
+        let tunnel_endpoint = self.tunnel_parameters.get_tunnel_endpoint();
+        EventConsequence::NewState((
+            self.into(),
+            TunnelStateTransition::Connected(tunnel_endpoint),
+        ))
+    }
+
     fn handle_tunnel_close_event(
         self,
         block_reason: Option<ErrorStateCause>,
@@ -6,7 +6,12 @@ use super::{
 use crate::{
     firewall::FirewallPolicy,
     routing::RouteManager,
-    tunnel::{self, tun_provider::TunProvider, TunnelEvent, TunnelMetadata, TunnelMonitor},
+    tunnel::{
+        self,
+        tun_provider::TunProvider,
+        wireguard::{ExitPeerHandle, TrafficStatsHandle},
+        TunnelEvent, TunnelMetadata, TunnelMonitor,
+    },
 };
 use cfg_if::cfg_if;
 use futures::{
@@ -36,6 +41,11 @@ use super::connected_state::TunnelEventsReceiver;
 
 pub(crate) type TunnelCloseEvent = Fuse<oneshot::Receiver<Option<ErrorStateCause>>>;
 
+/// Set by the tunnel monitor thread once the tunnel has been started, if the tunnel provides
+/// traffic statistics.
+pub(crate) type TrafficStats = Arc<Mutex<Option<TrafficStatsHandle>>>;
+pub(crate) type ExitPeer = Arc<Mutex<Option<ExitPeerHandle>>>;
+
 #[cfg(target_os = "android")]
 const MAX_ATTEMPTS_WITH_SAME_TUN: u32 = 5;
 const MIN_TUNNEL_ALIVE_TIME: Duration = Duration::from_millis(1000);
@@ -49,6 +59,8 @@ pub struct ConnectingState {
     tunnel_metadata: Option<TunnelMetadata>,
     tunnel_close_event: TunnelCloseEvent,
     tunnel_close_tx: oneshot::Sender<()>,
+    traffic_stats: TrafficStats,
+    exit_peer: ExitPeer,
     retry_attempt: u32,
 }
 
@@ -67,7 +79,10 @@ impl ConnectingState {
             peer_endpoint,
             tunnel: tunnel_metadata.clone(),
             allow_lan: shared_values.allow_lan,
+            block_ipv6: shared_values.block_ipv6,
             allowed_endpoint: shared_values.allowed_endpoint.clone(),
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_hosts: shared_values.allowed_hosts.clone(),
             #[cfg(windows)]
             relay_client: TunnelMonitor::get_relay_client(&shared_values.resource_dir, &params),
         };
@@ -97,6 +112,7 @@ impl ConnectingState {
         tun_provider: Arc<Mutex<TunProvider>>,
         route_manager: &mut RouteManager,
         retry_attempt: u32,
+        retry_delay: Duration,
     ) -> Self {
         let (event_tx, event_rx) = mpsc::unbounded();
         let on_tunnel_event =
@@ -112,12 +128,34 @@ impl ConnectingState {
         let log_dir = log_dir.clone();
         let resource_dir = resource_dir.to_path_buf();
 
-        let (tunnel_close_tx, tunnel_close_rx) = oneshot::channel();
+        let (tunnel_close_tx, mut tunnel_close_rx) = oneshot::channel();
         let (tunnel_close_event_tx, tunnel_close_event_rx) = oneshot::channel();
 
         let tunnel_parameters = parameters.clone();
+        let traffic_stats = TrafficStats::default();
+        let monitor_traffic_stats = traffic_stats.clone();
+        let exit_peer = ExitPeer::default();
+        let monitor_exit_peer = exit_peer.clone();
 
         tokio::task::spawn_blocking(move || {
+            if !retry_delay.is_zero() {
+                log::debug!("Waiting {:?} before connecting", retry_delay);
+                let closed = runtime.block_on(async {
+                    futures::select! {
+                        _ = tokio::time::sleep(retry_delay).fuse() => false,
+                        _ = &mut tunnel_close_rx => true,
+                    }
+                });
+                if closed {
+                    if tunnel_close_event_tx.send(None).is_err() {
+                        log::warn!(
+                            "Tunnel state machine stopped before receiving tunnel closed event"
+                        );
+                    }
+                    return;
+                }
+            }
+
             let start = Instant::now();
 
             let route_manager_handle = match route_manager_handle {
@@ -151,6 +189,12 @@ impl ConnectingState {
                 tunnel_close_rx,
             ) {
                 Ok(monitor) => {
+                    *monitor_traffic_stats
+                        .lock()
+                        .expect("Traffic stats lock poisoned") = monitor.traffic_stats_handle();
+                    *monitor_exit_peer
+                        .lock()
+                        .expect("Exit peer handle lock poisoned") = monitor.exit_peer_handle();
                     let reason = Self::wait_for_tunnel_monitor(monitor, retry_attempt);
                     log::debug!("Tunnel monitor exited with block reason: {:?}", reason);
                     reason
@@ -209,6 +253,8 @@ impl ConnectingState {
             tunnel_metadata: None,
             tunnel_close_event: tunnel_close_event_rx.fuse(),
             tunnel_close_tx,
+            traffic_stats,
+            exit_peer,
             retry_attempt,
         }
     }
@@ -253,6 +299,8 @@ impl ConnectingState {
             tunnel_parameters: self.tunnel_parameters,
             tunnel_close_event: self.tunnel_close_event,
             tunnel_close_tx: self.tunnel_close_tx,
+            traffic_stats: self.traffic_stats,
+            exit_peer: self.exit_peer,
         }
     }
 
@@ -344,6 +392,25 @@ impl ConnectingState {
                 let _ = tx.send(());
                 SameState(self.into())
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowHosts(endpoints, tx)) => {
+                if shared_values.allowed_hosts != endpoints {
+                    shared_values.allowed_hosts = endpoints;
+                    if let Err(error) = Self::set_firewall_policy(
+                        shared_values,
+                        &self.tunnel_parameters,
+                        &self.tunnel_metadata,
+                    ) {
+                        let _ = tx.send(());
+                        return self.disconnect(
+                            shared_values,
+                            AfterDisconnect::Block(ErrorStateCause::SetFirewallPolicyError(error)),
+                        );
+                    }
+                }
+                let _ = tx.send(());
+                SameState(self.into())
+            }
             Some(TunnelCommand::Dns(servers)) => match shared_values.set_dns_servers(servers) {
                 #[cfg(target_os = "android")]
                 Ok(true) => self.disconnect(shared_values, AfterDisconnect::Reconnect(0)),
@@ -354,9 +421,78 @@ impl ConnectingState {
                 shared_values.block_when_disconnected = block_when_disconnected;
                 SameState(self.into())
             }
+            Some(TunnelCommand::BlockIpv6(block_ipv6)) => {
+                if shared_values.block_ipv6 != block_ipv6 {
+                    shared_values.block_ipv6 = block_ipv6;
+                    self.reset_firewall(shared_values)
+                } else {
+                    SameState(self.into())
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::AllowExcludedWhenBlocked(allow_excluded)) => {
+                shared_values.allow_excluded_when_blocked = allow_excluded;
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LinuxRoutingOptions(options)) => {
+                shared_values.linux_routing_options = options;
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::AllowDnsProbe(servers, tx)) => {
+                shared_values.allow_dns_probe(servers);
+                let _ = tx.send(());
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::RevokeDnsProbe(servers)) => {
+                shared_values.revoke_dns_probe(servers);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::ConnectionRetryBackoff(backoff)) => {
+                shared_values.connection_retry_backoff = backoff;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RouteChangeReconnect(route_change_reconnect)) => {
+                shared_values.set_route_change_reconnect(route_change_reconnect);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTrafficStats(tx)) => {
+                let _ = tx.send(None);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetDnsServers(tx)) => {
+                let _ = tx.send(vec![]);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelInterface(tx)) => {
+                let _ = tx.send(None);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelParameters(tx)) => {
+                let _ = tx.send(None);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetFirewallExceptions(tx)) => {
+                let _ = tx.send(shared_values.firewall.exceptions());
+                SameState(self.into())
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::SetFirewallDropLogging(enabled, result_tx)) => {
+                shared_values.set_firewall_drop_logging(enabled, result_tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RefreshOfflineState(result_tx)) => {
+                shared_values.refresh_offline_state(result_tx);
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
-                if is_offline {
+                if is_offline && !shared_values.disconnect_when_offline() {
+                    log::info!("Keeping the tunnel up even though the device is offline");
+                    SameState(self.into())
+                } else if is_offline {
                     self.disconnect(
                         shared_values,
                         AfterDisconnect::Block(ErrorStateCause::IsOffline),
@@ -368,6 +504,10 @@ impl ConnectingState {
             Some(TunnelCommand::Connect) => {
                 self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
             }
+            Some(TunnelCommand::SetExitPeer(_, result_tx)) => {
+                let _ = result_tx.send(false);
+                self.disconnect(shared_values, AfterDisconnect::Reconnect(0))
+            }
             Some(TunnelCommand::Disconnect) | None => {
                 self.disconnect(shared_values, AfterDisconnect::Nothing)
             }
@@ -528,6 +668,9 @@ impl TunnelState for ConnectingState {
         if shared_values.is_offline {
             return ErrorState::enter(shared_values, ErrorStateCause::IsOffline);
         }
+        // Routing rules from any previous tunnel have been removed at this point
+        #[cfg(target_os = "linux")]
+        crate::linux::set_routing_options(shared_values.linux_routing_options);
         match shared_values
             .tunnel_parameters_generator
             .generate(retry_attempt)
@@ -578,6 +721,7 @@ impl TunnelState for ConnectingState {
                         shared_values.tun_provider.clone(),
                         &mut shared_values.route_manager,
                         retry_attempt,
+                        shared_values.connection_retry_backoff.delay(retry_attempt),
                     );
                     let params = connecting_state.tunnel_parameters.clone();
                     (
@@ -23,7 +23,12 @@ impl DisconnectedState {
         let result = if shared_values.block_when_disconnected {
             let policy = FirewallPolicy::Blocked {
                 allow_lan: shared_values.allow_lan,
+                block_ipv6: shared_values.block_ipv6,
                 allowed_endpoint: shared_values.allowed_endpoint.clone(),
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                allowed_hosts: shared_values.allowed_hosts.clone(),
+                #[cfg(target_os = "linux")]
+                allow_excluded: shared_values.allow_excluded_when_blocked,
                 #[cfg(target_os = "macos")]
                 dns_redirect_port: shared_values.filtering_resolver.listening_port(),
             };
@@ -157,6 +162,15 @@ impl TunnelState for DisconnectedState {
                 let _ = tx.send(());
                 SameState(self.into())
             }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::AllowHosts(endpoints, tx)) => {
+                if shared_values.allowed_hosts != endpoints {
+                    shared_values.allowed_hosts = endpoints;
+                    Self::set_firewall_policy(shared_values, true);
+                }
+                let _ = tx.send(());
+                SameState(self.into())
+            }
             Some(TunnelCommand::Dns(servers)) => {
                 // Same situation as allow LAN above.
                 shared_values
@@ -189,11 +203,87 @@ impl TunnelState for DisconnectedState {
                 }
                 SameState(self.into())
             }
+            Some(TunnelCommand::BlockIpv6(block_ipv6)) => {
+                if shared_values.block_ipv6 != block_ipv6 {
+                    shared_values.block_ipv6 = block_ipv6;
+                    if shared_values.block_when_disconnected {
+                        Self::set_firewall_policy(shared_values, false);
+                    }
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::AllowExcludedWhenBlocked(allow_excluded)) => {
+                if shared_values.allow_excluded_when_blocked != allow_excluded {
+                    shared_values.allow_excluded_when_blocked = allow_excluded;
+                    if shared_values.block_when_disconnected {
+                        Self::set_firewall_policy(shared_values, false);
+                    }
+                }
+                SameState(self.into())
+            }
+            #[cfg(target_os = "linux")]
+            Some(TunnelCommand::LinuxRoutingOptions(options)) => {
+                shared_values.linux_routing_options = options;
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::AllowDnsProbe(servers, tx)) => {
+                shared_values.allow_dns_probe(servers);
+                let _ = tx.send(());
+                SameState(self.into())
+            }
+            #[cfg(not(target_os = "android"))]
+            Some(TunnelCommand::RevokeDnsProbe(servers)) => {
+                shared_values.revoke_dns_probe(servers);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::ConnectionRetryBackoff(backoff)) => {
+                shared_values.connection_retry_backoff = backoff;
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RouteChangeReconnect(route_change_reconnect)) => {
+                shared_values.set_route_change_reconnect(route_change_reconnect);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTrafficStats(tx)) => {
+                let _ = tx.send(None);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetDnsServers(tx)) => {
+                let _ = tx.send(vec![]);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelInterface(tx)) => {
+                let _ = tx.send(None);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetTunnelParameters(tx)) => {
+                let _ = tx.send(None);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::GetFirewallExceptions(tx)) => {
+                let _ = tx.send(shared_values.firewall.exceptions());
+                SameState(self.into())
+            }
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            Some(TunnelCommand::SetFirewallDropLogging(enabled, result_tx)) => {
+                shared_values.set_firewall_drop_logging(enabled, result_tx);
+                SameState(self.into())
+            }
+            Some(TunnelCommand::RefreshOfflineState(result_tx)) => {
+                shared_values.refresh_offline_state(result_tx);
+                SameState(self.into())
+            }
             Some(TunnelCommand::IsOffline(is_offline)) => {
                 shared_values.is_offline = is_offline;
                 SameState(self.into())
             }
             Some(TunnelCommand::Connect) => NewState(ConnectingState::enter(shared_values, 0)),
+            Some(TunnelCommand::SetExitPeer(_, result_tx)) => {
+                let _ = result_tx.send(false);
+                NewState(ConnectingState::enter(shared_values, 0))
+            }
             Some(TunnelCommand::Block(reason)) => {
                 Self::reset_dns(shared_values);
                 NewState(ErrorState::enter(shared_values, reason))
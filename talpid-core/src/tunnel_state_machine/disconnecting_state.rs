@@ -32,6 +32,12 @@ impl DisconnectingState {
                     let _ = tx.send(());
                     AfterDisconnect::Nothing
                 }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::AllowHosts(endpoints, tx)) => {
+                    shared_values.allowed_hosts = endpoints;
+                    let _ = tx.send(());
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::Dns(servers)) => {
                     let _ = shared_values.set_dns_servers(servers);
                     AfterDisconnect::Nothing
@@ -40,11 +46,77 @@ impl DisconnectingState {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Nothing
                 }
+                Some(TunnelCommand::BlockIpv6(block_ipv6)) => {
+                    shared_values.block_ipv6 = block_ipv6;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::AllowExcludedWhenBlocked(allow_excluded)) => {
+                    shared_values.allow_excluded_when_blocked = allow_excluded;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::LinuxRoutingOptions(options)) => {
+                    shared_values.linux_routing_options = options;
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::AllowDnsProbe(servers, tx)) => {
+                    shared_values.allow_dns_probe(servers);
+                    let _ = tx.send(());
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::RevokeDnsProbe(servers)) => {
+                    shared_values.revoke_dns_probe(servers);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::ConnectionRetryBackoff(backoff)) => {
+                    shared_values.connection_retry_backoff = backoff;
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::RouteChangeReconnect(route_change_reconnect)) => {
+                    shared_values.set_route_change_reconnect(route_change_reconnect);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::GetTrafficStats(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::GetDnsServers(tx)) => {
+                    let _ = tx.send(vec![]);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::GetTunnelInterface(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::GetTunnelParameters(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::GetFirewallExceptions(tx)) => {
+                    let _ = tx.send(shared_values.firewall.exceptions());
+                    AfterDisconnect::Nothing
+                }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::SetFirewallDropLogging(enabled, result_tx)) => {
+                    shared_values.set_firewall_drop_logging(enabled, result_tx);
+                    AfterDisconnect::Nothing
+                }
+                Some(TunnelCommand::RefreshOfflineState(result_tx)) => {
+                    shared_values.refresh_offline_state(result_tx);
+                    AfterDisconnect::Nothing
+                }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     AfterDisconnect::Nothing
                 }
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
+                Some(TunnelCommand::SetExitPeer(_, result_tx)) => {
+                    let _ = result_tx.send(false);
+                    AfterDisconnect::Reconnect(0)
+                }
                 Some(TunnelCommand::Disconnect) | None => AfterDisconnect::Nothing,
                 Some(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
                 #[cfg(target_os = "android")]
@@ -68,6 +140,12 @@ impl DisconnectingState {
                     let _ = tx.send(());
                     AfterDisconnect::Block(reason)
                 }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::AllowHosts(endpoints, tx)) => {
+                    shared_values.allowed_hosts = endpoints;
+                    let _ = tx.send(());
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::Dns(servers)) => {
                     let _ = shared_values.set_dns_servers(servers);
                     AfterDisconnect::Block(reason)
@@ -76,6 +154,68 @@ impl DisconnectingState {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Block(reason)
                 }
+                Some(TunnelCommand::BlockIpv6(block_ipv6)) => {
+                    shared_values.block_ipv6 = block_ipv6;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::AllowExcludedWhenBlocked(allow_excluded)) => {
+                    shared_values.allow_excluded_when_blocked = allow_excluded;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::LinuxRoutingOptions(options)) => {
+                    shared_values.linux_routing_options = options;
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::AllowDnsProbe(servers, tx)) => {
+                    shared_values.allow_dns_probe(servers);
+                    let _ = tx.send(());
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::RevokeDnsProbe(servers)) => {
+                    shared_values.revoke_dns_probe(servers);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::ConnectionRetryBackoff(backoff)) => {
+                    shared_values.connection_retry_backoff = backoff;
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::RouteChangeReconnect(route_change_reconnect)) => {
+                    shared_values.set_route_change_reconnect(route_change_reconnect);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::GetTrafficStats(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::GetDnsServers(tx)) => {
+                    let _ = tx.send(vec![]);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::GetTunnelInterface(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::GetTunnelParameters(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::GetFirewallExceptions(tx)) => {
+                    let _ = tx.send(shared_values.firewall.exceptions());
+                    AfterDisconnect::Block(reason)
+                }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::SetFirewallDropLogging(enabled, result_tx)) => {
+                    shared_values.set_firewall_drop_logging(enabled, result_tx);
+                    AfterDisconnect::Block(reason)
+                }
+                Some(TunnelCommand::RefreshOfflineState(result_tx)) => {
+                    shared_values.refresh_offline_state(result_tx);
+                    AfterDisconnect::Block(reason)
+                }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     if !is_offline && reason == ErrorStateCause::IsOffline {
@@ -85,6 +225,10 @@ impl DisconnectingState {
                     }
                 }
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(0),
+                Some(TunnelCommand::SetExitPeer(_, result_tx)) => {
+                    let _ = result_tx.send(false);
+                    AfterDisconnect::Reconnect(0)
+                }
                 Some(TunnelCommand::Disconnect) => AfterDisconnect::Nothing,
                 Some(TunnelCommand::Block(new_reason)) => AfterDisconnect::Block(new_reason),
                 #[cfg(target_os = "android")]
@@ -109,6 +253,12 @@ impl DisconnectingState {
                     let _ = tx.send(());
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::AllowHosts(endpoints, tx)) => {
+                    shared_values.allowed_hosts = endpoints;
+                    let _ = tx.send(());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::Dns(servers)) => {
                     let _ = shared_values.set_dns_servers(servers);
                     AfterDisconnect::Reconnect(retry_attempt)
@@ -117,6 +267,68 @@ impl DisconnectingState {
                     shared_values.block_when_disconnected = block_when_disconnected;
                     AfterDisconnect::Reconnect(retry_attempt)
                 }
+                Some(TunnelCommand::BlockIpv6(block_ipv6)) => {
+                    shared_values.block_ipv6 = block_ipv6;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::AllowExcludedWhenBlocked(allow_excluded)) => {
+                    shared_values.allow_excluded_when_blocked = allow_excluded;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(target_os = "linux")]
+                Some(TunnelCommand::LinuxRoutingOptions(options)) => {
+                    shared_values.linux_routing_options = options;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::AllowDnsProbe(servers, tx)) => {
+                    shared_values.allow_dns_probe(servers);
+                    let _ = tx.send(());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(not(target_os = "android"))]
+                Some(TunnelCommand::RevokeDnsProbe(servers)) => {
+                    shared_values.revoke_dns_probe(servers);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::ConnectionRetryBackoff(backoff)) => {
+                    shared_values.connection_retry_backoff = backoff;
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::RouteChangeReconnect(route_change_reconnect)) => {
+                    shared_values.set_route_change_reconnect(route_change_reconnect);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::GetTrafficStats(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::GetDnsServers(tx)) => {
+                    let _ = tx.send(vec![]);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::GetTunnelInterface(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::GetTunnelParameters(tx)) => {
+                    let _ = tx.send(None);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::GetFirewallExceptions(tx)) => {
+                    let _ = tx.send(shared_values.firewall.exceptions());
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                #[cfg(any(target_os = "linux", target_os = "macos"))]
+                Some(TunnelCommand::SetFirewallDropLogging(enabled, result_tx)) => {
+                    shared_values.set_firewall_drop_logging(enabled, result_tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
+                Some(TunnelCommand::RefreshOfflineState(result_tx)) => {
+                    shared_values.refresh_offline_state(result_tx);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::IsOffline(is_offline)) => {
                     shared_values.is_offline = is_offline;
                     if is_offline {
@@ -126,6 +338,10 @@ impl DisconnectingState {
                     }
                 }
                 Some(TunnelCommand::Connect) => AfterDisconnect::Reconnect(retry_attempt),
+                Some(TunnelCommand::SetExitPeer(_, result_tx)) => {
+                    let _ = result_tx.send(false);
+                    AfterDisconnect::Reconnect(retry_attempt)
+                }
                 Some(TunnelCommand::Disconnect) | None => AfterDisconnect::Nothing,
                 Some(TunnelCommand::Block(reason)) => AfterDisconnect::Block(reason),
                 #[cfg(target_os = "android")]
@@ -15,11 +15,11 @@ use self::{
 use crate::split_tunnel;
 use crate::{
     dns::DnsMonitor,
-    firewall::{Firewall, FirewallArguments, InitialFirewallState},
+    firewall::{Firewall, FirewallArguments, FirewallException, InitialFirewallState},
     mpsc::Sender,
     offline,
     routing::RouteManager,
-    tunnel::{tun_provider::TunProvider, TunnelEvent},
+    tunnel::{tun_provider::TunProvider, wireguard::TrafficStats, TunnelEvent},
 };
 #[cfg(windows)]
 use std::ffi::OsString;
@@ -28,6 +28,8 @@ use futures::{
     channel::{mpsc, oneshot},
     stream, StreamExt,
 };
+#[cfg(not(target_os = "android"))]
+use std::collections::HashMap;
 #[cfg(target_os = "android")]
 use std::os::unix::io::RawFd;
 use std::{
@@ -38,11 +40,18 @@ use std::{
     sync::{Arc, Mutex},
     time::Duration,
 };
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use talpid_types::net::Endpoint;
+#[cfg(target_os = "linux")]
+use talpid_types::net::LinuxRoutingOptions;
 #[cfg(target_os = "android")]
 use talpid_types::{android::AndroidContext, ErrorExt};
 use talpid_types::{
     net::{AllowedEndpoint, TunnelParameters},
-    tunnel::{ErrorStateCause, ParameterGenerationError, TunnelStateTransition},
+    tunnel::{
+        ConnectionRetryBackoff, ErrorStateCause, ParameterGenerationError, RouteChangeReconnect,
+        TunnelStateTransition,
+    },
 };
 
 const TUNNEL_STATE_MACHINE_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
@@ -91,6 +100,8 @@ pub struct InitialTunnelState {
     pub allow_lan: bool,
     /// Block traffic unless connected to the VPN.
     pub block_when_disconnected: bool,
+    /// Block all IPv6 traffic in the firewall.
+    pub block_ipv6: bool,
     /// DNS servers to use. If `None`, the tunnel gateway is used.
     pub dns_servers: Option<Vec<IpAddr>>,
     /// A single endpoint that is allowed to communicate outside the tunnel, i.e.
@@ -98,6 +109,16 @@ pub struct InitialTunnelState {
     pub allowed_endpoint: AllowedEndpoint,
     /// Whether to reset any existing firewall rules when initializing the disconnected state.
     pub reset_firewall: bool,
+    /// Whether processes excluded from the tunnel may communicate in the blocked states.
+    #[cfg(target_os = "linux")]
+    pub allow_excluded_when_blocked: bool,
+    /// Firewall mark and routing table to use.
+    #[cfg(target_os = "linux")]
+    pub linux_routing_options: LinuxRoutingOptions,
+    /// Delay between consecutive connection attempts.
+    pub connection_retry_backoff: ConnectionRetryBackoff,
+    /// How to react when the device goes offline.
+    pub route_change_reconnect: RouteChangeReconnect,
     /// Programs to exclude from the tunnel using the split tunnel driver.
     #[cfg(windows)]
     pub exclude_paths: Vec<OsString>,
@@ -166,14 +187,68 @@ pub enum TunnelCommand {
     /// channel after attempting to set the firewall policy, regardless
     /// of whether it succeeded.
     AllowEndpoint(AllowedEndpoint, oneshot::Sender<()>),
+    /// Endpoints, in addition to the allowed endpoint, that should never be blocked. An empty
+    /// list removes the exceptions. `()` is sent to the channel after attempting to set the
+    /// firewall policy.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    AllowHosts(Vec<Endpoint>, oneshot::Sender<()>),
     /// Set DNS servers to use.
     Dns(Option<Vec<IpAddr>>),
     /// Enable or disable the block_when_disconnected feature.
     BlockWhenDisconnected(bool),
+    /// Enable or disable blocking of all IPv6 traffic in the firewall.
+    BlockIpv6(bool),
+    /// Allow or block traffic from excluded processes in the blocked states.
+    #[cfg(target_os = "linux")]
+    AllowExcludedWhenBlocked(bool),
+    /// Set the firewall mark and routing table. These are applied the next time a tunnel is
+    /// started.
+    #[cfg(target_os = "linux")]
+    LinuxRoutingOptions(LinuxRoutingOptions),
+    /// Set the delay between consecutive connection attempts.
+    ConnectionRetryBackoff(ConnectionRetryBackoff),
+    /// Set how to react when the device goes offline.
+    RouteChangeReconnect(RouteChangeReconnect),
+    /// Allow DNS requests to these servers through the tunnel, in addition to the servers in use,
+    /// so that they can be tested. `()` is sent to the channel after attempting to set the
+    /// firewall policy. Each probe must be followed by a matching [`TunnelCommand::RevokeDnsProbe`].
+    #[cfg(not(target_os = "android"))]
+    AllowDnsProbe(Vec<IpAddr>, oneshot::Sender<()>),
+    /// Revoke an exemption added by [`TunnelCommand::AllowDnsProbe`]. A server remains allowed
+    /// while other probes of it are in flight.
+    #[cfg(not(target_os = "android"))]
+    RevokeDnsProbe(Vec<IpAddr>),
+    /// Request the number of bytes sent and received through the tunnel. `None` is sent unless
+    /// a tunnel that provides statistics is connected.
+    GetTrafficStats(oneshot::Sender<Option<TrafficStats>>),
+    /// Request the DNS servers configured for the tunnel. An empty list is sent unless a tunnel
+    /// is connected.
+    GetDnsServers(oneshot::Sender<Vec<IpAddr>>),
+    /// Request the name of the tunnel interface. `None` is sent unless a tunnel is connected.
+    GetTunnelInterface(oneshot::Sender<Option<String>>),
+    /// Request the parameters that the tunnel was established with. `None` is sent unless a
+    /// tunnel is connected.
+    GetTunnelParameters(oneshot::Sender<Option<TunnelParameters>>),
+    /// Request the traffic that the enforced firewall policy lets through outside the tunnel, or
+    /// while blocking.
+    GetFirewallExceptions(oneshot::Sender<Vec<FirewallException>>),
+    /// Enable or disable logging of packets dropped by the firewall. The result of reapplying
+    /// the firewall policy is sent to the channel.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    SetFirewallDropLogging(bool, oneshot::Sender<Result<(), crate::firewall::Error>>),
+    /// Re-evaluate the connectivity of the device, in case a change was missed. The state is
+    /// updated as if the offline monitor had reported it, and whether the device is offline is
+    /// sent to the channel.
+    RefreshOfflineState(oneshot::Sender<bool>),
     /// Notify the state machine of the connectivity of the device.
     IsOffline(bool),
     /// Open tunnel connection.
     Connect,
+    /// Switch a connected multihop WireGuard tunnel to the exit relay in the given parameters,
+    /// keeping the entry hop up. In any other case, or if the exit cannot be replaced, this
+    /// reconnects like [`TunnelCommand::Connect`]. Whether the exit peer was replaced is sent to
+    /// the channel.
+    SetExitPeer(Box<TunnelParameters>, oneshot::Sender<bool>),
     /// Close tunnel connection.
     Disconnect,
     /// Disconnect any open tunnel and block all network access
@@ -225,6 +300,9 @@ impl TunnelStateMachine {
     ) -> Result<Self, Error> {
         let runtime = tokio::runtime::Handle::current();
 
+        #[cfg(target_os = "linux")]
+        crate::linux::set_routing_options(settings.linux_routing_options);
+
         #[cfg(target_os = "macos")]
         let filtering_resolver = crate::resolver::start_resolver().await?;
 
@@ -260,8 +338,26 @@ impl TunnelStateMachine {
 
         let (offline_tx, mut offline_rx) = mpsc::unbounded();
         let initial_offline_state_tx = offline_state_tx.clone();
+        let route_change_reconnect = Arc::new(Mutex::new(settings.route_change_reconnect));
+        let debounce_settings = route_change_reconnect.clone();
         tokio::spawn(async move {
-            while let Some(offline) = offline_rx.next().await {
+            while let Some(mut offline) = offline_rx.next().await {
+                let debounce = debounce_settings.lock().unwrap().debounce();
+                if offline && !debounce.is_zero() {
+                    // Only report the device as offline if it does not come back online soon
+                    let deadline = tokio::time::Instant::now() + debounce;
+                    while let Ok(next) = tokio::time::timeout_at(deadline, offline_rx.next()).await
+                    {
+                        match next {
+                            Some(next) => offline = next,
+                            None => return,
+                        }
+                    }
+                    if !offline {
+                        log::debug!("Ignoring brief loss of connectivity");
+                        continue;
+                    }
+                }
                 if let Some(tx) = command_tx.upgrade() {
                     let _ = tx.unbounded_send(TunnelCommand::IsOffline(offline));
                 } else {
@@ -271,7 +367,7 @@ impl TunnelStateMachine {
             }
         });
         let mut offline_monitor = offline::spawn_monitor(
-            offline_tx,
+            offline_tx.clone(),
             #[cfg(target_os = "linux")]
             route_manager
                 .handle()
@@ -296,12 +392,24 @@ impl TunnelStateMachine {
             firewall,
             dns_monitor,
             route_manager,
-            _offline_monitor: offline_monitor,
+            offline_monitor,
+            offline_tx,
             allow_lan: settings.allow_lan,
             block_when_disconnected: settings.block_when_disconnected,
+            block_ipv6: settings.block_ipv6,
+            #[cfg(target_os = "linux")]
+            allow_excluded_when_blocked: settings.allow_excluded_when_blocked,
+            #[cfg(target_os = "linux")]
+            linux_routing_options: settings.linux_routing_options,
+            connection_retry_backoff: settings.connection_retry_backoff,
+            route_change_reconnect,
+            #[cfg(not(target_os = "android"))]
+            dns_probe_servers: HashMap::new(),
             is_offline,
             dns_servers: settings.dns_servers,
             allowed_endpoint: settings.allowed_endpoint,
+            #[cfg(any(target_os = "linux", target_os = "macos"))]
+            allowed_hosts: vec![],
             tunnel_parameters_generator: Box::new(tunnel_parameters_generator),
             tun_provider: Arc::new(Mutex::new(tun_provider)),
             log_dir,
@@ -380,17 +488,39 @@ struct SharedTunnelStateValues {
     firewall: Firewall,
     dns_monitor: DnsMonitor,
     route_manager: RouteManager,
-    _offline_monitor: offline::MonitorHandle,
+    offline_monitor: offline::MonitorHandle,
+    /// Reports connectivity changes in the same way as the offline monitor.
+    offline_tx: mpsc::UnboundedSender<bool>,
     /// Should LAN access be allowed outside the tunnel.
     allow_lan: bool,
     /// Should network access be allowed when in the disconnected state.
     block_when_disconnected: bool,
+    /// Should all IPv6 traffic be blocked by the firewall.
+    block_ipv6: bool,
+    /// Should processes excluded from the tunnel be allowed to communicate in the blocked states.
+    #[cfg(target_os = "linux")]
+    allow_excluded_when_blocked: bool,
+    /// Firewall mark and routing table to apply when the next tunnel is started.
+    #[cfg(target_os = "linux")]
+    linux_routing_options: LinuxRoutingOptions,
+    /// Delay between consecutive connection attempts.
+    connection_retry_backoff: ConnectionRetryBackoff,
+    /// How to react when the device goes offline. Shared with the task that forwards connectivity
+    /// changes, which applies the debounce interval.
+    route_change_reconnect: Arc<Mutex<RouteChangeReconnect>>,
+    /// DNS servers that are being tested, and may be queried through the tunnel, along with the
+    /// number of probes of each server that are in flight.
+    #[cfg(not(target_os = "android"))]
+    dns_probe_servers: HashMap<IpAddr, usize>,
     /// True when the computer is known to be offline.
     is_offline: bool,
     /// DNS servers to use (overriding default).
     dns_servers: Option<Vec<IpAddr>>,
     /// Endpoint that should not be blocked by the firewall.
     allowed_endpoint: AllowedEndpoint,
+    /// Additional endpoints that should not be blocked by the firewall.
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    allowed_hosts: Vec<Endpoint>,
     /// The generator of new `TunnelParameter`s
     tunnel_parameters_generator: Box<dyn TunnelParametersGenerator>,
     /// The provider of tunnel devices.
@@ -414,6 +544,67 @@ struct SharedTunnelStateValues {
 }
 
 impl SharedTunnelStateValues {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub fn set_firewall_drop_logging(
+        &mut self,
+        enabled: bool,
+        result_tx: oneshot::Sender<Result<(), crate::firewall::Error>>,
+    ) {
+        use talpid_types::ErrorExt;
+
+        let result = self.firewall.set_drop_logging(enabled);
+        if let Err(error) = &result {
+            log::error!(
+                "{}",
+                error.display_chain_with_msg("Failed to set firewall drop logging")
+            );
+        }
+        let _ = result_tx.send(result);
+    }
+
+    /// Re-evaluates whether the device is offline and reports any change in the same way as the
+    /// offline monitor. The result is sent to `result_tx`.
+    pub fn refresh_offline_state(&mut self, result_tx: oneshot::Sender<bool>) {
+        let is_offline = self.runtime.block_on(self.offline_monitor.is_offline());
+        if is_offline != self.is_offline {
+            log::info!(
+                "Connectivity changed without being reported. Offline: {}",
+                is_offline
+            );
+            let _ = self.offline_tx.unbounded_send(is_offline);
+        }
+        let _ = result_tx.send(is_offline);
+    }
+
+    #[cfg(not(target_os = "android"))]
+    pub fn allow_dns_probe(&mut self, servers: Vec<IpAddr>) {
+        for server in servers {
+            *self.dns_probe_servers.entry(server).or_insert(0) += 1;
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    pub fn revoke_dns_probe(&mut self, servers: Vec<IpAddr>) {
+        for server in servers {
+            if let Some(count) = self.dns_probe_servers.get_mut(&server) {
+                *count -= 1;
+                if *count == 0 {
+                    self.dns_probe_servers.remove(&server);
+                }
+            }
+        }
+    }
+
+    pub fn set_route_change_reconnect(&mut self, route_change_reconnect: RouteChangeReconnect) {
+        *self.route_change_reconnect.lock().unwrap() = route_change_reconnect;
+    }
+
+    /// Returns whether a connecting or connected tunnel should be disconnected when the device
+    /// goes offline.
+    pub fn disconnect_when_offline(&self) -> bool {
+        self.route_change_reconnect.lock().unwrap().enabled
+    }
+
     pub fn set_allow_lan(&mut self, allow_lan: bool) -> Result<(), ErrorStateCause> {
         if self.allow_lan != allow_lan {
             self.allow_lan = allow_lan;
@@ -0,0 +1,71 @@
+use dbus::{arg, message::MatchRule};
+use std::time::Duration;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(err_derive::Error, Debug)]
+#[error(no_from)]
+pub enum Error {
+    #[error(display = "Failed to initialize a connection to D-Bus")]
+    ConnectDBus(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to subscribe to sleep signals from logind")]
+    AddMatchError(#[error(source)] dbus::Error),
+
+    #[error(display = "Failed to unsubscribe from sleep signals from logind")]
+    RemoveMatchError(#[error(source)] dbus::Error),
+}
+
+const LOGIND_MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const LOGIND_MANAGER_PATH: &str = "/org/freedesktop/login1";
+const PREPARE_FOR_SLEEP_SIGNAL: &str = "PrepareForSleep";
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Blocks and invokes `callback` every time logind announces that the machine is about to
+/// suspend (`true`) or has just resumed (`false`). Returns once `should_continue` returns false.
+pub fn watch_sleep<F: FnMut(bool) + Send + 'static, S: Fn() -> bool + Clone + Send + 'static>(
+    mut callback: F,
+    should_continue: S,
+) -> Result<()> {
+    let connection = crate::get_connection().map_err(Error::ConnectDBus)?;
+
+    let mut match_rule = MatchRule::new_signal(LOGIND_MANAGER_INTERFACE, PREPARE_FOR_SLEEP_SIGNAL);
+    match_rule.path = Some(LOGIND_MANAGER_PATH.into());
+    let should_continue_outer = should_continue.clone();
+    let sleep_matcher = connection
+        .add_match(
+            match_rule,
+            move |signal: PrepareForSleep, _connection, _message| {
+                callback(signal.start);
+                should_continue()
+            },
+        )
+        .map_err(Error::AddMatchError)?;
+
+    while should_continue_outer() {
+        if let Err(err) = connection.process(RPC_TIMEOUT) {
+            log::error!("Failed to process DBus messages: {}", err);
+        }
+    }
+
+    connection
+        .remove_match(sleep_matcher)
+        .map_err(Error::RemoveMatchError)
+}
+
+#[derive(Debug)]
+struct PrepareForSleep {
+    start: bool,
+}
+
+impl arg::ReadAll for PrepareForSleep {
+    fn read(i: &mut arg::Iter<'_>) -> std::result::Result<Self, arg::TypeMismatchError> {
+        Ok(PrepareForSleep { start: i.read()? })
+    }
+}
+
+impl dbus::message::SignalArgs for PrepareForSleep {
+    const NAME: &'static str = PREPARE_FOR_SLEEP_SIGNAL;
+    const INTERFACE: &'static str = LOGIND_MANAGER_INTERFACE;
+}
@@ -46,6 +46,10 @@ const NM_CONNECTION_ACTIVE: &str = "org.freedesktop.NetworkManager.Connection.Ac
 
 const NM_ADD_CONNECTION_VOLATILE: u32 = 0x2;
 
+const NM_ACCESS_POINT: &str = "org.freedesktop.NetworkManager.AccessPoint";
+const NM_WIRELESS_CONNECTION_TYPE: &str = "802-11-wireless";
+const NM_STATE_CHANGED: &str = "StateChanged";
+
 const RPC_TIMEOUT: std::time::Duration = Duration::from_secs(3);
 
 const DBUS_UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
@@ -636,6 +640,68 @@ impl NetworkManager {
         Err(Error::DeviceNotFound)
     }
 
+    /// Returns the SSIDs of all Wi-Fi networks that are currently connected.
+    pub fn wifi_ssids(&self) -> Result<Vec<String>> {
+        let active_connections: Vec<dbus::Path<'static>> = self
+            .as_manager()
+            .get(NM_MANAGER, "ActiveConnections")
+            .map_err(Error::Dbus)?;
+
+        let mut ssids = vec![];
+        for connection in &active_connections {
+            let connection_type: String = self
+                .as_path(connection)
+                .get(NM_CONNECTION_ACTIVE, "Type")
+                .map_err(Error::Dbus)?;
+            if connection_type != NM_WIRELESS_CONNECTION_TYPE {
+                continue;
+            }
+            // For Wi-Fi connections, the specific object is the access point
+            let access_point: dbus::Path<'static> = self
+                .as_path(connection)
+                .get(NM_CONNECTION_ACTIVE, "SpecificObject")
+                .map_err(Error::Dbus)?;
+            let ssid: Vec<u8> = self
+                .as_path(&access_point)
+                .get(NM_ACCESS_POINT, "Ssid")
+                .map_err(Error::Dbus)?;
+            ssids.push(String::from_utf8_lossy(&ssid).into_owned());
+        }
+        Ok(ssids)
+    }
+
+    /// Blocks and invokes `callback` every time the overall networking state of NetworkManager
+    /// changes. Returns once `should_continue` returns false.
+    pub fn watch_state<F: FnMut() + Send + 'static, S: Fn() -> bool + Clone + Send + 'static>(
+        &self,
+        mut callback: F,
+        should_continue: S,
+    ) -> Result<()> {
+        let mut match_rule = MatchRule::new_signal(NM_MANAGER, NM_STATE_CHANGED);
+        match_rule.path = Some(NM_MANAGER_PATH.into());
+        let should_continue_outer = should_continue.clone();
+        let state_matcher = self
+            .connection
+            .add_match(
+                match_rule,
+                move |_: ManagerStateChange, _connection, _message| {
+                    callback();
+                    should_continue()
+                },
+            )
+            .map_err(Error::Dbus)?;
+
+        while should_continue_outer() {
+            if let Err(err) = self.connection.process(RPC_TIMEOUT) {
+                log::error!("Failed to process DBus messages: {}", err);
+            }
+        }
+
+        self.connection
+            .remove_match(state_matcher)
+            .map_err(Error::Dbus)
+    }
+
     pub fn convert_address_to_dbus(address: &IpAddr) -> VariantMap {
         let mut map: VariantMap = HashMap::new();
         map.insert(
@@ -671,6 +737,22 @@ impl dbus::message::SignalArgs for DeviceStateChange {
     const INTERFACE: &'static str = NM_DEVICE;
 }
 
+#[derive(Debug)]
+struct ManagerStateChange {
+    _state: u32,
+}
+
+impl arg::ReadAll for ManagerStateChange {
+    fn read(i: &mut arg::Iter<'_>) -> std::result::Result<Self, arg::TypeMismatchError> {
+        Ok(ManagerStateChange { _state: i.read()? })
+    }
+}
+
+impl dbus::message::SignalArgs for ManagerStateChange {
+    const NAME: &'static str = NM_STATE_CHANGED;
+    const INTERFACE: &'static str = NM_MANAGER;
+}
+
 #[derive(Debug)]
 pub struct WireguardTunnel {
     config_path: dbus::Path<'static>,
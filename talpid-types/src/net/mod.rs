@@ -15,6 +15,36 @@ pub mod openvpn;
 pub mod proxy;
 pub mod wireguard;
 
+/// Firewall mark and routing table used to route traffic on Linux.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LinuxRoutingOptions {
+    /// Mark of traffic that should not be routed through the tunnel. A default mark is used if
+    /// this is unset.
+    pub fwmark: Option<u32>,
+    /// ID of the routing table that routes traffic through the tunnel. A default table is used if
+    /// this is unset.
+    pub routing_table: Option<u32>,
+}
+
+#[cfg(target_os = "linux")]
+impl LinuxRoutingOptions {
+    // b"mole" is [ 0x6d, 0x6f 0x6c, 0x65 ]
+    pub const DEFAULT_FWMARK: u32 = 0x6d6f6c65;
+    pub const DEFAULT_ROUTING_TABLE: u32 = 0x6d6f6c65;
+
+    /// Returns the fwmark in use, which is the default one unless it has been configured.
+    pub fn fwmark_or_default(&self) -> u32 {
+        self.fwmark.unwrap_or(Self::DEFAULT_FWMARK)
+    }
+
+    /// Returns the routing table in use, which is the default one unless it has been configured.
+    pub fn routing_table_or_default(&self) -> u32 {
+        self.routing_table.unwrap_or(Self::DEFAULT_ROUTING_TABLE)
+    }
+}
+
 /// TunnelParameters are used to encapsulate all the data needed to start a tunnel. This is enum
 /// should be generated by implementations of the trait
 /// `talpid-core::tunnel_state_machine::TunnelParametersGenerator`
@@ -3,7 +3,10 @@ use crate::net::{
     Endpoint, GenericTunnelOptions, TransportProtocol,
 };
 use serde::{Deserialize, Serialize};
-use std::net::SocketAddr;
+use std::{fmt, net::SocketAddr};
+
+/// Data channel cipher of OpenVPN tunnels. It is the only cipher offered to the server.
+pub const DATA_CIPHER: &str = "AES-256-GCM";
 
 /// Information needed by `OpenVpnMonitor` to establish a tunnel connection.
 /// See [`crate::net::TunnelParameters`].
@@ -104,12 +107,32 @@ impl RemoteProxySettings {
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+/// Credentials for a remote proxy. These are never included in the `Debug` output.
+#[derive(Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct ProxyAuth {
     pub username: String,
     pub password: String,
 }
 
+impl ProxyAuth {
+    /// Returns true if the credentials can be passed to OpenVPN. OpenVPN reads them from a file
+    /// with one field per line, so neither field may be empty or contain line breaks.
+    pub fn is_valid(&self) -> bool {
+        let is_valid_field =
+            |field: &str| !field.is_empty() && !field.contains(|c: char| c == '\n' || c == '\r');
+        is_valid_field(&self.username) && is_valid_field(&self.password)
+    }
+}
+
+impl fmt::Debug for ProxyAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyAuth")
+            .field("username", &"[REDACTED]")
+            .field("password", &"[REDACTED]")
+            .finish()
+    }
+}
+
 /// Options for a bundled Shadowsocks proxy.
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct ShadowsocksProxySettings {
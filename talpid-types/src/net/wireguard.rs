@@ -55,6 +55,9 @@ pub struct PeerConfig {
     pub allowed_ips: Vec<IpNetwork>,
     /// IP address of the WireGuard server.
     pub endpoint: SocketAddr,
+    /// Interval, in seconds, at which keepalive packets are sent to the peer. Disabled if unset.
+    #[serde(default)]
+    pub persistent_keepalive: Option<u16>,
 }
 
 #[derive(Clone, Eq, PartialEq, Deserialize, Serialize, Debug)]
@@ -78,6 +81,10 @@ pub struct TunnelOptions {
         jnix(map = "|maybe_mtu| maybe_mtu.map(|mtu| mtu as i32)")
     )]
     pub mtu: Option<u16>,
+    /// Persistent keepalive interval for the peer that is connected to directly, in seconds
+    #[cfg_attr(target_os = "android", jnix(skip))]
+    #[serde(default)]
+    pub wireguard_keepalive: Option<u16>,
     /// Temporary switch for wireguard-nt
     #[cfg(windows)]
     #[serde(default = "default_wgnt_setting")]
@@ -94,6 +101,7 @@ impl Default for TunnelOptions {
     fn default() -> Self {
         Self {
             mtu: None,
+            wireguard_keepalive: None,
             #[cfg(windows)]
             use_wireguard_nt: default_wgnt_setting(),
         }
@@ -186,6 +194,18 @@ impl PublicKey {
         base64::encode(self.as_bytes())
     }
 
+    /// Returns a short fingerprint of the key, for display and verification. This is the first
+    /// 8 bytes of the SHA-256 digest of the raw key, as lowercase hex in colon-separated groups of
+    /// four digits, e.g. `3f47:b406:0d59:584f`.
+    pub fn fingerprint(&self) -> String {
+        let digest = ring::digest::digest(&ring::digest::SHA256, self.as_bytes());
+        digest.as_ref()[..8]
+            .chunks(2)
+            .map(|group| format!("{:02x}{:02x}", group[0], group[1]))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+
     pub fn from_base64(key: &str) -> Result<Self, InvalidKeyError> {
         let bytes = base64::decode(key).map_err(|_| InvalidKeyError(()))?;
         if bytes.len() != 32 {
@@ -281,3 +301,14 @@ where
             Ok(From::from(key))
         })
 }
+
+#[cfg(test)]
+mod test {
+    use super::PublicKey;
+
+    #[test]
+    fn test_public_key_fingerprint() {
+        let key = PublicKey::from_base64("BLNHNoGO88LjV/wDBa7CUUwUzPq/fO2UwcGLy56hKy4=").unwrap();
+        assert_eq!(key.fingerprint(), "3f47:b406:0d59:584f");
+    }
+}
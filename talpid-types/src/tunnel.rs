@@ -2,9 +2,9 @@ use crate::net::TunnelEndpoint;
 #[cfg(target_os = "android")]
 use jnix::IntoJava;
 use serde::{Deserialize, Serialize};
-use std::fmt;
 #[cfg(target_os = "android")]
 use std::net::IpAddr;
+use std::{fmt, time::Duration};
 
 /// Event emitted from the states in `talpid_core::tunnel_state_machine` when the tunnel state
 /// machine enters a new state.
@@ -106,6 +106,12 @@ pub enum ErrorStateCause {
     /// Error reported by split tunnel module.
     #[cfg(target_os = "windows")]
     SplitTunnelError,
+    /// No request could be sent through the tunnel after it connected.
+    #[cfg(not(target_os = "android"))]
+    TunnelUnverified,
+    /// The tunnel did not connect within the configured deadline.
+    #[cfg(not(target_os = "android"))]
+    ConnectDeadlineExceeded,
 }
 
 impl ErrorStateCause {
@@ -130,6 +136,12 @@ pub enum ParameterGenerationError {
     /// Failure to select a matching bridge relay
     #[error(display = "Failure to select a matching bridge relay")]
     NoMatchingBridgeRelay,
+    /// Failure to select a matching relay where port forwarding is available
+    #[error(display = "Failure to select a matching relay with port forwarding")]
+    NoMatchingPortForwardingRelay,
+    /// Failure to select a matching relay that supports the selected obfuscation
+    #[error(display = "Failure to select a matching relay that supports the selected obfuscation")]
+    NoMatchingObfuscatorRelay,
     /// Returned when tunnel parameters can't be generated because wireguard key is not available.
     #[error(display = "No wireguard key available")]
     NoWireguardKey,
@@ -162,6 +174,58 @@ pub enum FirewallPolicyError {
     Locked(Option<BlockingApplication>),
 }
 
+/// How long to wait before retrying to connect the tunnel. The delay starts at `base_delay_secs`
+/// and is doubled for every consecutive attempt, but never exceeds `max_delay_secs`. By default,
+/// attempts are retried immediately.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ConnectionRetryBackoff {
+    pub base_delay_secs: u32,
+    pub max_delay_secs: u32,
+}
+
+impl ConnectionRetryBackoff {
+    /// Returns the delay to wait before making connection attempt `retry_attempt`. The first
+    /// attempt is never delayed.
+    pub fn delay(&self, retry_attempt: u32) -> Duration {
+        if retry_attempt == 0 {
+            return Duration::ZERO;
+        }
+        let multiplier = 1u32.checked_shl(retry_attempt - 1).unwrap_or(u32::MAX);
+        let delay_secs = self
+            .base_delay_secs
+            .saturating_mul(multiplier)
+            .min(self.max_delay_secs);
+        Duration::from_secs(u64::from(delay_secs))
+    }
+}
+
+/// How the tunnel reacts to routes or interfaces changing such that the device appears to be
+/// offline. By default, the tunnel is disconnected as soon as the device is offline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RouteChangeReconnect {
+    /// Whether a connecting or connected tunnel is disconnected when the device goes offline.
+    pub enabled: bool,
+    /// How long the device must stay offline before the tunnel is disconnected.
+    pub debounce_secs: u32,
+}
+
+impl Default for RouteChangeReconnect {
+    fn default() -> Self {
+        RouteChangeReconnect {
+            enabled: true,
+            debounce_secs: 0,
+        }
+    }
+}
+
+impl RouteChangeReconnect {
+    pub fn debounce(&self) -> Duration {
+        Duration::from_secs(u64::from(self.debounce_secs))
+    }
+}
+
 impl fmt::Display for ErrorStateCause {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use self::ErrorStateCause::*;
@@ -208,8 +272,32 @@ impl fmt::Display for ErrorStateCause {
             VpnPermissionDenied => "The Android VPN permission was denied when creating the tunnel",
             #[cfg(target_os = "windows")]
             SplitTunnelError => "The split tunneling module reported an error",
+            #[cfg(not(target_os = "android"))]
+            TunnelUnverified => "The connectivity of the tunnel could not be verified",
+            #[cfg(not(target_os = "android"))]
+            ConnectDeadlineExceeded => "The tunnel did not connect within the configured time",
         };
 
         write!(f, "{}", description)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_connection_retry_backoff() {
+        let backoff = ConnectionRetryBackoff {
+            base_delay_secs: 2,
+            max_delay_secs: 30,
+        };
+        assert_eq!(backoff.delay(0), Duration::ZERO);
+        assert_eq!(backoff.delay(1), Duration::from_secs(2));
+        assert_eq!(backoff.delay(3), Duration::from_secs(8));
+        assert_eq!(backoff.delay(5), Duration::from_secs(30));
+        assert_eq!(backoff.delay(100), Duration::from_secs(30));
+
+        assert_eq!(ConnectionRetryBackoff::default().delay(4), Duration::ZERO);
+    }
+}